@@ -198,9 +198,10 @@ impl TypeVar {
                     "can't double 256 lanes"
                 );
             }
-            DerivedFunc::LaneOf | DerivedFunc::ToBitVec | DerivedFunc::AsBool => {
-                /* no particular assertions */
-            }
+            DerivedFunc::LaneOf
+            | DerivedFunc::ToBitVec
+            | DerivedFunc::AsBool
+            | DerivedFunc::AsMask => { /* no particular assertions */ }
         }
 
         return TypeVar {
@@ -222,6 +223,9 @@ impl TypeVar {
     pub fn as_bool(&self) -> TypeVar {
         return self.derived(DerivedFunc::AsBool);
     }
+    pub fn as_mask(&self) -> TypeVar {
+        return self.derived(DerivedFunc::AsMask);
+    }
     pub fn half_width(&self) -> TypeVar {
         return self.derived(DerivedFunc::HalfWidth);
     }
@@ -339,6 +343,7 @@ impl ops::Deref for TypeVar {
 pub enum DerivedFunc {
     LaneOf,
     AsBool,
+    AsMask,
     HalfWidth,
     DoubleWidth,
     HalfVector,
@@ -351,6 +356,7 @@ impl DerivedFunc {
         match self {
             DerivedFunc::LaneOf => "lane_of",
             DerivedFunc::AsBool => "as_bool",
+            DerivedFunc::AsMask => "as_mask",
             DerivedFunc::HalfWidth => "half_width",
             DerivedFunc::DoubleWidth => "double_width",
             DerivedFunc::HalfVector => "half_vector",
@@ -453,6 +459,7 @@ impl TypeSet {
         match derived_func {
             DerivedFunc::LaneOf => self.lane_of(),
             DerivedFunc::AsBool => self.as_bool(),
+            DerivedFunc::AsMask => self.as_mask(),
             DerivedFunc::HalfWidth => self.half_width(),
             DerivedFunc::DoubleWidth => self.double_width(),
             DerivedFunc::HalfVector => self.half_vector(),
@@ -486,6 +493,22 @@ impl TypeSet {
         copy
     }
 
+    /// Return a TypeSet describing the image of self across as_mask.
+    fn as_mask(&self) -> TypeSet {
+        let mut copy = self.clone();
+        copy.ints = NumSet::new();
+        copy.floats = NumSet::new();
+        copy.refs = NumSet::new();
+        copy.bitvecs = NumSet::new();
+        copy.specials = Vec::new();
+        copy.bools = if self.ints.len() + self.floats.len() + self.bools.len() > 0 {
+            num_set![1]
+        } else {
+            NumSet::new()
+        };
+        copy
+    }
+
     /// Return a TypeSet describing the image of self across halfwidth.
     fn half_width(&self) -> TypeSet {
         let mut copy = self.clone();
@@ -633,6 +656,18 @@ impl TypeSet {
                 }
                 copy
             }
+            DerivedFunc::AsMask => {
+                let mut copy = self.clone();
+                copy.bitvecs = NumSet::new();
+                if self.bools.contains(&1) {
+                    copy.ints = NumSet::from_iter(vec![8, 16, 32, 64, 128]);
+                    copy.floats = NumSet::from_iter(vec![32, 64]);
+                    copy.bools = NumSet::from_iter(vec![1, 8, 16, 32, 64, 128]);
+                } else {
+                    copy.bools = NumSet::new();
+                }
+                copy
+            }
             DerivedFunc::HalfWidth => self.double_width(),
             DerivedFunc::DoubleWidth => self.half_width(),
             DerivedFunc::HalfVector => self.double_vector(),
@@ -1044,6 +1079,32 @@ fn test_as_bool() {
     assert_eq!(b.as_bool(), b_as_bool);
 }
 
+#[test]
+fn test_as_mask() {
+    let a = TypeSetBuilder::new()
+        .simd_lanes(8..64)
+        .ints(8..64)
+        .floats(32..64)
+        .build();
+    assert_eq!(
+        a.as_mask(),
+        TypeSetBuilder::new().simd_lanes(8..64).bools(1..1).build()
+    );
+    assert_eq!(
+        TypeSetBuilder::new()
+            .simd_lanes(8..64)
+            .bools(1..1)
+            .build()
+            .preimage(DerivedFunc::AsMask),
+        TypeSetBuilder::new()
+            .simd_lanes(8..64)
+            .ints(Interval::All)
+            .floats(Interval::All)
+            .bools(Interval::All)
+            .build()
+    );
+}
+
 #[test]
 fn test_forward_images() {
     let empty_set = TypeSetBuilder::new().build();
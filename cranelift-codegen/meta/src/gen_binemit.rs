@@ -31,7 +31,7 @@ fn gen_recipe(formats: &FormatRegistry, recipe: &EncodingRecipe, fmt: &mut Forma
         OperandConstraint::FixedReg(_) | OperandConstraint::TiedInput(_) => false,
     });
 
-    let is_regmove = ["RegMove", "RegSpill", "RegFill"].contains(&inst_format.name);
+    let is_regmove = ["RegMove", "RegSwap", "RegSpill", "RegFill"].contains(&inst_format.name);
 
     // Unpack the instruction data.
     fmtln!(fmt, "if let &InstructionData::{} {{", inst_format.name);
@@ -373,6 +373,90 @@ fn gen_instruction_data_impl(registry: &FormatRegistry, fmt: &mut Formatter) {
             fmt.line("}");
         });
         fmt.line("}");
+        fmt.empty_line();
+
+        fmt.doc_comment(r#"
+            Encode an `InstructionData` in the binary format of the `binary` module.
+
+            This operation requires a reference to a `ValueListPool` to
+            encode the contents of any `ValueLists`.
+        "#);
+        fmt.line("pub(crate) fn encode(&self, w: &mut crate::binary::Writer, pool: &ir::ValueListPool) {");
+        fmt.indent(|fmt| {
+            fmt.line("match *self {");
+            fmt.indent(|fmt| {
+                for format in registry.iter() {
+                    let name = format!("InstructionData::{}", format.name);
+                    let mut members = vec!["ref opcode"];
+                    let mut lines = vec!["w.encode(opcode);".to_string()];
+
+                    if format.typevar_operand.is_none() {
+                    } else if format.has_value_list {
+                        members.push("ref args");
+                        lines.push("w.values(args.as_slice(pool));".to_string());
+                    } else if format.num_value_operands == 1 {
+                        members.push("ref arg");
+                        lines.push("w.encode(arg);".to_string());
+                    } else {
+                        members.push("ref args");
+                        for i in 0..format.num_value_operands {
+                            lines.push(format!("w.encode(&args[{}]);", i));
+                        }
+                    }
+
+                    let imm_members = format.imm_fields.iter().map(|field| format!("ref {}", field.member)).collect::<Vec<_>>();
+                    for field in &format.imm_fields {
+                        lines.push(format!("w.encode({});", field.member));
+                    }
+                    let members = members.iter().map(|x| x.to_string()).chain(imm_members).collect::<Vec<_>>().join(", ");
+
+                    fmtln!(fmt, "{} {{ {} }} => {{", name, members);
+                    fmt.indent(|fmt| {
+                        for line in &lines {
+                            fmt.line(line);
+                        }
+                    });
+                    fmtln!(fmt, "}");
+                }
+            });
+            fmt.line("}");
+        });
+        fmt.line("}");
+        fmt.empty_line();
+
+        fmt.doc_comment(r#"
+            Decode an `InstructionData` encoded by `encode`.
+
+            Value lists are allocated in `pool`. Returns `None` if the data is malformed.
+        "#);
+        fmt.line("pub(crate) fn decode(r: &mut crate::binary::Reader, pool: &mut ir::ValueListPool) -> Option<Self> {");
+        fmt.indent(|fmt| {
+            fmt.line("let opcode: Opcode = r.decode()?;");
+            fmt.line("Some(match opcode.format() {");
+            fmt.indent(|fmt| {
+                for format in registry.iter() {
+                    fmtln!(fmt, "InstructionFormat::{} => InstructionData::{} {{", format.name, format.name);
+                    fmt.indent(|fmt| {
+                        fmt.line("opcode,");
+                        if format.typevar_operand.is_none() {
+                        } else if format.has_value_list {
+                            fmt.line("args: ir::ValueList::from_slice(&r.values()?, pool),");
+                        } else if format.num_value_operands == 1 {
+                            fmt.line("arg: r.decode()?,");
+                        } else {
+                            let args = (0..format.num_value_operands).map(|_| "r.decode()?").collect::<Vec<_>>().join(", ");
+                            fmtln!(fmt, "args: [{}],", args);
+                        }
+                        for field in &format.imm_fields {
+                            fmtln!(fmt, "{}: r.decode()?,", field.member);
+                        }
+                    });
+                    fmt.line("},");
+                }
+            });
+            fmt.line("})");
+        });
+        fmt.line("}");
     });
     fmt.line("}");
 }
@@ -410,6 +494,10 @@ fn gen_opcodes<'a>(all_inst: &AllInstructions, formats: &FormatRegistry, fmt: &m
     );
     fmt.line("#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]");
 
+    // Opcodes are stored as a byte in the binary serialization of functions, and decoded with
+    // `Opcode::from_u8`, which relies on this representation.
+    fmt.line("#[repr(u8)]");
+
     // We explicitly set the discriminant of the first variant to 1, which allows us to take
     // advantage of the NonZero optimization, meaning that wrapping enums can use the 0
     // discriminant instead of increasing the size of the whole type, and so the size of
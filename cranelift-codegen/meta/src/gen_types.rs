@@ -9,6 +9,7 @@
 
 use crate::cdsl::types as cdsl_types;
 use crate::error;
+use crate::shared::types as shared_types;
 use crate::srcgen;
 
 /// Emit a constant definition of a single value type.
@@ -64,6 +65,12 @@ fn emit_types(fmt: &mut srcgen::Formatter) -> Result<(), error::Error> {
         emit_vectors(*vec_size, fmt)?;
     }
 
+    // Emit the vectors of `b1` lanes used for masks, with one bit per lane of a SIMD vector.
+    for lanes in &[8_u64, 16, 32, 64] {
+        let mask = cdsl_types::VectorType::new(shared_types::Bool::B1.into(), *lanes);
+        emit_type(&cdsl_types::ValueType::from(mask), fmt)?;
+    }
+
     Ok(())
 }
 
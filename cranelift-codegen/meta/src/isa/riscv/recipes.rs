@@ -119,6 +119,7 @@ pub(crate) fn define<'formats>(
     recipes.push(
         EncodingRecipeBuilder::new("Iz", f_unary_imm, 4)
             .operands_out(vec![gpr])
+            .clobbers_flags(false)
             .inst_predicate(InstructionPredicate::new_is_signed_int(
                 format, "imm", 12, 0,
             ))
@@ -199,6 +200,7 @@ pub(crate) fn define<'formats>(
     recipes.push(
         EncodingRecipeBuilder::new("U", f_unary_imm, 4)
             .operands_out(vec![gpr])
+            .clobbers_flags(false)
             .inst_predicate(InstructionPredicate::new_is_signed_int(
                 format, "imm", 32, 12,
             ))
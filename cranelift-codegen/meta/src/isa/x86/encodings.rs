@@ -368,6 +368,25 @@ impl<'defs> PerCpuModeEncodings<'defs> {
     }
 }
 
+/// Specialize `template` with a VEX prefix for operating on `vector_size`-bit vectors.
+fn vex_template<'builder>(template: &Template<'builder>, vector_size: u64) -> Template<'builder> {
+    match vector_size {
+        128 => template.vex(),
+        256 => template.vex().l(),
+        _ => panic!("no VEX encoding for {}-bit vectors", vector_size),
+    }
+}
+
+/// Specialize `template` with an EVEX prefix for operating on `vector_size`-bit vectors.
+fn evex_template<'builder>(template: &Template<'builder>, vector_size: u64) -> Template<'builder> {
+    match vector_size {
+        128 => template.evex(),
+        256 => template.evex().l(),
+        512 => template.evex().l512(),
+        _ => panic!("no EVEX encoding for {}-bit vectors", vector_size),
+    }
+}
+
 // Definitions.
 
 pub(crate) fn define<'defs>(
@@ -462,6 +481,7 @@ pub(crate) fn define<'defs>(
     let raw_bitcast = shared.by_name("raw_bitcast");
     let regfill = shared.by_name("regfill");
     let regmove = shared.by_name("regmove");
+    let regswap = shared.by_name("regswap");
     let regspill = shared.by_name("regspill");
     let return_ = shared.by_name("return");
     let rotl = shared.by_name("rotl");
@@ -529,6 +549,9 @@ pub(crate) fn define<'defs>(
     let x86_smulx = x86.by_name("x86_smulx");
     let x86_udivmodx = x86.by_name("x86_udivmodx");
     let x86_umulx = x86.by_name("x86_umulx");
+    let x86_vpcmp = x86.by_name("x86_vpcmp");
+    let x86_vpermt2b = x86.by_name("x86_vpermt2b");
+    let x86_vpmovm2 = x86.by_name("x86_vpmovm2");
 
     // Shorthands for recipes.
     let rec_adjustsp = r.template("adjustsp");
@@ -579,6 +602,7 @@ pub(crate) fn define<'defs>(
     let rec_fstWithIndexDisp32 = r.template("fstWithIndexDisp32");
     let rec_fstWithIndexDisp8 = r.template("fstWithIndexDisp8");
     let rec_furm = r.template("furm");
+    let rec_furm_mask = r.template("furm_mask");
     let rec_furm_reg_to_ssa = r.template("furm_reg_to_ssa");
     let rec_furmi_rnd = r.template("furmi_rnd");
     let rec_get_pinned_reg = r.recipe("get_pinned_reg");
@@ -596,6 +620,13 @@ pub(crate) fn define<'defs>(
     let rec_jmpd = r.template("jmpd");
     let rec_jt_base = r.template("jt_base");
     let rec_jt_entry = r.template("jt_entry");
+    let rec_kfillnull = r.recipe("kfillnull");
+    let rec_kfillSib32 = r.template("kfillSib32");
+    let rec_kmov = r.template("kmov");
+    let rec_kregfill32 = r.template("kregfill32");
+    let rec_kregspill32 = r.template("kregspill32");
+    let rec_kspillSib32 = r.template("kspillSib32");
+    let rec_kurm = r.template("kurm");
     let rec_ld = r.template("ld");
     let rec_ldDisp32 = r.template("ldDisp32");
     let rec_ldDisp8 = r.template("ldDisp8");
@@ -629,6 +660,7 @@ pub(crate) fn define<'defs>(
     let rec_rfumr = r.template("rfumr");
     let rec_rfurm = r.template("rfurm");
     let rec_rmov = r.template("rmov");
+    let rec_rswap = r.template("rswap");
     let rec_rr = r.template("rr");
     let rec_rout = r.template("rout");
     let rec_rin = r.template("rin");
@@ -667,11 +699,17 @@ pub(crate) fn define<'defs>(
     let rec_umr = r.template("umr");
     let rec_umr_reg_to_ssa = r.template("umr_reg_to_ssa");
     let rec_ur = r.template("ur");
+    let rec_ur_ib1 = r.template("ur_ib1");
     let rec_urm = r.template("urm");
     let rec_urm_noflags = r.template("urm_noflags");
     let rec_urm_noflags_abcd = r.template("urm_noflags_abcd");
     let rec_vconst = r.template("vconst");
     let rec_vconst_optimized = r.template("vconst_optimized");
+    let rec_vfa = r.template("vfa");
+    let rec_vfa_nds = r.template("vfa_nds");
+    let rec_vfax = r.template("vfax");
+    let rec_vicscc_fpr = r.template("vicscc_fpr");
+    let rec_vicscc_mask = r.template("vicscc_mask");
 
     // Predicates shorthands.
     let all_ones_funcaddrs_and_not_is_pic =
@@ -686,6 +724,13 @@ pub(crate) fn define<'defs>(
     let use_sse41 = settings.predicate_by_name("use_sse41");
     let use_ssse3_simd = settings.predicate_by_name("use_ssse3_simd");
     let use_sse41_simd = settings.predicate_by_name("use_sse41_simd");
+    let use_avx = settings.predicate_by_name("use_avx");
+    let use_avx_simd = settings.predicate_by_name("use_avx_simd");
+    let use_avx2_simd = settings.predicate_by_name("use_avx2_simd");
+    let use_avx512f_simd = settings.predicate_by_name("use_avx512f_simd");
+    let use_avx512bw_simd = settings.predicate_by_name("use_avx512bw_simd");
+    let use_avx512dq_simd = settings.predicate_by_name("use_avx512dq_simd");
+    let use_avx512vbmi_simd = settings.predicate_by_name("use_avx512vbmi_simd");
 
     // Definitions.
     let mut e = PerCpuModeEncodings::new(formats);
@@ -725,6 +770,12 @@ pub(crate) fn define<'defs>(
     e.enc_both(bor.bind(B1), rec_rr.opcodes(&OR));
     e.enc_both(bxor.bind(B1), rec_rr.opcodes(&XOR));
 
+    // A `b1` is 0 or 1 in a register, so it is negated by flipping the lowest bit.
+    e.enc_both(
+        bnot.bind(B1),
+        rec_ur_ib1.opcodes(&XOR_IMM8_SIGN_EXTEND).rrr(6),
+    );
+
     e.enc_i32_i64(imul, rec_rrx.opcodes(&IMUL));
     e.enc_i32_i64(x86_sdivmodx, rec_div.opcodes(&IDIV).rrr(7));
     e.enc_i32_i64(x86_udivmodx, rec_div.opcodes(&DIV).rrr(6));
@@ -755,6 +806,21 @@ pub(crate) fn define<'defs>(
     e.enc64(regmove.bind(R32), rec_rmov.opcodes(&MOV_STORE).rex());
     e.enc64(regmove.bind(R64), rec_rmov.opcodes(&MOV_STORE).rex().w());
 
+    // Exchange full registers to leave the upper bits of narrower values intact.
+    for &ty in &[I8, I16, I32] {
+        e.enc32(regswap.bind(ty), rec_rswap.opcodes(&XCHG));
+        e.enc64(regswap.bind(ty), rec_rswap.opcodes(&XCHG).rex().w());
+    }
+    for &ty in &[B1, B8, B16, B32] {
+        e.enc32(regswap.bind(ty), rec_rswap.opcodes(&XCHG));
+        e.enc64(regswap.bind(ty), rec_rswap.opcodes(&XCHG).rex().w());
+    }
+    e.enc32(regswap.bind(R32), rec_rswap.opcodes(&XCHG));
+    e.enc64(regswap.bind(R32), rec_rswap.opcodes(&XCHG).rex().w());
+    e.enc64(regswap.bind(I64), rec_rswap.opcodes(&XCHG).rex().w());
+    e.enc64(regswap.bind(B64), rec_rswap.opcodes(&XCHG).rex().w());
+    e.enc64(regswap.bind(R64), rec_rswap.opcodes(&XCHG).rex().w());
+
     e.enc_i32_i64(iadd_imm, rec_r_ib.opcodes(&ADD_IMM8_SIGN_EXTEND).rrr(0));
     e.enc_i32_i64(iadd_imm, rec_r_id.opcodes(&ADD_IMM).rrr(0));
 
@@ -1661,6 +1727,27 @@ pub(crate) fn define<'defs>(
         e.enc_both_isap(inst.bind(F64), rec_furmi_rnd.opcodes(&ROUNDSD), use_sse41);
     }
 
+    // VEX-encoded binary arithmetic and bitwise ops. The three-operand forms don't tie the output
+    // to an input, which saves the register allocator from inserting copies. They are only
+    // defined for x86-64, and must precede the SSE encodings below to be picked when AVX is
+    // available.
+    for (inst, f32_opcodes, f64_opcodes) in &[
+        (fadd, &ADDSS[..], &ADDSD[..]),
+        (fsub, &SUBSS[..], &SUBSD[..]),
+        (fmul, &MULSS[..], &MULSD[..]),
+        (fdiv, &DIVSS[..], &DIVSD[..]),
+        (x86_fmin, &MINSS[..], &MINSD[..]),
+        (x86_fmax, &MAXSS[..], &MAXSD[..]),
+        (band, &ANDPS[..], &ANDPS[..]),
+        (bor, &ORPS[..], &ORPS[..]),
+        (bxor, &XORPS[..], &XORPS[..]),
+    ] {
+        e.enc64_isap(inst.bind(F32), rec_vfa.vex().opcodes(f32_opcodes), use_avx);
+        e.enc64_isap(inst.bind(F64), rec_vfa.vex().opcodes(f64_opcodes), use_avx);
+    }
+    e.enc64_isap(band_not.bind(F32), rec_vfax.vex().opcodes(&ANDNPS), use_avx);
+    e.enc64_isap(band_not.bind(F64), rec_vfax.vex().opcodes(&ANDNPS), use_avx);
+
     // Binary arithmetic ops.
     e.enc_both(fadd.bind(F32), rec_fa.opcodes(&ADDSS));
     e.enc_both(fadd.bind(F64), rec_fa.opcodes(&ADDSD));
@@ -1706,9 +1793,11 @@ pub(crate) fn define<'defs>(
     e.enc_both(ffcmp.bind(F32), rec_fcmp.opcodes(&UCOMISS));
     e.enc_both(ffcmp.bind(F64), rec_fcmp.opcodes(&UCOMISD));
 
-    // SIMD vector size: eventually multiple vector sizes may be supported but for now only
-    // SSE-sized vectors are available.
+    // SIMD vector sizes: SSE-sized vectors are available with SSE2, and AVX-sized vectors need a
+    // VEX prefix. The 256-bit vector types are only supported on x86-64 with AVX2.
     let sse_vector_size: u64 = 128;
+    let avx_vector_size: u64 = 256;
+    let avx512_vector_size: u64 = 512;
 
     // SIMD splat: before x86 can use vector data, it must be moved to XMM registers; see
     // legalize.rs for how this is done; once there, x86_pshuf* (below) is used for broadcasting the
@@ -1877,6 +1966,428 @@ pub(crate) fn define<'defs>(
         e.enc_32_64_maybe_isap(instruction, template, None); // from SSE
     }
 
+    // SIMD operations with AVX. These are only defined for x86-64, and must precede the SSE
+    // encodings of the same instructions so they get picked when AVX is available. The 128-bit
+    // vector registers are moved with the VEX encodings too, to avoid the penalty of mixing legacy
+    // SSE instructions with 256-bit AVX instructions.
+    for &(vector_size, isap) in &[
+        (sse_vector_size, use_avx_simd),
+        (avx_vector_size, use_avx2_simd),
+    ] {
+        let vex = |template| vex_template(template, vector_size);
+
+        // Register movement: store, load, spill, fill, regmove, copy.
+        for ty in ValueType::all_lane_types().filter(allowed_simd_type) {
+            for template in &[rec_fst, rec_fstDisp8, rec_fstDisp32] {
+                let bound_store = store.bind(vector(ty, vector_size)).bind(Any);
+                e.enc64_isap(bound_store, vex(template).opcodes(&MOVUPS_STORE), isap);
+            }
+            for template in &[rec_fld, rec_fldDisp8, rec_fldDisp32] {
+                let bound_load = load.bind(vector(ty, vector_size)).bind(Any);
+                e.enc64_isap(bound_load, vex(template).opcodes(&MOVUPS_LOAD), isap);
+            }
+            e.enc64_isap(
+                spill.bind(vector(ty, vector_size)),
+                vex(rec_fspillSib32).opcodes(&MOVUPS_STORE),
+                isap,
+            );
+            e.enc64_isap(
+                regspill.bind(vector(ty, vector_size)),
+                vex(rec_fregspill32).opcodes(&MOVUPS_STORE),
+                isap,
+            );
+            e.enc64_isap(
+                fill.bind(vector(ty, vector_size)),
+                vex(rec_ffillSib32).opcodes(&MOVUPS_LOAD),
+                isap,
+            );
+            e.enc64_isap(
+                regfill.bind(vector(ty, vector_size)),
+                vex(rec_fregfill32).opcodes(&MOVUPS_LOAD),
+                isap,
+            );
+            e.enc64_isap(
+                regmove.bind(vector(ty, vector_size)),
+                vex(rec_frmov).opcodes(&MOVAPS_LOAD),
+                isap,
+            );
+            e.enc64_isap(
+                copy.bind(vector(ty, vector_size)),
+                vex(rec_furm).opcodes(&MOVAPS_LOAD),
+                isap,
+            );
+        }
+
+        // Integer arithmetic.
+        for (ty, opcodes) in &[
+            (I8, &PADDB[..]),
+            (I16, &PADDW[..]),
+            (I32, &PADDD[..]),
+            (I64, &PADDQ[..]),
+        ] {
+            let iadd = iadd.bind(vector(*ty, vector_size));
+            e.enc64_isap(iadd, vex(rec_vfa).opcodes(opcodes), isap);
+        }
+        for (ty, opcodes) in &[
+            (I8, &PSUBB[..]),
+            (I16, &PSUBW[..]),
+            (I32, &PSUBD[..]),
+            (I64, &PSUBQ[..]),
+        ] {
+            let isub = isub.bind(vector(*ty, vector_size));
+            e.enc64_isap(isub, vex(rec_vfa).opcodes(opcodes), isap);
+        }
+        for (inst, ty, opcodes) in &[
+            (sadd_sat, I8, &PADDSB[..]),
+            (sadd_sat, I16, &PADDSW[..]),
+            (uadd_sat, I8, &PADDUSB[..]),
+            (uadd_sat, I16, &PADDUSW[..]),
+            (ssub_sat, I8, &PSUBSB[..]),
+            (ssub_sat, I16, &PSUBSW[..]),
+            (usub_sat, I8, &PSUBUSB[..]),
+            (usub_sat, I16, &PSUBUSW[..]),
+            (imul, I16, &PMULLW[..]),
+            (imul, I32, &PMULLD[..]),
+        ] {
+            let inst = inst.bind(vector(*ty, vector_size));
+            e.enc64_isap(inst, vex(rec_vfa).opcodes(opcodes), isap);
+        }
+
+        // Logical operations.
+        for ty in ValueType::all_lane_types().filter(allowed_simd_type) {
+            for (inst, template, opcodes) in &[
+                (band, rec_vfa, &PAND[..]),
+                (band_not, rec_vfax, &PANDN[..]),
+                (bor, rec_vfa, &POR[..]),
+                (bxor, rec_vfa, &PXOR[..]),
+            ] {
+                let inst = inst.bind(vector(ty, vector_size));
+                e.enc64_isap(inst, vex(template).opcodes(opcodes), isap);
+            }
+        }
+
+        // Shifts, by the count in the low 64 bits of an XMM register.
+        for (inst, ty, opcodes) in &[
+            (x86_psll, I16, &PSLLW[..]),
+            (x86_psll, I32, &PSLLD[..]),
+            (x86_psll, I64, &PSLLQ[..]),
+            (x86_psrl, I16, &PSRLW[..]),
+            (x86_psrl, I32, &PSRLD[..]),
+            (x86_psrl, I64, &PSRLQ[..]),
+            (x86_psra, I16, &PSRAW[..]),
+            (x86_psra, I32, &PSRAD[..]),
+        ] {
+            let inst = inst.bind(vector(*ty, vector_size));
+            e.enc64_isap(inst, vex(rec_vfa).opcodes(opcodes), isap);
+        }
+
+        // Equality comparisons.
+        for (ty, opcodes) in &[
+            (I8, &PCMPEQB[..]),
+            (I16, &PCMPEQW[..]),
+            (I32, &PCMPEQD[..]),
+            (I64, &PCMPEQQ[..]),
+        ] {
+            let instruction = icmp.bind(vector(*ty, vector_size));
+            let f_int_compare = formats.get(formats.by_name("IntCompare"));
+            let has_eq_condition_code =
+                InstructionPredicate::new_has_condition_code(f_int_compare, IntCC::Equal, "cond");
+            e.enc64_func(
+                instruction,
+                vex(rec_vicscc_fpr).opcodes(opcodes),
+                |builder| {
+                    builder
+                        .inst_predicate(has_eq_condition_code)
+                        .isa_predicate(isap)
+                },
+            );
+        }
+
+        // Floating point arithmetic.
+        for (inst, f32_opcodes, f64_opcodes) in &[
+            (fadd, &ADDPS[..], &ADDPD[..]),
+            (fsub, &SUBPS[..], &SUBPD[..]),
+            (fmul, &MULPS[..], &MULPD[..]),
+            (fdiv, &DIVPS[..], &DIVPD[..]),
+        ] {
+            let f32_inst = inst.bind(vector(F32, vector_size));
+            e.enc64_isap(f32_inst, vex(rec_vfa).opcodes(f32_opcodes), isap);
+            let f64_inst = inst.bind(vector(F64, vector_size));
+            e.enc64_isap(f64_inst, vex(rec_vfa).opcodes(f64_opcodes), isap);
+        }
+    }
+
+    // The 256-bit vectors are moved and bitcast like the SSE-sized ones below, but only the
+    // operations above are supported: splat, insertlane, extractlane and shuffles are not.
+    for ty in ValueType::all_lane_types().filter(allowed_simd_type) {
+        let instruction = vconst.bind(vector(ty, avx_vector_size));
+        let template = rec_vconst.vex().l().opcodes(&MOVUPS_LOAD);
+        e.enc64_isap(instruction, template, use_avx2_simd);
+
+        let bound_fill_nop = fill_nop.bind(vector(ty, avx_vector_size));
+        e.enc64_rec(bound_fill_nop, rec_ffillnull, 0);
+        let bound_copy_nop = copy_nop.bind(vector(ty, avx_vector_size));
+        e.enc64_rec(bound_copy_nop, rec_stacknull, 0);
+
+        for to_type in ValueType::all_lane_types().filter(|t| allowed_simd_type(t) && *t != ty) {
+            let instruction = raw_bitcast
+                .bind(vector(to_type, avx_vector_size))
+                .bind(vector(ty, avx_vector_size));
+            e.enc64_rec(instruction, rec_null_fpr, 0);
+        }
+    }
+
+    // SIMD operations on 512-bit vectors with AVX-512. These use the EVEX prefix, which compresses
+    // 8-bit displacements by the memory operand size, so only the recipes with no or 32-bit
+    // displacements are used for memory accesses.
+    let evex = |template| evex_template(template, avx512_vector_size);
+    for ty in ValueType::all_lane_types().filter(allowed_simd_type) {
+        for template in &[rec_fst, rec_fstDisp32] {
+            let bound_store = store.bind(vector(ty, avx512_vector_size)).bind(Any);
+            e.enc64_isap(
+                bound_store,
+                evex(template).opcodes(&MOVUPS_STORE),
+                use_avx512f_simd,
+            );
+        }
+        for template in &[rec_fld, rec_fldDisp32] {
+            let bound_load = load.bind(vector(ty, avx512_vector_size)).bind(Any);
+            e.enc64_isap(
+                bound_load,
+                evex(template).opcodes(&MOVUPS_LOAD),
+                use_avx512f_simd,
+            );
+        }
+        e.enc64_isap(
+            spill.bind(vector(ty, avx512_vector_size)),
+            evex(rec_fspillSib32).opcodes(&MOVUPS_STORE),
+            use_avx512f_simd,
+        );
+        e.enc64_isap(
+            regspill.bind(vector(ty, avx512_vector_size)),
+            evex(rec_fregspill32).opcodes(&MOVUPS_STORE),
+            use_avx512f_simd,
+        );
+        e.enc64_isap(
+            fill.bind(vector(ty, avx512_vector_size)),
+            evex(rec_ffillSib32).opcodes(&MOVUPS_LOAD),
+            use_avx512f_simd,
+        );
+        e.enc64_isap(
+            regfill.bind(vector(ty, avx512_vector_size)),
+            evex(rec_fregfill32).opcodes(&MOVUPS_LOAD),
+            use_avx512f_simd,
+        );
+        e.enc64_isap(
+            regmove.bind(vector(ty, avx512_vector_size)),
+            evex(rec_frmov).opcodes(&MOVAPS_LOAD),
+            use_avx512f_simd,
+        );
+        e.enc64_isap(
+            copy.bind(vector(ty, avx512_vector_size)),
+            evex(rec_furm).opcodes(&MOVAPS_LOAD),
+            use_avx512f_simd,
+        );
+        e.enc64_isap(
+            vconst.bind(vector(ty, avx512_vector_size)),
+            evex(rec_vconst).opcodes(&MOVUPS_LOAD),
+            use_avx512f_simd,
+        );
+
+        e.enc64_rec(
+            fill_nop.bind(vector(ty, avx512_vector_size)),
+            rec_ffillnull,
+            0,
+        );
+        e.enc64_rec(
+            copy_nop.bind(vector(ty, avx512_vector_size)),
+            rec_stacknull,
+            0,
+        );
+
+        for to_type in ValueType::all_lane_types().filter(|t| allowed_simd_type(t) && *t != ty) {
+            let instruction = raw_bitcast
+                .bind(vector(to_type, avx512_vector_size))
+                .bind(vector(ty, avx512_vector_size));
+            e.enc64_rec(instruction, rec_null_fpr, 0);
+        }
+
+        for (inst, template, opcodes) in &[
+            (band, rec_vfa, &PAND[..]),
+            (band_not, rec_vfax, &PANDN[..]),
+            (bor, rec_vfa, &POR[..]),
+            (bxor, rec_vfa, &PXOR[..]),
+        ] {
+            // These are VPANDD, VPANDND, VPORD and VPXORD, the lane size doesn't matter without a
+            // writemask.
+            let inst = inst.bind(vector(ty, avx512_vector_size));
+            e.enc64_isap(inst, evex(template).opcodes(opcodes), use_avx512f_simd);
+        }
+    }
+
+    // Integer arithmetic and shifts. The byte and word operations are part of AVX512BW, and
+    // the 64-bit lanes need EVEX.W1.
+    for (inst, ty, opcodes, w, isap) in &[
+        (iadd, I8, &PADDB[..], false, use_avx512bw_simd),
+        (iadd, I16, &PADDW[..], false, use_avx512bw_simd),
+        (iadd, I32, &PADDD[..], false, use_avx512f_simd),
+        (iadd, I64, &PADDQ[..], true, use_avx512f_simd),
+        (isub, I8, &PSUBB[..], false, use_avx512bw_simd),
+        (isub, I16, &PSUBW[..], false, use_avx512bw_simd),
+        (isub, I32, &PSUBD[..], false, use_avx512f_simd),
+        (isub, I64, &PSUBQ[..], true, use_avx512f_simd),
+        (sadd_sat, I8, &PADDSB[..], false, use_avx512bw_simd),
+        (sadd_sat, I16, &PADDSW[..], false, use_avx512bw_simd),
+        (uadd_sat, I8, &PADDUSB[..], false, use_avx512bw_simd),
+        (uadd_sat, I16, &PADDUSW[..], false, use_avx512bw_simd),
+        (ssub_sat, I8, &PSUBSB[..], false, use_avx512bw_simd),
+        (ssub_sat, I16, &PSUBSW[..], false, use_avx512bw_simd),
+        (usub_sat, I8, &PSUBUSB[..], false, use_avx512bw_simd),
+        (usub_sat, I16, &PSUBUSW[..], false, use_avx512bw_simd),
+        (imul, I16, &PMULLW[..], false, use_avx512bw_simd),
+        (imul, I32, &PMULLD[..], false, use_avx512f_simd),
+        // VPMULLQ shares its opcode with VPMULLD.
+        (imul, I64, &PMULLD[..], true, use_avx512dq_simd),
+        (x86_psll, I16, &PSLLW[..], false, use_avx512bw_simd),
+        (x86_psll, I32, &PSLLD[..], false, use_avx512f_simd),
+        (x86_psll, I64, &PSLLQ[..], true, use_avx512f_simd),
+        (x86_psrl, I16, &PSRLW[..], false, use_avx512bw_simd),
+        (x86_psrl, I32, &PSRLD[..], false, use_avx512f_simd),
+        (x86_psrl, I64, &PSRLQ[..], true, use_avx512f_simd),
+        (x86_psra, I16, &PSRAW[..], false, use_avx512bw_simd),
+        (x86_psra, I32, &PSRAD[..], false, use_avx512f_simd),
+        // VPSRAQ shares its opcode with VPSRAD.
+        (x86_psra, I64, &PSRAD[..], true, use_avx512f_simd),
+    ] {
+        let inst = inst.bind(vector(*ty, avx512_vector_size));
+        let template = evex(rec_vfa).opcodes(opcodes);
+        let template = if *w { template.w() } else { template };
+        e.enc64_isap(inst, template, *isap);
+    }
+
+    // Integer comparisons, which produce a mask register that is expanded back into a vector.
+    for (ty, bool_ty, cmp_opcodes, movm2_opcodes, w, cmp_isap, movm2_isap) in &[
+        (
+            I8,
+            B8,
+            &VPCMPB[..],
+            &VPMOVM2B[..],
+            false,
+            use_avx512bw_simd,
+            use_avx512bw_simd,
+        ),
+        (
+            I16,
+            B16,
+            &VPCMPB[..],
+            &VPMOVM2B[..],
+            true,
+            use_avx512bw_simd,
+            use_avx512bw_simd,
+        ),
+        (
+            I32,
+            B32,
+            &VPCMPD[..],
+            &VPMOVM2D[..],
+            false,
+            use_avx512f_simd,
+            use_avx512dq_simd,
+        ),
+        (
+            I64,
+            B64,
+            &VPCMPD[..],
+            &VPMOVM2D[..],
+            true,
+            use_avx512f_simd,
+            use_avx512dq_simd,
+        ),
+    ] {
+        let cmp = evex(rec_vicscc_mask).opcodes(cmp_opcodes);
+        let movm2 = evex(rec_furm_mask).opcodes(movm2_opcodes);
+        let (cmp, movm2) = if *w {
+            (cmp.w(), movm2.w())
+        } else {
+            (cmp, movm2)
+        };
+        let vpcmp = x86_vpcmp.bind(vector(*ty, avx512_vector_size));
+        e.enc64_isap(vpcmp, cmp, *cmp_isap);
+        let vpmovm2 = x86_vpmovm2.bind(vector(*bool_ty, avx512_vector_size));
+        e.enc64_isap(vpmovm2, movm2, *movm2_isap);
+    }
+
+    // Moves, spills and fills of the masks in opmask registers. A mask is a vector of `b1` lanes,
+    // one bit per lane of a 512-bit vector. KMOVD and KMOVQ share their opcodes with KMOVB and
+    // KMOVW.
+    for (lanes, load_opcodes, store_opcodes, w, isap) in &[
+        (
+            8,
+            &KMOVB_LOAD[..],
+            &KMOVB_STORE[..],
+            false,
+            use_avx512dq_simd,
+        ),
+        (
+            16,
+            &KMOVW_LOAD[..],
+            &KMOVW_STORE[..],
+            false,
+            use_avx512f_simd,
+        ),
+        (
+            32,
+            &KMOVB_LOAD[..],
+            &KMOVB_STORE[..],
+            true,
+            use_avx512bw_simd,
+        ),
+        (
+            64,
+            &KMOVW_LOAD[..],
+            &KMOVW_STORE[..],
+            true,
+            use_avx512bw_simd,
+        ),
+    ] {
+        for (inst, template, opcodes) in &[
+            (spill, &rec_kspillSib32, store_opcodes),
+            (regspill, &rec_kregspill32, store_opcodes),
+            (fill, &rec_kfillSib32, load_opcodes),
+            (regfill, &rec_kregfill32, load_opcodes),
+            (regmove, &rec_kmov, load_opcodes),
+            (copy, &rec_kurm, load_opcodes),
+        ] {
+            let template = template.vex().opcodes(opcodes);
+            let template = if *w { template.w() } else { template };
+            e.enc64_isap(inst.bind(vector(B1, *lanes)), template, *isap);
+        }
+        e.enc64_rec(fill_nop.bind(vector(B1, *lanes)), rec_kfillnull, 0);
+        e.enc64_rec(copy_nop.bind(vector(B1, *lanes)), rec_stacknull, 0);
+    }
+
+    // Floating point arithmetic.
+    for (inst, f32_opcodes, f64_opcodes) in &[
+        (fadd, &ADDPS[..], &ADDPD[..]),
+        (fsub, &SUBPS[..], &SUBPD[..]),
+        (fmul, &MULPS[..], &MULPD[..]),
+        (fdiv, &DIVPS[..], &DIVPD[..]),
+    ] {
+        let f32_inst = inst.bind(vector(F32, avx512_vector_size));
+        let f32_template = evex(rec_vfa).opcodes(f32_opcodes);
+        e.enc64_isap(f32_inst, f32_template, use_avx512f_simd);
+        let f64_inst = inst.bind(vector(F64, avx512_vector_size));
+        let f64_template = evex(rec_vfa).opcodes(f64_opcodes).w();
+        e.enc64_isap(f64_inst, f64_template, use_avx512f_simd);
+    }
+
+    // Two-table byte shuffles of 128-bit vectors, used to legalize `shuffle`.
+    for ty in ValueType::all_lane_types().filter(|t| t.lane_bits() == 8) {
+        let instruction = x86_vpermt2b.bind(vector(ty, sse_vector_size));
+        let template = rec_vfa_nds.evex().opcodes(&VPERMT2B);
+        e.enc64_isap(instruction, template, use_avx512vbmi_simd);
+    }
+
     // SIMD register movement: store, load, spill, fill, regmove. All of these use encodings of
     // MOVUPS and MOVAPS from SSE (TODO ideally all of these would either use MOVAPS when we have
     // alignment or type-specific encodings, see https://github.com/CraneStation/cranelift/issues/1039).
@@ -1995,6 +2506,19 @@ pub(crate) fn define<'defs>(
         e.enc_32_64(bxor, rec_fa.opcodes(&PXOR));
     }
 
+    // SIMD floating point arithmetic
+    for (inst, f32_opcodes, f64_opcodes) in &[
+        (fadd, &ADDPS[..], &ADDPD[..]),
+        (fsub, &SUBPS[..], &SUBPD[..]),
+        (fmul, &MULPS[..], &MULPD[..]),
+        (fdiv, &DIVPS[..], &DIVPD[..]),
+    ] {
+        let f32_inst = inst.bind(vector(F32, sse_vector_size));
+        e.enc_32_64(f32_inst, rec_fa.opcodes(f32_opcodes));
+        let f64_inst = inst.bind(vector(F64, sse_vector_size));
+        e.enc_32_64(f64_inst, rec_fa.opcodes(f64_opcodes));
+    }
+
     // SIMD bitcast from I32/I64 to the low bits of a vector (e.g. I64x2); this register movement
     // allows SIMD shifts to be legalized more easily. TODO ideally this would be typed as an
     // I128x1 but restrictions on the type builder prevent this; the general idea here is that
@@ -446,5 +446,88 @@ pub(crate) fn define(
         .operands_out(vec![a]),
     );
 
+    let Cond = &operand("Cond", &immediates.intcc);
+    let x = &operand("x", IxN);
+    let y = &operand("y", IxN);
+    let m = &operand_doc(
+        "m",
+        &IxN.as_mask(),
+        "A bit mask held in a k register, with one bit per lane",
+    );
+
+    ig.push(
+        Inst::new(
+            "x86_vpcmp",
+            r#"
+        Compare Packed Integers into Mask -- Compare the lanes of ``x`` and ``y`` using the
+        condition ``Cond``, and set the corresponding bits of the mask ``m`` where it holds, as
+        the AVX-512 VPCMP* and VPCMPU* instructions do.
+
+        The mask is a vector of ``b1`` lanes held in an opmask register. It can only be used by
+        ``x86_vpmovm2``, or moved, spilled and filled.
+        "#,
+        )
+        .operands_in(vec![Cond, x, y])
+        .operands_out(vec![m]),
+    );
+
+    let BxN = &TypeVar::new(
+        "BxN",
+        "A SIMD vector type containing booleans",
+        TypeSetBuilder::new()
+            .bools(8..64)
+            .simd_lanes(Interval::All)
+            .includes_scalars(false)
+            .build(),
+    );
+    let m = &operand_doc(
+        "m",
+        &BxN.as_mask(),
+        "A bit mask held in a k register, with one bit per lane",
+    );
+    let a = &operand("a", BxN);
+
+    ig.push(
+        Inst::new(
+            "x86_vpmovm2",
+            r#"
+        Convert a Mask Register to a Vector Register -- Set each lane of ``a`` to all ones when the
+        corresponding bit of the mask ``m`` is set, and to all zeros otherwise, as the AVX-512
+        VPMOVM2* instructions do.
+        "#,
+        )
+        .operands_in(vec![m])
+        .operands_out(vec![a]),
+    );
+
+    let I8xN = &TypeVar::new(
+        "I8xN",
+        "A SIMD vector type containing bytes",
+        TypeSetBuilder::new()
+            .ints(8..8)
+            .bools(8..8)
+            .simd_lanes(16..64)
+            .includes_scalars(false)
+            .build(),
+    );
+    let x = &operand_doc("x", I8xN, "The first table of bytes");
+    let y = &operand_doc("y", I8xN, "The second table of bytes");
+    let mask = &operand_doc("mask", I8xN, "The indexes of the bytes to select");
+    let a = &operand("a", I8xN);
+
+    ig.push(
+        Inst::new(
+            "x86_vpermt2b",
+            r#"
+        Full Permute of Bytes From Two Tables -- Select each byte of ``a`` from the concatenation
+        of ``x`` and ``y``, using the corresponding byte of ``mask`` as an index, as the AVX-512
+        VPERMT2B instruction does. Only the low bits of the index that are needed to address the
+        two tables are used.
+        "#,
+        )
+        .operands_in(vec![x, y, mask])
+        .operands_out(vec![a]),
+    );
+
     ig.build()
 }
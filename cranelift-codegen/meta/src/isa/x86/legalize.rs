@@ -3,7 +3,7 @@ use crate::cdsl::instructions::{vector, Bindable, InstructionGroup};
 use crate::cdsl::types::{LaneType, ValueType};
 use crate::cdsl::xform::TransformGroupBuilder;
 use crate::shared::types::Float::F64;
-use crate::shared::types::Int::{I16, I32, I64};
+use crate::shared::types::Int::{I16, I32, I64, I8};
 use crate::shared::Definitions as SharedDefinitions;
 
 pub(crate) fn define(shared: &mut SharedDefinitions, x86_instructions: &InstructionGroup) {
@@ -40,6 +40,7 @@ pub(crate) fn define(shared: &mut SharedDefinitions, x86_instructions: &Instruct
     let fmin = insts.by_name("fmin");
     let iadd = insts.by_name("iadd");
     let iconst = insts.by_name("iconst");
+    let icmp = insts.by_name("icmp");
     let imul = insts.by_name("imul");
     let ineg = insts.by_name("ineg");
     let insertlane = insts.by_name("insertlane");
@@ -71,6 +72,8 @@ pub(crate) fn define(shared: &mut SharedDefinitions, x86_instructions: &Instruct
     let x86_psrl = x86_instructions.by_name("x86_psrl");
     let x86_umulx = x86_instructions.by_name("x86_umulx");
     let x86_smulx = x86_instructions.by_name("x86_smulx");
+    let x86_vpcmp = x86_instructions.by_name("x86_vpcmp");
+    let x86_vpmovm2 = x86_instructions.by_name("x86_vpmovm2");
 
     let imm = &shared.imm;
 
@@ -328,8 +331,12 @@ pub(crate) fn define(shared: &mut SharedDefinitions, x86_instructions: &Instruct
     let c = var("c");
     let d = var("d");
 
-    // SIMD vector size: eventually multiple vector sizes may be supported but for now only SSE-sized vectors are available
+    // SIMD vector sizes: the SSE-sized vectors support all the operations below, while the
+    // AVX-sized and AVX-512-sized vectors only support the lane-wise operations, not splat,
+    // insertlane, extractlane or shuffle.
     let sse_vector_size: u64 = 128;
+    let avx_vector_size: u64 = 256;
+    let avx512_vector_size: u64 = 512;
     let allowed_simd_type = |t: &LaneType| t.lane_bits() >= 8 && t.lane_bits() < 128;
 
     // SIMD splat: 8-bits
@@ -393,56 +400,70 @@ pub(crate) fn define(shared: &mut SharedDefinitions, x86_instructions: &Instruct
         );
     }
 
-    // SIMD bnot
-    let ones = constant(vec![0xff; 16]);
-    for ty in ValueType::all_lane_types().filter(allowed_simd_type) {
-        let bnot = bnot.bind(vector(ty, sse_vector_size));
-        narrow.legalize(
-            def!(y = bnot(x)),
-            vec![def!(a = vconst(ones)), def!(y = bxor(a, x))],
-        );
-    }
-
-    // SIMD shift left (logical)
-    for ty in &[I16, I32, I64] {
-        let ishl = ishl.bind(vector(*ty, sse_vector_size));
-        let bitcast = bitcast.bind(vector(I64, sse_vector_size));
-        narrow.legalize(
-            def!(a = ishl(x, y)),
-            vec![def!(b = bitcast(y)), def!(a = x86_psll(x, b))],
-        );
-    }
-
-    // SIMD shift right (logical)
-    for ty in &[I16, I32, I64] {
-        let ushr = ushr.bind(vector(*ty, sse_vector_size));
-        let bitcast = bitcast.bind(vector(I64, sse_vector_size));
-        narrow.legalize(
-            def!(a = ushr(x, y)),
-            vec![def!(b = bitcast(y)), def!(a = x86_psrl(x, b))],
-        );
-    }
-
-    // SIMD shift left (arithmetic)
-    for ty in &[I16, I32, I64] {
-        let sshr = sshr.bind(vector(*ty, sse_vector_size));
-        let bitcast = bitcast.bind(vector(I64, sse_vector_size));
-        narrow.legalize(
-            def!(a = sshr(x, y)),
-            vec![def!(b = bitcast(y)), def!(a = x86_psra(x, b))],
-        );
+    for &vector_size in &[sse_vector_size, avx_vector_size, avx512_vector_size] {
+        // SIMD bnot
+        let ones = constant(vec![0xff; vector_size as usize / 8]);
+        for ty in ValueType::all_lane_types().filter(allowed_simd_type) {
+            let bnot = bnot.bind(vector(ty, vector_size));
+            narrow.legalize(
+                def!(y = bnot(x)),
+                vec![def!(a = vconst(ones)), def!(y = bxor(a, x))],
+            );
+        }
+
+        // SIMD shift left (logical); the shift amount is always moved to an XMM register.
+        for ty in &[I16, I32, I64] {
+            let ishl = ishl.bind(vector(*ty, vector_size));
+            let bitcast = bitcast.bind(vector(I64, sse_vector_size));
+            narrow.legalize(
+                def!(a = ishl(x, y)),
+                vec![def!(b = bitcast(y)), def!(a = x86_psll(x, b))],
+            );
+        }
+
+        // SIMD shift right (logical)
+        for ty in &[I16, I32, I64] {
+            let ushr = ushr.bind(vector(*ty, vector_size));
+            let bitcast = bitcast.bind(vector(I64, sse_vector_size));
+            narrow.legalize(
+                def!(a = ushr(x, y)),
+                vec![def!(b = bitcast(y)), def!(a = x86_psrl(x, b))],
+            );
+        }
+
+        // SIMD shift left (arithmetic)
+        for ty in &[I16, I32, I64] {
+            let sshr = sshr.bind(vector(*ty, vector_size));
+            let bitcast = bitcast.bind(vector(I64, sse_vector_size));
+            narrow.legalize(
+                def!(a = sshr(x, y)),
+                vec![def!(b = bitcast(y)), def!(a = x86_psra(x, b))],
+            );
+        }
+
+        // SIMD select
+        for ty in ValueType::all_lane_types().filter(allowed_simd_type) {
+            let bitselect = bitselect.bind(vector(ty, vector_size)); // must bind both x/y and c
+            narrow.legalize(
+                def!(d = bitselect(c, x, y)),
+                vec![
+                    def!(a = band(x, c)),
+                    def!(b = band_not(y, c)),
+                    def!(d = bor(a, b)),
+                ],
+            );
+        }
     }
 
-    // SIMD select
-    for ty in ValueType::all_lane_types().filter(allowed_simd_type) {
-        let bitselect = bitselect.bind(vector(ty, sse_vector_size)); // must bind both x/y and c
+    // SIMD integer comparisons of 512-bit vectors: AVX-512 compares into a mask register, which is
+    // then expanded back into a vector of booleans.
+    let cc = var("cc");
+    let m = var("m");
+    for ty in &[I8, I16, I32, I64] {
+        let icmp = icmp.bind(vector(*ty, avx512_vector_size));
         narrow.legalize(
-            def!(d = bitselect(c, x, y)),
-            vec![
-                def!(a = band(x, c)),
-                def!(b = band_not(y, c)),
-                def!(d = bor(a, b)),
-            ],
+            def!(a = icmp(cc, x, y)),
+            vec![def!(m = x86_vpcmp(cc, x, y)), def!(a = x86_vpmovm2(m))],
         );
     }
 
@@ -1,4 +1,7 @@
 //! Static, named definitions of instruction opcodes.
+//!
+//! The SSE opcodes also describe the AVX versions of the instructions: their VEX prefix encodes
+//! the same mandatory prefix and opcode map as the legacy prefix and escape bytes.
 
 /// Empty opcode for use as a default.
 pub static EMPTY: [u8; 0] = [];
@@ -15,6 +18,14 @@ pub static ADD_IMM: [u8; 1] = [0x81];
 /// Add sign-extended imm8 to r/m{16,32,64}.
 pub static ADD_IMM8_SIGN_EXTEND: [u8; 1] = [0x83];
 
+/// Add packed double-precision floating-point values from xmm2/mem to xmm1 and store the
+/// result in xmm1.
+pub static ADDPD: [u8; 3] = [0x66, 0x0f, 0x58];
+
+/// Add packed single-precision floating-point values from xmm2/mem to xmm1 and store the
+/// result in xmm1.
+pub static ADDPS: [u8; 2] = [0x0f, 0x58];
+
 /// Add the low double-precision floating-point value from xmm2/mem to xmm1
 /// and store the result in xmm1.
 pub static ADDSD: [u8; 3] = [0xf2, 0x0f, 0x58];
@@ -85,6 +96,14 @@ pub static CVTTSS2SI: [u8; 3] = [0xf3, 0x0f, 0x2c];
 /// Unsigned divide for {16,32,64}-bit.
 pub static DIV: [u8; 1] = [0xf7];
 
+/// Divide packed double-precision floating-point values in xmm1 by packed double-precision
+/// floating-point values in xmm2/mem.
+pub static DIVPD: [u8; 3] = [0x66, 0x0f, 0x5e];
+
+/// Divide packed single-precision floating-point values in xmm1 by packed single-precision
+/// floating-point values in xmm2/mem.
+pub static DIVPS: [u8; 2] = [0x0f, 0x5e];
+
 /// Divide low double-precision floating-point value in xmm1 by low double-precision
 /// floating-point value in xmm2/m64.
 pub static DIVSD: [u8; 3] = [0xf2, 0x0f, 0x5e];
@@ -128,6 +147,18 @@ pub static JUMP_SHORT_IF_NOT_EQUAL: [u8; 1] = [0x75];
 /// Jump short (rel8) if overflow (OF=1).
 pub static JUMP_SHORT_IF_OVERFLOW: [u8; 1] = [0x70];
 
+/// Move 8 bits (or 32 bits, with VEX.W1) from k2/m8 to k1 (AVX512DQ, or AVX512BW for KMOVD).
+pub static KMOVB_LOAD: [u8; 3] = [0x66, 0x0f, 0x90];
+
+/// Move 8 bits (or 32 bits, with VEX.W1) from k1 to m8 (AVX512DQ, or AVX512BW for KMOVD).
+pub static KMOVB_STORE: [u8; 3] = [0x66, 0x0f, 0x91];
+
+/// Move 16 bits (or 64 bits, with VEX.W1) from k2/m16 to k1 (AVX512F, or AVX512BW for KMOVQ).
+pub static KMOVW_LOAD: [u8; 2] = [0x0f, 0x90];
+
+/// Move 16 bits (or 64 bits, with VEX.W1) from k1 to m16 (AVX512F, or AVX512BW for KMOVQ).
+pub static KMOVW_STORE: [u8; 2] = [0x0f, 0x91];
+
 /// Store effective address for m in register r{16,32,64}.
 pub static LEA: [u8; 1] = [0x8d];
 
@@ -216,6 +247,14 @@ pub static MOVZX_WORD: [u8; 2] = [0x0f, 0xb7];
 /// Unsigned multiply for {16,32,64}-bit.
 pub static MUL: [u8; 1] = [0xf7];
 
+/// Multiply packed double-precision floating-point values in xmm2/mem by xmm1 and store the
+/// result in xmm1.
+pub static MULPD: [u8; 3] = [0x66, 0x0f, 0x59];
+
+/// Multiply packed single-precision floating-point values in xmm2/mem by xmm1 and store the
+/// result in xmm1.
+pub static MULPS: [u8; 2] = [0x0f, 0x59];
+
 /// Multiply the low double-precision floating-point value in xmm2/m64 by the
 /// low double-precision floating-point value in xmm1.
 pub static MULSD: [u8; 3] = [0xf2, 0x0f, 0x59];
@@ -411,6 +450,14 @@ pub static SQRTSS: [u8; 3] = [0xf3, 0x0f, 0x51];
 /// Subtract r{16,32,64} from r/m of same size.
 pub static SUB: [u8; 1] = [0x29];
 
+/// Subtract packed double-precision floating-point values in xmm2/mem from xmm1 and store the
+/// result in xmm1.
+pub static SUBPD: [u8; 3] = [0x66, 0x0f, 0x5c];
+
+/// Subtract packed single-precision floating-point values in xmm2/mem from xmm1 and store the
+/// result in xmm1.
+pub static SUBPS: [u8; 2] = [0x0f, 0x5c];
+
 /// Subtract the low double-precision floating-point value in xmm2/m64 from xmm1
 /// and store the result in xmm1.
 pub static SUBSD: [u8; 3] = [0xf2, 0x0f, 0x5c];
@@ -439,6 +486,31 @@ pub static UCOMISS: [u8; 2] = [0x0f, 0x2e];
 /// Raise invalid opcode instruction.
 pub static UNDEFINED2: [u8; 2] = [0x0f, 0x0b];
 
+/// Compare packed signed byte integers (or word integers, with EVEX.W1) in zmm2 and zmm3/m512
+/// using the comparison predicate imm8, and set vector mask k1 to reflect the result (AVX512BW).
+/// The unsigned comparisons VPCMPUB and VPCMPUW use the preceding opcode.
+pub static VPCMPB: [u8; 4] = [0x66, 0x0f, 0x3a, 0x3f];
+
+/// Compare packed signed doubleword integers (or quadword integers, with EVEX.W1) in zmm2 and
+/// zmm3/m512 using the comparison predicate imm8, and set vector mask k1 to reflect the result
+/// (AVX512F). The unsigned comparisons VPCMPUD and VPCMPUQ use the preceding opcode.
+pub static VPCMPD: [u8; 4] = [0x66, 0x0f, 0x3a, 0x1f];
+
+/// Permute bytes in xmm3/m128 and xmm1 using byte indexes in xmm2 and store the byte results in
+/// xmm1 (AVX512VBMI and AVX512VL).
+pub static VPERMT2B: [u8; 4] = [0x66, 0x0f, 0x38, 0x7d];
+
+/// Set each byte (or word, with EVEX.W1) in zmm1 to all 1's or all 0's based on the value of
+/// the corresponding bit in k1 (AVX512BW).
+pub static VPMOVM2B: [u8; 4] = [0xf3, 0x0f, 0x38, 0x28];
+
+/// Set each doubleword (or quadword, with EVEX.W1) in zmm1 to all 1's or all 0's based on the
+/// value of the corresponding bit in k1 (AVX512DQ).
+pub static VPMOVM2D: [u8; 4] = [0xf3, 0x0f, 0x38, 0x38];
+
+/// Exchange r/m{16,32,64} with register of the same size.
+pub static XCHG: [u8; 1] = [0x87];
+
 /// imm{16,32} XOR r/m{16,32,64}, possibly sign-extended.
 pub static XOR_IMM: [u8; 1] = [0x81];
 
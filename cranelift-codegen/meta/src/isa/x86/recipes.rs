@@ -73,8 +73,11 @@ impl<'builder> RecipeGroup<'builder> {
 // opcode format is indicated by the recipe name prefix.
 //
 // The match case below does not include the REX prefix which goes after the mandatory prefix.
-// VEX/XOP and EVEX prefixes are not yet supported. Encodings using any of these prefixes are
-// represented by separate recipes.
+// Encodings using a VEX prefix are represented by separate recipes, whose name is prefixed with
+// Vex128 or Vex256 depending on the VEX.L bit. The VEX prefix encodes the same pp and mm bits as
+// the legacy prefixes it replaces, and is always emitted in its three-byte form. Likewise, EVEX
+// encodings use recipes prefixed with Evex128, Evex256 or Evex512 depending on the EVEX.L'L bits.
+// XOP prefixes are not supported.
 //
 // The encoding bits are:
 //
@@ -90,10 +93,10 @@ impl<'builder> RecipeGroup<'builder> {
 //        10 0F 38 <op>  (Op3/Mp3)
 //        11 0F 3A <op>  (Op3/Mp3)
 // 12-14  rrr, opcode bits for the ModR/M byte for certain opcodes.
-// 15:    REX.W bit (or VEX.W/E)
+// 15:    REX.W bit (or VEX.W/E, or EVEX.W)
 //
 // There is some redundancy between bits 8-11 and the recipe names, but we have enough bits, and
-// the pp+mm format is ready for supporting VEX prefixes.
+// the pp+mm format is the one used by VEX prefixes.
 //
 // TODO Cranelift doesn't actually require recipe to have different encoding sizes anymore, so this
 // could be simplified.
@@ -180,9 +183,21 @@ pub(crate) struct Template<'builder> {
     /// Other recipe to use when REX-prefixed.
     when_prefixed: Option<Rc<Template<'builder>>>,
 
+    /// Does this recipe require a VEX or EVEX prefix?
+    requires_vex: bool,
+
+    /// Does this recipe require an EVEX prefix?
+    requires_evex: bool,
+
     // Specialized parameters.
     /// Should we include the REX prefix?
     rex: bool,
+    /// Should we use a VEX prefix instead of the legacy and REX prefixes?
+    vex: bool,
+    /// Should we use an EVEX prefix instead of the legacy and REX prefixes?
+    evex: bool,
+    /// Value of the VEX.L bit or of the EVEX.L'L bits (between 0 and 0b10).
+    ll_bits: u16,
     /// Value of the W bit (0 or 1).
     w_bit: u16,
     /// Value of the RRR bits (between 0 and 0b111).
@@ -203,7 +218,12 @@ impl<'builder> Template<'builder> {
             recipe,
             requires_prefix: false,
             when_prefixed: None,
+            requires_vex: false,
+            requires_evex: false,
             rex: false,
+            vex: false,
+            evex: false,
+            ll_bits: 0,
             w_bit: 0,
             rrr_bits: 0,
             op_bytes: &opcodes::EMPTY,
@@ -226,6 +246,18 @@ impl<'builder> Template<'builder> {
             ..self
         }
     }
+    fn requires_vex(self, value: bool) -> Self {
+        Self {
+            requires_vex: value,
+            ..self
+        }
+    }
+    fn requires_evex(self, value: bool) -> Self {
+        Self {
+            requires_evex: value,
+            ..self
+        }
+    }
 
     // Copy setters.
     pub fn opcodes(&self, op_bytes: &'static [u8]) -> Self {
@@ -252,6 +284,10 @@ impl<'builder> Template<'builder> {
         copy
     }
     pub fn rex(&self) -> Self {
+        assert!(
+            !self.vex && !self.evex,
+            "A VEX or EVEX prefix replaces the REX prefix."
+        );
         if let Some(prefixed) = &self.when_prefixed {
             let mut ret = prefixed.rex();
             // Forward specialized parameters.
@@ -264,17 +300,79 @@ impl<'builder> Template<'builder> {
         copy.rex = true;
         copy
     }
+    /// Use a VEX prefix, which can address all the registers in 64-bit mode.
+    pub fn vex(&self) -> Self {
+        assert!(!self.rex, "A VEX prefix replaces the REX prefix.");
+        assert!(!self.requires_evex, "Tail recipe requires EVEX prefix.");
+        let mut copy = self.clone();
+        copy.vex = true;
+        copy
+    }
+    /// Use an EVEX prefix. Only the first 16 vector registers are used, and no writemask.
+    pub fn evex(&self) -> Self {
+        assert!(!self.rex, "An EVEX prefix replaces the REX prefix.");
+        let mut copy = self.clone();
+        copy.evex = true;
+        copy
+    }
+    /// Set the VEX.L bit or the EVEX.L'L bits, for operations on 256-bit vectors.
+    pub fn l(&self) -> Self {
+        assert!(
+            self.vex || self.evex,
+            "Only VEX and EVEX prefixes have an L bit."
+        );
+        let mut copy = self.clone();
+        copy.ll_bits = 0b01;
+        copy
+    }
+    /// Set the EVEX.L'L bits, for operations on 512-bit vectors.
+    pub fn l512(&self) -> Self {
+        assert!(
+            self.evex,
+            "Only EVEX prefixes can encode 512-bit operations."
+        );
+        let mut copy = self.clone();
+        copy.ll_bits = 0b10;
+        copy
+    }
 
     pub fn build(mut self) -> (EncodingRecipe, u16) {
+        assert!(
+            self.vex || self.evex || !self.requires_vex,
+            "Tail recipe requires VEX or EVEX prefix."
+        );
+        assert!(
+            self.evex || !self.requires_evex,
+            "Tail recipe requires EVEX prefix."
+        );
         let (name, bits) = decode_opcodes(&self.op_bytes, self.rrr_bits, self.w_bit);
 
-        let (name, rex_prefix_size) = if self.rex {
-            ("Rex".to_string() + name, 1)
+        let (name, put_op, size_addendum) = if self.vex || self.evex {
+            assert_ne!(
+                bits & 0x0c00,
+                0,
+                "VEX and EVEX prefixes need an 0F, 0F38 or 0F3A opcode map"
+            );
+            let prefix = format!(
+                "{}{}",
+                if self.evex { "Evex" } else { "Vex" },
+                128 << self.ll_bits
+            );
+            // The three-byte VEX prefix and the four-byte EVEX prefix replace the legacy prefixes
+            // and escape bytes.
+            let prefix_size = if self.evex { 4 } else { 3 };
+            (prefix.clone() + name, prefix, prefix_size + 1)
+        } else if self.rex {
+            let name = "Rex".to_string() + name;
+            (name.clone(), name, self.op_bytes.len() as u64 + 1)
         } else {
-            (name.into(), 0)
+            (
+                name.to_string(),
+                name.to_string(),
+                self.op_bytes.len() as u64,
+            )
         };
 
-        let size_addendum = self.op_bytes.len() as u64 + rex_prefix_size;
         self.recipe.base_size += size_addendum;
 
         // Branch ranges are relative to the end of the instruction.
@@ -283,10 +381,11 @@ impl<'builder> Template<'builder> {
             .as_mut()
             .map(|range| range.inst_size += size_addendum);
 
-        self.recipe.emit = replace_put_op(self.recipe.emit, &name);
+        self.recipe.emit = replace_put_op(self.recipe.emit, &put_op);
         self.recipe.name = name + &self.recipe.name;
 
-        if !self.rex {
+        // VEX and EVEX prefixes can address all the registers, like REX prefixes.
+        if !self.rex && !self.vex && !self.evex {
             let operands_in = self.recipe.operands_in.unwrap_or(Vec::new());
             self.recipe.operands_in = Some(replace_nonrex_constraints(self.regs, operands_in));
             let operands_out = self.recipe.operands_out.unwrap_or(Vec::new());
@@ -298,8 +397,8 @@ impl<'builder> Template<'builder> {
 }
 
 /// Returns a predicate checking that the "cond" field of the instruction contains one of the
-/// directly supported floating point condition codes.
-fn supported_floatccs_predicate(
+/// directly supported condition codes.
+fn supported_ccs_predicate(
     supported_cc: &[Literal],
     format: &InstructionFormat,
 ) -> InstructionPredicate {
@@ -340,12 +439,23 @@ pub(crate) fn define<'shared>(
         .map(|name| Literal::enumerator_for(floatcc, name))
         .collect();
 
+    // The set of integer condition codes that vector comparisons support, which excludes the
+    // overflow conditions.
+    let intcc = &shared_defs.imm.intcc;
+    let vector_intccs: Vec<Literal> = [
+        "eq", "ne", "slt", "sge", "sgt", "sle", "ult", "uge", "ugt", "ule",
+    ]
+    .iter()
+    .map(|name| Literal::enumerator_for(intcc, name))
+    .collect();
+
     let formats = &shared_defs.format_registry;
 
     // Register classes shorthands.
     let abcd = regs.class_by_name("ABCD");
     let gpr = regs.class_by_name("GPR");
     let fpr = regs.class_by_name("FPR");
+    let mask = regs.class_by_name("MASK");
     let flag = regs.class_by_name("FLAG");
 
     // Operand constraints shorthands.
@@ -358,6 +468,7 @@ pub(crate) fn define<'shared>(
     // Stack operand with a 32-bit signed displacement from either RBP or RSP.
     let stack_gpr32 = Stack::new(gpr);
     let stack_fpr32 = Stack::new(fpr);
+    let stack_mask32 = Stack::new(mask);
 
     // Format shorthands, prefixed with f_.
     let f_binary = formats.by_name("Binary");
@@ -390,6 +501,7 @@ pub(crate) fn define<'shared>(
     let f_nullary = formats.by_name("NullAry");
     let f_reg_fill = formats.by_name("RegFill");
     let f_reg_move = formats.by_name("RegMove");
+    let f_reg_swap = formats.by_name("RegSwap");
     let f_reg_spill = formats.by_name("RegSpill");
     let f_stack_load = formats.by_name("StackLoad");
     let f_store = formats.by_name("Store");
@@ -465,6 +577,13 @@ pub(crate) fn define<'shared>(
             .clobbers_flags(false)
             .emit(""),
     );
+    recipes.add_recipe(
+        EncodingRecipeBuilder::new("kfillnull", f_unary, 0)
+            .operands_in(vec![stack_gpr32])
+            .operands_out(vec![mask])
+            .clobbers_flags(false)
+            .emit(""),
+    );
 
     recipes
         .add_recipe(EncodingRecipeBuilder::new("debugtrap", f_nullary, 1).emit("sink.put1(0xcc);"));
@@ -499,7 +618,7 @@ pub(crate) fn define<'shared>(
         EncodingRecipeBuilder::new("trapff", f_float_cond_trap, 4)
             .operands_in(vec![reg_rflags])
             .clobbers_flags(false)
-            .inst_predicate(supported_floatccs_predicate(
+            .inst_predicate(supported_ccs_predicate(
                 &supported_floatccs,
                 formats.get(f_float_cond_trap),
             ))
@@ -568,6 +687,62 @@ pub(crate) fn define<'shared>(
             ),
     );
 
+    // VEX XX /r with FPR ins and outs. Non-destructive three-operand form, with the first input
+    // in the VEX.vvvv field.
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("vfa", f_binary, 1)
+                .operands_in(vec![fpr, fpr])
+                .operands_out(vec![fpr])
+                .emit(
+                    r#"
+                    {{PUT_OP}}_nds(bits, rex2(in_reg1, out_reg0), in_reg0, sink);
+                    modrm_rr(in_reg1, out_reg0, sink);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_vex(true),
+    );
+
+    // VEX XX /r with FPR ins and outs. Non-destructive form with input operands swapped.
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("vfax", f_binary, 1)
+                .operands_in(vec![fpr, fpr])
+                .operands_out(vec![fpr])
+                .emit(
+                    r#"
+                    {{PUT_OP}}_nds(bits, rex2(in_reg0, out_reg0), in_reg1, sink);
+                    modrm_rr(in_reg0, out_reg0, sink);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_vex(true),
+    );
+
+    // EVEX XX /r with three FPR ins, where the output is tied to the first input and the third
+    // input is in the EVEX.vvvv field.
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("vfa_nds", f_ternary, 1)
+                .operands_in(vec![fpr, fpr, fpr])
+                .operands_out(vec![0])
+                .emit(
+                    r#"
+                    {{PUT_OP}}_nds(bits, rex2(in_reg1, in_reg0), in_reg2, sink);
+                    modrm_rr(in_reg1, in_reg0, sink);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_evex(true),
+    );
+
     // XX /r with FPR ins and outs. A form with a byte immediate.
     {
         let format = formats.get(f_insert_lane);
@@ -602,6 +777,20 @@ pub(crate) fn define<'shared>(
             ),
     );
 
+    // XX /n ib with an immediate of 1, for a unary operation like negating a boolean with XOR.
+    recipes.add_template_recipe(
+        EncodingRecipeBuilder::new("ur_ib1", f_unary, 2)
+            .operands_in(vec![gpr])
+            .operands_out(vec![0])
+            .emit(
+                r#"
+                    {{PUT_OP}}(bits, rex1(in_reg0), sink);
+                    modrm_r_bits(in_reg0, bits, sink);
+                    sink.put1(1);
+                "#,
+            ),
+    );
+
     // XX /r, but for a unary operator with separate input/output register, like
     // copies. MR form, preserving flags.
     recipes.add_template_recipe(
@@ -706,6 +895,44 @@ pub(crate) fn define<'shared>(
             ),
     );
 
+    // XX /r, with an opmask register input and an FPR output.
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("furm_mask", f_unary, 1)
+                .operands_in(vec![mask])
+                .operands_out(vec![fpr])
+                .clobbers_flags(false)
+                .emit(
+                    r#"
+                    {{PUT_OP}}(bits, rex2(in_reg0, out_reg0), sink);
+                    modrm_rr(in_reg0, out_reg0, sink);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_evex(true),
+    );
+
+    // XX /r, RM form, opmask register -> opmask register.
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("kurm", f_unary, 1)
+                .operands_in(vec![mask])
+                .operands_out(vec![mask])
+                .clobbers_flags(false)
+                .emit(
+                    r#"
+                    {{PUT_OP}}(bits, rex2(in_reg0, out_reg0), sink);
+                    modrm_rr(in_reg0, out_reg0, sink);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_vex(true),
+    );
+
     // Same as furm, but with the source register specified directly.
     recipes.add_template_recipe(
         EncodingRecipeBuilder::new("furm_reg_to_ssa", f_copy_to_ssa, 1)
@@ -795,6 +1022,37 @@ pub(crate) fn define<'shared>(
             ),
     );
 
+    // XX /r, for regmove instructions (opmask register version, RM encoded).
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("kmov", f_reg_move, 1)
+                .operands_in(vec![mask])
+                .clobbers_flags(false)
+                .emit(
+                    r#"
+                    {{PUT_OP}}(bits, rex2(src, dst), sink);
+                    modrm_rr(src, dst, sink);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_vex(true),
+    );
+
+    // XX /r, for regswap instructions.
+    recipes.add_template_recipe(
+        EncodingRecipeBuilder::new("rswap", f_reg_swap, 1)
+            .operands_in(vec![gpr, gpr])
+            .clobbers_flags(false)
+            .emit(
+                r#"
+                    {{PUT_OP}}(bits, rex2(dst, src), sink);
+                    modrm_rr(dst, src, sink);
+                "#,
+            ),
+    );
+
     // XX /n with one arg in %rcx, for shifts.
     recipes.add_template_recipe(
         EncodingRecipeBuilder::new("rc", f_binary, 1)
@@ -954,6 +1212,7 @@ pub(crate) fn define<'shared>(
         recipes.add_template_recipe(
             EncodingRecipeBuilder::new("u_id", f_unary_imm, 5)
                 .operands_out(vec![gpr])
+                .clobbers_flags(false)
                 .inst_predicate(InstructionPredicate::new_is_signed_int(
                     format, "imm", 32, 0,
                 ))
@@ -972,6 +1231,7 @@ pub(crate) fn define<'shared>(
     recipes.add_template_recipe(
         EncodingRecipeBuilder::new("pu_id", f_unary_imm, 4)
             .operands_out(vec![gpr])
+            .clobbers_flags(false)
             .emit(
                 r#"
                     // The destination register is encoded in the low bits of the opcode.
@@ -987,6 +1247,7 @@ pub(crate) fn define<'shared>(
     recipes.add_template_recipe(
         EncodingRecipeBuilder::new("pu_id_bool", f_unary_bool, 4)
             .operands_out(vec![gpr])
+            .clobbers_flags(false)
             .emit(
                 r#"
                     // The destination register is encoded in the low bits of the opcode.
@@ -1002,6 +1263,7 @@ pub(crate) fn define<'shared>(
     recipes.add_template_recipe(
         EncodingRecipeBuilder::new("pu_id_ref", f_nullary, 4)
             .operands_out(vec![gpr])
+            .clobbers_flags(false)
             .emit(
                 r#"
                     // The destination register is encoded in the low bits of the opcode.
@@ -1016,6 +1278,7 @@ pub(crate) fn define<'shared>(
     recipes.add_template_recipe(
         EncodingRecipeBuilder::new("pu_iq", f_unary_imm, 8)
             .operands_out(vec![gpr])
+            .clobbers_flags(false)
             .emit(
                 r#"
                     {{PUT_OP}}(bits | (out_reg0 & 7), rex1(out_reg0), sink);
@@ -1043,6 +1306,7 @@ pub(crate) fn define<'shared>(
         recipes.add_template_recipe(
             EncodingRecipeBuilder::new("f32imm_z", f_unary_ieee32, 1)
                 .operands_out(vec![fpr])
+                .clobbers_flags(false)
                 .inst_predicate(InstructionPredicate::new_is_zero_32bit_float(format, "imm"))
                 .emit(
                     r#"
@@ -1059,6 +1323,7 @@ pub(crate) fn define<'shared>(
         recipes.add_template_recipe(
             EncodingRecipeBuilder::new("f64imm_z", f_unary_ieee64, 1)
                 .operands_out(vec![fpr])
+                .clobbers_flags(false)
                 .inst_predicate(InstructionPredicate::new_is_zero_64bit_float(format, "imm"))
                 .emit(
                     r#"
@@ -1850,6 +2115,29 @@ pub(crate) fn define<'shared>(
             ),
     );
 
+    // Like spillSib32, but targeting an opmask register rather than a GPR.
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("kspillSib32", f_unary, 6)
+                .operands_in(vec![mask])
+                .operands_out(vec![stack_mask32])
+                .clobbers_flags(false)
+                .emit(
+                    r#"
+                    sink.trap(TrapCode::StackOverflow, func.srclocs[inst]);
+                    let base = stk_base(out_stk0.base);
+                    {{PUT_OP}}(bits, rex2(base, in_reg0), sink);
+                    modrm_sib_disp32(in_reg0, sink);
+                    sib_noindex(base, sink);
+                    sink.put4(out_stk0.offset as u32);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_vex(true),
+    );
+
     // Regspill using RSP-relative addressing.
     recipes.add_template_recipe(
         EncodingRecipeBuilder::new("regspill32", f_reg_spill, 6)
@@ -1886,6 +2174,29 @@ pub(crate) fn define<'shared>(
             ),
     );
 
+    // Like regspill32, but targeting an opmask register rather than a GPR.
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("kregspill32", f_reg_spill, 6)
+                .operands_in(vec![mask])
+                .clobbers_flags(false)
+                .emit(
+                    r#"
+                    sink.trap(TrapCode::StackOverflow, func.srclocs[inst]);
+                    let dst = StackRef::sp(dst, &func.stack_slots);
+                    let base = stk_base(dst.base);
+                    {{PUT_OP}}(bits, rex2(base, src), sink);
+                    modrm_sib_disp32(src, sink);
+                    sib_noindex(base, sink);
+                    sink.put4(dst.offset as u32);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_vex(true),
+    );
+
     // Load recipes.
 
     {
@@ -2242,6 +2553,28 @@ pub(crate) fn define<'shared>(
             ),
     );
 
+    // Like fillSib32, but targeting an opmask register rather than a GPR.
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("kfillSib32", f_unary, 6)
+                .operands_in(vec![stack_mask32])
+                .operands_out(vec![mask])
+                .clobbers_flags(false)
+                .emit(
+                    r#"
+                    let base = stk_base(in_stk0.base);
+                    {{PUT_OP}}(bits, rex2(base, out_reg0), sink);
+                    modrm_sib_disp32(out_reg0, sink);
+                    sib_noindex(base, sink);
+                    sink.put4(in_stk0.offset as u32);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_vex(true),
+    );
+
     // Regfill with RSP-relative 32-bit displacement.
     recipes.add_template_recipe(
         EncodingRecipeBuilder::new("regfill32", f_reg_fill, 6)
@@ -2276,6 +2609,28 @@ pub(crate) fn define<'shared>(
             ),
     );
 
+    // Like regfill32, but targeting an opmask register rather than a GPR.
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("kregfill32", f_reg_fill, 6)
+                .operands_in(vec![stack_mask32])
+                .clobbers_flags(false)
+                .emit(
+                    r#"
+                    let src = StackRef::sp(src, &func.stack_slots);
+                    let base = stk_base(src.base);
+                    {{PUT_OP}}(bits, rex2(base, dst), sink);
+                    modrm_sib_disp32(dst, sink);
+                    sib_noindex(base, sink);
+                    sink.put4(src.offset as u32);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_vex(true),
+    );
+
     // Call/return.
 
     recipes.add_template_recipe(EncodingRecipeBuilder::new("call_id", f_call, 4).emit(
@@ -2375,7 +2730,7 @@ pub(crate) fn define<'shared>(
             .operands_in(vec![reg_rflags])
             .branch_range((1, 8))
             .clobbers_flags(false)
-            .inst_predicate(supported_floatccs_predicate(
+            .inst_predicate(supported_ccs_predicate(
                 &supported_floatccs,
                 formats.get(f_branch_float),
             ))
@@ -2392,7 +2747,7 @@ pub(crate) fn define<'shared>(
             .operands_in(vec![reg_rflags])
             .branch_range((4, 32))
             .clobbers_flags(false)
-            .inst_predicate(supported_floatccs_predicate(
+            .inst_predicate(supported_ccs_predicate(
                 &supported_floatccs,
                 formats.get(f_branch_float),
             ))
@@ -2943,6 +3298,49 @@ pub(crate) fn define<'shared>(
             ),
     );
 
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("vicscc_fpr", f_int_compare, 1)
+                .operands_in(vec![fpr, fpr])
+                .operands_out(vec![fpr])
+                .emit(
+                    r#"
+                    // Non-destructive comparison instruction.
+                    {{PUT_OP}}_nds(bits, rex2(in_reg1, out_reg0), in_reg0, sink);
+                    modrm_rr(in_reg1, out_reg0, sink);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_vex(true),
+    );
+
+    // EVEX comparison into an opmask register, with the condition code in an immediate.
+    recipes.add_template(
+        Template::new(
+            EncodingRecipeBuilder::new("vicscc_mask", f_int_compare, 2)
+                .operands_in(vec![fpr, fpr])
+                .operands_out(vec![mask])
+                .clobbers_flags(false)
+                .inst_predicate(supported_ccs_predicate(
+                    &vector_intccs,
+                    formats.get(f_int_compare),
+                ))
+                .emit(
+                    r#"
+                    let (bits, imm) = vpcmp_opcode(bits, cond);
+                    {{PUT_OP}}_nds(bits, rex2(in_reg1, out_reg0), in_reg0, sink);
+                    modrm_rr(in_reg1, out_reg0, sink);
+                    sink.put1(imm);
+                "#,
+                ),
+            formats,
+            regs,
+        )
+        .requires_evex(true),
+    );
+
     {
         let format = formats.get(f_int_compare_imm);
 
@@ -3012,7 +3410,7 @@ pub(crate) fn define<'shared>(
         EncodingRecipeBuilder::new("fcscc", f_float_compare, 1 + 3)
             .operands_in(vec![fpr, fpr])
             .operands_out(vec![abcd])
-            .inst_predicate(supported_floatccs_predicate(
+            .inst_predicate(supported_ccs_predicate(
                 &supported_floatccs,
                 formats.get(f_float_compare),
             ))
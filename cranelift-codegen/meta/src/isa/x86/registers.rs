@@ -15,6 +15,13 @@ pub fn define() -> IsaRegs {
         .track_pressure(true);
     let float_regs = regs.add_bank(builder);
 
+    // The AVX-512 opmask registers. They are placed before the flags so their units are aligned
+    // like the other banks, and the low 3 bits of a unit encode the register number.
+    let builder = RegBankBuilder::new("MaskRegs", "k")
+        .units(8)
+        .track_pressure(true);
+    let mask_regs = regs.add_bank(builder);
+
     let builder = RegBankBuilder::new("FlagRegs", "")
         .units(1)
         .names(vec!["rflags"])
@@ -27,6 +34,9 @@ pub fn define() -> IsaRegs {
     let builder = RegClassBuilder::new_toplevel("FPR", float_regs);
     let fpr = regs.add_class(builder);
 
+    let builder = RegClassBuilder::new_toplevel("MASK", mask_regs);
+    regs.add_class(builder);
+
     let builder = RegClassBuilder::new_toplevel("FLAG", flag_reg);
     regs.add_class(builder);
 
@@ -9,9 +9,14 @@ pub fn define(shared: &SettingGroup) -> SettingGroup {
     let has_sse41 = settings.add_bool("has_sse41", "SSE4.1: CPUID.01H:ECX.SSE4_1[bit 19]", false);
     let has_sse42 = settings.add_bool("has_sse42", "SSE4.2: CPUID.01H:ECX.SSE4_2[bit 20]", false);
     let has_popcnt = settings.add_bool("has_popcnt", "POPCNT: CPUID.01H:ECX.POPCNT[bit 23]", false);
-    settings.add_bool("has_avx", "AVX: CPUID.01H:ECX.AVX[bit 28]", false);
+    let has_avx = settings.add_bool("has_avx", "AVX: CPUID.01H:ECX.AVX[bit 28]", false);
 
     // CPUID.(EAX=07H, ECX=0H):EBX
+    let has_avx2 = settings.add_bool(
+        "has_avx2",
+        "AVX2: CPUID.(EAX=07H, ECX=0H):EBX.AVX2[bit 5]",
+        false,
+    );
     let has_bmi1 = settings.add_bool(
         "has_bmi1",
         "BMI1: CPUID.(EAX=07H, ECX=0H):EBX.BMI1[bit 3]",
@@ -22,6 +27,33 @@ pub fn define(shared: &SettingGroup) -> SettingGroup {
         "BMI2: CPUID.(EAX=07H, ECX=0H):EBX.BMI2[bit 8]",
         false,
     );
+    let has_avx512f = settings.add_bool(
+        "has_avx512f",
+        "AVX512F: CPUID.(EAX=07H, ECX=0H):EBX.AVX512F[bit 16]",
+        false,
+    );
+    let has_avx512dq = settings.add_bool(
+        "has_avx512dq",
+        "AVX512DQ: CPUID.(EAX=07H, ECX=0H):EBX.AVX512DQ[bit 17]",
+        false,
+    );
+    let has_avx512bw = settings.add_bool(
+        "has_avx512bw",
+        "AVX512BW: CPUID.(EAX=07H, ECX=0H):EBX.AVX512BW[bit 30]",
+        false,
+    );
+    let has_avx512vl = settings.add_bool(
+        "has_avx512vl",
+        "AVX512VL: CPUID.(EAX=07H, ECX=0H):EBX.AVX512VL[bit 31]",
+        false,
+    );
+
+    // CPUID.(EAX=07H, ECX=0H):ECX
+    let has_avx512vbmi = settings.add_bool(
+        "has_avx512vbmi",
+        "AVX512VBMI: CPUID.(EAX=07H, ECX=0H):ECX.AVX512_VBMI[bit 1]",
+        false,
+    );
 
     // CPUID.EAX=80000001H:ECX
     let has_lzcnt = settings.add_bool(
@@ -35,6 +67,7 @@ pub fn define(shared: &SettingGroup) -> SettingGroup {
     settings.add_predicate("use_ssse3", predicate!(has_ssse3));
     settings.add_predicate("use_sse41", predicate!(has_sse41));
     settings.add_predicate("use_sse42", predicate!(has_sse41 && has_sse42));
+    settings.add_predicate("use_avx", predicate!(has_avx));
 
     settings.add_predicate(
         "use_ssse3_simd",
@@ -48,6 +81,27 @@ pub fn define(shared: &SettingGroup) -> SettingGroup {
         "use_sse42_simd",
         predicate!(shared_enable_simd && has_sse41 && has_sse42),
     );
+    settings.add_predicate("use_avx_simd", predicate!(shared_enable_simd && has_avx));
+    settings.add_predicate(
+        "use_avx2_simd",
+        predicate!(shared_enable_simd && has_avx && has_avx2),
+    );
+    settings.add_predicate(
+        "use_avx512f_simd",
+        predicate!(shared_enable_simd && has_avx512f),
+    );
+    settings.add_predicate(
+        "use_avx512bw_simd",
+        predicate!(shared_enable_simd && has_avx512f && has_avx512bw),
+    );
+    settings.add_predicate(
+        "use_avx512dq_simd",
+        predicate!(shared_enable_simd && has_avx512f && has_avx512dq),
+    );
+    settings.add_predicate(
+        "use_avx512vbmi_simd",
+        predicate!(shared_enable_simd && has_avx512f && has_avx512vl && has_avx512vbmi),
+    );
 
     settings.add_predicate("use_popcnt", predicate!(has_popcnt && has_sse42));
     settings.add_predicate("use_bmi1", predicate!(has_bmi1));
@@ -188,6 +188,13 @@ pub(crate) fn define(imm: &Immediates, entities: &EntityRefs) -> FormatRegistry
             .imm_with_name("src", &imm.regunit)
             .imm_with_name("dst", &imm.regunit),
     );
+    registry.insert(
+        Builder::new("RegSwap")
+            .value()
+            .value()
+            .imm_with_name("src", &imm.regunit)
+            .imm_with_name("dst", &imm.regunit),
+    );
     registry.insert(
         Builder::new("CopySpecial")
             .imm_with_name("src", &imm.regunit)
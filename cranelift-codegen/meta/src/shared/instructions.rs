@@ -1304,6 +1304,27 @@ pub(crate) fn define(
         .other_side_effects(true),
     );
 
+    let y = &operand("y", Any);
+
+    ig.push(
+        Inst::new(
+            "regswap",
+            r#"
+        Temporarily divert ``x`` from ``src`` to ``dst`` and ``y`` from ``dst``
+        to ``src``.
+
+        This instruction exchanges the locations of two values of the same type
+        without creating new SSA values. It is used by the register allocator
+        to break cycles of register moves when there are no free registers.
+
+        The register diversions created by this instruction must be undone
+        before the values leave the EBB, see `regmove`.
+        "#,
+        )
+        .operands_in(vec![x, y, src, dst])
+        .other_side_effects(true),
+    );
+
     ig.push(
         Inst::new(
             "copy_special",
@@ -81,6 +81,7 @@ pub(crate) fn define(insts: &InstructionGroup, imm: &Immediates) -> TransformGro
     let ifcmp_imm = insts.by_name("ifcmp_imm");
     let imul = insts.by_name("imul");
     let imul_imm = insts.by_name("imul_imm");
+    let ineg = insts.by_name("ineg");
     let ireduce = insts.by_name("ireduce");
     let irsub_imm = insts.by_name("irsub_imm");
     let ishl = insts.by_name("ishl");
@@ -109,6 +110,8 @@ pub(crate) fn define(insts: &InstructionGroup, imm: &Immediates) -> TransformGro
     let sshr_imm = insts.by_name("sshr_imm");
     let srem = insts.by_name("srem");
     let srem_imm = insts.by_name("srem_imm");
+    let stack_load = insts.by_name("stack_load");
+    let stack_store = insts.by_name("stack_store");
     let store = insts.by_name("store");
     let udiv = insts.by_name("udiv");
     let udiv_imm = insts.by_name("udiv_imm");
@@ -145,8 +148,13 @@ pub(crate) fn define(insts: &InstructionGroup, imm: &Immediates) -> TransformGro
     expand.custom_legalize(f64const, "expand_fconst");
 
     // Custom expansions for stack memory accesses.
-    expand.custom_legalize(insts.by_name("stack_load"), "expand_stack_load");
-    expand.custom_legalize(insts.by_name("stack_store"), "expand_stack_store");
+    expand.custom_legalize(stack_load, "expand_stack_load");
+    expand.custom_legalize(stack_store, "expand_stack_store");
+
+    // The controlling type of `ireduce` is its result, so reductions from types that need to be
+    // narrowed end up in the expand and widen groups.
+    expand.custom_legalize(ireduce, "expand_ireduce");
+    widen.custom_legalize(ireduce, "expand_ireduce");
 
     // List of variables to reuse in patterns.
     let x = var("x");
@@ -201,6 +209,14 @@ pub(crate) fn define(insts: &InstructionGroup, imm: &Immediates) -> TransformGro
 
     narrow.custom_legalize(load, "narrow_load");
     narrow.custom_legalize(store, "narrow_store");
+    narrow.custom_legalize(stack_load, "expand_stack_load");
+    narrow.custom_legalize(stack_store, "expand_stack_store");
+    narrow.custom_legalize(br_icmp, "expand_br_icmp");
+
+    // Shifts need to move bits across the two halves, depending on the dynamic shift amount.
+    narrow.custom_legalize(ishl, "narrow_shift");
+    narrow.custom_legalize(ushr, "narrow_shift");
+    narrow.custom_legalize(sshr, "narrow_shift");
 
     // iconst.i64 can't be legalized in the meta langage (because integer literals can't be
     // embedded as part of arguments), so use a custom legalization for now.
@@ -356,6 +372,156 @@ pub(crate) fn define(insts: &InstructionGroup, imm: &Immediates) -> TransformGro
         );
     }
 
+    // The narrow group doesn't fall back to the expansions, so immediate operands of `i128`
+    // instructions are materialized here too.
+    for &(inst_imm, inst) in &[
+        (iadd_imm, iadd),
+        (imul_imm, imul),
+        (sdiv_imm, sdiv),
+        (udiv_imm, udiv),
+        (srem_imm, srem),
+        (urem_imm, urem),
+        (band_imm, band),
+        (bor_imm, bor),
+        (bxor_imm, bxor),
+    ] {
+        narrow.legalize(
+            def!(a = inst_imm.I128(x, y)),
+            vec![def!(a1 = iconst(y)), def!(a = inst(x, a1))],
+        );
+    }
+
+    narrow.legalize(
+        def!(a = irsub_imm.I128(y, x)),
+        vec![def!(a1 = iconst(x)), def!(a = isub(a1, y))],
+    );
+
+    for &(inst_imm, inst) in &[
+        (rotl_imm, rotl),
+        (rotr_imm, rotr),
+        (ishl_imm, ishl),
+        (sshr_imm, sshr),
+        (ushr_imm, ushr),
+    ] {
+        narrow.legalize(
+            def!(a = inst_imm.I128(x, y)),
+            vec![def!(a1 = iconst.I32(y)), def!(a = inst(x, a1))],
+        );
+    }
+
+    let imm64_0 = Literal::constant(&imm.imm64, 0);
+    // The remaining `i128` operations are expressed in terms of their `i64` halves.
+    let (int_ty, int_ty_half) = (I128, I64);
+    let half_bits = Literal::constant(&imm.imm64, int_ty_half as i64);
+    let half_sign_bit = Literal::constant(&imm.imm64, int_ty_half as i64 - 1);
+    let bits = Literal::constant(&imm.imm64, int_ty as i64);
+
+    // Extensions from the half type, or from smaller types by extending to the half type
+    // first.
+    let uextend_half = uextend.bind(int_ty).bind(int_ty_half);
+    narrow.legalize(
+        def!(a = uextend_half(x)),
+        vec![
+            def!(ah = iconst.int_ty_half(imm64_0)),
+            def!(a = iconcat(x, ah)),
+        ],
+    );
+    let sextend_half = sextend.bind(int_ty).bind(int_ty_half);
+    narrow.legalize(
+        def!(a = sextend_half(x)),
+        vec![
+            def!(ah = sshr_imm(x, half_sign_bit)),
+            def!(a = iconcat(x, ah)),
+        ],
+    );
+    for &small_ty in &[I8, I16, I32] {
+        if small_ty as u8 >= int_ty_half as u8 {
+            continue;
+        }
+        for &extend_op in &[uextend, sextend] {
+            let bound = extend_op.bind(int_ty).bind(small_ty);
+            narrow.legalize(
+                def!(a = bound(x)),
+                vec![
+                    def!(a1 = extend_op.int_ty_half(x)),
+                    def!(a = extend_op.int_ty(a1)),
+                ],
+            );
+        }
+    }
+
+    narrow.legalize(
+        def!(a = bint.int_ty(x)),
+        vec![
+            def!(al = bint.int_ty_half(x)),
+            def!(ah = iconst.int_ty_half(imm64_0)),
+            def!(a = iconcat(al, ah)),
+        ],
+    );
+
+    narrow.legalize(
+        def!(a = ineg.int_ty(x)),
+        vec![def!(a1 = iconst(imm64_0)), def!(a = isub(a1, x))],
+    );
+
+    // Rotates are expressed with shifts, which are narrowed in turn. The shift amount is
+    // taken modulo the width of the type, so a rotation by zero shifts right by the full
+    // width, which is a no-op.
+    narrow.legalize(
+        def!(a = rotl.int_ty(x, y)),
+        vec![
+            def!(a1 = ishl(x, y)),
+            def!(b = irsub_imm(y, bits)),
+            def!(a2 = ushr(x, b)),
+            def!(a = bor(a1, a2)),
+        ],
+    );
+    narrow.legalize(
+        def!(a = rotr.int_ty(x, y)),
+        vec![
+            def!(a1 = ushr(x, y)),
+            def!(b = irsub_imm(y, bits)),
+            def!(a2 = ishl(x, b)),
+            def!(a = bor(a1, a2)),
+        ],
+    );
+
+    // Bit counts are computed on the halves, and then zero-extended.
+    narrow.legalize(
+        def!(a = popcnt.int_ty(x)),
+        vec![
+            def!((xl, xh) = isplit(x)),
+            def!(e1 = popcnt(xl)),
+            def!(e2 = popcnt(xh)),
+            def!(e3 = iadd(e1, e2)),
+            def!(a = uextend.int_ty(e3)),
+        ],
+    );
+    narrow.legalize(
+        def!(a = clz.int_ty(x)),
+        vec![
+            def!((xl, xh) = isplit(x)),
+            def!(e1 = clz(xh)),
+            def!(e2 = clz(xl)),
+            def!(e3 = iadd_imm(e2, half_bits)),
+            def!(b = icmp_imm(intcc_eq, xh, imm64_0)),
+            def!(e4 = select(b, e3, e1)),
+            def!(a = uextend.int_ty(e4)),
+        ],
+    );
+    narrow.legalize(
+        def!(a = ctz.int_ty(x)),
+        vec![
+            def!((xl, xh) = isplit(x)),
+            def!(e1 = ctz(xl)),
+            def!(e2 = ctz(xh)),
+            def!(e3 = iadd_imm(e2, half_bits)),
+            def!(b = icmp_imm(intcc_eq, xl, imm64_0)),
+            def!(e4 = select(b, e3, e1)),
+            def!(a = uextend.int_ty(e4)),
+        ],
+    );
+
     // Widen instructions with one input operand.
     for &op in &[bnot, popcnt] {
         for &int_ty in &[I8, I16] {
@@ -893,7 +1059,6 @@ pub(crate) fn define(insts: &InstructionGroup, imm: &Immediates) -> TransformGro
     )
     .chain_with(expand_id);
 
-    let imm64_0 = Literal::constant(&imm.imm64, 0);
     let intcc_ne = Literal::enumerator_for(&imm.intcc, "ne");
     let intcc_eq = Literal::enumerator_for(&imm.intcc, "eq");
 
@@ -16,6 +16,20 @@ pub fn define() -> SettingGroup {
         vec!["none", "speed", "speed_and_size"],
     );
 
+    settings.add_enum(
+        "regalloc",
+        r#"
+        Register allocator to use:
+
+        - coloring: SSA-based register allocator with coalescing, live range
+          splitting and spill cost heuristics. Generates the best code.
+        - linear_scan: Linear scan spilling followed by the same coloring
+          pass. Compiles very large functions faster, but generates more
+          copies and spills.
+        "#,
+        vec!["coloring", "linear_scan"],
+    );
+
     settings.add_bool(
         "enable_verifier",
         r#"
@@ -182,6 +196,37 @@ pub fn define() -> SettingGroup {
         false,
     );
 
+    // Prologue and epilogue options.
+
+    settings.add_bool(
+        "enable_shrink_wrapping",
+        r#"
+            Enable shrink-wrapping of the prologue and epilogue.
+
+            When enabled, the callee-saved registers are saved and the stack frame is set up
+            only on the paths through the function that need them, and leaf functions that
+            don't use the stack or any callee-saved registers don't get a frame at all.
+
+            This is currently only supported for the System V calling conventions on x86.
+            "#,
+        false,
+    );
+
+    settings.add_bool(
+        "omit_frame_pointer",
+        r#"
+            Omit the frame pointer.
+
+            When enabled, functions don't set up a frame pointer, and the frame pointer
+            register is available for register allocation like any other callee-saved
+            register. Stack slots are always addressed relative to the stack pointer.
+
+            This is currently only supported on x86, and is ignored for the Baldrdash
+            calling conventions.
+            "#,
+        false,
+    );
+
     // Stack probing options.
 
     settings.add_bool(
@@ -3,7 +3,8 @@
 //! This module provides functions and data structures that are useful for implementing the
 //! `TargetIsa::legalize_signature()` method.
 
-use crate::ir::{AbiParam, ArgumentExtension, ArgumentLoc, Type};
+use crate::ir::{types, AbiParam, ArgumentExtension, ArgumentLoc, ArgumentPurpose, Type};
+use crate::isa::CallConv;
 use alloc::vec::Vec;
 use core::cmp::Ordering;
 
@@ -75,6 +76,78 @@ impl ValueConversion {
     }
 }
 
+/// How a struct argument or return value is passed, see `ArgumentPurpose::StructArgument`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StructPassing {
+    /// The struct is split into the given number of eightbytes, which are passed like scalar
+    /// `f64` values if their bit is set in the mask, and like `i64` values otherwise. The last
+    /// piece may extend past the end of the struct.
+    Pieces(u32, u8),
+
+    /// The struct is copied to the stack argument area.
+    Memory,
+
+    /// A pointer to a copy of the struct is passed.
+    Reference,
+}
+
+impl StructPassing {
+    /// Get the types of the pieces the struct is split into, if any.
+    pub fn pieces(self) -> impl Iterator<Item = Type> {
+        let (count, floats) = match self {
+            StructPassing::Pieces(count, floats) => (count, floats),
+            StructPassing::Memory | StructPassing::Reference => (0, 0),
+        };
+        (0..count).map(move |i| {
+            if floats & (1 << i) != 0 {
+                types::F64
+            } else {
+                types::I64
+            }
+        })
+    }
+}
+
+/// Determine how a struct of `size` bytes is passed with the x86-64 calling convention
+/// `call_conv`. The bits of `floats` tell which eightbytes of the struct only hold floating point
+/// fields.
+///
+/// Windows passes structs of 1, 2, 4, or 8 bytes as a 64-bit integer, and other structs by
+/// reference.
+/// System V passes structs of up to 16 bytes as one or two eightbytes, each in an integer or float
+/// register depending on its fields, and larger structs in memory.
+///
+/// A struct return value that isn't passed in pieces is written to memory provided by the caller,
+/// and a pointer to it is passed as a `StructReturn` argument.
+pub fn struct_passing(call_conv: CallConv, size: u32, floats: u8) -> StructPassing {
+    if call_conv.extends_windows_fastcall() {
+        match size {
+            1 | 2 | 4 | 8 => StructPassing::Pieces(1, 0),
+            _ => StructPassing::Reference,
+        }
+    } else if size <= 16 {
+        let count = (size + 7) / 8;
+        StructPassing::Pieces(count, floats & ((1 << count) - 1))
+    } else {
+        StructPassing::Memory
+    }
+}
+
+/// Get the size of the struct if `param` is a struct argument that is passed in memory.
+///
+/// The legalized parameter for such a struct is the first eightbyte of the stack argument area
+/// holding it, and the rest of the struct follows.
+pub fn struct_in_memory(call_conv: CallConv, param: &AbiParam) -> Option<u32> {
+    match param.purpose {
+        ArgumentPurpose::StructArgument { size, floats, .. }
+            if struct_passing(call_conv, size, floats) == StructPassing::Memory =>
+        {
+            Some(size)
+        }
+        _ => None,
+    }
+}
+
 /// Common trait for assigning arguments to registers or stack locations.
 ///
 /// This will be implemented by individual ISAs.
@@ -219,4 +292,34 @@ mod tests {
             ValueConversion::IntBits
         );
     }
+
+    #[test]
+    fn structs() {
+        let sysv = CallConv::SystemV;
+        assert_eq!(struct_passing(sysv, 1, 0), StructPassing::Pieces(1, 0));
+        assert_eq!(struct_passing(sysv, 8, 0), StructPassing::Pieces(1, 0));
+        assert_eq!(struct_passing(sysv, 12, 0), StructPassing::Pieces(2, 0));
+        assert_eq!(struct_passing(sysv, 16, 0), StructPassing::Pieces(2, 0));
+        assert_eq!(struct_passing(sysv, 17, 0), StructPassing::Memory);
+        assert_eq!(
+            struct_passing(sysv, 12, 0b11),
+            StructPassing::Pieces(2, 0b11)
+        );
+        assert_eq!(
+            struct_passing(sysv, 8, 0b11),
+            StructPassing::Pieces(1, 0b01)
+        );
+        assert_eq!(struct_passing(sysv, 24, 0b111), StructPassing::Memory);
+        assert_eq!(
+            StructPassing::Pieces(2, 0b10).pieces().collect::<Vec<_>>(),
+            [types::I64, types::F64]
+        );
+        assert_eq!(StructPassing::Memory.pieces().count(), 0);
+
+        let win = CallConv::WindowsFastcall;
+        assert_eq!(struct_passing(win, 2, 0), StructPassing::Pieces(1, 0));
+        assert_eq!(struct_passing(win, 8, 0b1), StructPassing::Pieces(1, 0));
+        assert_eq!(struct_passing(win, 3, 0), StructPassing::Reference);
+        assert_eq!(struct_passing(win, 16, 0b11), StructPassing::Reference);
+    }
 }
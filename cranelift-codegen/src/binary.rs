@@ -0,0 +1,1470 @@
+//! Converting Cranelift IR to a compact binary format.
+//!
+//! The `binary` module provides the `serialize_function` and `deserialize_function` functions,
+//! which convert an IR `Function` to a binary form and back. Unlike the textual form produced by
+//! the `write` module, the binary form is lossless: it keeps the number of every entity, including
+//! the ones which are no longer used, and the state computed by the compilation passes, such as
+//! the stack slots sharing memory, the encodings, value locations, entry diversions and code
+//! offsets. A deserialized function is therefore identical to the serialized one, and can be
+//! handed to the later passes of the pipeline in another process.
+//!
+//! The binary form refers to opcodes and types by their number, which can change between versions
+//! of Cranelift, so it can only be deserialized by the version of Cranelift which produced it.
+
+use crate::entity::{EntityRef, PrimaryMap, SecondaryMap};
+use crate::ir::condcodes::{FloatCC, IntCC};
+use crate::ir::immediates::{Ieee32, Ieee64, Imm64, Offset32, Uimm32, Uimm64};
+use crate::ir::{
+    AbiParam, ArgumentExtension, ArgumentLoc, ArgumentPurpose, Constant, ConstantData, Ebb,
+    ExtFuncData, ExternalName, FuncRef, Function, GlobalValue, GlobalValueData, Heap, HeapData,
+    HeapStyle, Immediate, Inst, JumpTable, JumpTableData, LibCall, MemFlags, Opcode, SigRef,
+    Signature, SourceLoc, StackSlot, StackSlotData, StackSlotKind, Table, TableData, TrapCode,
+    Type, Value, ValueLabel, ValueLabelAssignments, ValueLabelStart, ValueLoc,
+};
+use crate::isa::{CallConv, Encoding};
+use crate::packed_option::ReservedValue;
+use crate::regalloc::RegDiversions;
+use crate::HashMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::str::FromStr;
+use failure_derive::Fail;
+
+/// Version of the binary format, stored in every serialized function.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Magic bytes at the start of every serialized function.
+const MAGIC: &[u8; 8] = b"CLIFFUNC";
+
+/// The register diversions in effect at the top of each EBB that has any, sorted by EBB.
+type EntryDiversions = Vec<(Ebb, Vec<(Value, ValueLoc, ValueLoc)>)>;
+
+/// An error returned by `deserialize_function`.
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum DeserializeError {
+    /// The data doesn't start with the magic bytes of a serialized function.
+    #[fail(display = "Not a serialized function")]
+    NotAFunction,
+
+    /// The function was serialized by another version of Cranelift or of the binary format.
+    #[fail(
+        display = "Function serialized by Cranelift {} with format version {}",
+        _0, _1
+    )]
+    Version(String, u32),
+
+    /// The data is truncated or malformed at the given offset.
+    #[fail(display = "Malformed function data at offset {}", _0)]
+    Malformed(usize),
+}
+
+/// Serialize `func` in the binary format.
+pub fn serialize_function(func: &Function) -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    w.bytes(MAGIC);
+    w.u32(FORMAT_VERSION);
+    w.str(crate::VERSION);
+
+    w.encode(&func.name);
+    w.encode(&func.signature);
+    w.encode(&func.stack_slots.values().cloned().collect::<Vec<_>>());
+    w.encode(
+        &func
+            .stack_slots
+            .keys()
+            .filter_map(|ss| func.stack_slots.shared_with(ss).map(|with| (ss, with)))
+            .collect::<Vec<_>>(),
+    );
+    w.encode(&func.stack_slots.frame_size);
+    w.encode(&func.global_values.values().cloned().collect::<Vec<_>>());
+    w.encode(&func.heaps.values().cloned().collect::<Vec<_>>());
+    w.encode(&func.tables.values().cloned().collect::<Vec<_>>());
+    w.encode(&func.jump_tables.values().cloned().collect::<Vec<_>>());
+
+    let dfg = &func.dfg;
+    w.encode(&dfg.signatures.values().cloned().collect::<Vec<_>>());
+    w.encode(&dfg.ext_funcs.values().cloned().collect::<Vec<_>>());
+    w.uleb(dfg.constants.len() as u64);
+    for (&constant, data) in dfg.constants.iter() {
+        w.encode(data);
+        w.encode(&dfg.constants.try_get_offset(constant));
+    }
+    w.encode(&dfg.immediates.values().cloned().collect::<Vec<_>>());
+    dfg.encode_entities(&mut w);
+    w.encode(&dfg.values_labels.as_ref().map(|labels| {
+        let mut labels = labels
+            .iter()
+            .map(|(&value, assignments)| (value, assignments.clone()))
+            .collect::<Vec<_>>();
+        labels.sort_by_key(|&(value, _)| value);
+        labels
+    }));
+
+    let layout = &func.layout;
+    w.uleb(layout.ebbs().count() as u64);
+    for ebb in layout.ebbs() {
+        w.encode(&ebb);
+        w.encode(&layout.ebb_insts(ebb).collect::<Vec<_>>());
+    }
+    let cold = (0..dfg.num_ebbs())
+        .map(Ebb::new)
+        .filter(|&ebb| layout.is_cold(ebb))
+        .collect::<Vec<_>>();
+    w.encode(&cold);
+
+    w.encode(&func.encodings.values().cloned().collect::<Vec<_>>());
+    w.encode(&func.locations.values().cloned().collect::<Vec<_>>());
+    let mut divert = RegDiversions::new();
+    let mut entry_diversions: EntryDiversions = Vec::new();
+    for ebb in (0..dfg.num_ebbs()).map(Ebb::new) {
+        divert.at_ebb(&func.entry_diversions, ebb);
+        if !divert.is_empty() {
+            let mut diversions = divert
+                .iter()
+                .map(|(&value, d)| (value, d.from, d.to))
+                .collect::<Vec<_>>();
+            diversions.sort_by_key(|&(value, _, _)| value);
+            entry_diversions.push((ebb, diversions));
+        }
+    }
+    w.encode(&entry_diversions);
+    w.encode(&func.offsets.values().cloned().collect::<Vec<_>>());
+    w.encode(&func.jt_offsets.values().cloned().collect::<Vec<_>>());
+    w.encode(&func.srclocs.values().cloned().collect::<Vec<_>>());
+    w.0
+}
+
+/// Deserialize a function produced by `serialize_function`.
+///
+/// The data is checked to be well-formed, and every entity it refers to must exist, so the function
+/// can be printed and verified. It isn't verified though, so functions coming from an untrusted
+/// source should be checked with the verifier before they are used.
+pub fn deserialize_function(data: &[u8]) -> Result<Function, DeserializeError> {
+    let mut r = Reader::new(data);
+    match r.bytes(MAGIC.len()) {
+        Some(magic) if magic == MAGIC => {}
+        _ => return Err(DeserializeError::NotAFunction),
+    }
+    let malformed = |r: &Reader| DeserializeError::Malformed(data.len() - r.0.len());
+    let (format_version, version) = match (r.u32(), r.str()) {
+        (Some(format_version), Some(version)) => (format_version, version),
+        _ => return Err(malformed(&r)),
+    };
+    if format_version != FORMAT_VERSION || version != crate::VERSION {
+        return Err(DeserializeError::Version(version, format_version));
+    }
+    match decode_function(&mut r) {
+        Some(func) if r.0.is_empty() => Ok(func),
+        _ => Err(malformed(&r)),
+    }
+}
+
+/// Decode the function following the header.
+fn decode_function(r: &mut Reader) -> Option<Function> {
+    let mut func = Function::with_name_signature(r.decode()?, r.decode()?);
+    let stack_slots: Vec<StackSlotData> = r.decode()?;
+    for data in stack_slots {
+        func.stack_slots.push_registered(data);
+    }
+    for (ss, with) in r.decode::<Vec<(StackSlot, StackSlot)>>()? {
+        if !func.stack_slots.can_share(ss, with) {
+            return None;
+        }
+        func.stack_slots.share(ss, with);
+    }
+    func.stack_slots.frame_size = r.decode()?;
+    decode_primary(r, &mut func.global_values)?;
+    decode_primary(r, &mut func.heaps)?;
+    decode_primary(r, &mut func.tables)?;
+    decode_primary(r, &mut func.jump_tables)?;
+
+    let dfg = &mut func.dfg;
+    decode_primary(r, &mut dfg.signatures)?;
+    decode_primary(r, &mut dfg.ext_funcs)?;
+    for _ in 0..r.count()? {
+        let constant = dfg.constants.insert(r.decode()?);
+        if constant.index() + 1 != dfg.constants.len() {
+            return None;
+        }
+        if let Some(offset) = r.decode()? {
+            dfg.constants.set_offset(constant, offset);
+        }
+    }
+    decode_primary(r, &mut dfg.immediates)?;
+    dfg.decode_entities(r)?;
+    let labels: Option<Vec<(Value, ValueLabelAssignments)>> = r.decode()?;
+    dfg.values_labels = labels.map(|labels| labels.into_iter().collect::<HashMap<_, _>>());
+
+    for _ in 0..r.count()? {
+        let ebb: Ebb = r.decode()?;
+        if ebb.index() >= dfg.num_ebbs() || func.layout.is_ebb_inserted(ebb) {
+            return None;
+        }
+        func.layout.append_ebb(ebb);
+        for inst in r.decode::<Vec<Inst>>()? {
+            if inst.index() >= dfg.num_insts() || func.layout.inst_ebb(inst).is_some() {
+                return None;
+            }
+            func.layout.append_inst(inst, ebb);
+        }
+    }
+    for ebb in r.decode::<Vec<Ebb>>()? {
+        if ebb.index() >= dfg.num_ebbs() {
+            return None;
+        }
+        func.layout.set_cold(ebb);
+    }
+
+    decode_secondary(r, &mut func.encodings)?;
+    decode_secondary(r, &mut func.locations)?;
+    let entry_diversions: EntryDiversions = r.decode()?;
+    let mut last_ebb = None;
+    for (ebb, diversions) in entry_diversions {
+        if last_ebb >= Some(ebb) || diversions.is_empty() {
+            return None;
+        }
+        last_ebb = Some(ebb);
+        let mut divert = RegDiversions::new();
+        for (value, from, to) in diversions {
+            match (from, to) {
+                (ValueLoc::Reg(from), ValueLoc::Reg(to)) => divert.regmove(value, from, to),
+                (ValueLoc::Reg(from), ValueLoc::Stack(to)) => divert.regspill(value, from, to),
+                (ValueLoc::Stack(from), ValueLoc::Reg(to)) => divert.regfill(value, from, to),
+                _ => return None,
+            }
+        }
+        divert.save_for_ebb(&mut func.entry_diversions, ebb);
+    }
+    decode_secondary(r, &mut func.offsets)?;
+    decode_secondary(r, &mut func.jt_offsets)?;
+    decode_secondary(r, &mut func.srclocs)?;
+
+    // The entity references are only checked now that all the entities have been decoded, since
+    // instructions refer to values which are decoded after them.
+    let dfg = &func.dfg;
+    let counts = [
+        (EntityKind::Ebb, dfg.num_ebbs()),
+        (EntityKind::Value, dfg.num_values()),
+        (EntityKind::Inst, dfg.num_insts()),
+        (EntityKind::StackSlot, func.stack_slots.values().len()),
+        (EntityKind::GlobalValue, func.global_values.len()),
+        (EntityKind::Constant, dfg.constants.len()),
+        (EntityKind::Immediate, dfg.immediates.len()),
+        (EntityKind::JumpTable, func.jump_tables.len()),
+        (EntityKind::FuncRef, dfg.ext_funcs.len()),
+        (EntityKind::SigRef, dfg.signatures.len()),
+        (EntityKind::Heap, func.heaps.len()),
+        (EntityKind::Table, func.tables.len()),
+    ];
+    for &(kind, count) in &counts {
+        r.check_references(kind, count)?;
+    }
+    Some(func)
+}
+
+/// Decode the values of an empty primary map, encoded as a `Vec`.
+fn decode_primary<K: EntityRef, V: Encode>(
+    r: &mut Reader,
+    map: &mut PrimaryMap<K, V>,
+) -> Option<()> {
+    let values: Vec<V> = r.decode()?;
+    for value in values {
+        map.push(value);
+    }
+    Some(())
+}
+
+/// Decode the values of a secondary map, encoded as a `Vec`.
+fn decode_secondary<K: EntityRef, V: Clone + Encode>(
+    r: &mut Reader,
+    map: &mut SecondaryMap<K, V>,
+) -> Option<()> {
+    let values: Vec<V> = r.decode()?;
+    map.resize(values.len());
+    for (i, value) in values.into_iter().enumerate() {
+        map[K::new(i)] = value;
+    }
+    Some(())
+}
+
+/// Little-endian serialization helper.
+pub(crate) struct Writer(pub Vec<u8>);
+
+impl Writer {
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    pub fn u8(&mut self, x: u8) {
+        self.0.push(x);
+    }
+
+    pub fn u32(&mut self, x: u32) {
+        self.bytes(&x.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, x: u64) {
+        self.bytes(&x.to_le_bytes());
+    }
+
+    /// Write an unsigned LEB128 number.
+    pub fn uleb(&mut self, mut x: u64) {
+        while x >= 0x80 {
+            self.u8(x as u8 | 0x80);
+            x >>= 7;
+        }
+        self.u8(x as u8);
+    }
+
+    /// Write a signed LEB128 number.
+    pub fn sleb(&mut self, mut x: i64) {
+        loop {
+            let byte = x as u8 & 0x7f;
+            x >>= 6;
+            if x == 0 || x == -1 {
+                self.u8(byte);
+                return;
+            }
+            x >>= 1;
+            self.u8(byte | 0x80);
+        }
+    }
+
+    pub fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes(s.as_bytes());
+    }
+
+    pub fn name(&mut self, name: &ExternalName) {
+        match *name {
+            ExternalName::User { namespace, index } => {
+                self.u8(0);
+                self.u32(namespace);
+                self.u32(index);
+            }
+            ExternalName::TestCase { length, ascii } => {
+                self.u8(1);
+                self.u8(length);
+                self.bytes(&ascii);
+            }
+            ExternalName::LibCall(libcall) => {
+                self.u8(2);
+                self.str(&libcall.to_string());
+            }
+        }
+    }
+
+    pub fn encode<T: Encode>(&mut self, x: &T) {
+        x.encode(self)
+    }
+
+    pub fn values(&mut self, values: &[Value]) {
+        self.uleb(values.len() as u64);
+        for value in values {
+            self.encode(value);
+        }
+    }
+}
+
+/// Little-endian deserialization helper, returning `None` at the end of the data.
+///
+/// The reader also keeps track of the highest number of each kind of entity it decodes, so the
+/// references can be checked once the entities themselves have been decoded.
+pub(crate) struct Reader<'a>(pub &'a [u8], [usize; ENTITY_KINDS]);
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader(data, [0; ENTITY_KINDS])
+    }
+
+    /// Record a reference to the entity number `index` of the given kind.
+    fn reference(&mut self, kind: EntityKind, index: usize) {
+        let count = &mut self.1[kind as usize];
+        *count = (*count).max(index + 1);
+    }
+
+    /// Check that all the entities of the given kind decoded so far are among the first `count`.
+    fn check_references(&self, kind: EntityKind, count: usize) -> Option<()> {
+        if self.1[kind as usize] <= count {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.bytes(4)?);
+        Some(u32::from_le_bytes(b))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.bytes(8)?);
+        Some(u64::from_le_bytes(b))
+    }
+
+    /// Read an unsigned LEB128 number.
+    pub fn uleb(&mut self) -> Option<u64> {
+        let mut x = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift >= 64 {
+                return None;
+            }
+            x |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(x);
+            }
+        }
+    }
+
+    /// Read a signed LEB128 number.
+    pub fn sleb(&mut self) -> Option<i64> {
+        let mut x = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift >= 64 {
+                return None;
+            }
+            x |= i64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    x |= -1 << shift;
+                }
+                return Some(x);
+            }
+        }
+    }
+
+    /// Read the number of elements of a sequence.
+    ///
+    /// Every element takes at least one byte, so larger counts than the remaining data are
+    /// rejected before anything is allocated for them.
+    pub fn count(&mut self) -> Option<usize> {
+        let count = self.uleb()?;
+        if count > self.0.len() as u64 {
+            return None;
+        }
+        Some(count as usize)
+    }
+
+    pub fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).ok()
+    }
+
+    pub fn name(&mut self) -> Option<ExternalName> {
+        match self.u8()? {
+            0 => Some(ExternalName::User {
+                namespace: self.u32()?,
+                index: self.u32()?,
+            }),
+            1 => {
+                let length = self.u8()?;
+                let mut ascii = [0; 16];
+                ascii.copy_from_slice(self.bytes(16)?);
+                Some(ExternalName::TestCase { length, ascii })
+            }
+            2 => LibCall::from_str(&self.str()?)
+                .ok()
+                .map(ExternalName::LibCall),
+            _ => None,
+        }
+    }
+
+    pub fn decode<T: Encode>(&mut self) -> Option<T> {
+        T::decode(self)
+    }
+
+    pub fn values(&mut self) -> Option<Vec<Value>> {
+        self.decode()
+    }
+}
+
+/// A part of a function with a binary form.
+pub(crate) trait Encode: Sized {
+    /// Write the binary form of `self`.
+    fn encode(&self, w: &mut Writer);
+
+    /// Read a binary form written by `encode`, or return `None` if it is malformed.
+    fn decode(r: &mut Reader) -> Option<Self>;
+}
+
+macro_rules! encode_entities {
+    ($($entity:ident),*) => {
+        /// The kinds of entity references, see `Reader::reference`.
+        #[derive(Clone, Copy)]
+        enum EntityKind {
+            $($entity),*
+        }
+
+        /// The number of `EntityKind`s.
+        const ENTITY_KINDS: usize = [$(EntityKind::$entity),*].len();
+
+        $(
+            impl Encode for $entity {
+                fn encode(&self, w: &mut Writer) {
+                    w.uleb(self.index() as u64);
+                }
+
+                fn decode(r: &mut Reader) -> Option<Self> {
+                    // The reserved value is used as a placeholder by the parser, and encoded like
+                    // any other entity.
+                    match r.uleb()? {
+                        index if index < u64::from(u32::max_value()) => {
+                            r.reference(EntityKind::$entity, index as usize);
+                            Some(Self::new(index as usize))
+                        }
+                        index if index == u64::from(u32::max_value()) => {
+                            Some(Self::reserved_value())
+                        }
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+encode_entities!(
+    Ebb,
+    Value,
+    Inst,
+    StackSlot,
+    GlobalValue,
+    Constant,
+    Immediate,
+    JumpTable,
+    FuncRef,
+    SigRef,
+    Heap,
+    Table,
+    ValueLabel
+);
+
+/// Encode enums without fields by their position in a table of all their variants.
+macro_rules! encode_variants {
+    ($($ty:ty: [$($variant:expr),*]),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, w: &mut Writer) {
+                    let variants = [$($variant),*];
+                    w.u8(variants.iter().position(|v| v == self).unwrap() as u8);
+                }
+
+                fn decode(r: &mut Reader) -> Option<Self> {
+                    let variants = [$($variant),*];
+                    variants.get(usize::from(r.u8()?)).cloned()
+                }
+            }
+        )*
+    };
+}
+
+encode_variants!(
+    IntCC: [
+        IntCC::Equal,
+        IntCC::NotEqual,
+        IntCC::SignedLessThan,
+        IntCC::SignedGreaterThanOrEqual,
+        IntCC::SignedGreaterThan,
+        IntCC::SignedLessThanOrEqual,
+        IntCC::UnsignedLessThan,
+        IntCC::UnsignedGreaterThanOrEqual,
+        IntCC::UnsignedGreaterThan,
+        IntCC::UnsignedLessThanOrEqual,
+        IntCC::Overflow,
+        IntCC::NotOverflow
+    ],
+    FloatCC: [
+        FloatCC::Ordered,
+        FloatCC::Unordered,
+        FloatCC::Equal,
+        FloatCC::NotEqual,
+        FloatCC::OrderedNotEqual,
+        FloatCC::UnorderedOrEqual,
+        FloatCC::LessThan,
+        FloatCC::LessThanOrEqual,
+        FloatCC::GreaterThan,
+        FloatCC::GreaterThanOrEqual,
+        FloatCC::UnorderedOrLessThan,
+        FloatCC::UnorderedOrLessThanOrEqual,
+        FloatCC::UnorderedOrGreaterThan,
+        FloatCC::UnorderedOrGreaterThanOrEqual
+    ],
+    CallConv: [
+        CallConv::Fast,
+        CallConv::Cold,
+        CallConv::SystemV,
+        CallConv::WindowsFastcall,
+        CallConv::BaldrdashSystemV,
+        CallConv::BaldrdashWindows,
+        CallConv::Probestack
+    ],
+    ArgumentExtension: [
+        ArgumentExtension::None,
+        ArgumentExtension::Uext,
+        ArgumentExtension::Sext
+    ],
+    StackSlotKind: [
+        StackSlotKind::SpillSlot,
+        StackSlotKind::ExplicitSlot,
+        StackSlotKind::IncomingArg,
+        StackSlotKind::OutgoingArg,
+        StackSlotKind::EmergencySlot
+    ]
+);
+
+impl Encode for bool {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(*self as u8);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        match r.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(*self);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.u8()
+    }
+}
+
+impl Encode for u16 {
+    fn encode(&self, w: &mut Writer) {
+        w.uleb(u64::from(*self));
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        let x = r.uleb()?;
+        if x > u64::from(u16::max_value()) {
+            return None;
+        }
+        Some(x as u16)
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, w: &mut Writer) {
+        w.uleb(u64::from(*self));
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        let x = r.uleb()?;
+        if x > u64::from(u32::max_value()) {
+            return None;
+        }
+        Some(x as u32)
+    }
+}
+
+impl Encode for i32 {
+    fn encode(&self, w: &mut Writer) {
+        w.sleb(i64::from(*self));
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        let x = r.sleb()?;
+        if x < i64::from(i32::min_value()) || x > i64::from(i32::max_value()) {
+            return None;
+        }
+        Some(x as i32)
+    }
+}
+
+impl Encode for Imm64 {
+    fn encode(&self, w: &mut Writer) {
+        w.sleb((*self).into());
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.sleb().map(Self::new)
+    }
+}
+
+impl Encode for Uimm64 {
+    fn encode(&self, w: &mut Writer) {
+        w.uleb((*self).into());
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.uleb().map(Self::new)
+    }
+}
+
+impl Encode for Uimm32 {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&Into::<u32>::into(*self));
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.decode::<u32>().map(Self::from)
+    }
+}
+
+impl Encode for Offset32 {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&Into::<i32>::into(*self));
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.decode::<i32>().map(Self::new)
+    }
+}
+
+impl Encode for Ieee32 {
+    fn encode(&self, w: &mut Writer) {
+        w.u32(self.bits());
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.u32().map(Self::with_bits)
+    }
+}
+
+impl Encode for Ieee64 {
+    fn encode(&self, w: &mut Writer) {
+        w.u64(self.bits());
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.u64().map(Self::with_bits)
+    }
+}
+
+impl Encode for Type {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(self.index() as u8);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Type::from_index(r.u8()?)
+    }
+}
+
+impl Encode for Opcode {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(*self as u8);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Opcode::from_u8(r.u8()?)
+    }
+}
+
+impl Encode for SourceLoc {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&self.bits());
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.decode().map(Self::new)
+    }
+}
+
+impl Encode for TrapCode {
+    fn encode(&self, w: &mut Writer) {
+        match *self {
+            TrapCode::User(code) => {
+                w.u8(0);
+                w.encode(&code);
+            }
+            code => w.u8(1 + TRAP_CODES.iter().position(|&c| c == code).unwrap() as u8),
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        match r.u8()? {
+            0 => r.decode().map(TrapCode::User),
+            n => TRAP_CODES.get(usize::from(n) - 1).cloned(),
+        }
+    }
+}
+
+/// Every trap code but `TrapCode::User`.
+const TRAP_CODES: [TrapCode; 11] = [
+    TrapCode::StackOverflow,
+    TrapCode::HeapOutOfBounds,
+    TrapCode::TableOutOfBounds,
+    TrapCode::OutOfBounds,
+    TrapCode::IndirectCallToNull,
+    TrapCode::BadSignature,
+    TrapCode::IntegerOverflow,
+    TrapCode::IntegerDivisionByZero,
+    TrapCode::BadConversionToInteger,
+    TrapCode::UnreachableCodeReached,
+    TrapCode::Interrupt,
+];
+
+impl Encode for MemFlags {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(self.notrap() as u8 | (self.aligned() as u8) << 1 | (self.readonly() as u8) << 2);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        let bits = r.u8()?;
+        if bits >= 1 << 3 {
+            return None;
+        }
+        let mut flags = MemFlags::new();
+        if bits & 1 != 0 {
+            flags.set_notrap();
+        }
+        if bits & 2 != 0 {
+            flags.set_aligned();
+        }
+        if bits & 4 != 0 {
+            flags.set_readonly();
+        }
+        Some(flags)
+    }
+}
+
+impl Encode for ExternalName {
+    fn encode(&self, w: &mut Writer) {
+        w.name(self);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        r.name()
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, w: &mut Writer) {
+        match *self {
+            None => w.u8(0),
+            Some(ref x) => {
+                w.u8(1);
+                w.encode(x);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        match r.u8()? {
+            0 => Some(None),
+            1 => r.decode().map(Some),
+            _ => None,
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, w: &mut Writer) {
+        w.uleb(self.len() as u64);
+        for x in self {
+            w.encode(x);
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        let count = r.count()?;
+        let mut v = Vec::with_capacity(count);
+        for _ in 0..count {
+            v.push(r.decode()?);
+        }
+        Some(v)
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&self.0);
+        w.encode(&self.1);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some((r.decode()?, r.decode()?))
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode> Encode for (A, B, C) {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&self.0);
+        w.encode(&self.1);
+        w.encode(&self.2);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some((r.decode()?, r.decode()?, r.decode()?))
+    }
+}
+
+impl Encode for ConstantData {
+    fn encode(&self, w: &mut Writer) {
+        w.uleb(self.len() as u64);
+        for &byte in self.iter() {
+            w.u8(byte);
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        let len = r.count()?;
+        Some(r.bytes(len)?.into())
+    }
+}
+
+impl Encode for ArgumentLoc {
+    fn encode(&self, w: &mut Writer) {
+        match *self {
+            ArgumentLoc::Unassigned => w.u8(0),
+            ArgumentLoc::Reg(reg) => {
+                w.u8(1);
+                w.encode(&reg);
+            }
+            ArgumentLoc::Stack(offset) => {
+                w.u8(2);
+                w.encode(&offset);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        match r.u8()? {
+            0 => Some(ArgumentLoc::Unassigned),
+            1 => r.decode().map(ArgumentLoc::Reg),
+            2 => r.decode().map(ArgumentLoc::Stack),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for ArgumentPurpose {
+    fn encode(&self, w: &mut Writer) {
+        match *self {
+            ArgumentPurpose::Normal => w.u8(0),
+            ArgumentPurpose::StructReturn => w.u8(1),
+            ArgumentPurpose::Link => w.u8(2),
+            ArgumentPurpose::FramePointer => w.u8(3),
+            ArgumentPurpose::CalleeSaved => w.u8(4),
+            ArgumentPurpose::VMContext => w.u8(5),
+            ArgumentPurpose::SignatureId => w.u8(6),
+            ArgumentPurpose::StackLimit => w.u8(7),
+            ArgumentPurpose::StructArgument {
+                size,
+                align,
+                floats,
+            } => {
+                w.u8(8);
+                w.encode(&size);
+                w.encode(&align);
+                w.u8(floats);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        match r.u8()? {
+            0 => Some(ArgumentPurpose::Normal),
+            1 => Some(ArgumentPurpose::StructReturn),
+            2 => Some(ArgumentPurpose::Link),
+            3 => Some(ArgumentPurpose::FramePointer),
+            4 => Some(ArgumentPurpose::CalleeSaved),
+            5 => Some(ArgumentPurpose::VMContext),
+            6 => Some(ArgumentPurpose::SignatureId),
+            7 => Some(ArgumentPurpose::StackLimit),
+            8 => Some(ArgumentPurpose::StructArgument {
+                size: r.decode()?,
+                align: r.decode()?,
+                floats: r.u8()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for ValueLoc {
+    fn encode(&self, w: &mut Writer) {
+        match *self {
+            ValueLoc::Unassigned => w.u8(0),
+            ValueLoc::Reg(reg) => {
+                w.u8(1);
+                w.encode(&reg);
+            }
+            ValueLoc::Stack(ss) => {
+                w.u8(2);
+                w.encode(&ss);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        match r.u8()? {
+            0 => Some(ValueLoc::Unassigned),
+            1 => r.decode().map(ValueLoc::Reg),
+            2 => r.decode().map(ValueLoc::Stack),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for Encoding {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&(self.recipe() as u16));
+        w.encode(&self.bits());
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(Encoding::new(r.decode()?, r.decode()?))
+    }
+}
+
+impl Encode for AbiParam {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&self.value_type);
+        w.encode(&self.purpose);
+        w.encode(&self.extension);
+        w.encode(&self.location);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(AbiParam {
+            value_type: r.decode()?,
+            purpose: r.decode()?,
+            extension: r.decode()?,
+            location: r.decode()?,
+        })
+    }
+}
+
+impl Encode for Signature {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&self.params);
+        w.encode(&self.returns);
+        w.encode(&self.call_conv);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(Signature {
+            params: r.decode()?,
+            returns: r.decode()?,
+            call_conv: r.decode()?,
+        })
+    }
+}
+
+impl Encode for ExtFuncData {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&self.name);
+        w.encode(&self.signature);
+        w.encode(&self.colocated);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(ExtFuncData {
+            name: r.decode()?,
+            signature: r.decode()?,
+            colocated: r.decode()?,
+        })
+    }
+}
+
+impl Encode for StackSlotData {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&self.kind);
+        w.encode(&self.size);
+        w.encode(&self.offset);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(StackSlotData {
+            kind: r.decode()?,
+            size: r.decode()?,
+            offset: r.decode()?,
+        })
+    }
+}
+
+impl Encode for GlobalValueData {
+    fn encode(&self, w: &mut Writer) {
+        match *self {
+            GlobalValueData::VMContext => w.u8(0),
+            GlobalValueData::Load {
+                base,
+                offset,
+                global_type,
+                readonly,
+            } => {
+                w.u8(1);
+                w.encode(&base);
+                w.encode(&offset);
+                w.encode(&global_type);
+                w.encode(&readonly);
+            }
+            GlobalValueData::IAddImm {
+                base,
+                offset,
+                global_type,
+            } => {
+                w.u8(2);
+                w.encode(&base);
+                w.encode(&offset);
+                w.encode(&global_type);
+            }
+            GlobalValueData::Symbol {
+                ref name,
+                offset,
+                colocated,
+            } => {
+                w.u8(3);
+                w.encode(name);
+                w.encode(&offset);
+                w.encode(&colocated);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(match r.u8()? {
+            0 => GlobalValueData::VMContext,
+            1 => GlobalValueData::Load {
+                base: r.decode()?,
+                offset: r.decode()?,
+                global_type: r.decode()?,
+                readonly: r.decode()?,
+            },
+            2 => GlobalValueData::IAddImm {
+                base: r.decode()?,
+                offset: r.decode()?,
+                global_type: r.decode()?,
+            },
+            3 => GlobalValueData::Symbol {
+                name: r.decode()?,
+                offset: r.decode()?,
+                colocated: r.decode()?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+impl Encode for HeapData {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&self.base);
+        w.encode(&self.min_size);
+        w.encode(&self.offset_guard_size);
+        match self.style {
+            HeapStyle::Dynamic { bound_gv } => {
+                w.u8(0);
+                w.encode(&bound_gv);
+            }
+            HeapStyle::Static { bound } => {
+                w.u8(1);
+                w.encode(&bound);
+            }
+        }
+        w.encode(&self.index_type);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(HeapData {
+            base: r.decode()?,
+            min_size: r.decode()?,
+            offset_guard_size: r.decode()?,
+            style: match r.u8()? {
+                0 => HeapStyle::Dynamic {
+                    bound_gv: r.decode()?,
+                },
+                1 => HeapStyle::Static { bound: r.decode()? },
+                _ => return None,
+            },
+            index_type: r.decode()?,
+        })
+    }
+}
+
+impl Encode for TableData {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&self.base_gv);
+        w.encode(&self.min_size);
+        w.encode(&self.bound_gv);
+        w.encode(&self.element_size);
+        w.encode(&self.index_type);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(TableData {
+            base_gv: r.decode()?,
+            min_size: r.decode()?,
+            bound_gv: r.decode()?,
+            element_size: r.decode()?,
+            index_type: r.decode()?,
+        })
+    }
+}
+
+impl Encode for JumpTableData {
+    fn encode(&self, w: &mut Writer) {
+        w.encode(&self.as_slice().to_vec());
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        let table: Vec<Ebb> = r.decode()?;
+        let mut data = JumpTableData::with_capacity(table.len());
+        for ebb in table {
+            data.push_entry(ebb);
+        }
+        Some(data)
+    }
+}
+
+impl Encode for ValueLabelAssignments {
+    fn encode(&self, w: &mut Writer) {
+        match *self {
+            ValueLabelAssignments::Starts(ref starts) => {
+                w.u8(0);
+                w.uleb(starts.len() as u64);
+                for start in starts {
+                    w.encode(&start.from);
+                    w.encode(&start.label);
+                }
+            }
+            ValueLabelAssignments::Alias { from, value } => {
+                w.u8(1);
+                w.encode(&from);
+                w.encode(&value);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        Some(match r.u8()? {
+            0 => {
+                let starts: Vec<(SourceLoc, ValueLabel)> = r.decode()?;
+                ValueLabelAssignments::Starts(
+                    starts
+                        .into_iter()
+                        .map(|(from, label)| ValueLabelStart { from, label })
+                        .collect(),
+                )
+            }
+            1 => ValueLabelAssignments::Alias {
+                from: r.decode()?,
+                value: r.decode()?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::ir::types;
+    use crate::ir::{InstBuilder, InstructionData};
+    use crate::isa;
+    use crate::settings::{self, Configurable};
+    use crate::Context;
+    use target_lexicon::triple;
+
+    fn roundtrip(func: &Function, isa: Option<&dyn isa::TargetIsa>) -> Function {
+        let data = serialize_function(func);
+        let copy = deserialize_function(&data).unwrap();
+        assert_eq!(copy.display(isa).to_string(), func.display(isa).to_string());
+        assert_eq!(serialize_function(&copy), data);
+        copy
+    }
+
+    fn function() -> Function {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        let mut func = Function::with_name_signature(ExternalName::testcase("binary"), sig);
+        let ss = func.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 8));
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let unused = func.dfg.make_ebb();
+        let x = func.dfg.append_ebb_param(ebb0, types::I32);
+        let y = func.dfg.append_ebb_param(ebb1, types::I32);
+        func.dfg.append_ebb_param(unused, types::F64);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        pos.ins().stack_store(x, ss, 4);
+        let c = pos.ins().iconst(types::I32, -7);
+        let f = pos.ins().f64const(Ieee64::with_float(1.5));
+        pos.ins().fdemote(types::F32, f);
+        let cmp = pos.ins().icmp(IntCC::SignedLessThan, x, c);
+        pos.ins().brnz(cmp, ebb1, &[c]);
+        pos.ins().jump(ebb1, &[x]);
+        pos.insert_ebb(ebb1);
+        pos.set_srcloc(SourceLoc::new(42));
+        let z = pos.ins().iadd_imm(y, 1);
+        pos.ins().return_(&[z]);
+        func.layout.set_cold(ebb1);
+        func
+    }
+
+    #[test]
+    fn leb128() {
+        let mut w = Writer(Vec::new());
+        let signed = [
+            0,
+            1,
+            -1,
+            63,
+            64,
+            -64,
+            -65,
+            i64::min_value(),
+            i64::max_value(),
+        ];
+        let unsigned = [0, 1, 127, 128, 1 << 35, u64::max_value()];
+        for &x in &signed {
+            w.sleb(x);
+        }
+        for &x in &unsigned {
+            w.uleb(x);
+        }
+        let mut r = Reader::new(&w.0);
+        for &x in &signed {
+            assert_eq!(r.sleb(), Some(x));
+        }
+        for &x in &unsigned {
+            assert_eq!(r.uleb(), Some(x));
+        }
+        assert!(r.0.is_empty());
+        assert_eq!(Reader::new(&[0x80]).uleb(), None);
+    }
+
+    #[test]
+    fn roundtrip_ir() {
+        let func = function();
+        let copy = roundtrip(&func, None);
+        let ebb1 = Ebb::new(1);
+        assert!(copy.layout.is_cold(ebb1));
+        assert_eq!(copy.dfg.num_ebbs(), 3);
+        assert_eq!(
+            copy.srclocs[copy.layout.first_inst(ebb1).unwrap()].bits(),
+            42
+        );
+    }
+
+    #[test]
+    fn roundtrip_struct_argument() {
+        let mut func = function();
+        let mut sig = Signature::new(CallConv::SystemV);
+        let purpose = ArgumentPurpose::StructArgument {
+            size: 16,
+            align: 8,
+            floats: 0b10,
+        };
+        sig.params.push(AbiParam::special(types::I64, purpose));
+        sig.returns.push(AbiParam::special(types::I64, purpose));
+        func.import_signature(sig);
+        let copy = roundtrip(&func, None);
+        assert_eq!(
+            copy.dfg.signatures[SigRef::new(0)].params[0].purpose,
+            purpose
+        );
+    }
+
+    #[test]
+    fn roundtrip_shared_stack_slots() {
+        let mut func = function();
+        let ss0 = StackSlot::new(0);
+        let ss1 = func.create_stack_slot(StackSlotData::new(StackSlotKind::SpillSlot, 4));
+        let ss2 = func.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 8));
+        func.stack_slots.share(ss2, ss0);
+        let copy = roundtrip(&func, None);
+        assert_eq!(copy.stack_slots.shared_with(ss0), None);
+        assert_eq!(copy.stack_slots.shared_with(ss1), None);
+        assert_eq!(copy.stack_slots.shared_with(ss2), Some(ss0));
+    }
+
+    #[test]
+    fn roundtrip_compiled() {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("opt_level", "speed").unwrap();
+        let isa = isa::lookup(triple!("x86_64"))
+            .unwrap()
+            .finish(settings::Flags::new(flag_builder));
+        let mut ctx = Context::for_function(function());
+        ctx.compile(&*isa).unwrap();
+        assert!(!ctx.func.offsets.is_empty());
+        let copy = roundtrip(&ctx.func, Some(&*isa));
+        assert_eq!(copy.offsets.values().len(), ctx.func.offsets.values().len());
+        assert_eq!(copy.stack_slots.frame_size, ctx.func.stack_slots.frame_size);
+    }
+
+    #[test]
+    fn errors() {
+        let data = serialize_function(&function());
+        assert_eq!(
+            deserialize_function(b"CLIFCODE").unwrap_err(),
+            DeserializeError::NotAFunction
+        );
+        let mut other = data.clone();
+        other[8] += 1;
+        assert_eq!(
+            deserialize_function(&other).unwrap_err(),
+            DeserializeError::Version(crate::VERSION.to_string(), FORMAT_VERSION + 1)
+        );
+        match deserialize_function(&data[..data.len() - 1]) {
+            Err(DeserializeError::Malformed(_)) => {}
+            _ => panic!("truncated data should be malformed"),
+        }
+        let mut longer = data.clone();
+        longer.push(0);
+        assert_eq!(
+            deserialize_function(&longer).unwrap_err(),
+            DeserializeError::Malformed(data.len())
+        );
+    }
+
+    #[test]
+    fn dangling_references() {
+        // Each function refers to an entity which doesn't exist.
+        let mut bad_arg = function();
+        let first = bad_arg.layout.first_inst(Ebb::new(0)).unwrap();
+        bad_arg.dfg.inst_args_mut(first)[0] = Value::new(100);
+
+        let mut bad_dest = function();
+        let last = bad_dest.layout.last_inst(Ebb::new(0)).unwrap();
+        bad_dest.change_branch_destination(last, Ebb::new(7));
+
+        let mut bad_slot = function();
+        bad_slot.dfg[first] = InstructionData::StackStore {
+            opcode: Opcode::StackStore,
+            arg: Value::new(0),
+            stack_slot: StackSlot::new(3),
+            offset: Offset32::new(4),
+        };
+
+        for func in &[bad_arg, bad_dest, bad_slot] {
+            let data = serialize_function(func);
+            assert_eq!(
+                deserialize_function(&data).unwrap_err(),
+                DeserializeError::Malformed(data.len())
+            );
+        }
+    }
+}
@@ -26,7 +26,7 @@ pub fn shrink_instructions(func: &mut Function, isa: &dyn TargetIsa) {
         for inst in func.layout.ebb_insts(ebb) {
             let enc = func.encodings[inst];
             if enc.is_legal() {
-                // regmove/regfill/regspill are special instructions with register immediates
+                // regmove/regswap/regfill/regspill are special instructions with register immediates
                 // that represented as normal operands, so the normal predicates below don't
                 // handle them correctly.
                 //
@@ -38,6 +38,7 @@ pub fn shrink_instructions(func: &mut Function, isa: &dyn TargetIsa) {
                 let inst_data = &func.dfg[inst];
                 match inst_data {
                     InstructionData::RegMove { .. }
+                    | InstructionData::RegSwap { .. }
                     | InstructionData::RegFill { .. }
                     | InstructionData::RegSpill { .. } => {
                         divert.apply(inst_data);
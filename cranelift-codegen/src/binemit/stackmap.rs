@@ -76,6 +76,18 @@ impl Stackmap {
         Self { bitmap }
     }
 
+    /// Get the words of the bitmap, for serialization.
+    pub(crate) fn words(&self) -> Vec<Num> {
+        self.bitmap.iter().map(|word| word.0).collect()
+    }
+
+    /// Create a stackmap from the words returned by `words`.
+    pub(crate) fn from_words(words: Vec<Num>) -> Self {
+        Self {
+            bitmap: words.into_iter().map(BitSet).collect(),
+        }
+    }
+
     /// Returns a specified bit.
     pub fn get_bit(&self, bit_index: usize) -> bool {
         assert!(bit_index < NUM_BITS * self.bitmap.len());
@@ -0,0 +1,214 @@
+//! Hot/cold block layout.
+//!
+//! This pass runs after register allocation and reorders the EBBs of a function so that:
+//!
+//! - the EBBs marked as cold are moved to the end of the function, keeping their relative order,
+//! - the hot EBBs are arranged in chains where each EBB is followed by the target of its final
+//!   jump, which can then become a fall-through in the branch relaxation pass.
+//!
+//! When the final jump of an EBB can't be turned into a fall-through because its target is cold
+//! or already placed, the conditional branch before it is inverted if its own target is hot, so
+//! the hot path falls through.
+
+use crate::cursor::{Cursor, FuncCursor};
+use crate::entity::{EntitySet, SecondaryMap};
+use crate::flowgraph::ControlFlowGraph;
+use crate::ir::condcodes::CondCode;
+use crate::ir::{Ebb, Function, Inst, InstBuilder, InstructionData, Opcode};
+use crate::isa::{EncInfo, TargetIsa};
+use crate::timing;
+use alloc::vec::Vec;
+
+/// Reorder the EBBs of `func` to move cold code out of the way and arrange fall-throughs for the
+/// hot code.
+pub fn do_block_layout(isa: &dyn TargetIsa, func: &mut Function, cfg: &mut ControlFlowGraph) {
+    let _tt = timing::block_layout();
+    let entry = match func.layout.entry_block() {
+        Some(entry) => entry,
+        None => return,
+    };
+
+    // An explicit `fallthrough` ties the layout of two EBBs together, and the EBB ending with a
+    // `fallthrough_return` must stay at the end of the function.
+    let mut last = None;
+    for ebb in func.layout.ebbs() {
+        match func.dfg[func.layout.last_inst(ebb).unwrap()].opcode() {
+            Opcode::Fallthrough => return,
+            Opcode::FallthroughReturn => last = Some(ebb),
+            _ => {}
+        }
+    }
+
+    let encinfo = isa.encoding_info();
+    let mut placed = EntitySet::new();
+    let mut order = Vec::new();
+    let ebbs: Vec<Ebb> = func.layout.ebbs().collect();
+    let is_hot = |func: &Function, placed: &EntitySet<Ebb>, ebb: Ebb| {
+        !func.layout.is_cold(ebb) && !placed.contains(ebb) && ebb != entry && Some(ebb) != last
+    };
+
+    for &start in &ebbs {
+        if placed.contains(start) || Some(start) == last {
+            continue;
+        }
+        if func.layout.is_cold(start) && start != entry {
+            continue;
+        }
+
+        // Follow the chain of final jumps from `start`.
+        let mut ebb = start;
+        loop {
+            placed.insert(ebb);
+            order.push(ebb);
+
+            let jump = func.layout.last_inst(ebb).unwrap();
+            let dest = match func.dfg[jump] {
+                InstructionData::Jump {
+                    opcode: Opcode::Jump,
+                    destination,
+                    ..
+                } => destination,
+                _ => break,
+            };
+            if is_hot(func, &placed, dest) {
+                ebb = dest;
+                continue;
+            }
+
+            let branch = match func.layout.prev_inst(jump) {
+                Some(branch) => branch,
+                None => break,
+            };
+            match func.dfg[branch].branch_destination() {
+                Some(branch_dest)
+                    if branch_dest != dest
+                        && is_hot(func, &placed, branch_dest)
+                        && invert_branch(isa, &encinfo, func, branch, jump) =>
+                {
+                    cfg.recompute_ebb(func, ebb);
+                    ebb = branch_dest;
+                }
+                _ => break,
+            }
+        }
+    }
+    order.extend(
+        ebbs.iter()
+            .cloned()
+            .filter(|&ebb| !placed.contains(ebb) && Some(ebb) != last),
+    );
+    order.extend(last);
+
+    if order == ebbs {
+        return;
+    }
+
+    let mut insts = SecondaryMap::<Ebb, Vec<Inst>>::new();
+    for &ebb in &ebbs {
+        insts[ebb] = func.layout.ebb_insts(ebb).collect();
+    }
+    let mut pos = FuncCursor::new(func);
+    for &ebb in &ebbs {
+        pos.goto_top(ebb);
+        while pos.next_inst().is_some() {
+            pos.remove_inst_and_step_back();
+        }
+        pos.func.layout.remove_ebb(ebb);
+    }
+    for ebb in order {
+        pos.func.layout.append_ebb(ebb);
+        for &inst in &insts[ebb] {
+            pos.func.layout.append_inst(inst, ebb);
+        }
+    }
+}
+
+/// Swap the destinations of the conditional `branch` and of the `jump` following it, inverting
+/// the condition of `branch`.
+///
+/// Returns `false` and leaves the instructions alone if there is no encoding for the inverted
+/// branch with the same operand constraints.
+fn invert_branch(
+    isa: &dyn TargetIsa,
+    encinfo: &EncInfo,
+    func: &mut Function,
+    branch: Inst,
+    jump: Inst,
+) -> bool {
+    let old_branch = func.dfg[branch].clone();
+    let old_jump = func.dfg[jump].clone();
+
+    let jump_dest = func.dfg[jump].branch_destination().unwrap();
+    let jump_args = func.dfg.inst_variable_args(jump).to_vec();
+    let branch_dest = func.dfg[branch].branch_destination().unwrap();
+    let branch_args = func.dfg.inst_variable_args(branch).to_vec();
+    let fixed = func.dfg.inst_fixed_args(branch).to_vec();
+
+    match old_branch {
+        InstructionData::Branch {
+            opcode: Opcode::Brz,
+            ..
+        } => {
+            func.dfg
+                .replace(branch)
+                .brnz(fixed[0], jump_dest, &jump_args);
+        }
+        InstructionData::Branch {
+            opcode: Opcode::Brnz,
+            ..
+        } => {
+            func.dfg
+                .replace(branch)
+                .brz(fixed[0], jump_dest, &jump_args);
+        }
+        InstructionData::BranchInt {
+            opcode: Opcode::Brif,
+            cond,
+            ..
+        } => {
+            func.dfg
+                .replace(branch)
+                .brif(cond.inverse(), fixed[0], jump_dest, &jump_args);
+        }
+        InstructionData::BranchFloat {
+            opcode: Opcode::Brff,
+            cond,
+            ..
+        } => {
+            func.dfg
+                .replace(branch)
+                .brff(cond.inverse(), fixed[0], jump_dest, &jump_args);
+        }
+        InstructionData::BranchIcmp {
+            opcode: Opcode::BrIcmp,
+            cond,
+            ..
+        } => {
+            func.dfg.replace(branch).br_icmp(
+                cond.inverse(),
+                fixed[0],
+                fixed[1],
+                jump_dest,
+                &jump_args,
+            );
+        }
+        _ => return false,
+    }
+
+    let ctrl_type = func.dfg.ctrl_typevar(branch);
+    match isa.encode(func, &func.dfg[branch], ctrl_type) {
+        Ok(enc)
+            if encinfo.operand_constraints(enc)
+                == encinfo.operand_constraints(func.encodings[branch]) =>
+        {
+            func.encodings[branch] = enc;
+            func.dfg.replace(jump).jump(branch_dest, &branch_args);
+            true
+        }
+        _ => {
+            func.dfg[branch] = old_branch;
+            func.dfg[jump] = old_jump;
+            false
+        }
+    }
+}
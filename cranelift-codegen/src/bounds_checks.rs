@@ -0,0 +1,407 @@
+//! Elimination of redundant heap bounds checks.
+//!
+//! Every `heap_addr` on a dynamic heap is legalized into a bounds check followed by the address
+//! computation. A check proves that `offset + size <= bound` at the point where it is executed,
+//! and that fact still holds further down the dominator tree as long as the bound can't change in
+//! between. The bound changes when a call resizes the heap, and a store can overwrite it when it is
+//! loaded from writable memory.
+//!
+//! This pass runs before legalization and:
+//!
+//! - hoists checks on loop-invariant offsets from the loop header to a pre-header that jumps
+//!   straight to it,
+//! - merges checks of the same offset in a block, by widening the first one,
+//! - expands the `heap_addr` instructions that are covered by a dominating check into the address
+//!   computation only.
+//!
+//! Offsets are tracked as a root value plus a constant, following `iadd_imm` chains. A check of
+//! `x` with size `S` covers an access to `x + d` with size `s` when `d + s <= S`.
+
+use crate::cursor::{Cursor, FuncCursor};
+use crate::dominator_tree::DominatorTree;
+use crate::entity::EntitySet;
+use crate::flowgraph::{BasicBlock, ControlFlowGraph};
+use crate::ir::immediates::Uimm32;
+use crate::ir::{
+    Ebb, Function, GlobalValue, GlobalValueData, Heap, HeapStyle, Inst, InstructionData, Opcode,
+    Value, ValueDef,
+};
+use crate::isa::TargetIsa;
+use crate::legalizer::expand_unchecked_heap_addr;
+use crate::loop_analysis::{Loop, LoopAnalysis};
+use crate::timing;
+use alloc::vec::Vec;
+
+/// Does `func` have any dynamic heaps, whose accesses are bounds checked?
+///
+/// The pass has nothing to do without them, so the analyses it needs can be skipped.
+pub fn has_dynamic_heaps(func: &Function) -> bool {
+    func.heaps.values().any(|heap| match heap.style {
+        HeapStyle::Dynamic { .. } => true,
+        HeapStyle::Static { .. } => false,
+    })
+}
+
+/// Removes, merges and hoists the bounds checks of dynamic heap accesses in `func`.
+pub fn do_bounds_check_elimination(
+    isa: &dyn TargetIsa,
+    func: &mut Function,
+    cfg: &ControlFlowGraph,
+    domtree: &DominatorTree,
+    loop_analysis: &LoopAnalysis,
+) {
+    let _tt = timing::bounds_checks();
+    debug_assert!(cfg.is_valid());
+    debug_assert!(domtree.is_valid());
+    debug_assert!(loop_analysis.is_valid());
+
+    let writable_bounds = has_writable_bounds(func);
+    for lp in loop_analysis.loops() {
+        hoist_invariant_checks(func, cfg, loop_analysis, lp, writable_bounds);
+    }
+
+    for inst in find_redundant_checks(func, cfg, domtree, writable_bounds) {
+        expand_unchecked_heap_addr(inst, func, isa);
+    }
+}
+
+/// A bounds check on a dynamic heap: `root + addend + size <= bound`, where the addition of
+/// `root` and `addend` wraps around like the offset computation itself.
+#[derive(Clone, Copy)]
+struct Check {
+    heap: Heap,
+    root: Value,
+    addend: u64,
+    size: u64,
+    inst: Inst,
+}
+
+impl Check {
+    /// Get the check performed by `inst`, if it is a `heap_addr` on a dynamic heap.
+    fn from_inst(func: &Function, inst: Inst) -> Option<Self> {
+        match func.dfg[inst] {
+            InstructionData::HeapAddr {
+                opcode: Opcode::HeapAddr,
+                heap,
+                arg,
+                imm,
+            } => match func.heaps[heap].style {
+                HeapStyle::Dynamic { .. } => {
+                    let (root, addend) = split_offset(func, arg);
+                    let size: u32 = imm.into();
+                    Some(Self {
+                        heap,
+                        root,
+                        addend,
+                        size: u64::from(size),
+                        inst,
+                    })
+                }
+                HeapStyle::Static { .. } => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// How far past the offset checked by `self` does `other` start, if the offsets have the same
+    /// root?
+    fn distance(&self, func: &Function, other: &Self) -> Option<u64> {
+        if self.heap != other.heap || self.root != other.root {
+            return None;
+        }
+        let bits = func.dfg.value_type(self.root).bits();
+        let mask = if bits >= 64 { !0 } else { (1 << bits) - 1 };
+        Some(other.addend.wrapping_sub(self.addend) & mask)
+    }
+
+    /// Does `self` prove that `other` is in bounds?
+    ///
+    /// Since `self` proves that its own offset plus `d + other.size` doesn't exceed the bound, the
+    /// offset of `other` can't have wrapped around either.
+    fn covers(&self, func: &Function, other: &Self) -> bool {
+        match self.distance(func, other) {
+            Some(d) => d <= self.size && other.size <= self.size - d,
+            None => false,
+        }
+    }
+}
+
+/// Split `offset` into a root value and a constant added to it with `iadd_imm`.
+fn split_offset(func: &Function, offset: Value) -> (Value, u64) {
+    let mut root = func.dfg.resolve_aliases(offset);
+    let mut addend = 0u64;
+    while let ValueDef::Result(inst, _) = func.dfg.value_def(root) {
+        match func.dfg[inst] {
+            InstructionData::BinaryImm {
+                opcode: Opcode::IaddImm,
+                arg,
+                imm,
+            } => {
+                let imm: i64 = imm.into();
+                addend = addend.wrapping_add(imm as u64);
+                root = func.dfg.resolve_aliases(arg);
+            }
+            _ => break,
+        }
+    }
+    (root, addend)
+}
+
+/// Is the bound of any dynamic heap in `func` loaded from memory that stores can write to?
+fn has_writable_bounds(func: &Function) -> bool {
+    func.heaps.values().any(|heap| match heap.style {
+        HeapStyle::Dynamic { bound_gv } => is_writable(func, bound_gv),
+        HeapStyle::Static { .. } => false,
+    })
+}
+
+/// Does the global value `gv` depend on a load from writable memory?
+fn is_writable(func: &Function, gv: GlobalValue) -> bool {
+    match func.global_values[gv] {
+        GlobalValueData::Load { readonly, .. } => !readonly,
+        GlobalValueData::IAddImm { base, .. } => is_writable(func, base),
+        GlobalValueData::VMContext | GlobalValueData::Symbol { .. } => false,
+    }
+}
+
+/// Can `inst` change the bound of a dynamic heap?
+///
+/// A call can resize a heap, and a store can overwrite a bound loaded from writable memory.
+fn changes_bounds(func: &Function, inst: Inst, writable_bounds: bool) -> bool {
+    let opcode = func.dfg[inst].opcode();
+    opcode.is_call() || (writable_bounds && opcode.can_store())
+}
+
+/// Can `inst` have effects that must not be reordered with a bounds check trap?
+fn has_side_effects(func: &Function, inst: Inst) -> bool {
+    let opcode = func.dfg[inst].opcode();
+    opcode.is_call()
+        || opcode.is_branch()
+        || opcode.is_terminator()
+        || opcode.is_return()
+        || opcode.can_trap()
+        || opcode.can_store()
+        || opcode.other_side_effects()
+}
+
+/// Move the checks at the top of the loop header whose offset is loop-invariant into the
+/// pre-header.
+///
+/// The pre-header must end in a `jump` to the header and have no other branches, so the checks
+/// are only executed on the paths entering the loop, at the same time as before. The bound can't
+/// change in the loop when it contains no calls, and no stores if a bound is loaded from writable
+/// memory.
+fn hoist_invariant_checks(
+    func: &mut Function,
+    cfg: &ControlFlowGraph,
+    loop_analysis: &LoopAnalysis,
+    lp: Loop,
+    writable_bounds: bool,
+) {
+    let header = loop_analysis.loop_header(lp);
+
+    // Find a pre-header which always jumps to the header.
+    let mut pre_header_branch = None;
+    for BasicBlock { ebb, inst } in cfg.pred_iter(header) {
+        if loop_analysis.is_in_loop(ebb, lp) {
+            continue;
+        }
+        if pre_header_branch.is_some()
+            || func.dfg[inst].opcode() != Opcode::Jump
+            || func
+                .layout
+                .ebb_insts(ebb)
+                .any(|other| other != inst && func.dfg[other].opcode().is_branch())
+            || func.layout.last_inst(ebb) != Some(inst)
+        {
+            return;
+        }
+        pre_header_branch = Some(inst);
+    }
+    let pre_header_branch = match pre_header_branch {
+        Some(inst) => inst,
+        None => return,
+    };
+
+    for ebb in func.layout.ebbs() {
+        if loop_analysis.is_in_loop(ebb, lp)
+            && func
+                .layout
+                .ebb_insts(ebb)
+                .any(|inst| changes_bounds(func, inst, writable_bounds))
+        {
+            return;
+        }
+    }
+
+    let is_invariant = |func: &Function, value: Value| match func.dfg.value_def(value) {
+        ValueDef::Result(inst, _) => {
+            !loop_analysis.is_in_loop(func.layout.inst_ebb(inst).unwrap(), lp)
+        }
+        ValueDef::Param(ebb, _) => !loop_analysis.is_in_loop(ebb, lp),
+    };
+
+    let mut pos = FuncCursor::new(func).at_top(header);
+    while let Some(inst) = pos.next_inst() {
+        if let Some(check) = Check::from_inst(pos.func, inst) {
+            let offset = pos
+                .func
+                .dfg
+                .resolve_aliases(pos.func.dfg.inst_args(inst)[0]);
+            if is_invariant(pos.func, offset) {
+                pos.remove_inst_and_step_back();
+                pos.func.layout.insert_inst(check.inst, pre_header_branch);
+                continue;
+            }
+        }
+        if has_side_effects(pos.func, inst) {
+            break;
+        }
+    }
+}
+
+/// Collect the `heap_addr` instructions covered by dominating checks, and merge the checks of the
+/// same offset within blocks.
+fn find_redundant_checks(
+    func: &mut Function,
+    cfg: &ControlFlowGraph,
+    domtree: &DominatorTree,
+    writable_bounds: bool,
+) -> Vec<Inst> {
+    let mut redundant = Vec::new();
+
+    // The EBBs containing instructions that may change the bounds.
+    let mut changing_ebbs = EntitySet::new();
+    for ebb in func.layout.ebbs() {
+        if func
+            .layout
+            .ebb_insts(ebb)
+            .any(|inst| changes_bounds(func, inst, writable_bounds))
+        {
+            changing_ebbs.insert(ebb);
+        }
+    }
+
+    // The checks seen so far in the dominating scopes. Only the checks from `valid_from` on are
+    // still known to hold, and the checks from `merge_from` on can be widened.
+    let mut checks: Vec<Check> = Vec::new();
+    let mut valid_from = 0;
+    let mut merge_from;
+
+    // A scope is entered at each EBB and after each branch in the middle of an EBB, like in the
+    // GVN pass. Each scope saves the state to restore when leaving it.
+    let mut scope_stack: Vec<(Inst, usize, usize)> = Vec::new();
+
+    for &ebb in domtree.cfg_postorder().iter().rev() {
+        while let Some(&(scope, len, saved_valid_from)) = scope_stack.last() {
+            if domtree.dominates(scope, ebb, &func.layout) {
+                break;
+            }
+            scope_stack.pop();
+            checks.truncate(len);
+            valid_from = saved_valid_from;
+        }
+        scope_stack.push((
+            func.layout.first_inst(ebb).unwrap(),
+            checks.len(),
+            valid_from,
+        ));
+        if !changing_ebbs.is_empty()
+            && !is_unchanged_from_idom(func, cfg, domtree, &changing_ebbs, writable_bounds, ebb)
+        {
+            valid_from = checks.len();
+        }
+        merge_from = checks.len();
+
+        let mut pos = FuncCursor::new(func).at_top(ebb);
+        while let Some(inst) = pos.next_inst() {
+            let opcode = pos.func.dfg[inst].opcode();
+            if let Some(check) = Check::from_inst(pos.func, inst) {
+                let func = &*pos.func;
+                if checks[valid_from..]
+                    .iter()
+                    .any(|prev| prev.covers(func, &check))
+                {
+                    redundant.push(inst);
+                    continue;
+                }
+
+                // Widen a check of the same offset earlier in the block, when nothing observable
+                // happens in between.
+                if let Some(prev) = checks[merge_from..]
+                    .iter_mut()
+                    .rev()
+                    .find(|prev| prev.distance(func, &check) == Some(0))
+                {
+                    debug_assert!(prev.size < check.size);
+                    prev.size = check.size;
+                    if let InstructionData::HeapAddr { ref mut imm, .. } = pos.func.dfg[prev.inst] {
+                        *imm = Uimm32::from(check.size as u32);
+                    }
+                    redundant.push(inst);
+                    continue;
+                }
+
+                // The new check may trap, so it is the only one that can be widened from now on.
+                checks.push(check);
+                merge_from = checks.len() - 1;
+                continue;
+            }
+
+            if changes_bounds(pos.func, inst, writable_bounds) {
+                valid_from = checks.len();
+            }
+            if has_side_effects(pos.func, inst) {
+                merge_from = checks.len();
+            }
+            if opcode.is_branch() && !opcode.is_terminator() {
+                let next = pos.func.layout.next_inst(inst).unwrap();
+                scope_stack.push((next, checks.len(), valid_from));
+            }
+        }
+    }
+
+    redundant
+}
+
+/// Check that the bounds can't change on the paths from the immediate dominator of `ebb` to `ebb`,
+/// excluding `ebb` itself unless it is part of a loop.
+fn is_unchanged_from_idom(
+    func: &Function,
+    cfg: &ControlFlowGraph,
+    domtree: &DominatorTree,
+    changing_ebbs: &EntitySet<Ebb>,
+    writable_bounds: bool,
+    ebb: Ebb,
+) -> bool {
+    let idom = match domtree.idom(ebb) {
+        Some(idom) => idom,
+        None => return true,
+    };
+    let idom_ebb = func.layout.inst_ebb(idom).unwrap();
+
+    let mut visited = EntitySet::new();
+    let mut worklist: Vec<Ebb> = cfg.pred_iter(ebb).map(|pred| pred.ebb).collect();
+    while let Some(pred) = worklist.pop() {
+        if !visited.insert(pred) {
+            continue;
+        }
+        if pred == idom_ebb {
+            // Only the part of the dominating EBB after the dominating branch matters here, the
+            // instructions before it have been taken into account while scanning the EBB.
+            let mut inst = idom;
+            while let Some(next) = func.layout.next_inst(inst) {
+                if changes_bounds(func, next, writable_bounds) {
+                    return false;
+                }
+                inst = next;
+            }
+            continue;
+        }
+        if changing_ebbs.contains(pred) {
+            return false;
+        }
+        worklist.extend(cfg.pred_iter(pred).map(|pred| pred.ebb));
+    }
+    true
+}
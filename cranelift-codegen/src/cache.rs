@@ -0,0 +1,559 @@
+//! Compilation cache.
+//!
+//! The code emitted for a function only depends on its IR, on the target ISA and on the settings.
+//! A `CacheKey` is a stable hash of all of these, which can be used to look up the code emitted by
+//! a previous compilation in a `CompilationCache`, instead of compiling the function again.
+//!
+//! The cached code is stored in a `CachedCode`, along with the relocations, traps and stackmaps
+//! emitted with it, so that emitting it replays them into the sinks like emitting the compiled
+//! function would. `Context::compile_cached` and `Context::compile_and_emit_cached` go through a
+//! cache.
+//!
+//! The key includes `CACHE_VERSION` and the version of this crate, so entries written by another
+//! version of Cranelift are never used.
+
+use crate::binary::{Encode, Reader, Writer};
+use crate::binemit::{
+    Addend, CodeInfo, CodeOffset, Reloc, RelocSink, Stackmap, StackmapSink, TrapSink,
+};
+use crate::entity::EntityRef;
+use crate::ir::{ConstantOffset, ExternalName, Function, JumpTable, SourceLoc, TrapCode};
+use crate::isa::TargetIsa;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::ptr;
+use core::str::FromStr;
+
+/// The version of the format of the cache keys and of the serialized `CachedCode`.
+///
+/// This must be bumped whenever either of them changes.
+pub const CACHE_VERSION: u32 = 2;
+
+/// The magic number at the start of a serialized `CachedCode`.
+const MAGIC: &[u8; 8] = b"CLIFCODE";
+
+/// A stable hash identifying the code emitted for a function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey([u8; 16]);
+
+impl CacheKey {
+    /// Compute the key of `func` compiled for `isa`, including all the settings.
+    pub fn new(func: &Function, isa: &dyn TargetIsa) -> Self {
+        let mut hasher = StableHasher::new();
+        // Writing to the hasher never fails.
+        let _ = write!(
+            hasher,
+            "cranelift-codegen {} cache {}\n{} {}\n{}\n{}",
+            env!("CARGO_PKG_VERSION"),
+            CACHE_VERSION,
+            isa.name(),
+            isa.triple(),
+            isa,
+            func.display(isa)
+        );
+        Self(hasher.finish().to_le_bytes())
+    }
+
+    /// Get the bytes of the key.
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// A 128-bit FNV-1a hasher, which gives the same result on every host and Rust version.
+struct StableHasher(u128);
+
+impl StableHasher {
+    const OFFSET_BASIS: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+    fn new() -> Self {
+        StableHasher(Self::OFFSET_BASIS)
+    }
+
+    fn finish(&self) -> u128 {
+        self.0
+    }
+}
+
+impl Write for StableHasher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.0 = (self.0 ^ u128::from(byte)).wrapping_mul(Self::PRIME);
+        }
+        Ok(())
+    }
+}
+
+/// A relocation recorded in a `CachedCode`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CachedReloc {
+    /// A relocation to an EBB, see `RelocSink::reloc_ebb`.
+    Ebb(CodeOffset, Reloc, CodeOffset),
+    /// A relocation to an external symbol, see `RelocSink::reloc_external`.
+    External(CodeOffset, Reloc, ExternalName, Addend),
+    /// A relocation to a constant, see `RelocSink::reloc_constant`.
+    Constant(CodeOffset, Reloc, ConstantOffset),
+    /// A relocation to a jump table, see `RelocSink::reloc_jt`.
+    JumpTable(CodeOffset, Reloc, JumpTable),
+}
+
+/// The code emitted for a function, with everything emitted to the sinks along with it.
+#[derive(Clone, Debug)]
+pub struct CachedCode {
+    /// The sizes of the code and read-only data.
+    pub info: CodeInfo,
+    /// The code and read-only data, before relocation.
+    pub code: Vec<u8>,
+    /// The relocations, in the order they were emitted.
+    pub relocs: Vec<CachedReloc>,
+    /// The traps, in the order they were emitted.
+    pub traps: Vec<(CodeOffset, SourceLoc, TrapCode)>,
+    /// The stackmaps, in the order they were emitted.
+    pub stackmaps: Vec<(CodeOffset, Stackmap)>,
+    /// The source locations of the code, see `Function::srcloc_changes`.
+    ///
+    /// Since the function isn't compiled on a cache hit, this is the only place to find them.
+    pub srclocs: Vec<(CodeOffset, SourceLoc)>,
+}
+
+impl CachedCode {
+    /// Create an empty `CachedCode`, to be filled by emitting a function into it.
+    pub(crate) fn new(info: CodeInfo) -> Self {
+        Self {
+            info,
+            code: vec![0; info.total_size as usize],
+            relocs: Vec::new(),
+            traps: Vec::new(),
+            stackmaps: Vec::new(),
+            srclocs: Vec::new(),
+        }
+    }
+
+    /// Emit the cached code into `mem`, and replay the relocations, traps and stackmaps into the
+    /// sinks.
+    ///
+    /// This function is unsafe since it does not perform bounds checking on the memory buffer,
+    /// and it can't guarantee that the `mem` pointer is valid.
+    pub unsafe fn emit_to_memory(
+        &self,
+        mem: *mut u8,
+        relocs: &mut dyn RelocSink,
+        traps: &mut dyn TrapSink,
+        stackmaps: &mut dyn StackmapSink,
+    ) -> CodeInfo {
+        ptr::copy_nonoverlapping(self.code.as_ptr(), mem, self.code.len());
+        for reloc in &self.relocs {
+            match *reloc {
+                CachedReloc::Ebb(offset, reloc, ebb_offset) => {
+                    relocs.reloc_ebb(offset, reloc, ebb_offset)
+                }
+                CachedReloc::External(offset, reloc, ref name, addend) => {
+                    relocs.reloc_external(offset, reloc, name, addend)
+                }
+                CachedReloc::Constant(offset, reloc, constant_offset) => {
+                    relocs.reloc_constant(offset, reloc, constant_offset)
+                }
+                CachedReloc::JumpTable(offset, reloc, jt) => relocs.reloc_jt(offset, reloc, jt),
+            }
+        }
+        for &(offset, srcloc, code) in &self.traps {
+            traps.trap(offset, srcloc, code);
+        }
+        for &(offset, ref stackmap) in &self.stackmaps {
+            stackmaps.add_stackmap(offset, stackmap.clone());
+        }
+        self.info
+    }
+
+    /// Serialize the cached code, for storage outside of the process.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::with_capacity(64 + self.code.len()));
+        w.bytes(MAGIC);
+        w.u32(CACHE_VERSION);
+        w.u32(self.info.code_size);
+        w.u32(self.info.jumptables_size);
+        w.u32(self.info.rodata_size);
+        w.u32(self.info.total_size);
+        w.u32(self.code.len() as u32);
+        w.bytes(&self.code);
+
+        w.u32(self.relocs.len() as u32);
+        for reloc in &self.relocs {
+            match *reloc {
+                CachedReloc::Ebb(offset, reloc, ebb_offset) => {
+                    w.u8(0);
+                    w.u32(offset);
+                    w.encode(&reloc);
+                    w.u32(ebb_offset);
+                }
+                CachedReloc::External(offset, reloc, ref name, addend) => {
+                    w.u8(1);
+                    w.u32(offset);
+                    w.encode(&reloc);
+                    w.name(name);
+                    w.u64(addend as u64);
+                }
+                CachedReloc::Constant(offset, reloc, constant_offset) => {
+                    w.u8(2);
+                    w.u32(offset);
+                    w.encode(&reloc);
+                    w.u32(constant_offset);
+                }
+                CachedReloc::JumpTable(offset, reloc, jt) => {
+                    w.u8(3);
+                    w.u32(offset);
+                    w.encode(&reloc);
+                    w.u32(jt.index() as u32);
+                }
+            }
+        }
+
+        w.u32(self.traps.len() as u32);
+        for &(offset, srcloc, code) in &self.traps {
+            w.u32(offset);
+            w.u32(srcloc.bits());
+            w.str(&code.to_string());
+        }
+
+        w.u32(self.stackmaps.len() as u32);
+        for &(offset, ref stackmap) in &self.stackmaps {
+            w.u32(offset);
+            let words = stackmap.words();
+            w.u32(words.len() as u32);
+            for word in words {
+                w.u32(word);
+            }
+        }
+
+        w.u32(self.srclocs.len() as u32);
+        for &(offset, srcloc) in &self.srclocs {
+            w.u32(offset);
+            w.u32(srcloc.bits());
+        }
+        w.0
+    }
+
+    /// Deserialize cached code produced by `serialize`.
+    ///
+    /// Returns `None` if the data is invalid or was serialized by another version.
+    pub fn deserialize(data: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(data);
+        if r.bytes(MAGIC.len())? != MAGIC || r.u32()? != CACHE_VERSION {
+            return None;
+        }
+        let info = CodeInfo {
+            code_size: r.u32()?,
+            jumptables_size: r.u32()?,
+            rodata_size: r.u32()?,
+            total_size: r.u32()?,
+        };
+        let len = r.u32()? as usize;
+        let code = r.bytes(len)?.to_vec();
+        if code.len() != info.total_size as usize {
+            return None;
+        }
+
+        let count = r.u32()?;
+        let mut relocs = Vec::new();
+        for _ in 0..count {
+            let reloc = match r.u8()? {
+                0 => CachedReloc::Ebb(r.u32()?, r.decode()?, r.u32()?),
+                1 => CachedReloc::External(r.u32()?, r.decode()?, r.name()?, r.u64()? as Addend),
+                2 => CachedReloc::Constant(r.u32()?, r.decode()?, r.u32()?),
+                3 => {
+                    CachedReloc::JumpTable(r.u32()?, r.decode()?, JumpTable::new(r.u32()? as usize))
+                }
+                _ => return None,
+            };
+            relocs.push(reloc);
+        }
+
+        let count = r.u32()?;
+        let mut traps = Vec::new();
+        for _ in 0..count {
+            let offset = r.u32()?;
+            let srcloc = SourceLoc::new(r.u32()?);
+            let code = TrapCode::from_str(&r.str()?).ok()?;
+            traps.push((offset, srcloc, code));
+        }
+
+        let count = r.u32()?;
+        let mut stackmaps = Vec::new();
+        for _ in 0..count {
+            let offset = r.u32()?;
+            let len = r.u32()?;
+            let mut words = Vec::new();
+            for _ in 0..len {
+                words.push(r.u32()?);
+            }
+            stackmaps.push((offset, Stackmap::from_words(words)));
+        }
+
+        let count = r.u32()?;
+        let mut srclocs = Vec::new();
+        for _ in 0..count {
+            srclocs.push((r.u32()?, SourceLoc::new(r.u32()?)));
+        }
+
+        if !r.0.is_empty() {
+            return None;
+        }
+        Some(Self {
+            info,
+            code,
+            relocs,
+            traps,
+            stackmaps,
+            srclocs,
+        })
+    }
+}
+
+/// A sink recording what is emitted along with the code into one of the lists of a `CachedCode`.
+pub(crate) struct Recorder<'a, T>(pub &'a mut Vec<T>);
+
+impl<'a> RelocSink for Recorder<'a, CachedReloc> {
+    fn reloc_ebb(&mut self, offset: CodeOffset, reloc: Reloc, ebb_offset: CodeOffset) {
+        self.0.push(CachedReloc::Ebb(offset, reloc, ebb_offset));
+    }
+
+    fn reloc_external(
+        &mut self,
+        offset: CodeOffset,
+        reloc: Reloc,
+        name: &ExternalName,
+        addend: Addend,
+    ) {
+        self.0
+            .push(CachedReloc::External(offset, reloc, name.clone(), addend));
+    }
+
+    fn reloc_constant(
+        &mut self,
+        offset: CodeOffset,
+        reloc: Reloc,
+        constant_offset: ConstantOffset,
+    ) {
+        self.0
+            .push(CachedReloc::Constant(offset, reloc, constant_offset));
+    }
+
+    fn reloc_jt(&mut self, offset: CodeOffset, reloc: Reloc, jt: JumpTable) {
+        self.0.push(CachedReloc::JumpTable(offset, reloc, jt));
+    }
+}
+
+impl<'a> TrapSink for Recorder<'a, (CodeOffset, SourceLoc, TrapCode)> {
+    fn trap(&mut self, offset: CodeOffset, srcloc: SourceLoc, code: TrapCode) {
+        self.0.push((offset, srcloc, code));
+    }
+}
+
+impl<'a> StackmapSink for Recorder<'a, (CodeOffset, Stackmap)> {
+    fn add_stackmap(&mut self, offset: CodeOffset, stackmap: Stackmap) {
+        self.0.push((offset, stackmap));
+    }
+}
+
+/// Every relocation kind, indexed by its serialized value.
+const RELOCS: [Reloc; 10] = [
+    Reloc::Abs4,
+    Reloc::Abs8,
+    Reloc::X86PCRel4,
+    Reloc::X86PCRelRodata4,
+    Reloc::X86CallPCRel4,
+    Reloc::X86CallPLTRel4,
+    Reloc::X86GOTPCRel4,
+    Reloc::Arm32Call,
+    Reloc::Arm64Call,
+    Reloc::RiscvCall,
+];
+
+impl Encode for Reloc {
+    fn encode(&self, w: &mut Writer) {
+        let index = RELOCS.iter().position(|r| r == self).unwrap();
+        w.u8(index as u8);
+    }
+
+    fn decode(r: &mut Reader) -> Option<Self> {
+        RELOCS.get(r.u8()? as usize).cloned()
+    }
+}
+
+/// A cache of the code emitted for functions.
+pub trait CompilationCache {
+    /// Look up the code cached for `key`.
+    fn get(&mut self, key: &CacheKey) -> Option<CachedCode>;
+
+    /// Store the code emitted for `key`.
+    ///
+    /// Caches may drop entries at any time, so this can't fail.
+    fn insert(&mut self, key: &CacheKey, code: &CachedCode);
+}
+
+/// A `CompilationCache` storing each entry in a file of a directory.
+///
+/// The entries are written atomically, so a directory can be shared by several processes.
+#[cfg(feature = "std")]
+pub struct DiskCache {
+    dir: std::path::PathBuf,
+}
+
+#[cfg(feature = "std")]
+impl DiskCache {
+    /// Create a cache in the directory `dir`, which is created if needed.
+    pub fn new<P: Into<std::path::PathBuf>>(dir: P) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+}
+
+#[cfg(feature = "std")]
+impl CompilationCache for DiskCache {
+    fn get(&mut self, key: &CacheKey) -> Option<CachedCode> {
+        let data = std::fs::read(self.dir.join(key.to_string())).ok()?;
+        CachedCode::deserialize(&data)
+    }
+
+    fn insert(&mut self, key: &CacheKey, code: &CachedCode) {
+        let path = self.dir.join(key.to_string());
+        let tmp = self.dir.join(format!("{}.{}.tmp", key, std::process::id()));
+        if std::fs::write(&tmp, code.serialize()).is_err() || std::fs::rename(&tmp, path).is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::ir::{types, AbiParam, InstBuilder, LibCall, Signature};
+    use crate::isa::{lookup, CallConv};
+    use crate::settings::{self, Configurable};
+    use crate::Context;
+    use core::str::FromStr;
+    use target_lexicon::triple;
+
+    struct MapCache(crate::HashMap<CacheKey, CachedCode>, usize);
+
+    impl CompilationCache for MapCache {
+        fn get(&mut self, key: &CacheKey) -> Option<CachedCode> {
+            self.0.get(key).cloned()
+        }
+
+        fn insert(&mut self, key: &CacheKey, code: &CachedCode) {
+            self.1 += 1;
+            self.0.insert(*key, code.clone());
+        }
+    }
+
+    fn isa(opt_level: &str) -> alloc::boxed::Box<dyn TargetIsa> {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("opt_level", opt_level).unwrap();
+        lookup(triple!("x86_64"))
+            .unwrap()
+            .finish(settings::Flags::new(flag_builder))
+    }
+
+    fn function(addend: i64) -> Function {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I32));
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        let mut func = Function::with_name_signature(ExternalName::user(0, 0), sig);
+        let ebb = func.dfg.make_ebb();
+        let x = func.dfg.append_ebb_param(ebb, types::I32);
+        let y = func.dfg.append_ebb_param(ebb, types::I32);
+        let signature = func.import_signature(Signature::new(CallConv::SystemV));
+        let callee = func.import_function(crate::ir::ExtFuncData {
+            name: ExternalName::LibCall(LibCall::Probestack),
+            signature,
+            colocated: false,
+        });
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb);
+        pos.set_srcloc(SourceLoc::new(3));
+        let q = pos.ins().udiv(x, y);
+        let v = pos.ins().iadd_imm(q, addend);
+        pos.ins().call(callee, &[]);
+        pos.ins().return_(&[v]);
+        func
+    }
+
+    /// Compile `func` through `cache`, returning the code and what was emitted with it.
+    fn compile(
+        func: Function,
+        isa: &dyn TargetIsa,
+        cache: &mut dyn CompilationCache,
+    ) -> (Vec<u8>, CachedCode) {
+        let mut emitted = CachedCode::new(CodeInfo {
+            code_size: 0,
+            jumptables_size: 0,
+            rodata_size: 0,
+            total_size: 0,
+        });
+        let mut code = Vec::new();
+        let mut ctx = Context::for_function(func);
+        ctx.compile_and_emit_cached(
+            isa,
+            &mut code,
+            &mut Recorder(&mut emitted.relocs),
+            &mut Recorder(&mut emitted.traps),
+            &mut Recorder(&mut emitted.stackmaps),
+            cache,
+        )
+        .unwrap();
+        (code, emitted)
+    }
+
+    #[test]
+    fn stable_keys() {
+        let isa = isa("speed");
+        let key = CacheKey::new(&function(3), &*isa);
+        assert_eq!(key, CacheKey::new(&function(3), &*isa));
+        assert_ne!(key, CacheKey::new(&function(5), &*isa));
+        assert_ne!(key, CacheKey::new(&function(3), &*self::isa("none")));
+        assert_eq!(key.to_string().len(), 32);
+    }
+
+    #[test]
+    fn cached_compilation() {
+        let isa = isa("speed");
+        let mut cache = MapCache(crate::HashMap::new(), 0);
+
+        let (expected, emitted) = compile(function(7), &*isa, &mut cache);
+        assert_eq!(cache.1, 1);
+        assert!(!emitted.relocs.is_empty());
+        assert!(!emitted.traps.is_empty());
+
+        // The second compilation is a cache hit, emitting the same code and relocations.
+        let (code, replayed) = compile(function(7), &*isa, &mut cache);
+        assert_eq!(cache.1, 1);
+        assert_eq!(code, expected);
+        assert_eq!(replayed.relocs, emitted.relocs);
+        assert_eq!(replayed.traps, emitted.traps);
+
+        // The entries survive serialization.
+        let cached = cache.0.values().next().unwrap();
+        let copy = CachedCode::deserialize(&cached.serialize()).unwrap();
+        assert_eq!(copy.code, cached.code);
+        assert_eq!(copy.relocs, cached.relocs);
+        assert_eq!(copy.traps, cached.traps);
+        assert_eq!(copy.srclocs, cached.srclocs);
+        assert_eq!(cached.srclocs[0].1, SourceLoc::new(3));
+        assert!(CachedCode::deserialize(&cached.serialize()[1..]).is_none());
+    }
+}
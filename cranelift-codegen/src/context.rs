@@ -13,6 +13,9 @@ use crate::binemit::{
     relax_branches, shrink_instructions, CodeInfo, MemoryCodeSink, RelocSink, StackmapSink,
     TrapSink,
 };
+use crate::block_layout::do_block_layout;
+use crate::bounds_checks::{do_bounds_check_elimination, has_dynamic_heaps};
+use crate::cache::{CacheKey, CachedCode, CompilationCache, Recorder};
 use crate::dce::do_dce;
 use crate::dominator_tree::DominatorTree;
 use crate::flowgraph::ControlFlowGraph;
@@ -21,14 +24,17 @@ use crate::isa::TargetIsa;
 use crate::legalize_function;
 use crate::licm::do_licm;
 use crate::loop_analysis::LoopAnalysis;
+use crate::loop_unrolling::do_loop_unrolling;
 use crate::nan_canonicalization::do_nan_canonicalization;
 use crate::postopt::do_postopt;
 use crate::redundant_reload_remover::RedundantReloadRemover;
 use crate::regalloc;
 use crate::result::CodegenResult;
+use crate::scheduling::do_scheduling;
 use crate::settings::{FlagsOrIsa, OptLevel};
 use crate::simple_gvn::do_simple_gvn;
 use crate::simple_preopt::do_preopt;
+use crate::stack_coloring::color_stack_slots;
 use crate::timing;
 use crate::unreachable_code::eliminate_unreachable_code;
 use crate::value_label::{build_value_labels_ranges, ComparableSourceLoc, ValueLabelsRanges};
@@ -55,6 +61,9 @@ pub struct Context {
 
     /// Redundant-reload remover context.
     pub redundant_reload_remover: RedundantReloadRemover,
+
+    /// The code emitted for `func`, when it comes from a compilation cache.
+    cached_code: Option<CachedCode>,
 }
 
 impl Context {
@@ -78,6 +87,7 @@ impl Context {
             regalloc: regalloc::Context::new(),
             loop_analysis: LoopAnalysis::new(),
             redundant_reload_remover: RedundantReloadRemover::new(),
+            cached_code: None,
         }
     }
 
@@ -89,6 +99,7 @@ impl Context {
         self.regalloc.clear();
         self.loop_analysis.clear();
         self.redundant_reload_remover.clear();
+        self.cached_code = None;
     }
 
     /// Compile the function, and emit machine code into a `Vec<u8>`.
@@ -120,6 +131,100 @@ impl Context {
         Ok(info)
     }
 
+    /// Compile the function through a compilation cache, and emit machine code into a `Vec<u8>`.
+    ///
+    /// This is like `compile_and_emit`, except that the code is taken from `cache` when it has
+    /// already been compiled, see `compile_cached`.
+    pub fn compile_and_emit_cached(
+        &mut self,
+        isa: &dyn TargetIsa,
+        mem: &mut Vec<u8>,
+        relocs: &mut dyn RelocSink,
+        traps: &mut dyn TrapSink,
+        stackmaps: &mut dyn StackmapSink,
+        cache: &mut dyn CompilationCache,
+    ) -> CodegenResult<CodeInfo> {
+        let info = self.compile_cached(isa, cache)?;
+        let old_len = mem.len();
+        mem.resize(old_len + info.total_size as usize, 0);
+        unsafe {
+            self.emit_to_memory(isa, mem.as_mut_ptr().add(old_len), relocs, traps, stackmaps)
+        };
+        Ok(info)
+    }
+
+    /// Compile the function through a compilation cache.
+    ///
+    /// When `cache` has an entry for the function and `isa`, the function isn't compiled, and the
+    /// cached code is emitted by `emit_to_memory` instead. Otherwise, the function is compiled and
+    /// the emitted code is stored in `cache`.
+    ///
+    /// On a cache hit, `func` is left as it was before compilation: it has no encodings, value
+    /// locations or code offsets. Callers must not inspect it for the results of the compilation,
+    /// and should use the `CachedCode` returned by `cached_code` instead.
+    ///
+    /// Returns information about the function's code and read-only data.
+    pub fn compile_cached(
+        &mut self,
+        isa: &dyn TargetIsa,
+        cache: &mut dyn CompilationCache,
+    ) -> CodegenResult<CodeInfo> {
+        let key = CacheKey::new(&self.func, isa);
+        if let Some(code) = cache.get(&key) {
+            let info = code.info;
+            self.cached_code = Some(code);
+            return Ok(info);
+        }
+        let info = self.compile(isa)?;
+        self.record_cached_code(isa, info);
+        cache.insert(&key, self.cached_code.as_ref().unwrap());
+        Ok(info)
+    }
+
+    /// Get the code emitted for the function when it comes from a compilation cache, or has been
+    /// recorded for one.
+    pub fn cached_code(&self) -> Option<&CachedCode> {
+        self.cached_code.as_ref()
+    }
+
+    /// Use `code`, taken from a compilation cache, as the code emitted for the function.
+    ///
+    /// The function is not compiled, so like after a cache hit in `compile_cached`, `func` must
+    /// not be inspected for the results of the compilation.
+    pub fn set_cached_code(&mut self, code: CachedCode) {
+        self.cached_code = Some(code);
+    }
+
+    /// Emit the compiled function into a `CachedCode`, so `cached_code` can be stored in a
+    /// compilation cache.
+    pub fn record_cached_code(&mut self, isa: &dyn TargetIsa, info: CodeInfo) {
+        let mut code = CachedCode::new(info);
+        {
+            let CachedCode {
+                code: ref mut bytes,
+                ref mut relocs,
+                ref mut traps,
+                ref mut stackmaps,
+                ..
+            } = code;
+            let mut reloc_sink = Recorder(relocs);
+            let mut trap_sink = Recorder(traps);
+            let mut stackmap_sink = Recorder(stackmaps);
+            let mut sink = unsafe {
+                MemoryCodeSink::new(
+                    bytes.as_mut_ptr(),
+                    &mut reloc_sink,
+                    &mut trap_sink,
+                    &mut stackmap_sink,
+                )
+            };
+            isa.emit_function_to_memory(&self.func, &mut sink);
+            debug_assert!(sink.info == info);
+        }
+        code.srclocs = self.func.srcloc_changes(&isa.encoding_info());
+        self.cached_code = Some(code);
+    }
+
     /// Compile the function.
     ///
     /// Run the function through all the passes necessary to generate code for the target ISA
@@ -129,6 +234,7 @@ impl Context {
     /// Returns information about the function's code and read-only data.
     pub fn compile(&mut self, isa: &dyn TargetIsa) -> CodegenResult<CodeInfo> {
         let _tt = timing::compile();
+        self.cached_code = None;
         self.verify_if(isa)?;
         debug!("Compiling:\n{}", self.func.display(isa));
 
@@ -138,6 +244,16 @@ impl Context {
         if opt_level != OptLevel::None {
             self.preopt(isa)?;
         }
+        if opt_level == OptLevel::SpeedAndSize {
+            self.compute_domtree();
+            self.compute_loop_analysis();
+            self.unroll_loops(isa)?;
+        }
+        if opt_level != OptLevel::None && has_dynamic_heaps(&self.func) {
+            self.compute_domtree();
+            self.compute_loop_analysis();
+            self.eliminate_bounds_checks(isa)?;
+        }
         if isa.flags().enable_nan_canonicalization() {
             self.canonicalize_nans(isa)?;
         }
@@ -153,8 +269,12 @@ impl Context {
         self.eliminate_unreachable_code(isa)?;
         if opt_level != OptLevel::None {
             self.dce(isa)?;
+            self.schedule(isa)?;
         }
         self.regalloc(isa)?;
+        if opt_level != OptLevel::None {
+            self.color_stack_slots(isa)?;
+        }
         self.prologue_epilogue(isa)?;
         if opt_level == OptLevel::Speed || opt_level == OptLevel::SpeedAndSize {
             self.redundant_reload_remover(isa)?;
@@ -162,6 +282,9 @@ impl Context {
         if opt_level == OptLevel::SpeedAndSize {
             self.shrink_instructions(isa)?;
         }
+        if opt_level != OptLevel::None {
+            self.block_layout(isa)?;
+        }
         let result = self.relax_branches(isa);
 
         debug!("Compiled:\n{}", self.func.display(isa));
@@ -175,6 +298,9 @@ impl Context {
     ///
     /// The machine code is not relocated. Instead, any relocations are emitted into `relocs`.
     ///
+    /// When the code comes from a compilation cache, it is copied and the relocations, traps and
+    /// stackmaps recorded with it are replayed into the sinks.
+    ///
     /// This function is unsafe since it does not perform bounds checking on the memory buffer,
     /// and it can't guarantee that the `mem` pointer is valid.
     ///
@@ -187,6 +313,9 @@ impl Context {
         traps: &mut dyn TrapSink,
         stackmaps: &mut dyn StackmapSink,
     ) -> CodeInfo {
+        if let Some(ref code) = self.cached_code {
+            return code.emit_to_memory(mem, relocs, traps, stackmaps);
+        }
         let _tt = timing::binemit();
         let mut sink = MemoryCodeSink::new(mem, relocs, traps, stackmaps);
         isa.emit_function_to_memory(&self.func, &mut sink);
@@ -262,7 +391,7 @@ impl Context {
         // TODO: Avoid doing this when legalization doesn't actually mutate the CFG.
         self.domtree.clear();
         self.loop_analysis.clear();
-        legalize_function(&mut self.func, &mut self.cfg, isa);
+        legalize_function(&mut self.func, &mut self.cfg, isa)?;
         debug!("Legalized:\n{}", self.func.display(isa));
         self.verify_if(isa)
     }
@@ -314,6 +443,35 @@ impl Context {
         self.verify_if(isa)
     }
 
+    /// Perform induction variable strength reduction and loop unrolling on the function.
+    pub fn unroll_loops<'a, FOI: Into<FlagsOrIsa<'a>>>(&mut self, fisa: FOI) -> CodegenResult<()> {
+        do_loop_unrolling(
+            &mut self.func,
+            &mut self.cfg,
+            &mut self.domtree,
+            &mut self.loop_analysis,
+        );
+        self.verify_if(fisa)
+    }
+
+    /// Eliminate redundant bounds checks on dynamic heaps.
+    pub fn eliminate_bounds_checks(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
+        do_bounds_check_elimination(
+            isa,
+            &mut self.func,
+            &self.cfg,
+            &self.domtree,
+            &self.loop_analysis,
+        );
+        self.verify_if(isa)
+    }
+
+    /// Reorder the instructions in each EBB to reduce stalls.
+    pub fn schedule(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
+        do_scheduling(isa, &mut self.func);
+        self.verify_if(isa)
+    }
+
     /// Perform unreachable code elimination.
     pub fn eliminate_unreachable_code<'a, FOI>(&mut self, fisa: FOI) -> CodegenResult<()>
     where
@@ -329,9 +487,21 @@ impl Context {
             .run(isa, &mut self.func, &mut self.cfg, &mut self.domtree)
     }
 
+    /// Let stack slots with disjoint lifetimes share memory.
+    pub fn color_stack_slots(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
+        color_stack_slots(&mut self.func);
+        self.verify_if(isa)?;
+        Ok(())
+    }
+
     /// Insert prologue and epilogues after computing the stack frame layout.
     pub fn prologue_epilogue(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
         isa.prologue_epilogue(&mut self.func)?;
+        if isa.flags().enable_shrink_wrapping() {
+            // Shrink-wrapping may have duplicated EBBs.
+            self.compute_cfg();
+            self.compute_domtree();
+        }
         self.verify_if(isa)?;
         self.verify_locations_if(isa)?;
         Ok(())
@@ -353,6 +523,14 @@ impl Context {
         Ok(())
     }
 
+    /// Move the cold EBBs to the end of the function and arrange fall-throughs for the hot EBBs.
+    pub fn block_layout(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
+        do_block_layout(isa, &mut self.func, &mut self.cfg);
+        self.compute_domtree();
+        self.verify_if(isa)?;
+        self.verify_locations_if(isa)
+    }
+
     /// Run the branch relaxation pass and return information about the function's code and
     /// read-only data.
     pub fn relax_branches(&mut self, isa: &dyn TargetIsa) -> CodegenResult<CodeInfo> {
@@ -0,0 +1,682 @@
+//! Function inlining.
+//!
+//! The inliner splices the body of a callee function into a caller at the site of a direct `call`
+//! instruction. Both functions are expected to be in their pre-legalization form, as they would be
+//! when produced by a front-end: the callee can't have any value locations, encodings or
+//! ABI-specific stack slots yet.
+//!
+//! Inlining a call site proceeds as follows:
+//!
+//! 1. The EBB containing the call is split right after the call, and the continuation EBB takes
+//!    the call results as EBB parameters.
+//! 2. The global values, heaps, tables, signatures, external functions, stack slots and jump
+//!    tables referenced by the callee are copied into the caller.
+//! 3. The instructions of the callee's entry block replace the call instruction, with the entry
+//!    block parameters mapped to the call arguments. The remaining callee EBBs are copied in
+//!    layout order between the calling EBB and the continuation EBB.
+//! 4. Every `return` in the callee becomes a `jump` to the continuation EBB.
+//!
+//! Which call sites get inlined is decided by an `InlineHeuristic`. The `inline_calls` driver
+//! applies a heuristic to all the direct calls in a function, including the calls that were
+//! brought in by inlining other functions.
+
+use crate::entity::SecondaryMap;
+use crate::ir::{
+    AbiParam, ArgumentPurpose, Ebb, ExtFuncData, ExternalName, FuncRef, Function, GlobalValue,
+    GlobalValueData, Heap, HeapData, HeapStyle, Inst, InstructionData, JumpTable, JumpTableData,
+    Opcode, SigRef, StackSlot, StackSlotData, StackSlotKind, Table, TableData, Value, ValueList,
+};
+use crate::timing;
+use alloc::vec::Vec;
+use log::debug;
+
+/// Decides which call sites get inlined by `inline_calls`.
+pub trait InlineHeuristic {
+    /// Should the direct call `call` in `caller` to `callee` be inlined?
+    ///
+    /// The `caller_size` is the current number of instructions in `caller`, as counted by
+    /// `function_size`. The `depth` is 0 for calls that were present in the original function,
+    /// and is incremented for each level of inlining a call site went through.
+    fn should_inline(
+        &self,
+        caller: &Function,
+        caller_size: usize,
+        call: Inst,
+        callee: &Function,
+        depth: usize,
+    ) -> bool;
+}
+
+/// An inlining heuristic based on the number of instructions in the functions involved.
+#[derive(Clone, Copy, Debug)]
+pub struct SizeHeuristic {
+    /// Callees with more than this number of instructions are never inlined.
+    pub max_callee_size: usize,
+
+    /// Don't inline anything that would make the caller grow past this number of instructions.
+    pub max_caller_size: usize,
+
+    /// Maximum nesting depth of inlined call sites.
+    pub max_depth: usize,
+}
+
+impl Default for SizeHeuristic {
+    fn default() -> Self {
+        Self {
+            max_callee_size: 30,
+            max_caller_size: 2000,
+            max_depth: 3,
+        }
+    }
+}
+
+impl InlineHeuristic for SizeHeuristic {
+    fn should_inline(
+        &self,
+        _caller: &Function,
+        caller_size: usize,
+        _call: Inst,
+        callee: &Function,
+        depth: usize,
+    ) -> bool {
+        let callee_size = function_size(callee);
+        depth < self.max_depth
+            && callee_size <= self.max_callee_size
+            && caller_size + callee_size <= self.max_caller_size
+    }
+}
+
+/// Count the number of instructions in the layout of `func`.
+pub fn function_size(func: &Function) -> usize {
+    func.layout
+        .ebbs()
+        .map(|ebb| func.layout.ebb_insts(ebb).count())
+        .sum()
+}
+
+/// Inline the direct calls in `func` selected by `heuristic`.
+///
+/// The `lookup` function maps the external name of a called function to its body, if it is
+/// available for inlining. Calls to `func` itself are never inlined.
+///
+/// Returns the number of inlined call sites.
+pub fn inline_calls<'a, F>(
+    func: &mut Function,
+    mut lookup: F,
+    heuristic: &dyn InlineHeuristic,
+) -> usize
+where
+    F: FnMut(&ExternalName) -> Option<&'a Function>,
+{
+    let _tt = timing::inline();
+
+    // Work list of call sites with their inlining depth, in layout order.
+    let mut worklist = Vec::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            if func.dfg[inst].opcode() == Opcode::Call {
+                worklist.push((inst, 0));
+            }
+        }
+    }
+    worklist.reverse();
+
+    // Splicing a callee replaces the call with one instruction per callee instruction, so the
+    // caller size can be kept up to date without counting its instructions again.
+    let mut caller_size = function_size(func);
+    let mut inlined = 0;
+    while let Some((call, depth)) = worklist.pop() {
+        let func_ref = match func.dfg[call] {
+            InstructionData::Call { func_ref, .. } => func_ref,
+            _ => panic!("{} is not a direct call", call),
+        };
+        let name = &func.dfg.ext_funcs[func_ref].name;
+        if *name == func.name {
+            continue;
+        }
+        let callee = match lookup(name) {
+            Some(callee) => callee,
+            None => continue,
+        };
+        if !heuristic.should_inline(func, caller_size, call, callee, depth) {
+            continue;
+        }
+        if let Some(new_calls) = splice_callee(func, call, callee) {
+            caller_size = caller_size + function_size(callee) - 1;
+            inlined += 1;
+            worklist.extend(new_calls.into_iter().rev().map(|inst| (inst, depth + 1)));
+        }
+    }
+    inlined
+}
+
+/// Inline `callee` at the direct call instruction `call` in `func`.
+///
+/// Returns `false` and leaves `func` unchanged if `callee` can't be inlined at this call site.
+pub fn inline_call(func: &mut Function, call: Inst, callee: &Function) -> bool {
+    let _tt = timing::inline();
+    splice_callee(func, call, callee).is_some()
+}
+
+/// Check if `callee` can be inlined at `call` in `func`.
+fn can_inline(func: &Function, call: Inst, callee: &Function) -> bool {
+    let func_ref = match func.dfg[call] {
+        InstructionData::Call { func_ref, .. } => func_ref,
+        _ => return false,
+    };
+    let entry = match callee.layout.entry_block() {
+        Some(ebb) => ebb,
+        None => return false,
+    };
+
+    // The call signature must agree with the callee's definition.
+    let sig = &func.dfg.signatures[func.dfg.ext_funcs[func_ref].signature];
+    if !same_types(&sig.params, &callee.signature.params)
+        || !same_types(&sig.returns, &callee.signature.returns)
+        || callee.dfg.num_ebb_params(entry) != callee.signature.params.len()
+    {
+        return false;
+    }
+
+    // Legalized functions have ABI-specific parameters and stack slots that only make sense in
+    // their own frame.
+    let abi_param = |p: &AbiParam| match p.purpose {
+        ArgumentPurpose::Normal | ArgumentPurpose::StructReturn | ArgumentPurpose::VMContext => {
+            false
+        }
+        _ => true,
+    };
+    if callee.signature.params.iter().any(abi_param)
+        || callee.signature.returns.iter().any(abi_param)
+        || callee
+            .stack_slots
+            .values()
+            .any(|ss| ss.kind != StackSlotKind::ExplicitSlot)
+    {
+        return false;
+    }
+
+    // A `vmctx` global value in the callee refers to its own `vmctx` parameter. That only works
+    // in the caller if the call passes the caller's `vmctx` through.
+    if callee.global_values.values().any(is_vmctx) {
+        let callee_vmctx = callee
+            .signature
+            .special_param_index(ArgumentPurpose::VMContext);
+        let caller_vmctx = func.special_param(ArgumentPurpose::VMContext);
+        match (callee_vmctx, caller_vmctx) {
+            (Some(idx), Some(vmctx)) => {
+                let arg = func.dfg.inst_args(call)[idx];
+                if func.dfg.resolve_aliases(arg) != vmctx {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// Do `a` and `b` have the same value types?
+fn same_types(a: &[AbiParam], b: &[AbiParam]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.value_type == y.value_type)
+}
+
+fn is_vmctx(data: &GlobalValueData) -> bool {
+    match *data {
+        GlobalValueData::VMContext => true,
+        _ => false,
+    }
+}
+
+/// Mapping from callee entities to the caller entities they were copied into.
+struct EntityMap {
+    values: SecondaryMap<Value, Option<Value>>,
+    ebbs: SecondaryMap<Ebb, Option<Ebb>>,
+    global_values: SecondaryMap<GlobalValue, Option<GlobalValue>>,
+    heaps: SecondaryMap<Heap, Option<Heap>>,
+    tables: SecondaryMap<Table, Option<Table>>,
+    sigs: SecondaryMap<SigRef, Option<SigRef>>,
+    func_refs: SecondaryMap<FuncRef, Option<FuncRef>>,
+    stack_slots: SecondaryMap<StackSlot, Option<StackSlot>>,
+    jump_tables: SecondaryMap<JumpTable, Option<JumpTable>>,
+}
+
+impl EntityMap {
+    fn new() -> Self {
+        Self {
+            values: SecondaryMap::new(),
+            ebbs: SecondaryMap::new(),
+            global_values: SecondaryMap::new(),
+            heaps: SecondaryMap::new(),
+            tables: SecondaryMap::new(),
+            sigs: SecondaryMap::new(),
+            func_refs: SecondaryMap::new(),
+            stack_slots: SecondaryMap::new(),
+            jump_tables: SecondaryMap::new(),
+        }
+    }
+
+    fn value(&self, callee: &Function, v: Value) -> Value {
+        let v = callee.dfg.resolve_aliases(v);
+        self.values[v].unwrap_or_else(|| panic!("{} is not defined in the callee", v))
+    }
+
+    fn ebb(&self, ebb: Ebb) -> Ebb {
+        self.ebbs[ebb].expect("branch to an EBB that is not in the layout")
+    }
+
+    fn global_value(&self, gv: GlobalValue) -> GlobalValue {
+        self.global_values[gv].unwrap()
+    }
+}
+
+/// Copy the preamble entities of `callee` into `func`.
+fn copy_preamble(func: &mut Function, callee: &Function, map: &mut EntityMap) {
+    for (gv, data) in callee.global_values.iter() {
+        let new_gv = match *data {
+            // There can only be one meaningful `vmctx` global value: reuse the caller's.
+            GlobalValueData::VMContext => func
+                .global_values
+                .iter()
+                .find(|&(_, data)| is_vmctx(data))
+                .map(|(gv, _)| gv)
+                .unwrap_or_else(|| func.create_global_value(GlobalValueData::VMContext)),
+            _ => func.create_global_value(data.clone()),
+        };
+        map.global_values[gv] = Some(new_gv);
+    }
+    // Global values may be defined in terms of each other, so fix up the bases in a second pass.
+    for gv in callee.global_values.keys() {
+        let new_gv = map.global_value(gv);
+        match func.global_values[new_gv] {
+            GlobalValueData::Load { ref mut base, .. }
+            | GlobalValueData::IAddImm { ref mut base, .. } => *base = map.global_value(*base),
+            GlobalValueData::VMContext | GlobalValueData::Symbol { .. } => {}
+        }
+    }
+
+    for (heap, data) in callee.heaps.iter() {
+        let style = match data.style {
+            HeapStyle::Dynamic { bound_gv } => HeapStyle::Dynamic {
+                bound_gv: map.global_value(bound_gv),
+            },
+            HeapStyle::Static { bound } => HeapStyle::Static { bound },
+        };
+        map.heaps[heap] = Some(func.create_heap(HeapData {
+            base: map.global_value(data.base),
+            min_size: data.min_size,
+            offset_guard_size: data.offset_guard_size,
+            style,
+            index_type: data.index_type,
+        }));
+    }
+
+    for (table, data) in callee.tables.iter() {
+        map.tables[table] = Some(func.create_table(TableData {
+            base_gv: map.global_value(data.base_gv),
+            min_size: data.min_size,
+            bound_gv: map.global_value(data.bound_gv),
+            element_size: data.element_size,
+            index_type: data.index_type,
+        }));
+    }
+
+    for (sig, data) in callee.dfg.signatures.iter() {
+        map.sigs[sig] = Some(func.import_signature(data.clone()));
+    }
+
+    for (func_ref, data) in callee.dfg.ext_funcs.iter() {
+        map.func_refs[func_ref] = Some(func.import_function(ExtFuncData {
+            name: data.name.clone(),
+            signature: map.sigs[data.signature].unwrap(),
+            colocated: data.colocated,
+        }));
+    }
+
+    for (ss, data) in callee.stack_slots.iter() {
+        debug_assert_eq!(data.kind, StackSlotKind::ExplicitSlot);
+        map.stack_slots[ss] =
+            Some(func.create_stack_slot(StackSlotData::new(data.kind, data.size)));
+    }
+}
+
+/// Copy the instruction data of `inst` in `callee`, translating all entity references except the
+/// value arguments, which are left as callee values in a list owned by `func`.
+fn copy_inst_data(
+    func: &mut Function,
+    callee: &Function,
+    inst: Inst,
+    map: &EntityMap,
+) -> InstructionData {
+    let mut data = callee.dfg[inst].clone();
+
+    // Value lists live in the callee's pool, so they need to be copied over.
+    if let Some(list) = data.take_value_list() {
+        let args = list.as_slice(&callee.dfg.value_lists);
+        data.put_value_list(ValueList::from_slice(args, &mut func.dfg.value_lists));
+    }
+
+    if let Some(dest) = data.branch_destination_mut() {
+        *dest = map.ebb(*dest);
+    }
+
+    match data {
+        InstructionData::UnaryConst {
+            ref mut constant_handle,
+            ..
+        } => {
+            let constant = callee.dfg.constants.get(*constant_handle).clone();
+            *constant_handle = func.dfg.constants.insert(constant);
+        }
+        InstructionData::UnaryGlobalValue {
+            ref mut global_value,
+            ..
+        } => *global_value = map.global_value(*global_value),
+        InstructionData::Shuffle { ref mut mask, .. } => {
+            let bytes = callee.dfg.immediates[*mask].clone();
+            *mask = func.dfg.immediates.push(bytes);
+        }
+        InstructionData::BranchTable {
+            ref mut table,
+            ref mut destination,
+            ..
+        } => {
+            *table = map.jump_tables[*table].unwrap();
+            *destination = map.ebb(*destination);
+        }
+        InstructionData::BranchTableEntry { ref mut table, .. }
+        | InstructionData::BranchTableBase { ref mut table, .. }
+        | InstructionData::IndirectJump { ref mut table, .. } => {
+            *table = map.jump_tables[*table].unwrap()
+        }
+        InstructionData::Call {
+            ref mut func_ref, ..
+        }
+        | InstructionData::FuncAddr {
+            ref mut func_ref, ..
+        } => *func_ref = map.func_refs[*func_ref].unwrap(),
+        InstructionData::CallIndirect {
+            ref mut sig_ref, ..
+        } => *sig_ref = map.sigs[*sig_ref].unwrap(),
+        InstructionData::StackLoad {
+            ref mut stack_slot, ..
+        }
+        | InstructionData::StackStore {
+            ref mut stack_slot, ..
+        } => *stack_slot = map.stack_slots[*stack_slot].unwrap(),
+        InstructionData::HeapAddr { ref mut heap, .. } => *heap = map.heaps[*heap].unwrap(),
+        InstructionData::TableAddr { ref mut table, .. } => *table = map.tables[*table].unwrap(),
+        _ => {}
+    }
+
+    data
+}
+
+/// Splice `callee` into `func` at `call`.
+///
+/// Returns the direct call instructions that were copied from the callee, or `None` if the callee
+/// can't be inlined.
+fn splice_callee(func: &mut Function, call: Inst, callee: &Function) -> Option<Vec<Inst>> {
+    if !can_inline(func, call, callee) {
+        return None;
+    }
+    debug!(
+        "Inlining {} into {} at {}",
+        callee.name,
+        func.name,
+        func.dfg.display_inst(call, None)
+    );
+
+    let call_ebb = func
+        .layout
+        .inst_ebb(call)
+        .expect("call must be in the layout");
+    let call_srcloc = func.srclocs[call];
+
+    // Split the calling EBB after the call, and turn the call results into parameters of the
+    // continuation EBB.
+    let cont_ebb = func.dfg.make_ebb();
+    let next_inst = func
+        .layout
+        .next_inst(call)
+        .expect("a call can't terminate an EBB");
+    func.layout.split_ebb(cont_ebb, next_inst);
+    let results = func.dfg.inst_results(call).to_vec();
+    func.dfg.clear_results(call);
+    for result in results {
+        func.dfg.attach_ebb_param(cont_ebb, result);
+    }
+
+    let mut map = EntityMap::new();
+    copy_preamble(func, callee, &mut map);
+
+    // Create the EBBs. The entry block parameters map directly to the call arguments, and its
+    // instructions go in place of the call.
+    let callee_entry = callee.layout.entry_block().unwrap();
+    map.ebbs[callee_entry] = Some(call_ebb);
+    for (&param, &arg) in callee
+        .dfg
+        .ebb_params(callee_entry)
+        .iter()
+        .zip(func.dfg.inst_args(call))
+    {
+        map.values[param] = Some(arg);
+    }
+    for ebb in callee.layout.ebbs().skip(1) {
+        let new_ebb = func.dfg.make_ebb();
+        func.layout.insert_ebb(new_ebb, cont_ebb);
+        if callee.layout.is_cold(ebb) {
+            func.layout.set_cold(new_ebb);
+        }
+        for &param in callee.dfg.ebb_params(ebb) {
+            let ty = callee.dfg.value_type(param);
+            map.values[param] = Some(func.dfg.append_ebb_param(new_ebb, ty));
+        }
+        map.ebbs[ebb] = Some(new_ebb);
+    }
+
+    for (jt, data) in callee.jump_tables.iter() {
+        let mut new_data = JumpTableData::with_capacity(data.len());
+        for &dest in data.iter() {
+            new_data.push_entry(map.ebb(dest));
+        }
+        map.jump_tables[jt] = Some(func.create_jump_table(new_data));
+    }
+
+    // Copy the instructions and create their results. The value arguments are translated in a
+    // second pass once all the callee values have been mapped.
+    let mut new_insts = Vec::new();
+    let mut new_calls = Vec::new();
+    for ebb in callee.layout.ebbs() {
+        let new_ebb = map.ebb(ebb);
+        for inst in callee.layout.ebb_insts(ebb) {
+            let opcode = callee.dfg[inst].opcode();
+            let new_inst = if opcode.is_return() {
+                let args = callee.dfg.inst_args(inst);
+                let args = ValueList::from_slice(args, &mut func.dfg.value_lists);
+                func.dfg.make_inst(InstructionData::Jump {
+                    opcode: Opcode::Jump,
+                    destination: cont_ebb,
+                    args,
+                })
+            } else {
+                let data = copy_inst_data(func, callee, inst, &map);
+                let new_inst = func.dfg.make_inst(data);
+                let ctrl_typevar = callee.dfg.ctrl_typevar(inst);
+                func.dfg.make_inst_results(new_inst, ctrl_typevar);
+                for (&old, &new) in callee
+                    .dfg
+                    .inst_results(inst)
+                    .iter()
+                    .zip(func.dfg.inst_results(new_inst))
+                {
+                    map.values[old] = Some(new);
+                }
+                if opcode == Opcode::Call {
+                    new_calls.push(new_inst);
+                }
+                new_inst
+            };
+
+            if new_ebb == call_ebb {
+                func.layout.insert_inst(new_inst, call);
+            } else {
+                func.layout.append_inst(new_inst, new_ebb);
+            }
+
+            // Instructions without a source location of their own are attributed to the call.
+            let srcloc = match callee.srclocs[inst] {
+                srcloc if srcloc.is_default() => call_srcloc,
+                srcloc => srcloc,
+            };
+            if !srcloc.is_default() {
+                func.srclocs[new_inst] = srcloc;
+            }
+            new_insts.push(new_inst);
+        }
+    }
+
+    for new_inst in new_insts {
+        for arg in func.dfg.inst_args_mut(new_inst) {
+            *arg = map.value(callee, *arg);
+        }
+    }
+
+    func.layout.remove_inst(call);
+
+    Some(new_calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::ir::types::I32;
+    use crate::ir::{InstBuilder, Signature};
+    use crate::isa::CallConv;
+    use crate::settings;
+    use crate::verifier::verify_function;
+    use alloc::string::ToString;
+
+    fn binary_sig() -> Signature {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(I32));
+        sig.params.push(AbiParam::new(I32));
+        sig.returns.push(AbiParam::new(I32));
+        sig
+    }
+
+    /// Build `%add(a, b) -> a + b`.
+    fn make_callee() -> Function {
+        let mut func = Function::with_name_signature(ExternalName::testcase("add"), binary_sig());
+        let ebb0 = func.dfg.make_ebb();
+        let a = func.dfg.append_ebb_param(ebb0, I32);
+        let b = func.dfg.append_ebb_param(ebb0, I32);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        let sum = pos.ins().iadd(a, b);
+        pos.ins().return_(&[sum]);
+        func
+    }
+
+    /// Build `%caller(x) -> add(x, x) + 1`.
+    fn make_caller() -> (Function, Inst) {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(I32));
+        sig.returns.push(AbiParam::new(I32));
+        let mut func = Function::with_name_signature(ExternalName::testcase("caller"), sig);
+        let sigref = func.import_signature(binary_sig());
+        let fnref = func.import_function(ExtFuncData {
+            name: ExternalName::testcase("add"),
+            signature: sigref,
+            colocated: true,
+        });
+        let ebb0 = func.dfg.make_ebb();
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        let call = pos.ins().call(fnref, &[x, x]);
+        let res = pos.func.dfg.first_result(call);
+        let inc = pos.ins().iadd_imm(res, 1);
+        pos.ins().return_(&[inc]);
+        (func, call)
+    }
+
+    #[test]
+    fn inline_single_call() {
+        let callee = make_callee();
+        let (mut func, call) = make_caller();
+        assert!(inline_call(&mut func, call, &callee));
+
+        let flags = settings::Flags::new(settings::builder());
+        verify_function(&func, &flags).unwrap();
+        assert_eq!(
+            func.to_string(),
+            "function %caller(i32) -> i32 system_v {\n    \
+             sig0 = (i32, i32) -> i32 system_v\n    \
+             fn0 = colocated %add sig0\n\n\
+             ebb0(v0: i32):\n    \
+             v3 = iadd v0, v0\n    \
+             jump ebb1(v3)\n\n\
+             ebb1(v1: i32):\n    \
+             v2 = iadd_imm v1, 1\n    \
+             return v2\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn heuristic_limits() {
+        let callee = make_callee();
+        let (mut func, _) = make_caller();
+        let heuristic = SizeHeuristic {
+            max_callee_size: 1,
+            ..SizeHeuristic::default()
+        };
+        assert_eq!(inline_calls(&mut func, |_| Some(&callee), &heuristic), 0);
+        assert_eq!(
+            inline_calls(&mut func, |_| Some(&callee), &SizeHeuristic::default()),
+            1
+        );
+    }
+
+    /// A heuristic that inlines everything and checks the caller size it is given.
+    struct CheckSize;
+
+    impl InlineHeuristic for CheckSize {
+        fn should_inline(
+            &self,
+            caller: &Function,
+            caller_size: usize,
+            _call: Inst,
+            _callee: &Function,
+            depth: usize,
+        ) -> bool {
+            assert_eq!(caller_size, function_size(caller));
+            depth < 2
+        }
+    }
+
+    #[test]
+    fn tracked_caller_size() {
+        // Make the callee call itself through a different name, so each level of inlining brings
+        // in a new call site.
+        let mut callee = make_callee();
+        let sig = callee.import_signature(binary_sig());
+        let fn_ref = callee.import_function(ExtFuncData {
+            name: ExternalName::testcase("add2"),
+            signature: sig,
+            colocated: true,
+        });
+        let mut pos = FuncCursor::new(&mut callee);
+        let ebb = pos.func.layout.entry_block().unwrap();
+        let ret = pos.func.layout.last_inst(ebb).unwrap();
+        pos.goto_inst(ret);
+        let args = pos.func.dfg.ebb_params(ebb).to_vec();
+        pos.ins().call(fn_ref, &args);
+
+        let (mut func, _) = make_caller();
+        assert_eq!(inline_calls(&mut func, |_| Some(&callee), &CheckSize), 2);
+    }
+}
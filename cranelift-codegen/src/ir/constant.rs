@@ -269,6 +269,13 @@ impl ConstantPool {
             )
     }
 
+    /// Retrieve the offset of a given constant, or `None` if it hasn't been set yet.
+    pub(crate) fn try_get_offset(&self, constant_handle: Constant) -> Option<ConstantOffset> {
+        self.handles_to_values
+            .get(&constant_handle)
+            .and_then(|e| e.offset)
+    }
+
     /// Iterate over the constants in insertion order.
     pub fn iter(&self) -> impl Iterator<Item = (&Constant, &ConstantData)> {
         self.handles_to_values.iter().map(|(h, e)| (h, &e.data))
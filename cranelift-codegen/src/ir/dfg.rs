@@ -1,5 +1,6 @@
 //! Data flow graph tracking Instructions, Values, and EBBs.
 
+use crate::binary::{Reader, Writer};
 use crate::entity::{self, PrimaryMap, SecondaryMap};
 use crate::ir;
 use crate::ir::builder::ReplaceBuilder;
@@ -1086,6 +1087,85 @@ impl DataFlowGraph {
     }
 }
 
+/// Binary serialization routines. These routines should not be used outside the `binary` module.
+impl DataFlowGraph {
+    /// Encode the instructions, EBBs and values, keeping their numbers.
+    pub(crate) fn encode_entities(&self, w: &mut Writer) {
+        w.uleb(self.insts.len() as u64);
+        for (inst, data) in self.insts.iter() {
+            data.encode(w, &self.value_lists);
+            w.values(self.results[inst].as_slice(&self.value_lists));
+        }
+        w.uleb(self.ebbs.len() as u64);
+        for data in self.ebbs.values() {
+            w.values(data.params.as_slice(&self.value_lists));
+        }
+        w.uleb(self.values.len() as u64);
+        for data in self.values.values() {
+            match *data {
+                ValueData::Inst { ty, num, inst } => {
+                    w.u8(0);
+                    w.encode(&ty);
+                    w.encode(&num);
+                    w.encode(&inst);
+                }
+                ValueData::Param { ty, num, ebb } => {
+                    w.u8(1);
+                    w.encode(&ty);
+                    w.encode(&num);
+                    w.encode(&ebb);
+                }
+                ValueData::Alias { ty, original } => {
+                    w.u8(2);
+                    w.encode(&ty);
+                    w.encode(&original);
+                }
+            }
+        }
+    }
+
+    /// Decode the instructions, EBBs and values encoded by `encode_entities` into this empty
+    /// data flow graph.
+    ///
+    /// The entities they refer to are checked to exist by the caller, once the whole function has
+    /// been decoded.
+    pub(crate) fn decode_entities(&mut self, r: &mut Reader) -> Option<()> {
+        debug_assert!(self.insts.is_empty() && self.ebbs.is_empty() && self.values.is_empty());
+        for _ in 0..r.count()? {
+            let data = InstructionData::decode(r, &mut self.value_lists)?;
+            let inst = self.make_inst(data);
+            let results = r.values()?;
+            self.results[inst] = ValueList::from_slice(&results, &mut self.value_lists);
+        }
+        for _ in 0..r.count()? {
+            let params = r.values()?;
+            let ebb = self.make_ebb();
+            self.ebbs[ebb].params = ValueList::from_slice(&params, &mut self.value_lists);
+        }
+        for _ in 0..r.count()? {
+            let data = match r.u8()? {
+                0 => ValueData::Inst {
+                    ty: r.decode()?,
+                    num: r.decode()?,
+                    inst: r.decode()?,
+                },
+                1 => ValueData::Param {
+                    ty: r.decode()?,
+                    num: r.decode()?,
+                    ebb: r.decode()?,
+                },
+                2 => ValueData::Alias {
+                    ty: r.decode()?,
+                    original: r.decode()?,
+                },
+                _ => return None,
+            };
+            self.values.push(data);
+        }
+        Some(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
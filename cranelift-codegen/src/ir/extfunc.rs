@@ -270,23 +270,57 @@ pub enum ArgumentPurpose {
     /// This is a pointer to a stack limit. It is used to check the current stack pointer
     /// against. Can only appear once in a signature.
     StackLimit,
-}
 
-/// Text format names of the `ArgumentPurpose` variants.
-static PURPOSE_NAMES: [&str; 8] = [
-    "normal",
-    "sret",
-    "link",
-    "fp",
-    "csr",
-    "vmctx",
-    "sigid",
-    "stack_limit",
-];
+    /// A struct passed by value.
+    ///
+    /// The value is a pointer to a struct with the given size and alignment in bytes. ABI
+    /// legalization passes the contents of the struct as required by the calling convention, and
+    /// the callee gets a pointer to its own copy. As a return value, the callee returns a pointer
+    /// to the struct, and the caller gets a pointer to a copy in its own stack frame.
+    ///
+    /// Calling conventions that pass the eightbytes of a struct in registers by class, like
+    /// x86-64 System V, need to know which eightbytes hold only floating point fields. Those are
+    /// passed in float registers, and the others in general purpose registers.
+    ///
+    /// The legalized signature uses this purpose for the `i64` and `f64` pieces the struct is
+    /// split into, or for a pointer-sized argument when the struct is copied to the stack.
+    StructArgument {
+        /// Size of the struct in bytes.
+        size: u32,
+        /// Alignment of the struct in bytes.
+        align: u32,
+        /// Bit `i` is set if the `i`th eightbyte of the struct only holds floating point fields.
+        floats: u8,
+    },
+}
 
 impl fmt::Display for ArgumentPurpose {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(PURPOSE_NAMES[*self as usize])
+        f.write_str(match *self {
+            ArgumentPurpose::Normal => "normal",
+            ArgumentPurpose::StructReturn => "sret",
+            ArgumentPurpose::Link => "link",
+            ArgumentPurpose::FramePointer => "fp",
+            ArgumentPurpose::CalleeSaved => "csr",
+            ArgumentPurpose::VMContext => "vmctx",
+            ArgumentPurpose::SignatureId => "sigid",
+            ArgumentPurpose::StackLimit => "stack_limit",
+            ArgumentPurpose::StructArgument {
+                size,
+                align,
+                floats,
+            } => {
+                write!(f, "sarg({}, {}", size, align)?;
+                // The classes of the eightbytes up to the last float one, as `i` and `f`.
+                if floats != 0 {
+                    f.write_str(", ")?;
+                    for i in 0..8 - floats.leading_zeros() {
+                        f.write_str(if floats & (1 << i) != 0 { "f" } else { "i" })?;
+                    }
+                }
+                return f.write_str(")");
+            }
+        })
     }
 }
 
@@ -302,11 +336,46 @@ impl FromStr for ArgumentPurpose {
             "vmctx" => Ok(ArgumentPurpose::VMContext),
             "sigid" => Ok(ArgumentPurpose::SignatureId),
             "stack_limit" => Ok(ArgumentPurpose::StackLimit),
-            _ => Err(()),
+            _ => {
+                // A struct argument is written as `sarg(size, align)`, or `sarg(size, align, ifi)`
+                // with the classes of its eightbytes.
+                if !s.starts_with("sarg(") || !s.ends_with(')') {
+                    return Err(());
+                }
+                let mut args = s[5..s.len() - 1].split(',').map(str::trim);
+                let (size, align, classes) = match (args.next(), args.next(), args.next()) {
+                    (Some(size), Some(align), classes) if args.next().is_none() => {
+                        (size, align, classes.unwrap_or(""))
+                    }
+                    _ => return Err(()),
+                };
+                Ok(ArgumentPurpose::StructArgument {
+                    size: size.parse().map_err(|_| ())?,
+                    align: align.parse().map_err(|_| ())?,
+                    floats: parse_struct_classes(classes)?,
+                })
+            }
         }
     }
 }
 
+/// Parse the classes of the eightbytes of a struct argument, written as one `i` or `f` per
+/// eightbyte, into the `floats` mask of `ArgumentPurpose::StructArgument`.
+fn parse_struct_classes(classes: &str) -> Result<u8, ()> {
+    if classes.len() > 8 {
+        return Err(());
+    }
+    let mut floats = 0;
+    for (i, class) in classes.chars().enumerate() {
+        match class {
+            'i' => {}
+            'f' => floats |= 1 << i,
+            _ => return Err(()),
+        }
+    }
+    Ok(floats)
+}
+
 /// An external function.
 ///
 /// Information about a function that can be called directly with a direct `call` instruction.
@@ -351,19 +420,48 @@ mod tests {
     #[test]
     fn argument_purpose() {
         let all_purpose = [
-            ArgumentPurpose::Normal,
-            ArgumentPurpose::StructReturn,
-            ArgumentPurpose::Link,
-            ArgumentPurpose::FramePointer,
-            ArgumentPurpose::CalleeSaved,
-            ArgumentPurpose::VMContext,
-            ArgumentPurpose::SignatureId,
-            ArgumentPurpose::StackLimit,
+            (ArgumentPurpose::Normal, "normal"),
+            (ArgumentPurpose::StructReturn, "sret"),
+            (ArgumentPurpose::Link, "link"),
+            (ArgumentPurpose::FramePointer, "fp"),
+            (ArgumentPurpose::CalleeSaved, "csr"),
+            (ArgumentPurpose::VMContext, "vmctx"),
+            (ArgumentPurpose::SignatureId, "sigid"),
+            (ArgumentPurpose::StackLimit, "stack_limit"),
+            (
+                ArgumentPurpose::StructArgument {
+                    size: 24,
+                    align: 8,
+                    floats: 0,
+                },
+                "sarg(24, 8)",
+            ),
+            (
+                ArgumentPurpose::StructArgument {
+                    size: 16,
+                    align: 8,
+                    floats: 0b10,
+                },
+                "sarg(16, 8, if)",
+            ),
         ];
-        for (&e, &n) in all_purpose.iter().zip(PURPOSE_NAMES.iter()) {
+        for &(e, n) in all_purpose.iter() {
             assert_eq!(e.to_string(), n);
             assert_eq!(Ok(e), n.parse());
         }
+        assert_eq!(Err(()), "sarg(24)".parse::<ArgumentPurpose>());
+        assert_eq!(Err(()), "sarg(24, 8, 1)".parse::<ArgumentPurpose>());
+        assert_eq!(Err(()), "sarg24, 8)".parse::<ArgumentPurpose>());
+        assert_eq!(Err(()), "sarg(16, 8, fx)".parse::<ArgumentPurpose>());
+        assert_eq!(Err(()), "sarg(16, 8, f, f)".parse::<ArgumentPurpose>());
+        assert_eq!(
+            Ok(ArgumentPurpose::StructArgument {
+                size: 8,
+                align: 4,
+                floats: 0,
+            }),
+            "sarg(8, 4, i)".parse()
+        );
     }
 
     #[test]
@@ -6,7 +6,7 @@
 use crate::binemit::CodeOffset;
 use crate::entity::{PrimaryMap, SecondaryMap};
 use crate::ir;
-use crate::ir::{DataFlowGraph, ExternalName, Layout, Signature};
+use crate::ir::{DataFlowGraph, ExternalName, Layout, Signature, SourceLoc};
 use crate::ir::{
     Ebb, ExtFuncData, FuncRef, GlobalValue, GlobalValueData, Heap, HeapData, Inst, JumpTable,
     JumpTableData, SigRef, StackSlot, StackSlotData, Table, TableData,
@@ -17,6 +17,7 @@ use crate::isa::{CallConv, EncInfo, Encoding, Legalize, TargetIsa};
 use crate::regalloc::{EntryRegDiversions, RegDiversions};
 use crate::value_label::ValueLabelsRanges;
 use crate::write::write_function;
+use alloc::vec::Vec;
 use core::fmt;
 
 #[cfg(feature = "basic-blocks")]
@@ -218,6 +219,28 @@ impl Function {
         }
     }
 
+    /// Get the source locations of the code, as the code offsets where the source location
+    /// changes, in increasing order.
+    ///
+    /// Instructions without a source location or without any code are skipped. Like
+    /// `inst_offsets`, this can only be used once the code layout has been computed.
+    pub fn srcloc_changes(&self, encinfo: &EncInfo) -> Vec<(CodeOffset, SourceLoc)> {
+        let mut srclocs: Vec<(CodeOffset, SourceLoc)> = Vec::new();
+        for ebb in self.layout.ebbs() {
+            for (offset, inst, size) in self.inst_offsets(ebb, encinfo) {
+                let srcloc = self.srclocs[inst];
+                if size == 0 || srcloc.is_default() {
+                    continue;
+                }
+                match srclocs.last() {
+                    Some(&(_, last)) if last == srcloc => {}
+                    _ => srclocs.push((offset, srcloc)),
+                }
+            }
+        }
+        srclocs
+    }
+
     /// Wrapper around `encode` which assigns `inst` the resulting encoding.
     pub fn update_encoding(&mut self, inst: ir::Inst, isa: &dyn TargetIsa) -> Result<(), Legalize> {
         self.encode(inst, isa).map(|e| self.encodings[inst] = e)
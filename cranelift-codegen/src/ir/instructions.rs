@@ -61,6 +61,15 @@ impl Opcode {
     pub fn constraints(self) -> OpcodeConstraints {
         OPCODE_CONSTRAINTS[self as usize - 1]
     }
+
+    /// Get the opcode numbered `n`, as in `opcode as u8`, or `None` if there is no such opcode.
+    pub(crate) fn from_u8(n: u8) -> Option<Self> {
+        if n == 0 || usize::from(n) > OPCODE_FORMAT.len() {
+            return None;
+        }
+        // Opcodes are `#[repr(u8)]`, and numbered consecutively from 1.
+        Some(unsafe { core::mem::transmute::<u8, Self>(n) })
+    }
 }
 
 // This trait really belongs in cranelift-reader where it is used by the `.clif` file parser, but since
@@ -507,6 +516,9 @@ enum OperandConstraint {
     /// This operand is `ctrlType.as_bool()`.
     AsBool,
 
+    /// This operand is `ctrlType.as_mask()`.
+    AsMask,
+
     /// This operand is `ctrlType.half_width()`.
     HalfWidth,
 
@@ -532,6 +544,7 @@ impl OperandConstraint {
             Same => Bound(ctrl_type),
             LaneOf => Bound(ctrl_type.lane_of()),
             AsBool => Bound(ctrl_type.as_bool()),
+            AsMask => Bound(ctrl_type.as_mask()),
             HalfWidth => Bound(ctrl_type.half_width().expect("invalid type for half_width")),
             DoubleWidth => Bound(
                 ctrl_type
@@ -591,6 +604,15 @@ mod tests {
         assert_eq!(mem::size_of::<Opcode>(), mem::size_of::<Option<Opcode>>());
     }
 
+    #[test]
+    fn opcode_from_u8() {
+        assert_eq!(Opcode::from_u8(0), None);
+        assert_eq!(Opcode::from_u8(Opcode::Iadd as u8), Some(Opcode::Iadd));
+        let last = OPCODE_FORMAT.len() as u8;
+        assert_eq!(Opcode::from_u8(last).map(|op| op as u8), Some(last));
+        assert_eq!(Opcode::from_u8(last + 1), None);
+    }
+
     #[test]
     fn instruction_data() {
         use core::mem;
@@ -464,6 +464,16 @@ impl Layout {
     pub fn next_ebb(&self, ebb: Ebb) -> Option<Ebb> {
         self.ebbs[ebb].next.expand()
     }
+
+    /// Mark `ebb` as cold, i.e. rarely executed.
+    pub fn set_cold(&mut self, ebb: Ebb) {
+        self.ebbs[ebb].cold = true;
+    }
+
+    /// Is `ebb` marked as cold?
+    pub fn is_cold(&self, ebb: Ebb) -> bool {
+        self.ebbs[ebb].cold
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -473,6 +483,7 @@ struct EbbNode {
     first_inst: PackedOption<Inst>,
     last_inst: PackedOption<Inst>,
     seq: SequenceNumber,
+    cold: bool,
 }
 
 /// Iterate over EBBs in layout order. See `Layout::ebbs()`.
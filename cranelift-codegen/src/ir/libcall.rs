@@ -46,6 +46,14 @@ pub enum LibCall {
     Memset,
     /// libc.memmove
     Memmove,
+    /// udiv.i128
+    UdivI128,
+    /// sdiv.i128
+    SdivI128,
+    /// urem.i128
+    UremI128,
+    /// srem.i128
+    SremI128,
 }
 
 impl fmt::Display for LibCall {
@@ -71,6 +79,10 @@ impl FromStr for LibCall {
             "Memcpy" => Ok(LibCall::Memcpy),
             "Memset" => Ok(LibCall::Memset),
             "Memmove" => Ok(LibCall::Memmove),
+            "UdivI128" => Ok(LibCall::UdivI128),
+            "SdivI128" => Ok(LibCall::SdivI128),
+            "UremI128" => Ok(LibCall::UremI128),
+            "SremI128" => Ok(LibCall::SremI128),
             _ => Err(()),
         }
     }
@@ -97,6 +109,13 @@ impl LibCall {
                 Opcode::Nearest => LibCall::NearestF64,
                 _ => return None,
             },
+            types::I128 => match opcode {
+                Opcode::Udiv => LibCall::UdivI128,
+                Opcode::Sdiv => LibCall::SdivI128,
+                Opcode::Urem => LibCall::UremI128,
+                Opcode::Srem => LibCall::SremI128,
+                _ => return None,
+            },
             _ => return None,
         })
     }
@@ -215,10 +234,12 @@ mod tests {
     fn display() {
         assert_eq!(LibCall::CeilF32.to_string(), "CeilF32");
         assert_eq!(LibCall::NearestF64.to_string(), "NearestF64");
+        assert_eq!(LibCall::UdivI128.to_string(), "UdivI128");
     }
 
     #[test]
     fn parsing() {
         assert_eq!("FloorF32".parse(), Ok(LibCall::FloorF32));
+        assert_eq!("SremI128".parse(), Ok(LibCall::SremI128));
     }
 }
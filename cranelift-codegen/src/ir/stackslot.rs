@@ -3,7 +3,7 @@
 //! The `StackSlotData` struct keeps track of a single stack slot in a function.
 //!
 
-use crate::entity::{Iter, IterMut, Keys, PrimaryMap};
+use crate::entity::{Iter, IterMut, Keys, PrimaryMap, SecondaryMap};
 use crate::ir::{StackSlot, Type};
 use crate::packed_option::PackedOption;
 use alloc::vec::Vec;
@@ -166,6 +166,11 @@ pub struct StackSlots {
     /// All the emergency slots.
     emergency: Vec<StackSlot>,
 
+    /// Slots that share the memory of another slot because their lifetimes don't overlap.
+    ///
+    /// This is computed by the stack slot coloring pass.
+    shared: SecondaryMap<StackSlot, Option<StackSlot>>,
+
     /// The total size of the stack frame.
     ///
     /// This is the distance from the stack pointer in the current function to the stack pointer in
@@ -184,6 +189,7 @@ impl StackSlots {
             slots: PrimaryMap::new(),
             outgoing: Vec::new(),
             emergency: Vec::new(),
+            shared: SecondaryMap::new(),
             frame_size: None,
         }
     }
@@ -193,6 +199,7 @@ impl StackSlots {
         self.slots.clear();
         self.outgoing.clear();
         self.emergency.clear();
+        self.shared.clear();
         self.frame_size = None;
     }
 
@@ -204,6 +211,28 @@ impl StackSlots {
         self.slots.push(data)
     }
 
+    /// Allocate a new stack slot, and keep track of it if it is an outgoing argument or an
+    /// emergency slot, like `get_outgoing_arg` and `get_emergency_slot` do.
+    ///
+    /// This is used when deserializing a function.
+    pub(crate) fn push_registered(&mut self, data: StackSlotData) -> StackSlot {
+        let ss = self.slots.push(data);
+        match self[ss].kind {
+            StackSlotKind::OutgoingArg if self[ss].offset.is_some() => {
+                let key = (self[ss].offset.unwrap(), self[ss].size);
+                if let Err(inspos) = self
+                    .outgoing
+                    .binary_search_by_key(&key, |&ss| (self[ss].offset.unwrap(), self[ss].size))
+                {
+                    self.outgoing.insert(inspos, ss);
+                }
+            }
+            StackSlotKind::EmergencySlot => self.emergency.push(ss),
+            _ => {}
+        }
+        ss
+    }
+
     /// Check if `ss` is a valid stack slot reference.
     pub fn is_valid(&self, ss: StackSlot) -> bool {
         self.slots.is_valid(ss)
@@ -234,6 +263,32 @@ impl StackSlots {
         self.slots.keys()
     }
 
+    /// Let `ss` share the memory of the stack slot `with`, which must have the same size.
+    ///
+    /// Both slots will get the same offset when the stack frame is laid out.
+    pub fn share(&mut self, ss: StackSlot, with: StackSlot) {
+        debug_assert!(self.can_share(ss, with), "{} can't share {}", ss, with);
+        self.shared[ss] = Some(with);
+    }
+
+    /// Check if `ss` can share the memory of the stack slot `with`: both slots must exist and
+    /// have the same size, and sharing must not be chained, so neither `ss` nor `with` may
+    /// already share another slot, and no slot may already share `ss`.
+    pub fn can_share(&self, ss: StackSlot, with: StackSlot) -> bool {
+        self.is_valid(ss)
+            && self.is_valid(with)
+            && ss != with
+            && self[ss].size == self[with].size
+            && self.shared[ss].is_none()
+            && self.shared[with].is_none()
+            && self.shared.values().all(|&shared| shared != Some(ss))
+    }
+
+    /// Get the stack slot whose memory `ss` shares, if any.
+    pub fn shared_with(&self, ss: StackSlot) -> Option<StackSlot> {
+        self.shared[ss]
+    }
+
     /// Get a reference to the next stack slot that would be created by `push()`.
     ///
     /// This should just be used by the parser.
@@ -263,24 +318,22 @@ impl StackSlots {
         self.push(StackSlotData::new(StackSlotKind::SpillSlot, spill_size(ty)))
     }
 
-    /// Create a stack slot representing an incoming function argument.
-    pub fn make_incoming_arg(&mut self, ty: Type, offset: StackOffset) -> StackSlot {
-        let mut data = StackSlotData::new(StackSlotKind::IncomingArg, ty.bytes());
+    /// Create a stack slot representing an incoming function argument of `size` bytes.
+    pub fn make_incoming_arg(&mut self, size: StackSize, offset: StackOffset) -> StackSlot {
+        let mut data = StackSlotData::new(StackSlotKind::IncomingArg, size);
         debug_assert!(offset <= StackOffset::max_value() - data.size as StackOffset);
         data.offset = Some(offset);
         self.push(data)
     }
 
-    /// Get a stack slot representing an outgoing argument.
+    /// Get a stack slot representing an outgoing argument of `size` bytes.
     ///
     /// This may create a new stack slot, or reuse an existing outgoing stack slot with the
     /// requested offset and size.
     ///
     /// The requested offset is relative to this function's stack pointer immediately before making
     /// the call.
-    pub fn get_outgoing_arg(&mut self, ty: Type, offset: StackOffset) -> StackSlot {
-        let size = ty.bytes();
-
+    pub fn get_outgoing_arg(&mut self, size: StackSize, offset: StackOffset) -> StackSlot {
         // Look for an existing outgoing stack slot with the same offset and size.
         let inspos = match self.outgoing.binary_search_by_key(&(offset, size), |&ss| {
             (self[ss].offset.unwrap(), self[ss].size)
@@ -365,9 +418,9 @@ mod tests {
     fn outgoing() {
         let mut sss = StackSlots::new();
 
-        let ss0 = sss.get_outgoing_arg(types::I32, 8);
-        let ss1 = sss.get_outgoing_arg(types::I32, 4);
-        let ss2 = sss.get_outgoing_arg(types::I64, 8);
+        let ss0 = sss.get_outgoing_arg(4, 8);
+        let ss1 = sss.get_outgoing_arg(4, 4);
+        let ss2 = sss.get_outgoing_arg(8, 8);
 
         assert_eq!(sss[ss0].offset, Some(8));
         assert_eq!(sss[ss0].size, 4);
@@ -378,9 +431,9 @@ mod tests {
         assert_eq!(sss[ss2].offset, Some(8));
         assert_eq!(sss[ss2].size, 8);
 
-        assert_eq!(sss.get_outgoing_arg(types::I32, 8), ss0);
-        assert_eq!(sss.get_outgoing_arg(types::I32, 4), ss1);
-        assert_eq!(sss.get_outgoing_arg(types::I64, 8), ss2);
+        assert_eq!(sss.get_outgoing_arg(4, 8), ss0);
+        assert_eq!(sss.get_outgoing_arg(4, 4), ss1);
+        assert_eq!(sss.get_outgoing_arg(8, 8), ss2);
     }
 
     #[test]
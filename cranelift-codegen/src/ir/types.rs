@@ -124,6 +124,13 @@ impl Type {
         }
     }
 
+    /// Get a type with the same number of lanes as this type, but with `b1` lanes.
+    ///
+    /// This is the type of a vector comparison result held in a mask register, one bit per lane.
+    pub fn as_mask(self) -> Self {
+        self.replace_lanes(B1)
+    }
+
     /// Get a type with the same number of lanes as this type, but with lanes that are half the
     /// number of bits.
     pub fn half_width(self) -> Option<Self> {
@@ -284,6 +291,23 @@ impl Type {
         usize::from(self.0)
     }
 
+    /// Get the type with the given `index()`, or `None` if no such type exists.
+    pub(crate) fn from_index(index: u8) -> Option<Self> {
+        let ty = Type(index);
+        let lane = ty.lane_type();
+        if ty.is_invalid()
+            || ty.is_flags()
+            || lane.is_bool()
+            || lane.is_int()
+            || lane.is_float()
+            || lane.is_ref()
+        {
+            Some(ty)
+        } else {
+            None
+        }
+    }
+
     /// True iff:
     ///
     /// 1. `self.lane_count() == other.lane_count()` and
@@ -502,6 +526,8 @@ mod tests {
     fn as_bool() {
         assert_eq!(I32X4.as_bool(), B32X4);
         assert_eq!(I32.as_bool(), B1);
+        assert_eq!(I8X16.as_mask(), B1X16);
+        assert_eq!(F64X8.as_mask(), B1X8);
         assert_eq!(I32X4.as_bool_pedantic(), B32X4);
         assert_eq!(I32.as_bool_pedantic(), B32);
     }
@@ -372,6 +372,14 @@ pub trait TargetIsa: fmt::Display + Sync {
     /// Emit a whole function into memory.
     fn emit_function_to_memory(&self, func: &ir::Function, sink: &mut binemit::MemoryCodeSink);
 
+    /// Get the latency in cycles of an instruction with the given opcode.
+    ///
+    /// This is used by the instruction scheduler, which is disabled for ISAs that don't provide a
+    /// latency model.
+    fn instruction_latency(&self, _opcode: ir::Opcode) -> Option<u32> {
+        None
+    }
+
     /// IntCC condition for Unsigned Addition Overflow (Carry).
     fn unsigned_add_overflow_condition(&self) -> ir::condcodes::IntCC;
 
@@ -3,8 +3,13 @@
 use super::super::settings as shared_settings;
 use super::registers::{FPR, GPR, RU};
 use super::settings as isa_settings;
-use crate::abi::{legalize_args, ArgAction, ArgAssigner, ValueConversion};
+use super::shrink_wrap;
+use crate::abi::{
+    legalize_args, struct_passing, ArgAction, ArgAssigner, StructPassing, ValueConversion,
+};
 use crate::cursor::{Cursor, CursorPosition, EncCursor};
+use crate::dominator_tree::DominatorTree;
+use crate::flowgraph::ControlFlowGraph;
 use crate::ir;
 use crate::ir::immediates::Imm64;
 use crate::ir::stackslot::{StackOffset, StackSize};
@@ -40,6 +45,9 @@ struct Args {
     fpr_limit: usize,
     fpr_used: usize,
     offset: u32,
+    /// Pieces left of the struct argument being assigned, and whether they go on the stack.
+    struct_pieces: u32,
+    struct_in_memory: bool,
     call_conv: CallConv,
     shared_flags: shared_settings::Flags,
     #[allow(dead_code)]
@@ -72,6 +80,8 @@ impl Args {
             fpr_limit,
             fpr_used: 0,
             offset,
+            struct_pieces: 0,
+            struct_in_memory: false,
             call_conv,
             shared_flags: shared_flags.clone(),
             isa_flags: isa_flags.clone(),
@@ -83,6 +93,46 @@ impl ArgAssigner for Args {
     fn assign(&mut self, arg: &AbiParam) -> ArgAction {
         let ty = arg.value_type;
 
+        if let ArgumentPurpose::StructArgument {
+            size,
+            align,
+            floats,
+        } = arg.purpose
+        {
+            // Structs on the stack are eightbyte aligned, or 16-byte aligned if they need more.
+            let stack_align = if align > 8 { 15 } else { 7 };
+            match struct_passing(self.call_conv, size, floats) {
+                StructPassing::Memory => {
+                    self.offset = (self.offset + stack_align) & !stack_align;
+                    let loc = ArgumentLoc::Stack(self.offset as i32);
+                    self.offset += (size + 7) & !7;
+                    debug_assert!(self.offset <= i32::MAX as u32);
+                    return loc.into();
+                }
+                StructPassing::Pieces(count, floats) => {
+                    // The whole struct is passed in memory when there aren't enough registers
+                    // of either class left for all of its pieces.
+                    if self.struct_pieces == 0 {
+                        let float_pieces = floats.count_ones() as usize;
+                        let int_pieces = count as usize - float_pieces;
+                        self.struct_pieces = count;
+                        self.struct_in_memory = self.gpr_used + int_pieces > self.gpr.len()
+                            || self.fpr_used + float_pieces > self.fpr_limit;
+                        if self.struct_in_memory {
+                            self.offset = (self.offset + stack_align) & !stack_align;
+                        }
+                    }
+                    self.struct_pieces -= 1;
+                    if self.struct_in_memory {
+                        let loc = ArgumentLoc::Stack(self.offset as i32);
+                        self.offset += u32::from(self.pointer_bytes);
+                        return loc.into();
+                    }
+                }
+                StructPassing::Reference => {}
+            }
+        }
+
         // Vectors should stay in vector registers unless SIMD is not enabled--then they are split
         if ty.is_vector() {
             if self.shared_flags.enable_simd() {
@@ -95,6 +145,17 @@ impl ArgAssigner for Args {
 
         // Large integers and booleans are broken down to fit in a register.
         if !ty.is_float() && ty.bits() > u16::from(self.pointer_bits) {
+            // The System V ABI passes a 128-bit integer either in two registers, or in 16-byte
+            // aligned stack memory when there aren't two registers left. The halves are assigned
+            // next, so make sure they both end up in the same place.
+            if ty.bits() == 128
+                && self.pointer_bits == 64
+                && !self.call_conv.extends_windows_fastcall()
+                && self.gpr_used + 2 > self.gpr.len()
+            {
+                self.gpr_used = self.gpr.len();
+                self.offset = (self.offset + 15) & !15;
+            }
             return ValueConversion::IntSplit.into();
         }
 
@@ -235,10 +296,16 @@ pub fn regclass_for_abi_type(ty: ir::Type) -> RegClass {
 }
 
 /// Get the set of allocatable registers for `func`.
-pub fn allocatable_registers(triple: &Triple, flags: &shared_settings::Flags) -> RegisterSet {
+pub fn allocatable_registers(
+    triple: &Triple,
+    flags: &shared_settings::Flags,
+    call_conv: CallConv,
+) -> RegisterSet {
     let mut regs = RegisterSet::new();
     regs.take(GPR, RU::rsp as RegUnit);
-    regs.take(GPR, RU::rbp as RegUnit);
+    if has_frame_pointer(flags, call_conv) {
+        regs.take(GPR, RU::rbp as RegUnit);
+    }
 
     // 32-bit arch only has 8 registers.
     if triple.pointer_width().unwrap() != PointerWidth::U64 {
@@ -260,6 +327,13 @@ pub fn allocatable_registers(triple: &Triple, flags: &shared_settings::Flags) ->
     regs
 }
 
+/// Does a function with the calling convention `call_conv` keep a frame pointer in %rbp?
+///
+/// Otherwise %rbp is allocatable, and saved like the other callee-saved registers when used.
+fn has_frame_pointer(flags: &shared_settings::Flags, call_conv: CallConv) -> bool {
+    !flags.omit_frame_pointer() || call_conv.extends_baldrdash()
+}
+
 /// Get the set of callee-saved registers.
 fn callee_saved_gprs(isa: &dyn TargetIsa, call_conv: CallConv) -> &'static [RU] {
     match isa.triple().pointer_width().unwrap() {
@@ -294,6 +368,9 @@ fn callee_saved_gprs_used(isa: &dyn TargetIsa, func: &ir::Function) -> RegisterS
     for reg in callee_saved_gprs(isa, func.signature.call_conv) {
         all_callee_saved.free(GPR, *reg as RegUnit);
     }
+    if !has_frame_pointer(isa.flags(), func.signature.call_conv) {
+        all_callee_saved.free(GPR, RU::rbp as RegUnit);
+    }
 
     let mut used = RegisterSet::empty();
     for value_loc in func.locations.values() {
@@ -307,7 +384,7 @@ fn callee_saved_gprs_used(isa: &dyn TargetIsa, func: &ir::Function) -> RegisterS
         }
     }
 
-    // regmove and regfill instructions may temporarily divert values into other registers,
+    // regmove, regswap and regfill instructions may temporarily divert values into other registers,
     // and these are not reflected in `func.locations`. Scan the function for such instructions
     // and note which callee-saved registers they use.
     //
@@ -322,6 +399,13 @@ fn callee_saved_gprs_used(isa: &dyn TargetIsa, func: &ir::Function) -> RegisterS
                         used.free(GPR, dst);
                     }
                 }
+                ir::instructions::InstructionData::RegSwap { src, dst, .. } => {
+                    for reg in &[src, dst] {
+                        if !used.is_avail(GPR, *reg) {
+                            used.free(GPR, *reg);
+                        }
+                    }
+                }
                 _ => (),
             }
         }
@@ -396,14 +480,15 @@ fn fastcall_prologue_epilogue(func: &mut ir::Function, isa: &dyn TargetIsa) -> C
     //  you don’t have to use them as such"
     //
     // The reserved stack area is composed of:
-    //   return address + frame pointer + all callee-saved registers + shadow space
+    //   return address + frame pointer (unless omitted) + all callee-saved registers + shadow space
     //
     // Pushing the return address is an implicit function of the `call`
     // instruction. Each of the others we will then push explicitly. Then we
     // will adjust the stack pointer to make room for the rest of the required
     // space for this frame.
     const SHADOW_STORE_SIZE: i32 = 32;
-    let csr_stack_size = ((csrs.iter(GPR).len() + 2) * word_size) as i32;
+    let frame_pointer = has_frame_pointer(isa.flags(), func.signature.call_conv);
+    let csr_stack_size = ((csrs.iter(GPR).len() + 1 + frame_pointer as usize) * word_size) as i32;
 
     // TODO: eventually use the 32 bytes (shadow store) as spill slot. This currently doesn't work
     //       since cranelift does not support spill slots before incoming args
@@ -418,13 +503,15 @@ fn fastcall_prologue_epilogue(func: &mut ir::Function, isa: &dyn TargetIsa) -> C
     let local_stack_size = i64::from(total_stack_size - csr_stack_size);
 
     // Add CSRs to function signature
-    let fp_arg = ir::AbiParam::special_reg(
-        reg_type,
-        ir::ArgumentPurpose::FramePointer,
-        RU::rbp as RegUnit,
-    );
-    func.signature.params.push(fp_arg);
-    func.signature.returns.push(fp_arg);
+    if frame_pointer {
+        let fp_arg = ir::AbiParam::special_reg(
+            reg_type,
+            ir::ArgumentPurpose::FramePointer,
+            RU::rbp as RegUnit,
+        );
+        func.signature.params.push(fp_arg);
+        func.signature.returns.push(fp_arg);
+    }
 
     for csr in csrs.iter(GPR) {
         let csr_arg = ir::AbiParam::special_reg(reg_type, ir::ArgumentPurpose::CalleeSaved, csr);
@@ -439,7 +526,7 @@ fn fastcall_prologue_epilogue(func: &mut ir::Function, isa: &dyn TargetIsa) -> C
 
     // Reset the cursor and insert the epilogue
     let mut pos = pos.at_position(CursorPosition::Nowhere);
-    insert_common_epilogues(&mut pos, local_stack_size, reg_type, &csrs);
+    insert_common_epilogues(&mut pos, local_stack_size, reg_type, &csrs, None);
 
     Ok(())
 }
@@ -455,14 +542,34 @@ fn system_v_prologue_epilogue(func: &mut ir::Function, isa: &dyn TargetIsa) -> C
 
     let csrs = callee_saved_gprs_used(isa, func);
 
+    let entry_ebb = func.layout.entry_block().expect("missing entry block");
+    let save_ebb = if isa.flags().enable_shrink_wrapping() {
+        match shrink_wrap::save_point(func, isa, &csrs) {
+            Some(ebb) => ebb,
+            None => {
+                // This function doesn't need a frame, so the return address is all there is.
+                func.create_stack_slot(ir::StackSlotData {
+                    kind: ir::StackSlotKind::IncomingArg,
+                    size: word_size as u32,
+                    offset: Some(-(word_size as StackOffset)),
+                });
+                layout_stack(&mut func.stack_slots, stack_align)?;
+                return Ok(());
+            }
+        }
+    } else {
+        entry_ebb
+    };
+
     // The reserved stack area is composed of:
-    //   return address + frame pointer + all callee-saved registers
+    //   return address + frame pointer (unless omitted) + all callee-saved registers
     //
     // Pushing the return address is an implicit function of the `call`
     // instruction. Each of the others we will then push explicitly. Then we
     // will adjust the stack pointer to make room for the rest of the required
     // space for this frame.
-    let csr_stack_size = ((csrs.iter(GPR).len() + 2) * word_size) as i32;
+    let frame_pointer = has_frame_pointer(isa.flags(), func.signature.call_conv);
+    let csr_stack_size = ((csrs.iter(GPR).len() + 1 + frame_pointer as usize) * word_size) as i32;
     func.create_stack_slot(ir::StackSlotData {
         kind: ir::StackSlotKind::IncomingArg,
         size: csr_stack_size as u32,
@@ -472,14 +579,26 @@ fn system_v_prologue_epilogue(func: &mut ir::Function, isa: &dyn TargetIsa) -> C
     let total_stack_size = layout_stack(&mut func.stack_slots, stack_align)? as i32;
     let local_stack_size = i64::from(total_stack_size - csr_stack_size);
 
+    // The stack check and the stack probe use %rax, which is only known to be free on entry.
+    let save_ebb = if func.special_param(ArgumentPurpose::StackLimit).is_some()
+        || (isa.flags().probestack_enabled()
+            && local_stack_size > (1 << isa.flags().probestack_size_log2()))
+    {
+        entry_ebb
+    } else {
+        save_ebb
+    };
+
     // Add CSRs to function signature
-    let fp_arg = ir::AbiParam::special_reg(
-        reg_type,
-        ir::ArgumentPurpose::FramePointer,
-        RU::rbp as RegUnit,
-    );
-    func.signature.params.push(fp_arg);
-    func.signature.returns.push(fp_arg);
+    if frame_pointer {
+        let fp_arg = ir::AbiParam::special_reg(
+            reg_type,
+            ir::ArgumentPurpose::FramePointer,
+            RU::rbp as RegUnit,
+        );
+        func.signature.params.push(fp_arg);
+        func.signature.returns.push(fp_arg);
+    }
 
     for csr in csrs.iter(GPR) {
         let csr_arg = ir::AbiParam::special_reg(reg_type, ir::ArgumentPurpose::CalleeSaved, csr);
@@ -488,13 +607,24 @@ fn system_v_prologue_epilogue(func: &mut ir::Function, isa: &dyn TargetIsa) -> C
     }
 
     // Set up the cursor and insert the prologue
-    let entry_ebb = func.layout.entry_block().expect("missing entry block");
-    let mut pos = EncCursor::new(func, isa).at_first_insertion_point(entry_ebb);
+    let mut pos = EncCursor::new(func, isa).at_first_insertion_point(save_ebb);
     insert_common_prologue(&mut pos, local_stack_size, reg_type, &csrs, isa);
 
     // Reset the cursor and insert the epilogue
     let mut pos = pos.at_position(CursorPosition::Nowhere);
-    insert_common_epilogues(&mut pos, local_stack_size, reg_type, &csrs);
+    if save_ebb == entry_ebb {
+        insert_common_epilogues(&mut pos, local_stack_size, reg_type, &csrs, None);
+    } else {
+        let cfg = ControlFlowGraph::with_function(pos.func);
+        let domtree = DominatorTree::with_function(pos.func, &cfg);
+        insert_common_epilogues(
+            &mut pos,
+            local_stack_size,
+            reg_type,
+            &csrs,
+            Some((save_ebb, &domtree)),
+        );
+    }
 
     Ok(())
 }
@@ -517,20 +647,24 @@ fn insert_common_prologue(
             // also should be accounted for.
             // TODO: Check if the function body actually contains a `call` instruction.
             let word_size = isa.pointer_bytes();
-            let total_stack_size = (csrs.iter(GPR).len() + 1 + 1) as i64 * word_size as i64;
+            let frame_pointer = has_frame_pointer(isa.flags(), pos.func.signature.call_conv);
+            let total_stack_size =
+                (csrs.iter(GPR).len() + 1 + frame_pointer as usize) as i64 * word_size as i64;
 
             insert_stack_check(pos, total_stack_size, stack_limit_arg);
         }
     }
 
-    // Append param to entry EBB
-    let ebb = pos.current_ebb().expect("missing ebb under cursor");
-    let fp = pos.func.dfg.append_ebb_param(ebb, reg_type);
-    pos.func.locations[fp] = ir::ValueLoc::Reg(RU::rbp as RegUnit);
+    // Append param to entry EBB. With shrink-wrapping, the cursor may be in a different EBB.
+    let ebb = pos.func.layout.entry_block().expect("missing entry block");
+    if has_frame_pointer(isa.flags(), pos.func.signature.call_conv) {
+        let fp = pos.func.dfg.append_ebb_param(ebb, reg_type);
+        pos.func.locations[fp] = ir::ValueLoc::Reg(RU::rbp as RegUnit);
 
-    pos.ins().x86_push(fp);
-    pos.ins()
-        .copy_special(RU::rsp as RegUnit, RU::rbp as RegUnit);
+        pos.ins().x86_push(fp);
+        pos.ins()
+            .copy_special(RU::rsp as RegUnit, RU::rbp as RegUnit);
+    }
 
     for reg in csrs.iter(GPR) {
         // Append param to entry EBB
@@ -613,17 +747,37 @@ fn insert_stack_check(pos: &mut EncCursor, stack_size: i64, stack_limit_arg: ir:
 }
 
 /// Find all `return` instructions and insert epilogues before them.
+///
+/// When the prologue has been shrink-wrapped into the EBB `save`, the returns that aren't
+/// dominated by it don't get an epilogue. They return the incoming frame pointer and
+/// callee-saved registers as they are.
 fn insert_common_epilogues(
     pos: &mut EncCursor,
     stack_size: i64,
     reg_type: ir::types::Type,
     csrs: &RegisterSet,
+    save: Option<(ir::Ebb, &DominatorTree)>,
 ) {
     while let Some(ebb) = pos.next_ebb() {
         pos.goto_last_inst(ebb);
         if let Some(inst) = pos.current_inst() {
             if pos.func.dfg[inst].opcode().is_return() {
-                insert_common_epilogue(inst, stack_size, pos, reg_type, csrs);
+                match save {
+                    Some((save_ebb, domtree))
+                        if !domtree.dominates(save_ebb, inst, &pos.func.layout) =>
+                    {
+                        let frame_pointer =
+                            has_frame_pointer(pos.isa.flags(), pos.func.signature.call_conv);
+                        let entry = pos.func.layout.entry_block().unwrap();
+                        let params = pos.func.dfg.ebb_params(entry);
+                        let num_saved = csrs.iter(GPR).len() + frame_pointer as usize;
+                        let saved = params[params.len() - num_saved..].to_vec();
+                        for value in saved {
+                            pos.func.dfg.append_inst_arg(inst, value);
+                        }
+                    }
+                    _ => insert_common_epilogue(inst, stack_size, pos, reg_type, csrs),
+                }
             }
         }
     }
@@ -644,11 +798,13 @@ fn insert_common_epilogue(
 
     // Pop all the callee-saved registers, stepping backward each time to
     // preserve the correct order.
-    let fp_ret = pos.ins().x86_pop(reg_type);
-    pos.prev_inst();
+    if has_frame_pointer(pos.isa.flags(), pos.func.signature.call_conv) {
+        let fp_ret = pos.ins().x86_pop(reg_type);
+        pos.prev_inst();
 
-    pos.func.locations[fp_ret] = ir::ValueLoc::Reg(RU::rbp as RegUnit);
-    pos.func.dfg.append_inst_arg(inst, fp_ret);
+        pos.func.locations[fp_ret] = ir::ValueLoc::Reg(RU::rbp as RegUnit);
+        pos.func.dfg.append_inst_arg(inst, fp_ret);
+    }
 
     for reg in csrs.iter(GPR) {
         let csr_ret = pos.ins().x86_pop(reg_type);
@@ -161,6 +161,80 @@ fn put_rexmp3<CS: CodeSink + ?Sized>(bits: u16, rex: u8, sink: &mut CS) {
     sink.put1(bits as u8);
 }
 
+// Emit a three-byte VEX prefix followed by the opcode byte: C4 RXBmmmmm WvvvvLpp XX
+//
+// The R, X, and B bits are taken from a REX prefix computed with the functions above, and are
+// stored inverted like the `vvvv` register. The W, pp and mm bits are extracted from `bits`.
+fn vex_prefix<CS: CodeSink + ?Sized>(bits: u16, rex: u8, vvvv: RegUnit, l: u8, sink: &mut CS) {
+    debug_assert_eq!(rex & 0xf8, BASE_REX);
+    debug_assert_ne!(bits & 0x0c00, 0, "Invalid encoding bits for VEX");
+    let rxb = (!(rex << 5)) & 0xe0;
+    let mm = ((bits >> 10) & 3) as u8;
+    let w = ((bits >> 15) & 1) as u8;
+    let vvvv = (!(vvvv as u8) & 0xf) << 3;
+    let pp = ((bits >> 8) & 3) as u8;
+    sink.put1(0xc4);
+    sink.put1(rxb | mm);
+    sink.put1((w << 7) | vvvv | (l << 2) | pp);
+    sink.put1(bits as u8);
+}
+
+// Emit a VEX.128 opcode which doesn't use the `vvvv` register.
+fn put_vex128<CS: CodeSink + ?Sized>(bits: u16, rex: u8, sink: &mut CS) {
+    // The unused `vvvv` field must be 0b1111, which is the inverted encoding of register 0.
+    vex_prefix(bits, rex, 0, 0, sink);
+}
+
+// Emit a VEX.256 opcode which doesn't use the `vvvv` register.
+fn put_vex256<CS: CodeSink + ?Sized>(bits: u16, rex: u8, sink: &mut CS) {
+    vex_prefix(bits, rex, 0, 1, sink);
+}
+
+// Emit a VEX.128 opcode with a non-destructive source operand in `vvvv`.
+fn put_vex128_nds<CS: CodeSink + ?Sized>(bits: u16, rex: u8, nds: RegUnit, sink: &mut CS) {
+    vex_prefix(bits, rex, nds, 0, sink);
+}
+
+// Emit a VEX.256 opcode with a non-destructive source operand in `vvvv`.
+fn put_vex256_nds<CS: CodeSink + ?Sized>(bits: u16, rex: u8, nds: RegUnit, sink: &mut CS) {
+    vex_prefix(bits, rex, nds, 1, sink);
+}
+
+// Emit a four-byte EVEX prefix followed by the opcode byte: 62 RXBR'00mm Wvvvv1pp zL'Lb'V'aaa XX
+//
+// The R, X, and B bits are taken from a REX prefix computed with the functions above. Only the
+// first 16 vector registers are used, so the inverted R' and V' bits are always set. Neither
+// writemasks nor embedded broadcasts and roundings are used.
+fn evex_prefix<CS: CodeSink + ?Sized>(bits: u16, rex: u8, vvvv: RegUnit, ll: u8, sink: &mut CS) {
+    debug_assert_eq!(rex & 0xf8, BASE_REX);
+    debug_assert_ne!(bits & 0x0c00, 0, "Invalid encoding bits for EVEX");
+    let rxb = (!(rex << 5)) & 0xe0;
+    let mm = ((bits >> 10) & 3) as u8;
+    let w = ((bits >> 15) & 1) as u8;
+    let vvvv = (!(vvvv as u8) & 0xf) << 3;
+    let pp = ((bits >> 8) & 3) as u8;
+    sink.put1(0x62);
+    sink.put1(rxb | 0b0001_0000 | mm);
+    sink.put1((w << 7) | vvvv | 0b100 | pp);
+    sink.put1((ll << 5) | 0b1000);
+    sink.put1(bits as u8);
+}
+
+// Emit an EVEX.512 opcode which doesn't use the `vvvv` register.
+fn put_evex512<CS: CodeSink + ?Sized>(bits: u16, rex: u8, sink: &mut CS) {
+    evex_prefix(bits, rex, 0, 2, sink);
+}
+
+// Emit an EVEX.128 opcode with a non-destructive source operand in `vvvv`.
+fn put_evex128_nds<CS: CodeSink + ?Sized>(bits: u16, rex: u8, nds: RegUnit, sink: &mut CS) {
+    evex_prefix(bits, rex, nds, 0, sink);
+}
+
+// Emit an EVEX.512 opcode with a non-destructive source operand in `vvvv`.
+fn put_evex512_nds<CS: CodeSink + ?Sized>(bits: u16, rex: u8, nds: RegUnit, sink: &mut CS) {
+    evex_prefix(bits, rex, nds, 2, sink);
+}
+
 /// Emit a ModR/M byte for reg-reg operands.
 fn modrm_rr<CS: CodeSink + ?Sized>(rm: RegUnit, reg: RegUnit, sink: &mut CS) {
     let reg = reg as u8 & 7;
@@ -291,6 +365,27 @@ fn icc2opc(cond: IntCC) -> u16 {
     }
 }
 
+/// Get the opcode and the comparison predicate immediate of an AVX-512 integer comparison.
+///
+/// The encoding bits are for the signed VPCMP* opcodes, and the unsigned VPCMPU* opcodes precede
+/// them.
+fn vpcmp_opcode(bits: u16, cond: IntCC) -> (u16, u8) {
+    use crate::ir::condcodes::IntCC::*;
+    match cond {
+        Equal => (bits, 0),
+        SignedLessThan => (bits, 1),
+        SignedLessThanOrEqual => (bits, 2),
+        NotEqual => (bits, 4),
+        SignedGreaterThanOrEqual => (bits, 5),
+        SignedGreaterThan => (bits, 6),
+        UnsignedLessThan => (bits - 1, 1),
+        UnsignedLessThanOrEqual => (bits - 1, 2),
+        UnsignedGreaterThanOrEqual => (bits - 1, 5),
+        UnsignedGreaterThan => (bits - 1, 6),
+        Overflow | NotOverflow => panic!("no vector comparison for {}", cond),
+    }
+}
+
 /// Get the low 4 bits of an opcode for a floating point condition code.
 ///
 /// The ucomiss/ucomisd instructions set the FLAGS bits CF/PF/CF like this:
@@ -904,7 +904,7 @@ fn convert_shuffle(
     inst: ir::Inst,
     func: &mut ir::Function,
     _cfg: &mut ControlFlowGraph,
-    _isa: &dyn TargetIsa,
+    isa: &dyn TargetIsa,
 ) {
     let mut pos = FuncCursor::new(func).at_inst(inst);
     pos.use_srcloc(inst);
@@ -925,7 +925,23 @@ fn convert_shuffle(
             .get(mask)
             .expect("The shuffle immediate should have been recorded before this point")
             .clone();
-        if a == b {
+        let a_type = pos.func.dfg.value_type(a);
+        // With AVX-512 VBMI, VPERMT2B selects bytes from both arguments with a single mask, as
+        // long as no lane needs to be zeroed.
+        let vpermt2b = ir::InstructionData::Ternary {
+            opcode: ir::Opcode::X86Vpermt2b,
+            args: [a, b, a],
+        };
+        if a != b
+            && mask.iter().all(|&lane| lane < 32)
+            && isa.encode(pos.func, &vpermt2b, a_type).is_ok()
+        {
+            let handle = pos.func.dfg.constants.insert(mask);
+            // Move the mask into another XMM register.
+            let mask_value = pos.ins().vconst(a_type, handle);
+            // Shuffle both arguments at once.
+            pos.func.dfg.replace(inst).x86_vpermt2b(a, b, mask_value);
+        } else if a == b {
             // PSHUFB the first argument (since it is the same as the second).
             let constructed_mask = mask
                 .iter()
@@ -935,7 +951,6 @@ fn convert_shuffle(
                 .collect();
             let handle = pos.func.dfg.constants.insert(constructed_mask);
             // Move the built mask into another XMM register.
-            let a_type = pos.func.dfg.value_type(a);
             let mask_value = pos.ins().vconst(a_type, handle);
             // Shuffle the single incoming argument.
             pos.func.dfg.replace(inst).x86_pshufb(a, mask_value);
@@ -944,7 +959,6 @@ fn convert_shuffle(
             let constructed_mask = mask.iter().cloned().map(zero_unknown_lane_index).collect();
             let handle = pos.func.dfg.constants.insert(constructed_mask);
             // Move the built mask into another XMM register.
-            let a_type = pos.func.dfg.value_type(a);
             let mask_value = pos.ins().vconst(a_type, handle);
             // Shuffle the first argument.
             let shuffled_first_arg = pos.ins().x86_pshufb(a, mask_value);
@@ -967,8 +981,6 @@ fn convert_shuffle(
                 .dfg
                 .replace(inst)
                 .bor(shuffled_first_arg, shuffled_second_arg);
-
-            // TODO when AVX512 is enabled we should replace this sequence with a single VPERMB
         };
     }
 }
@@ -1111,6 +1123,11 @@ fn convert_ineg(
             let zero_immediate = pos.func.dfg.constants.insert(vec![0; 16].into());
             let zero_value = pos.ins().vconst(value_type, zero_immediate); // this should be legalized to a PXOR
             pos.func.dfg.replace(inst).isub(zero_value, arg);
+        } else {
+            // Integers that are too wide for a register, such as `i128`, are subtracted from
+            // zero and narrowed later.
+            let zero_value = pos.ins().iconst(value_type, 0);
+            pos.func.dfg.replace(inst).isub(zero_value, arg);
         }
     }
 }
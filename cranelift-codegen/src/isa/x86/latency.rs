@@ -0,0 +1,76 @@
+//! Instruction latencies for the x86 instruction scheduler.
+//!
+//! These are rough figures for recent Intel and AMD cores, in cycles. Loads are assumed to hit in
+//! the L1 cache.
+
+use crate::ir::Opcode;
+
+/// Get the latency of an instruction with the given opcode.
+pub fn latency(opcode: Opcode) -> u32 {
+    match opcode {
+        Opcode::Load
+        | Opcode::LoadComplex
+        | Opcode::Uload8
+        | Opcode::Uload8Complex
+        | Opcode::Sload8
+        | Opcode::Sload8Complex
+        | Opcode::Uload16
+        | Opcode::Uload16Complex
+        | Opcode::Sload16
+        | Opcode::Sload16Complex
+        | Opcode::Uload32
+        | Opcode::Uload32Complex
+        | Opcode::Sload32
+        | Opcode::Sload32Complex
+        | Opcode::StackLoad
+        | Opcode::X86Pop => 4,
+
+        Opcode::Imul
+        | Opcode::ImulImm
+        | Opcode::Umulhi
+        | Opcode::Smulhi
+        | Opcode::X86Umulx
+        | Opcode::X86Smulx
+        | Opcode::Popcnt
+        | Opcode::Clz
+        | Opcode::Ctz
+        | Opcode::X86Bsr
+        | Opcode::X86Bsf => 3,
+
+        Opcode::Udiv
+        | Opcode::Sdiv
+        | Opcode::Urem
+        | Opcode::Srem
+        | Opcode::X86Udivmodx
+        | Opcode::X86Sdivmodx => 26,
+
+        Opcode::Fadd
+        | Opcode::Fsub
+        | Opcode::Fmul
+        | Opcode::Fma
+        | Opcode::Fmin
+        | Opcode::Fmax
+        | Opcode::X86Fmin
+        | Opcode::X86Fmax
+        | Opcode::Fcmp
+        | Opcode::Ffcmp
+        | Opcode::Fpromote
+        | Opcode::Fdemote
+        | Opcode::FcvtFromSint
+        | Opcode::FcvtFromUint
+        | Opcode::FcvtToSint
+        | Opcode::FcvtToSintSat
+        | Opcode::FcvtToUint
+        | Opcode::FcvtToUintSat
+        | Opcode::X86Cvtt2si
+        | Opcode::Ceil
+        | Opcode::Floor
+        | Opcode::Trunc
+        | Opcode::Nearest => 4,
+
+        Opcode::Fdiv => 14,
+        Opcode::Sqrt => 18,
+
+        _ => 1,
+    }
+}
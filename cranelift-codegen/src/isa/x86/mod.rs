@@ -3,8 +3,10 @@
 mod abi;
 mod binemit;
 mod enc_tables;
+mod latency;
 mod registers;
 pub mod settings;
+mod shrink_wrap;
 
 use super::super::settings as shared_settings;
 #[cfg(feature = "testing_hooks")]
@@ -119,8 +121,8 @@ impl TargetIsa for Isa {
         abi::regclass_for_abi_type(ty)
     }
 
-    fn allocatable_registers(&self, _func: &ir::Function) -> regalloc::RegisterSet {
-        abi::allocatable_registers(&self.triple, &self.shared_flags)
+    fn allocatable_registers(&self, func: &ir::Function) -> regalloc::RegisterSet {
+        abi::allocatable_registers(&self.triple, &self.shared_flags, func.signature.call_conv)
     }
 
     #[cfg(feature = "testing_hooks")]
@@ -143,6 +145,10 @@ impl TargetIsa for Isa {
         abi::prologue_epilogue(func, self)
     }
 
+    fn instruction_latency(&self, opcode: ir::Opcode) -> Option<u32> {
+        Some(latency::latency(opcode))
+    }
+
     fn unsigned_add_overflow_condition(&self) -> ir::condcodes::IntCC {
         ir::condcodes::IntCC::UnsignedLessThan
     }
@@ -0,0 +1,276 @@
+//! Shrink-wrapping of the x86 prologue and epilogue.
+//!
+//! The prologue normally sets up the frame and saves the callee-saved registers on entry to the
+//! function, and every `return` restores them. When only some paths through the function need a
+//! frame, for example a rarely taken slow path containing a call, this work is wasted on the
+//! other paths.
+//!
+//! Shrink-wrapping moves the prologue to a single *save point*: an EBB that dominates all the
+//! EBBs needing the frame. The save point must not be part of a loop, and all EBBs reachable from
+//! it must be dominated by it. The latter guarantees that any `return` reached after the prologue
+//! is dominated by it, so the epilogue is inserted in exactly the returns that need it. Returns
+//! that can't be reached from the save point return directly to the caller.
+//!
+//! Functions that don't need a frame at all, typically small leaf functions, get no prologue or
+//! epilogue.
+
+use super::registers::GPR;
+use crate::cursor::{Cursor, EncCursor};
+use crate::dominator_tree::DominatorTree;
+use crate::flowgraph::ControlFlowGraph;
+use crate::ir::{Ebb, Function, InstBuilder, InstructionData, Opcode, Value, ValueDef, ValueLoc};
+use crate::isa::TargetIsa;
+use crate::regalloc::{RegDiversions, RegisterSet};
+use alloc::vec::Vec;
+
+/// Find the EBB where the prologue should be inserted.
+///
+/// Returns `None` if the function doesn't need a frame at all. This may duplicate EBBs that only
+/// return, so that the region dominated by the save point can be made closed.
+pub fn save_point(func: &mut Function, isa: &dyn TargetIsa, csrs: &RegisterSet) -> Option<Ebb> {
+    let entry = func.layout.entry_block().expect("missing entry block");
+    let need = frame_ebbs(func, csrs);
+    if need.is_empty() {
+        // Values that are no longer in the layout may still have a callee-saved register
+        // assigned. Keep things simple and save them on entry.
+        return if csrs.iter(GPR).len() == 0 {
+            None
+        } else {
+            Some(entry)
+        };
+    }
+
+    let mut cfg = ControlFlowGraph::with_function(func);
+    let mut domtree = DominatorTree::with_function(func, &cfg);
+
+    // Start from the nearest common dominator of the EBBs that need the frame.
+    let mut save = need[0];
+    for &ebb in &need[1..] {
+        while !domtree.dominates(save, ebb, &func.layout) {
+            save = idom_ebb(func, &domtree, save);
+        }
+    }
+
+    loop {
+        if save == entry {
+            return Some(entry);
+        }
+
+        // A loop header would run the prologue once per iteration, and the prologue clobbers
+        // the CPU flags, so no flags value may be live across it.
+        if flags_live_in(func, &cfg, save)
+            || cfg
+                .pred_iter(save)
+                .any(|pred| domtree.dominates(save, pred.ebb, &func.layout))
+        {
+            save = idom_ebb(func, &domtree, save);
+            continue;
+        }
+
+        let exits = region_exits(func, &cfg, &domtree, save);
+        if exits.is_empty() {
+            return Some(save);
+        }
+
+        // Edges leaving the region are fine if they go to an EBB that only returns; give the
+        // region its own copy of such EBBs.
+        if exits
+            .iter()
+            .all(|&(pred, succ)| can_duplicate(func, pred, succ))
+        {
+            for (pred, succ) in exits {
+                duplicate_return(func, isa, pred, succ);
+            }
+            cfg.compute(func);
+            domtree.compute(func, &cfg);
+            continue;
+        }
+
+        save = idom_ebb(func, &domtree, save);
+    }
+}
+
+/// Get the EBBs that contain instructions that need the stack frame or use a callee-saved
+/// register, in layout order.
+fn frame_ebbs(func: &Function, csrs: &RegisterSet) -> Vec<Ebb> {
+    let needs_loc = |value: Value| match func.locations[value] {
+        ValueLoc::Stack(_) => true,
+        ValueLoc::Reg(reg) => csrs.is_avail(GPR, reg),
+        ValueLoc::Unassigned => false,
+    };
+
+    func.layout
+        .ebbs()
+        .filter(|&ebb| {
+            func.dfg.ebb_params(ebb).iter().any(|&v| needs_loc(v))
+                || func.layout.ebb_insts(ebb).any(|inst| {
+                    let opcode = func.dfg[inst].opcode();
+                    opcode.is_call()
+                        || match opcode {
+                            Opcode::X86Push
+                            | Opcode::X86Pop
+                            | Opcode::AdjustSpDown
+                            | Opcode::AdjustSpUpImm
+                            | Opcode::AdjustSpDownImm
+                            | Opcode::IfcmpSp
+                            | Opcode::CopySpecial => true,
+                            _ => false,
+                        }
+                        || match func.dfg[inst] {
+                            InstructionData::StackLoad { .. }
+                            | InstructionData::StackStore { .. }
+                            | InstructionData::RegSpill { .. } => true,
+                            InstructionData::RegMove { dst, .. }
+                            | InstructionData::RegFill { dst, .. } => csrs.is_avail(GPR, dst),
+                            InstructionData::RegSwap { src, dst, .. } => {
+                                csrs.is_avail(GPR, src) || csrs.is_avail(GPR, dst)
+                            }
+                            _ => false,
+                        }
+                        || func.dfg.inst_args(inst).iter().any(|&v| needs_loc(v))
+                        || func.dfg.inst_results(inst).iter().any(|&v| needs_loc(v))
+                })
+        })
+        .collect()
+}
+
+/// Is a CPU flags value defined outside of `ebb` live on entry to it?
+///
+/// A flags value may be live through `ebb` without being used in it, so look for a use that can
+/// be reached from `ebb` without going through the definition of the value.
+fn flags_live_in(func: &Function, cfg: &ControlFlowGraph, ebb: Ebb) -> bool {
+    let mut uses: Vec<(Value, Ebb)> = Vec::new();
+    for use_ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(use_ebb) {
+            for &arg in func.dfg.inst_args(inst) {
+                if func.dfg.value_type(arg).is_flags() && !uses.contains(&(arg, use_ebb)) {
+                    uses.push((arg, use_ebb));
+                }
+            }
+        }
+    }
+
+    let def_ebb = |value: Value| match func.dfg.value_def(value) {
+        ValueDef::Result(inst, _) => func.layout.inst_ebb(inst),
+        ValueDef::Param(param_ebb, _) => Some(param_ebb),
+    };
+    let mut values: Vec<Value> = uses.iter().map(|&(value, _)| value).collect();
+    values.sort();
+    values.dedup();
+    values.into_iter().any(|value| {
+        let def = def_ebb(value);
+        if def == Some(ebb) {
+            return false;
+        }
+        let mut visited = vec![ebb];
+        let mut worklist = vec![ebb];
+        while let Some(cur) = worklist.pop() {
+            if uses.contains(&(value, cur)) {
+                return true;
+            }
+            for succ in cfg.succ_iter(cur) {
+                if Some(succ) != def && !visited.contains(&succ) {
+                    visited.push(succ);
+                    worklist.push(succ);
+                }
+            }
+        }
+        false
+    })
+}
+
+/// Get the EBB containing the immediate dominator of `ebb`.
+fn idom_ebb(func: &Function, domtree: &DominatorTree, ebb: Ebb) -> Ebb {
+    let idom = domtree.idom(ebb).expect("unreachable EBB");
+    func.layout
+        .inst_ebb(idom)
+        .expect("dangling idom instruction")
+}
+
+/// Get the CFG edges leaving the region of EBBs reachable from `save` to an EBB that isn't
+/// dominated by `save`.
+fn region_exits(
+    func: &Function,
+    cfg: &ControlFlowGraph,
+    domtree: &DominatorTree,
+    save: Ebb,
+) -> Vec<(Ebb, Ebb)> {
+    let mut exits = Vec::new();
+    let mut visited = vec![save];
+    let mut worklist = vec![save];
+    while let Some(ebb) = worklist.pop() {
+        for succ in cfg.succ_iter(ebb) {
+            if !domtree.dominates(save, succ, &func.layout) {
+                if !exits.contains(&(ebb, succ)) {
+                    exits.push((ebb, succ));
+                }
+            } else if !visited.contains(&succ) {
+                visited.push(succ);
+                worklist.push(succ);
+            }
+        }
+    }
+    exits
+}
+
+/// Can `succ` be duplicated to give `pred` its own copy?
+///
+/// This is the case when `succ` contains nothing but a `return` of its own parameters, and all
+/// the branches from `pred` to `succ` can be redirected.
+fn can_duplicate(func: &Function, pred: Ebb, succ: Ebb) -> bool {
+    let mut insts = func.layout.ebb_insts(succ);
+    let ret = match (insts.next(), insts.next()) {
+        (Some(inst), None) if func.dfg[inst].opcode() == Opcode::Return => inst,
+        _ => return false,
+    };
+    let params = func.dfg.ebb_params(succ);
+    if !func.dfg.inst_args(ret).iter().all(|v| params.contains(v)) {
+        return false;
+    }
+
+    let mut divert = RegDiversions::new();
+    divert.at_ebb(&func.entry_diversions, succ);
+    if !divert.is_empty() {
+        return false;
+    }
+
+    func.layout.ebb_insts(pred).all(|inst| {
+        let data = &func.dfg[inst];
+        data.opcode() != Opcode::Fallthrough
+            && (data.branch_destination() != None || !data.opcode().is_branch())
+    })
+}
+
+/// Make a copy of the return EBB `succ` and redirect all branches in `pred` to it.
+fn duplicate_return(func: &mut Function, isa: &dyn TargetIsa, pred: Ebb, succ: Ebb) {
+    let copy = func.dfg.make_ebb();
+    func.layout.append_ebb(copy);
+
+    let params = func.dfg.ebb_params(succ).to_vec();
+    let mut new_params = Vec::with_capacity(params.len());
+    for &param in &params {
+        let ty = func.dfg.value_type(param);
+        let new_param = func.dfg.append_ebb_param(copy, ty);
+        func.locations[new_param] = func.locations[param];
+        new_params.push(new_param);
+    }
+
+    let ret = func.layout.last_inst(succ).expect("empty return EBB");
+    let args: Vec<Value> = func
+        .dfg
+        .inst_args(ret)
+        .iter()
+        .map(|arg| new_params[params.iter().position(|p| p == arg).unwrap()])
+        .collect();
+    let mut pos = EncCursor::new(func, isa).at_bottom(copy);
+    pos.ins().return_(&args);
+
+    let branches: Vec<_> = func
+        .layout
+        .ebb_insts(pred)
+        .filter(|&inst| func.dfg[inst].branch_destination() == Some(succ))
+        .collect();
+    for inst in branches {
+        func.change_branch_destination(inst, copy);
+    }
+}
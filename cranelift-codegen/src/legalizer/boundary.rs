@@ -16,17 +16,25 @@
 //!
 //! Between the two phases, preamble signatures and call/return arguments don't match. This
 //! intermediate state doesn't type check.
+//!
+//! Struct arguments and return values are lowered before the first phase, by rewriting the
+//! signatures, the entry block, and all calls and returns at once. After that, the IR type checks
+//! again.
 
-use crate::abi::{legalize_abi_value, ValueConversion};
+use crate::abi::{
+    legalize_abi_value, struct_in_memory, struct_passing, StructPassing, ValueConversion,
+};
 use crate::cursor::{Cursor, FuncCursor};
 use crate::flowgraph::ControlFlowGraph;
 use crate::ir::instructions::CallInfo;
 use crate::ir::{
-    AbiParam, ArgumentLoc, ArgumentPurpose, DataFlowGraph, Ebb, Function, Inst, InstBuilder,
-    SigRef, Signature, Type, Value, ValueLoc,
+    types, AbiParam, ArgumentLoc, ArgumentPurpose, DataFlowGraph, Ebb, Function, Inst, InstBuilder,
+    MemFlags, SigRef, Signature, StackSlot, StackSlotData, StackSlotKind, Type, Value, ValueList,
+    ValueLoc,
 };
-use crate::isa::TargetIsa;
+use crate::isa::{CallConv, TargetIsa};
 use crate::legalizer::split::{isplit, vsplit};
+use crate::result::{CodegenError, CodegenResult};
 use alloc::vec::Vec;
 use log::debug;
 
@@ -35,7 +43,9 @@ use log::debug;
 /// This changes all signatures to be ABI-compliant with full `ArgumentLoc` annotations. It doesn't
 /// change the entry block arguments, calls, or return instructions, so this can leave the function
 /// in a state with type discrepancies.
-pub fn legalize_signatures(func: &mut Function, isa: &dyn TargetIsa) {
+pub fn legalize_signatures(func: &mut Function, isa: &dyn TargetIsa) -> CodegenResult<()> {
+    legalize_struct_arguments(func, isa)?;
+
     legalize_signature(&mut func.signature, true, isa);
     for sig_data in func.dfg.signatures.values_mut() {
         legalize_signature(sig_data, false, isa);
@@ -45,6 +55,7 @@ pub fn legalize_signatures(func: &mut Function, isa: &dyn TargetIsa) {
         legalize_entry_params(func, entry);
         spill_entry_params(func, entry);
     }
+    Ok(())
 }
 
 /// Legalize the libcall signature, which we may generate on the fly after
@@ -60,6 +71,535 @@ fn legalize_signature(signature: &mut Signature, current: bool, isa: &dyn Target
     isa.legalize_signature(signature, current);
 }
 
+/// Lower the struct arguments and return values in all the signatures of `func`.
+///
+/// A struct passed in pieces is replaced by its pieces, and a struct passed by reference by a
+/// pointer to a copy made by the caller. A struct returned in memory is written by the callee to
+/// memory provided by the caller, which passes a pointer to it as a new `StructReturn` argument.
+/// The entry block, calls, and returns are rewritten to match.
+///
+/// Structs passed in memory keep their parameter until the argument locations are known. They
+/// are copied to the stack by `spill_call_arguments()`, and `legalize_entry_params()` gives the
+/// callee the address of its copy.
+fn legalize_struct_arguments(func: &mut Function, isa: &dyn TargetIsa) -> CodegenResult<()> {
+    check_struct_signature(&func.signature, isa)?;
+    for sig in func.dfg.signatures.values() {
+        check_struct_signature(sig, isa)?;
+    }
+
+    let pointer_type = isa.pointer_type();
+    pass_wide_ints_by_reference(func, pointer_type);
+
+    let old_signature = lower_struct_signature(&mut func.signature, pointer_type);
+    let mut old_signatures = Vec::new();
+    for (sig_ref, sig) in func.dfg.signatures.iter_mut() {
+        if let Some(old) = lower_struct_signature(sig, pointer_type) {
+            old_signatures.push((sig_ref, old));
+        }
+    }
+    if old_signature.is_none() && old_signatures.is_empty() {
+        return Ok(());
+    }
+
+    if let (Some(old), Some(entry)) = (&old_signature, func.layout.entry_block()) {
+        lower_struct_params(func, entry, old, pointer_type);
+    }
+
+    let mut pos = FuncCursor::new(func);
+    while let Some(_ebb) = pos.next_ebb() {
+        while let Some(inst) = pos.next_inst() {
+            if pos.func.dfg[inst].opcode().is_return() {
+                if let Some(old) = &old_signature {
+                    lower_struct_return(&mut pos, inst, old, pointer_type);
+                }
+            } else if let Some(sig_ref) = pos.func.dfg.call_signature(inst) {
+                if let Some((_, old)) = old_signatures.iter().find(|&&(s, _)| s == sig_ref) {
+                    lower_struct_call(&mut pos, inst, old, pointer_type);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that the struct arguments and return values in `sig` can be lowered for `isa`.
+fn check_struct_signature(sig: &Signature, isa: &dyn TargetIsa) -> CodegenResult<()> {
+    let call_conv = sig.call_conv;
+    let is_struct = |param: &AbiParam| {
+        struct_lowering(call_conv, param).is_some() || is_wide_int(call_conv, param)
+    };
+    if !sig.params.iter().chain(&sig.returns).any(is_struct) {
+        return Ok(());
+    }
+    if isa.name() != "x86" || isa.pointer_bits() != 64 {
+        return Err(CodegenError::Unsupported(format!(
+            "struct arguments on {} with {}-bit pointers",
+            isa.name(),
+            isa.pointer_bits()
+        )));
+    }
+    for param in sig.params.iter().chain(&sig.returns) {
+        if let ArgumentPurpose::StructArgument { align, .. } = param.purpose {
+            if !align.is_power_of_two() {
+                return Err(CodegenError::Unsupported(format!(
+                    "struct alignment {} which isn't a power of two in {}",
+                    align, sig
+                )));
+            }
+        }
+    }
+    let in_memory = sig
+        .returns
+        .iter()
+        .filter(|&ret| match struct_lowering(call_conv, ret) {
+            Some(StructPassing::Memory) | Some(StructPassing::Reference) => true,
+            _ => is_wide_int(call_conv, ret),
+        })
+        .count();
+    if in_memory > 1 {
+        return Err(CodegenError::Unsupported(format!(
+            "{} struct return values in memory in {}",
+            in_memory, sig
+        )));
+    }
+    Ok(())
+}
+
+/// Is `param` a 128-bit integer that the calling convention passes by reference?
+fn is_wide_int(call_conv: CallConv, param: &AbiParam) -> bool {
+    call_conv.extends_windows_fastcall()
+        && param.value_type == types::I128
+        && param.purpose == ArgumentPurpose::Normal
+        && !param.location.is_assigned()
+}
+
+/// Pass the 128-bit integers in the Windows signatures of `func` by reference.
+///
+/// The Windows x64 calling convention passes arguments that don't fit in 8 bytes by reference to
+/// a copy made by the caller, and returns them in memory provided by the caller. Such integers
+/// are passed as pointers to 16-byte copies, and returned as 16-byte structs, which are then
+/// lowered with the other struct arguments.
+fn pass_wide_ints_by_reference(func: &mut Function, pointer_type: Type) {
+    let old_signature = wide_int_signature(&mut func.signature, pointer_type);
+    let mut old_signatures = Vec::new();
+    for (sig_ref, sig) in func.dfg.signatures.iter_mut() {
+        if let Some(old) = wide_int_signature(sig, pointer_type) {
+            old_signatures.push((sig_ref, old));
+        }
+    }
+    if old_signature.is_none() && old_signatures.is_empty() {
+        return;
+    }
+
+    let flags = MemFlags::trusted();
+    if let (Some(old), Some(entry)) = (&old_signature, func.layout.entry_block()) {
+        let mut pos = FuncCursor::new(func).at_first_inst(entry);
+        let ebb_params = pos.func.dfg.ebb_params(entry).to_vec();
+        for (&arg, param) in ebb_params.iter().zip(&old.params) {
+            if is_wide_int(old.call_conv, param) {
+                let ptr = pos.func.dfg.replace_ebb_param(arg, pointer_type);
+                pos.ins().with_result(arg).load(types::I128, flags, ptr, 0);
+            }
+        }
+    }
+
+    let mut pos = FuncCursor::new(func);
+    while let Some(_ebb) = pos.next_ebb() {
+        while let Some(inst) = pos.next_inst() {
+            let old = if pos.func.dfg[inst].opcode().is_return() {
+                match &old_signature {
+                    Some(old) => old,
+                    None => continue,
+                }
+            } else if let Some(sig_ref) = pos.func.dfg.call_signature(inst) {
+                match old_signatures.iter().find(|&&(s, _)| s == sig_ref) {
+                    Some((_, old)) => old,
+                    None => continue,
+                }
+            } else {
+                continue;
+            };
+            pos.use_srcloc(inst);
+
+            // Returned integers are copied to memory like the arguments, and then copied again
+            // to the memory provided by the caller when the struct return value is lowered.
+            let params = if pos.func.dfg[inst].opcode().is_return() {
+                &old.returns
+            } else {
+                &old.params
+            };
+            let args = pos.func.dfg.inst_variable_args(inst).to_vec();
+            for (i, (&arg, param)) in args.iter().zip(params).enumerate() {
+                if is_wide_int(old.call_conv, param) {
+                    let ss = make_struct_slot(pos.func, 16, 16);
+                    pos.ins().stack_store(arg, ss, 0);
+                    let addr = pos.ins().stack_addr(pointer_type, ss, 0);
+                    pos.func.dfg.inst_variable_args_mut(inst)[i] = addr;
+                }
+            }
+
+            if !pos.func.dfg[inst].opcode().is_return() {
+                let results = pos.func.dfg.inst_results(inst).to_vec();
+                pos.next_inst();
+                for (&res, ret) in results.iter().zip(&old.returns) {
+                    if is_wide_int(old.call_conv, ret) {
+                        let ptr = pos.func.dfg.replace_result(res, pointer_type);
+                        pos.ins().with_result(res).load(types::I128, flags, ptr, 0);
+                    }
+                }
+                pos.goto_inst(inst);
+            }
+        }
+    }
+}
+
+/// Pass the 128-bit integers in `sig` by reference, if its calling convention requires it.
+///
+/// Returns the original signature if anything changed.
+fn wide_int_signature(sig: &mut Signature, pointer_type: Type) -> Option<Signature> {
+    let call_conv = sig.call_conv;
+    if sig
+        .params
+        .iter()
+        .chain(&sig.returns)
+        .all(|param| !is_wide_int(call_conv, param))
+    {
+        return None;
+    }
+
+    let old = sig.clone();
+    for param in &mut sig.params {
+        if is_wide_int(call_conv, param) {
+            *param = AbiParam::new(pointer_type);
+        }
+    }
+    for ret in &mut sig.returns {
+        if is_wide_int(call_conv, ret) {
+            *ret = AbiParam::special(
+                pointer_type,
+                ArgumentPurpose::StructArgument {
+                    size: 16,
+                    align: 16,
+                    floats: 0,
+                },
+            );
+        }
+    }
+    Some(old)
+}
+
+/// Get how `param` is passed if it is a struct argument that hasn't been lowered yet.
+fn struct_lowering(call_conv: CallConv, param: &AbiParam) -> Option<StructPassing> {
+    match param.purpose {
+        ArgumentPurpose::StructArgument { size, floats, .. } if !param.location.is_assigned() => {
+            Some(struct_passing(call_conv, size, floats))
+        }
+        _ => None,
+    }
+}
+
+/// Get the size and alignment of a struct argument.
+fn struct_layout(param: &AbiParam) -> (u32, u32) {
+    match param.purpose {
+        ArgumentPurpose::StructArgument { size, align, .. } => (size, align),
+        _ => panic!("{} is not a struct argument", param),
+    }
+}
+
+/// Lower the struct arguments and return values in `sig`.
+///
+/// Returns the original signature if anything changed.
+fn lower_struct_signature(sig: &mut Signature, pointer_type: Type) -> Option<Signature> {
+    let call_conv = sig.call_conv;
+    if sig
+        .params
+        .iter()
+        .chain(&sig.returns)
+        .all(|param| struct_lowering(call_conv, param).is_none())
+    {
+        return None;
+    }
+
+    let old = sig.clone();
+    sig.params.clear();
+    sig.returns.clear();
+
+    let mut sret = false;
+    for ret in &old.returns {
+        match struct_lowering(call_conv, ret) {
+            None => sig.returns.push(*ret),
+            Some(passing @ StructPassing::Pieces(..)) => {
+                for ty in passing.pieces() {
+                    sig.returns.push(AbiParam::special(ty, ret.purpose));
+                }
+            }
+            Some(StructPassing::Memory) | Some(StructPassing::Reference) => {
+                debug_assert!(!sret, "only one struct can be returned in memory");
+                sret = true;
+            }
+        }
+    }
+    if sret {
+        debug_assert!(old
+            .special_param_index(ArgumentPurpose::StructReturn)
+            .is_none());
+        sig.params.push(AbiParam::special(
+            pointer_type,
+            ArgumentPurpose::StructReturn,
+        ));
+        sig.returns.push(AbiParam::special(
+            pointer_type,
+            ArgumentPurpose::StructReturn,
+        ));
+    }
+
+    for param in &old.params {
+        match struct_lowering(call_conv, param) {
+            None | Some(StructPassing::Memory) => sig.params.push(*param),
+            Some(passing @ StructPassing::Pieces(..)) => {
+                for ty in passing.pieces() {
+                    sig.params.push(AbiParam::special(ty, param.purpose));
+                }
+            }
+            Some(StructPassing::Reference) => sig.params.push(AbiParam::new(pointer_type)),
+        }
+    }
+
+    Some(old)
+}
+
+/// Does the lowered version of `sig` return a struct in memory?
+fn returns_struct_in_memory(sig: &Signature) -> Option<&AbiParam> {
+    sig.returns
+        .iter()
+        .find(|ret| match struct_lowering(sig.call_conv, ret) {
+            Some(StructPassing::Memory) | Some(StructPassing::Reference) => true,
+            _ => false,
+        })
+}
+
+/// Rewrite the parameters of the `entry` block after lowering the struct arguments in the
+/// function's `old` signature.
+fn lower_struct_params(func: &mut Function, entry: Ebb, old: &Signature, pointer_type: Type) {
+    let mut pos = FuncCursor::new(func).at_first_inst(entry);
+    let ebb_params = pos.func.dfg.detach_ebb_params(entry);
+    let ebb_params = ebb_params.as_slice(&pos.func.dfg.value_lists).to_vec();
+
+    if returns_struct_in_memory(old).is_some() {
+        pos.func.dfg.append_ebb_param(entry, pointer_type);
+    }
+
+    for (&arg, param) in ebb_params.iter().zip(&old.params) {
+        match struct_lowering(old.call_conv, param) {
+            // Store the pieces to a stack slot, which becomes the callee's copy of the struct.
+            Some(passing @ StructPassing::Pieces(..)) => {
+                let (size, align) = struct_layout(param);
+                let ss = make_struct_slot(pos.func, size, align);
+                for (i, ty) in passing.pieces().enumerate() {
+                    let piece = pos.func.dfg.append_ebb_param(entry, ty);
+                    pos.ins().stack_store(piece, ss, i as i32 * 8);
+                }
+                pos.ins().with_result(arg).stack_addr(pointer_type, ss, 0);
+            }
+            _ => pos.func.dfg.attach_ebb_param(entry, arg),
+        }
+    }
+}
+
+/// Rewrite the return instruction `inst` after lowering the struct return values in the
+/// function's `old` signature.
+fn lower_struct_return(pos: &mut FuncCursor, inst: Inst, old: &Signature, pointer_type: Type) {
+    pos.use_srcloc(inst);
+    let args = pos.func.dfg.inst_variable_args(inst).to_vec();
+    let mut new_args = Vec::with_capacity(args.len());
+    for (&arg, ret) in args.iter().zip(&old.returns) {
+        match struct_lowering(old.call_conv, ret) {
+            None => new_args.push(arg),
+            Some(passing @ StructPassing::Pieces(..)) => {
+                let (size, _) = struct_layout(ret);
+                for (i, ty) in passing.pieces().enumerate() {
+                    new_args.push(load_piece(pos, ty, arg, i as u32 * 8, size));
+                }
+            }
+            // The pointer to the returned struct is appended by `handle_return_abi()`.
+            Some(StructPassing::Memory) | Some(StructPassing::Reference) => {
+                let (size, _) = struct_layout(ret);
+                let sret = pos
+                    .func
+                    .special_param(ArgumentPurpose::StructReturn)
+                    .expect("missing sret parameter");
+                debug_assert_eq!(pos.func.dfg.value_type(sret), pointer_type);
+                copy_struct(pos, arg, sret, 0, size);
+            }
+        }
+    }
+    set_variable_args(&mut pos.func.dfg, inst, &new_args);
+}
+
+/// Rewrite the call instruction `inst` after lowering the struct arguments and return values in
+/// the callee's `old` signature.
+fn lower_struct_call(pos: &mut FuncCursor, inst: Inst, old: &Signature, pointer_type: Type) {
+    pos.use_srcloc(inst);
+    let args = pos.func.dfg.inst_variable_args(inst).to_vec();
+    let results = pos.func.dfg.detach_results(inst);
+    let results = results.as_slice(&pos.func.dfg.value_lists).to_vec();
+    let mut new_args = Vec::with_capacity(args.len());
+
+    // A struct returned in memory goes to a stack slot in the caller, and the result is its
+    // address.
+    if let Some(ret) = returns_struct_in_memory(old) {
+        let (size, align) = struct_layout(ret);
+        let ss = make_struct_slot(pos.func, size, align);
+        let idx = old.returns.iter().position(|r| r == ret).unwrap();
+        let addr = pos
+            .ins()
+            .with_result(results[idx])
+            .stack_addr(pointer_type, ss, 0);
+        new_args.push(addr);
+    }
+
+    for (&arg, param) in args.iter().zip(&old.params) {
+        match struct_lowering(old.call_conv, param) {
+            None | Some(StructPassing::Memory) => new_args.push(arg),
+            Some(passing @ StructPassing::Pieces(..)) => {
+                let (size, _) = struct_layout(param);
+                for (i, ty) in passing.pieces().enumerate() {
+                    new_args.push(load_piece(pos, ty, arg, i as u32 * 8, size));
+                }
+            }
+            // Windows requires the copy to be 16-byte aligned.
+            Some(StructPassing::Reference) => {
+                let (size, align) = struct_layout(param);
+                let ss = make_struct_slot(pos.func, size, align.max(16));
+                let addr = pos.ins().stack_addr(pointer_type, ss, 0);
+                copy_struct(pos, arg, addr, 0, size);
+                new_args.push(addr);
+            }
+        }
+    }
+    set_variable_args(&mut pos.func.dfg, inst, &new_args);
+
+    // Store the pieces of returned structs to stack slots after the call.
+    pos.goto_inst(inst);
+    pos.next_inst();
+    let mut sret = false;
+    for (&res, ret) in results.iter().zip(&old.returns) {
+        match struct_lowering(old.call_conv, ret) {
+            None => pos.func.dfg.attach_result(inst, res),
+            Some(passing @ StructPassing::Pieces(..)) => {
+                let (size, align) = struct_layout(ret);
+                let ss = make_struct_slot(pos.func, size, align);
+                for (i, ty) in passing.pieces().enumerate() {
+                    let piece = pos.func.dfg.append_result(inst, ty);
+                    pos.ins().stack_store(piece, ss, i as i32 * 8);
+                }
+                pos.ins().with_result(res).stack_addr(pointer_type, ss, 0);
+            }
+            Some(StructPassing::Memory) | Some(StructPassing::Reference) => sret = true,
+        }
+    }
+    if sret {
+        pos.func.dfg.append_result(inst, pointer_type);
+    }
+    pos.goto_inst(inst);
+}
+
+/// Replace the variable arguments of `inst` with `args`.
+fn set_variable_args(dfg: &mut DataFlowGraph, inst: Inst, args: &[Value]) {
+    let num_fixed_values = dfg[inst].opcode().constraints().num_fixed_value_arguments();
+    let mut vlist = dfg[inst]
+        .take_value_list()
+        .expect("Call must have a value list");
+    let mut values = vlist.as_slice(&dfg.value_lists)[..num_fixed_values].to_vec();
+    values.extend_from_slice(args);
+    vlist.clear(&mut dfg.value_lists);
+    let vlist = ValueList::from_slice(&values, &mut dfg.value_lists);
+    dfg[inst].put_value_list(vlist);
+}
+
+/// Create an explicit stack slot for a copy of a struct.
+///
+/// The alignment was checked to be a power of two by `check_struct_signature()`.
+fn make_struct_slot(func: &mut Function, size: u32, align: u32) -> StackSlot {
+    debug_assert!(align.is_power_of_two());
+    // Stack slots are aligned according to their size. Round up to whole eightbytes too, so the
+    // pieces of the struct can be stored in full.
+    let align = align.max(8);
+    let size = (size + align - 1) & !(align - 1);
+    func.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, size))
+}
+
+/// Load the piece of type `ty` at `offset` in the struct of `size` bytes at `ptr`.
+///
+/// Bytes past the end of the struct are not accessed, and read as zero.
+fn load_piece(pos: &mut FuncCursor, ty: Type, ptr: Value, offset: u32, size: u32) -> Value {
+    let flags = MemFlags::new();
+    let bytes = size - offset;
+    if bytes >= ty.bytes() {
+        return pos.ins().load(ty, flags, ptr, offset as i32);
+    }
+    if ty == types::F64 {
+        // A float eightbyte that is cut short holds a single `f32`.
+        let piece = load_piece(pos, types::I64, ptr, offset, size);
+        return pos.ins().bitcast(ty, piece);
+    }
+
+    debug_assert_eq!(ty, types::I64);
+    let mut piece = None;
+    let mut done = 0;
+    for &chunk in &[4, 2, 1] {
+        if bytes - done < chunk {
+            continue;
+        }
+        let at = (offset + done) as i32;
+        let mut part = match chunk {
+            4 => pos.ins().uload32(flags, ptr, at),
+            2 => pos.ins().uload16(ty, flags, ptr, at),
+            _ => pos.ins().uload8(ty, flags, ptr, at),
+        };
+        if done > 0 {
+            part = pos.ins().ishl_imm(part, i64::from(done * 8));
+        }
+        piece = Some(match piece {
+            Some(lower) => pos.ins().bor(lower, part),
+            None => part,
+        });
+        done += chunk;
+    }
+    piece.unwrap()
+}
+
+/// Copy the bytes from `start` to the end of a struct of `size` bytes from `src` to `dst`.
+fn copy_struct(pos: &mut FuncCursor, src: Value, dst: Value, start: u32, size: u32) {
+    let flags = MemFlags::new();
+    let mut offset = start;
+    while offset < size {
+        let at = offset as i32;
+        offset += match size - offset {
+            1 => {
+                let byte = pos.ins().uload8(types::I32, flags, src, at);
+                pos.ins().istore8(flags, byte, dst, at);
+                1
+            }
+            2 | 3 => {
+                let half = pos.ins().uload16(types::I32, flags, src, at);
+                pos.ins().istore16(flags, half, dst, at);
+                2
+            }
+            4..=7 => {
+                let word = pos.ins().load(types::I32, flags, src, at);
+                pos.ins().store(flags, word, dst, at);
+                4
+            }
+            _ => {
+                let quad = pos.ins().load(types::I64, flags, src, at);
+                pos.ins().store(flags, quad, dst, at);
+                8
+            }
+        };
+    }
+}
+
 /// Legalize the entry block parameters after `func`'s signature has been legalized.
 ///
 /// The legalized signature may contain more parameters than the original signature, and the
@@ -92,12 +632,24 @@ fn legalize_entry_params(func: &mut Function, entry: Ebb) {
 
         let abi_type = pos.func.signature.params[abi_arg];
         let arg_type = pos.func.dfg.value_type(arg);
-        if arg_type == abi_type.value_type {
+        if let Some(size) = struct_in_memory(pos.func.signature.call_conv, &abi_type) {
+            // The ABI parameter is the start of the struct in the incoming argument area, and
+            // the original parameter is the address of the struct.
+            let offset = match abi_type.location {
+                ArgumentLoc::Stack(offset) => offset,
+                _ => panic!("Struct argument {} not on the stack", abi_type),
+            };
+            pos.func.dfg.append_ebb_param(entry, abi_type.value_type);
+            let ss = pos.func.stack_slots.make_incoming_arg(size, offset);
+            pos.ins().with_result(arg).stack_addr(arg_type, ss, 0);
+            abi_arg += 1;
+        } else if arg_type == abi_type.value_type {
             // No value translation is necessary, this argument matches the ABI type.
             // Just use the original EBB argument value. This is the most common case.
             pos.func.dfg.attach_ebb_param(entry, arg);
             match abi_type.purpose {
                 ArgumentPurpose::Normal => {}
+                ArgumentPurpose::StructArgument { .. } => {}
                 ArgumentPurpose::FramePointer => {}
                 ArgumentPurpose::CalleeSaved => {}
                 ArgumentPurpose::StructReturn => {
@@ -147,7 +699,7 @@ fn legalize_entry_params(func: &mut Function, entry: Ebb) {
     for &arg in &pos.func.signature.params[abi_arg..] {
         match arg.purpose {
             // Any normal parameters should have been processed above.
-            ArgumentPurpose::Normal => {
+            ArgumentPurpose::Normal | ArgumentPurpose::StructArgument { .. } => {
                 panic!("Leftover arg: {}", arg);
             }
             // The callee-save parameters should not appear until after register allocation is
@@ -646,7 +1198,9 @@ pub fn handle_return_abi(inst: Inst, func: &mut Function, cfg: &ControlFlowGraph
 fn spill_entry_params(func: &mut Function, entry: Ebb) {
     for (abi, &arg) in func.signature.params.iter().zip(func.dfg.ebb_params(entry)) {
         if let ArgumentLoc::Stack(offset) = abi.location {
-            let ss = func.stack_slots.make_incoming_arg(abi.value_type, offset);
+            let ss = func
+                .stack_slots
+                .make_incoming_arg(abi.value_type.bytes(), offset);
             func.locations[arg] = ValueLoc::Stack(ss);
         }
     }
@@ -675,6 +1229,7 @@ fn spill_call_arguments(pos: &mut FuncCursor) -> bool {
     let arglist = {
         let locations = &pos.func.locations;
         let stack_slots = &mut pos.func.stack_slots;
+        let call_conv = pos.func.dfg.signatures[sig_ref].call_conv;
         pos.func
             .dfg
             .inst_variable_args(inst)
@@ -687,9 +1242,11 @@ fn spill_call_arguments(pos: &mut FuncCursor) -> bool {
                         // Assign `arg` to a new stack slot, unless it's already in the correct
                         // slot. The legalization needs to be idempotent, so we should see a
                         // correct outgoing slot on the second pass.
-                        let ss = stack_slots.get_outgoing_arg(abi.value_type, offset);
+                        let struct_size = struct_in_memory(call_conv, abi);
+                        let size = struct_size.unwrap_or_else(|| abi.value_type.bytes());
+                        let ss = stack_slots.get_outgoing_arg(size, offset);
                         if locations[arg] != ValueLoc::Stack(ss) {
-                            Some((idx, arg, ss))
+                            Some((idx, arg, ss, struct_size))
                         } else {
                             None
                         }
@@ -705,7 +1262,16 @@ fn spill_call_arguments(pos: &mut FuncCursor) -> bool {
     }
 
     // Insert the spill instructions and rewrite call arguments.
-    for (idx, arg, ss) in arglist {
+    for (idx, mut arg, ss, struct_size) in arglist {
+        if let Some(size) = struct_size {
+            // A struct passed in memory is copied to the outgoing argument area. Everything but
+            // the first eightbyte is copied directly, and the first eightbyte is spilled to the
+            // argument slot like any other stack argument.
+            let ty = pos.func.dfg.value_type(arg);
+            let area = pos.ins().stack_addr(ty, ss, 0);
+            copy_struct(pos, arg, area, ty.bytes(), size);
+            arg = pos.ins().load(ty, MemFlags::new(), arg, 0);
+        }
         let stack_val = pos.ins().spill(arg);
         pos.func.locations[stack_val] = ValueLoc::Stack(ss);
         pos.func.dfg.inst_variable_args_mut(inst)[idx] = stack_val;
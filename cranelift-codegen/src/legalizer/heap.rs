@@ -1,7 +1,8 @@
 //! Legalization of heaps.
 //!
 //! This module exports the `expand_heap_addr` function which transforms a `heap_addr`
-//! instruction into code that depends on the kind of heap referenced.
+//! instruction into code that depends on the kind of heap referenced, and the
+//! `expand_unchecked_heap_addr` function for accesses whose bounds check is redundant.
 
 use crate::cursor::{Cursor, FuncCursor};
 use crate::flowgraph::ControlFlowGraph;
@@ -16,19 +17,7 @@ pub fn expand_heap_addr(
     cfg: &mut ControlFlowGraph,
     isa: &dyn TargetIsa,
 ) {
-    // Unpack the instruction.
-    let (heap, offset, access_size) = match func.dfg[inst] {
-        ir::InstructionData::HeapAddr {
-            opcode,
-            heap,
-            arg,
-            imm,
-        } => {
-            debug_assert_eq!(opcode, ir::Opcode::HeapAddr);
-            (heap, arg, imm.into())
-        }
-        _ => panic!("Wanted heap_addr: {}", func.dfg.display_inst(inst, None)),
-    };
+    let (heap, offset, access_size) = unpack_heap_addr(inst, func);
 
     match func.heaps[heap].style {
         ir::HeapStyle::Dynamic { bound_gv } => {
@@ -47,6 +36,32 @@ pub fn expand_heap_addr(
     }
 }
 
+/// Expand a `heap_addr` instruction without a bounds check.
+///
+/// This is used when the access is known to be in bounds, so only the address computation remains.
+pub fn expand_unchecked_heap_addr(inst: ir::Inst, func: &mut ir::Function, isa: &dyn TargetIsa) {
+    let (heap, offset, _) = unpack_heap_addr(inst, func);
+    let offset_ty = func.dfg.value_type(offset);
+    let addr_ty = func.dfg.value_type(func.dfg.first_result(inst));
+    compute_addr(isa, inst, heap, addr_ty, offset, offset_ty, func);
+}
+
+/// Get the heap, offset and access size of a `heap_addr` instruction.
+fn unpack_heap_addr(inst: ir::Inst, func: &ir::Function) -> (ir::Heap, ir::Value, u32) {
+    match func.dfg[inst] {
+        ir::InstructionData::HeapAddr {
+            opcode,
+            heap,
+            arg,
+            imm,
+        } => {
+            debug_assert_eq!(opcode, ir::Opcode::HeapAddr);
+            (heap, arg, imm.into())
+        }
+        _ => panic!("Wanted heap_addr: {}", func.dfg.display_inst(inst, None)),
+    }
+}
+
 /// Expand a `heap_addr` for a dynamic heap.
 fn dynamic_addr(
     isa: &dyn TargetIsa,
@@ -16,10 +16,11 @@
 use crate::bitset::BitSet;
 use crate::cursor::{Cursor, FuncCursor};
 use crate::flowgraph::ControlFlowGraph;
-use crate::ir::types::{I32, I64};
+use crate::ir::types::I32;
 use crate::ir::{self, InstBuilder, MemFlags};
 use crate::isa::TargetIsa;
 use crate::predicates;
+use crate::result::CodegenResult;
 use crate::timing;
 use alloc::collections::BTreeSet;
 use alloc::vec::Vec;
@@ -38,6 +39,8 @@ use self::heap::expand_heap_addr;
 use self::libcall::expand_as_libcall;
 use self::table::expand_table_addr;
 
+pub(crate) use self::heap::expand_unchecked_heap_addr;
+
 enum LegalizeInstResult {
     Done,
     Legalized,
@@ -137,11 +140,17 @@ fn legalize_inst(
 /// - Transform any instructions that don't have a legal representation in `isa`.
 /// - Fill out `func.encodings`.
 ///
-pub fn legalize_function(func: &mut ir::Function, cfg: &mut ControlFlowGraph, isa: &dyn TargetIsa) {
+/// Fails with `CodegenError::Unsupported` if the signatures of `func` use a feature that `isa`
+/// can't lower.
+pub fn legalize_function(
+    func: &mut ir::Function,
+    cfg: &mut ControlFlowGraph,
+    isa: &dyn TargetIsa,
+) -> CodegenResult<()> {
     let _tt = timing::legalize();
     debug_assert!(cfg.is_valid());
 
-    boundary::legalize_signatures(func, isa);
+    boundary::legalize_signatures(func, isa)?;
 
     func.encodings.resize(func.dfg.num_insts());
 
@@ -194,6 +203,7 @@ pub fn legalize_function(func: &mut ir::Function, cfg: &mut ControlFlowGraph, is
     if !isa.flags().jump_tables_enabled() {
         pos.func.jump_tables.clear();
     }
+    Ok(())
 }
 
 // Include legalization patterns that were generated by `gen_legalizer.rs` from the
@@ -662,9 +672,10 @@ fn narrow_iconst(
     pos.use_srcloc(inst);
 
     let ty = pos.func.dfg.ctrl_typevar(inst);
-    if isa.pointer_bits() == 32 && ty == I64 {
-        let low = pos.ins().iconst(I32, imm & 0xffffffff);
-        let high = pos.ins().iconst(I32, imm >> 32);
+    if ty.bits() == 2 * u16::from(isa.pointer_bits()) {
+        let (low, high) = split_imm(imm, ty.half_width().unwrap());
+        let low = pos.ins().iconst(isa.pointer_type(), low);
+        let high = pos.ins().iconst(isa.pointer_type(), high);
         // The instruction has as many results as iconcat, so no need to replace them.
         pos.func.dfg.replace(inst).iconcat(low, high);
         return;
@@ -673,6 +684,16 @@ fn narrow_iconst(
     unimplemented!("missing encoding or legalization for iconst.{:?}", ty);
 }
 
+/// Split the sign-extended immediate `imm` into the values of its low and high halves, when
+/// interpreted as an integer twice as wide as `half_ty`.
+fn split_imm(imm: i64, half_ty: ir::Type) -> (i64, i64) {
+    let imm = i128::from(imm);
+    let bits = half_ty.bits();
+    let low = imm & ((1 << bits) - 1);
+    let high = imm >> bits;
+    (low as i64, high as i64)
+}
+
 fn narrow_icmp_imm(
     inst: ir::Inst,
     func: &mut ir::Function,
@@ -697,12 +718,9 @@ fn narrow_icmp_imm(
     let ty = pos.func.dfg.ctrl_typevar(inst);
     let ty_half = ty.half_width().unwrap();
 
-    let imm_low = pos
-        .ins()
-        .iconst(ty_half, imm & (1u128 << ty_half.bits() - 1) as i64);
-    let imm_high = pos
-        .ins()
-        .iconst(ty_half, imm.wrapping_shr(ty_half.bits().into()));
+    let (imm_low, imm_high) = split_imm(imm, ty_half);
+    let imm_low = pos.ins().iconst(ty_half, imm_low);
+    let imm_high = pos.ins().iconst(ty_half, imm_high);
     let (arg_low, arg_high) = pos.ins().isplit(arg);
 
     match cond {
@@ -736,3 +754,135 @@ fn narrow_icmp_imm(
         _ => unimplemented!("missing legalization for condition {:?}", cond),
     }
 }
+
+/// Expand a shift of an integer that is too wide for a register into shifts of its halves.
+///
+/// The shift amount is taken modulo the width of the type, so a shift by at least the width of a
+/// half moves one half into the other, and the remaining bits are shifted by the amount modulo the
+/// width of a half. Otherwise, bits are carried across the halves.
+fn narrow_shift(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &dyn TargetIsa,
+) {
+    use crate::ir::condcodes::IntCC;
+
+    let (opcode, arg, amt) = match func.dfg[inst] {
+        ir::InstructionData::Binary { opcode, args } => (opcode, args[0], args[1]),
+        _ => panic!("unexpected instruction in narrow_shift"),
+    };
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    let ty = pos.func.dfg.ctrl_typevar(inst);
+    if ty.is_vector() {
+        unimplemented!("missing encoding or legalization for {}.{}", opcode, ty);
+    }
+    let ty_half = ty.half_width().expect("Can't narrow shift");
+
+    // Only the low bits of the shift amount matter, so bring it to the width of a half.
+    let amt_ty = pos.func.dfg.value_type(amt);
+    let amt = if amt_ty == ty_half {
+        amt
+    } else if amt_ty.bits() > ty_half.bits() {
+        let (amt_low, _) = pos.ins().isplit(amt);
+        amt_low
+    } else {
+        pos.ins().uextend(ty_half, amt)
+    };
+
+    let (arg_low, arg_high) = pos.ins().isplit(arg);
+
+    // Is the shift amount at least the width of a half?
+    let half_bits = i64::from(ty_half.bits());
+    let large = pos.ins().band_imm(amt, half_bits);
+    let large = pos.ins().icmp_imm(IntCC::NotEqual, large, 0);
+
+    // The bits carried from one half into the other are shifted by `half_bits - amt`, which is
+    // done in two steps so a zero shift amount carries nothing.
+    let carry_amt = pos.ins().bnot(amt);
+
+    let (low, high) = match opcode {
+        ir::Opcode::Ishl => {
+            let low = pos.ins().ishl(arg_low, amt);
+            let high = pos.ins().ishl(arg_high, amt);
+            let carry = pos.ins().ushr_imm(arg_low, 1);
+            let carry = pos.ins().ushr(carry, carry_amt);
+            let high = pos.ins().bor(high, carry);
+            let zero = pos.ins().iconst(ty_half, 0);
+            (
+                pos.ins().select(large, zero, low),
+                pos.ins().select(large, low, high),
+            )
+        }
+        ir::Opcode::Ushr | ir::Opcode::Sshr => {
+            let low = pos.ins().ushr(arg_low, amt);
+            let carry = pos.ins().ishl_imm(arg_high, 1);
+            let carry = pos.ins().ishl(carry, carry_amt);
+            let low = pos.ins().bor(low, carry);
+            let (high, fill) = if opcode == ir::Opcode::Ushr {
+                let high = pos.ins().ushr(arg_high, amt);
+                (high, pos.ins().iconst(ty_half, 0))
+            } else {
+                let high = pos.ins().sshr(arg_high, amt);
+                (high, pos.ins().sshr_imm(arg_high, half_bits - 1))
+            };
+            (
+                pos.ins().select(large, high, low),
+                pos.ins().select(large, fill, high),
+            )
+        }
+        _ => panic!("unexpected instruction in narrow_shift"),
+    };
+
+    pos.func.dfg.replace(inst).iconcat(low, high);
+}
+
+/// Expand an `ireduce` of an integer that is too wide for a register by reducing its low half.
+///
+/// This is also used to widen reductions between types narrower than `i32`, which are expressed as
+/// a reduction from `i32`.
+fn expand_ireduce(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    isa: &dyn TargetIsa,
+) {
+    let arg = match func.dfg[inst] {
+        ir::InstructionData::Unary {
+            opcode: ir::Opcode::Ireduce,
+            arg,
+        } => arg,
+        _ => panic!("unexpected instruction in expand_ireduce"),
+    };
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    let ty = pos.func.dfg.ctrl_typevar(inst);
+    let arg_ty = pos.func.dfg.value_type(arg);
+    if arg_ty.bits() < 32 {
+        let wide = pos.ins().uextend(I32, arg);
+        pos.func.dfg.replace(inst).ireduce(ty, wide);
+        return;
+    }
+    if arg_ty.bits() <= u16::from(isa.pointer_bits()) {
+        unimplemented!(
+            "missing encoding or legalization for ireduce.{}.{}",
+            ty,
+            arg_ty
+        );
+    }
+
+    let (arg_low, _) = pos.ins().isplit(arg);
+    if pos.func.dfg.value_type(arg_low) == ty {
+        let result = pos.func.dfg.first_result(inst);
+        pos.func.dfg.clear_results(inst);
+        pos.remove_inst();
+        pos.func.dfg.change_to_alias(result, arg_low);
+    } else {
+        pos.func.dfg.replace(inst).ireduce(ty, arg_low);
+    }
+}
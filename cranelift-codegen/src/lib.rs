@@ -64,12 +64,15 @@ pub use crate::write::write_function;
 pub use cranelift_bforest as bforest;
 pub use cranelift_entity as entity;
 
+pub mod binary;
 pub mod binemit;
+pub mod cache;
 pub mod cfg_printer;
 pub mod cursor;
 pub mod dbg;
 pub mod dominator_tree;
 pub mod flowgraph;
+pub mod inline;
 pub mod ir;
 pub mod isa;
 pub mod loop_analysis;
@@ -83,6 +86,8 @@ pub use crate::entity::packed_option;
 
 mod abi;
 mod bitset;
+mod block_layout;
+mod bounds_checks;
 mod constant_hash;
 mod context;
 mod dce;
@@ -91,6 +96,7 @@ mod fx;
 mod iterators;
 mod legalizer;
 mod licm;
+mod loop_unrolling;
 mod nan_canonicalization;
 mod partition_slice;
 mod postopt;
@@ -98,9 +104,11 @@ mod predicates;
 mod redundant_reload_remover;
 mod regalloc;
 mod result;
+mod scheduling;
 mod scoped_hash_map;
 mod simple_gvn;
 mod simple_preopt;
+mod stack_coloring;
 mod stack_layout;
 mod topo_order;
 mod unreachable_code;
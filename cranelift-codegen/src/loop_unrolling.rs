@@ -0,0 +1,632 @@
+//! Loop unrolling and induction variable strength reduction.
+//!
+//! This pass only transforms counted loops consisting of a single EBB. Such a loop has an
+//! induction variable passed as an EBB parameter, incremented by a constant step on the back
+//! edge, and it ends with a compare-and-branch of that variable against a loop-invariant bound.
+//!
+//! Address computations of the form `base + iv * scale` are first turned into a new EBB
+//! parameter incremented by `step * scale` on each iteration.
+//!
+//! The loop is then partially unrolled. A new guard EBB checks whether enough iterations are left
+//! to run the unrolled body without intermediate exit tests. If not, it branches to the original
+//! loop, which is kept as the remainder loop.
+
+use crate::cursor::{Cursor, FuncCursor};
+use crate::dominator_tree::DominatorTree;
+use crate::entity::SecondaryMap;
+use crate::flowgraph::{BasicBlock, ControlFlowGraph};
+use crate::ir::condcodes::{CondCode, IntCC};
+use crate::ir::immediates::Imm64;
+use crate::ir::instructions::BranchInfo;
+use crate::ir::{
+    types, Ebb, Function, Inst, InstBuilder, InstructionData, Opcode, Type, Value, ValueDef,
+    ValueList,
+};
+use crate::loop_analysis::{Loop, LoopAnalysis};
+use crate::packed_option::PackedOption;
+use crate::timing;
+use alloc::vec::Vec;
+
+/// Number of copies of the loop body in the unrolled loop, when the body is small enough.
+const UNROLL_FACTOR: usize = 4;
+
+/// Maximum number of instructions in the unrolled loop body.
+const MAX_UNROLLED_SIZE: usize = 64;
+
+/// Performs strength reduction and partial unrolling on the counted loops of `func`.
+///
+/// The CFG and domtree are recomputed, and the loop analysis is invalidated.
+pub fn do_loop_unrolling(
+    func: &mut Function,
+    cfg: &mut ControlFlowGraph,
+    domtree: &mut DominatorTree,
+    loop_analysis: &mut LoopAnalysis,
+) {
+    let _tt = timing::loop_unrolling();
+    debug_assert!(cfg.is_valid());
+    debug_assert!(domtree.is_valid());
+    debug_assert!(loop_analysis.is_valid());
+
+    let loops: Vec<Loop> = loop_analysis.loops().collect();
+    for lp in loops {
+        let header = loop_analysis.loop_header(lp);
+        if !is_single_ebb_loop(header, lp, cfg, loop_analysis) {
+            continue;
+        }
+        if let Some(counted) = CountedLoop::analyze(func, cfg, header) {
+            strength_reduce(func, &counted);
+            if unroll(func, &counted) {
+                cfg.compute(func);
+            }
+        }
+    }
+
+    cfg.compute(func);
+    domtree.compute(func, cfg);
+    loop_analysis.clear();
+}
+
+/// Is `lp` made of its header only?
+fn is_single_ebb_loop(
+    header: Ebb,
+    lp: Loop,
+    cfg: &ControlFlowGraph,
+    loop_analysis: &LoopAnalysis,
+) -> bool {
+    // The loop contains the EBBs which reach a back edge without going through the header, so it
+    // is reduced to the header when all the back edges come from the header itself.
+    cfg.pred_iter(header)
+        .all(|BasicBlock { ebb, .. }| ebb == header || !loop_analysis.is_in_loop(ebb, lp))
+}
+
+/// The bound the induction variable is compared against.
+#[derive(Clone, Copy)]
+enum Bound {
+    Value(Value),
+    Imm(Imm64),
+}
+
+/// A counted loop consisting of a single EBB.
+struct CountedLoop {
+    header: Ebb,
+    /// The branches entering the loop from outside.
+    entries: Vec<Inst>,
+    /// The conditional branch and the jump terminating the header.
+    branch: Inst,
+    jump: Inst,
+    /// Which of `branch` and `jump` go back to the header, and which leave the loop.
+    back_edge: Inst,
+    exit: Inst,
+    /// Index of the induction variable in the header parameters.
+    iv: usize,
+    /// Amount added to the induction variable on each iteration.
+    step: i64,
+    /// The loop keeps iterating while `cond(iv + offset, bound)` holds.
+    cond: IntCC,
+    offset: i64,
+    bound: Bound,
+}
+
+impl CountedLoop {
+    /// Recognize a counted loop with the given header, which must be the only EBB in the loop.
+    fn analyze(func: &Function, cfg: &ControlFlowGraph, header: Ebb) -> Option<Self> {
+        let dfg = &func.dfg;
+        if func.layout.entry_block() == Some(header) {
+            return None;
+        }
+
+        let jump = func.layout.last_inst(header)?;
+        let branch = func.layout.prev_inst(jump)?;
+        if dfg[jump].opcode() != Opcode::Jump {
+            return None;
+        }
+        if func
+            .layout
+            .ebb_insts(header)
+            .any(|inst| inst != branch && inst != jump && dfg[inst].opcode().is_branch())
+        {
+            return None;
+        }
+        let (back_edge, exit) = match (
+            dfg[branch].branch_destination(),
+            dfg[jump].branch_destination(),
+        ) {
+            (Some(taken), Some(next)) if taken == header && next != header => (branch, jump),
+            (Some(taken), Some(next)) if taken != header && next == header => (jump, branch),
+            _ => return None,
+        };
+
+        let mut entries = Vec::new();
+        for BasicBlock { ebb, inst } in cfg.pred_iter(header) {
+            if ebb == header {
+                continue;
+            }
+            match dfg.analyze_branch(inst) {
+                BranchInfo::SingleDest(..) => entries.push(inst),
+                _ => return None,
+            }
+        }
+
+        // Find the condition under which `branch` is taken.
+        let (taken, lhs, rhs) = match dfg[branch] {
+            InstructionData::Branch {
+                opcode, ref args, ..
+            } => {
+                let cmp = match dfg.value_def(dfg.resolve_aliases(args.first(&dfg.value_lists)?)) {
+                    ValueDef::Result(inst, _) if func.layout.inst_ebb(inst) == Some(header) => inst,
+                    _ => return None,
+                };
+                let (cond, lhs, rhs) = match dfg[cmp] {
+                    InstructionData::IntCompare {
+                        opcode: Opcode::Icmp,
+                        cond,
+                        args,
+                    } => (cond, args[0], Bound::Value(args[1])),
+                    InstructionData::IntCompareImm {
+                        opcode: Opcode::IcmpImm,
+                        cond,
+                        arg,
+                        imm,
+                    } => (cond, arg, Bound::Imm(imm)),
+                    _ => return None,
+                };
+                match opcode {
+                    Opcode::Brnz => (cond, lhs, rhs),
+                    Opcode::Brz => (cond.inverse(), lhs, rhs),
+                    _ => return None,
+                }
+            }
+            InstructionData::BranchIcmp { cond, ref args, .. } => {
+                let args = args.as_slice(&dfg.value_lists);
+                (cond, args[0], Bound::Value(args[1]))
+            }
+            _ => return None,
+        };
+        let cond = if branch == back_edge {
+            taken
+        } else {
+            taken.inverse()
+        };
+
+        // Find the induction variable among the compared values.
+        let params = dfg.ebb_params(header);
+        let back_args = dfg.inst_variable_args(back_edge);
+        let find_iv = |v: Value| -> Option<(usize, i64, i64)> {
+            let v = dfg.resolve_aliases(v);
+            params.iter().enumerate().find_map(|(i, &param)| {
+                let next = dfg.resolve_aliases(back_args[i]);
+                let step = increment(func, header, next, param)?;
+                if v == param {
+                    Some((i, step, 0))
+                } else if v == next {
+                    Some((i, step, step))
+                } else {
+                    None
+                }
+            })
+        };
+        let (iv, step, offset, cond, bound) = match find_iv(lhs) {
+            Some((iv, step, offset)) => (iv, step, offset, cond, rhs),
+            None => match rhs {
+                Bound::Value(rhs) => {
+                    let (iv, step, offset) = find_iv(rhs)?;
+                    (iv, step, offset, cond.reverse(), Bound::Value(lhs))
+                }
+                Bound::Imm(_) => return None,
+            },
+        };
+
+        // The bound must be loop-invariant, and the condition must eventually become false.
+        if let Bound::Value(bound) = bound {
+            if is_defined_in(func, dfg.resolve_aliases(bound), header) {
+                return None;
+            }
+        }
+        let counts_up = match cond {
+            IntCC::SignedLessThan
+            | IntCC::SignedLessThanOrEqual
+            | IntCC::UnsignedLessThan
+            | IntCC::UnsignedLessThanOrEqual => true,
+            IntCC::SignedGreaterThan
+            | IntCC::SignedGreaterThanOrEqual
+            | IntCC::UnsignedGreaterThan
+            | IntCC::UnsignedGreaterThanOrEqual => false,
+            _ => return None,
+        };
+        if counts_up != (step > 0) {
+            return None;
+        }
+
+        Some(Self {
+            header,
+            entries,
+            branch,
+            jump,
+            back_edge,
+            exit,
+            iv,
+            step,
+            cond,
+            offset,
+            bound,
+        })
+    }
+
+    /// The induction variable parameter of the header.
+    fn iv_param(&self, func: &Function) -> Value {
+        func.dfg.ebb_params(self.header)[self.iv]
+    }
+}
+
+/// If `next` is computed in `header` as `param` plus a non-zero constant, return that constant.
+fn increment(func: &Function, header: Ebb, next: Value, param: Value) -> Option<i64> {
+    let ty = func.dfg.value_type(param);
+    if ty != types::I32 && ty != types::I64 {
+        return None;
+    }
+    let inst = match func.dfg.value_def(next) {
+        ValueDef::Result(inst, _) if func.layout.inst_ebb(inst) == Some(header) => inst,
+        _ => return None,
+    };
+    match func.dfg[inst] {
+        InstructionData::BinaryImm {
+            opcode: Opcode::IaddImm,
+            arg,
+            imm,
+        } if func.dfg.resolve_aliases(arg) == param => {
+            let step = sign_extend(imm.into(), ty);
+            if step != 0 {
+                Some(step)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// If `value` is computed in `header` as `iv` times a constant, return that constant.
+fn iv_scale(func: &Function, header: Ebb, value: Value, iv: Value) -> Option<i64> {
+    let inst = match func.dfg.value_def(func.dfg.resolve_aliases(value)) {
+        ValueDef::Result(inst, _) if func.layout.inst_ebb(inst) == Some(header) => inst,
+        _ => return None,
+    };
+    let ty = func.dfg.value_type(iv);
+    match func.dfg[inst] {
+        InstructionData::BinaryImm { opcode, arg, imm } if func.dfg.resolve_aliases(arg) == iv => {
+            let imm: i64 = imm.into();
+            match opcode {
+                Opcode::ImulImm => Some(sign_extend(imm, ty)),
+                Opcode::IshlImm if imm >= 0 && imm < i64::from(ty.bits()) => Some(1 << imm),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Is `value` defined by an instruction or a parameter of `ebb`?
+fn is_defined_in(func: &Function, value: Value, ebb: Ebb) -> bool {
+    match func.dfg.value_def(value) {
+        ValueDef::Result(inst, _) => func.layout.inst_ebb(inst) == Some(ebb),
+        ValueDef::Param(param_ebb, _) => param_ebb == ebb,
+    }
+}
+
+/// Sign-extend the low bits of `imm` according to the width of `ty`.
+fn sign_extend(imm: i64, ty: Type) -> i64 {
+    let shift = 64 - ty.bits();
+    (imm << shift) >> shift
+}
+
+/// Replace every `iadd base, iv * scale` in the loop with a new header parameter, initialized on
+/// the entry edges and incremented by `step * scale` on the back edge.
+fn strength_reduce(func: &mut Function, lp: &CountedLoop) {
+    let iv = lp.iv_param(func);
+    let ty = func.dfg.value_type(iv);
+
+    let mut candidates = Vec::new();
+    for inst in func.layout.ebb_insts(lp.header) {
+        if let InstructionData::Binary {
+            opcode: Opcode::Iadd,
+            args,
+        } = func.dfg[inst]
+        {
+            for &(scaled, base) in &[(args[0], args[1]), (args[1], args[0])] {
+                let base = func.dfg.resolve_aliases(base);
+                if func.dfg.value_type(base) != ty || is_defined_in(func, base, lp.header) {
+                    continue;
+                }
+                if let Some(scale) = iv_scale(func, lp.header, scaled, iv) {
+                    candidates.push((inst, base, scale));
+                    break;
+                }
+            }
+        }
+    }
+
+    for (inst, base, scale) in candidates {
+        let result = func.dfg.first_result(inst);
+        let reduced = func.dfg.append_ebb_param(lp.header, ty);
+
+        for &entry in &lp.entries {
+            let init = func.dfg.inst_variable_args(entry)[lp.iv];
+            let mut pos = FuncCursor::new(func).at_inst(entry);
+            pos.use_srcloc(inst);
+            let offset = pos.ins().imul_imm(init, scale);
+            let start = pos.ins().iadd(base, offset);
+            pos.func.dfg.append_inst_arg(entry, start);
+        }
+
+        let mut pos = FuncCursor::new(func).at_inst(lp.branch);
+        pos.use_srcloc(inst);
+        let next = pos.ins().iadd_imm(reduced, lp.step.wrapping_mul(scale));
+        pos.func.dfg.append_inst_arg(lp.back_edge, next);
+
+        func.layout.remove_inst(inst);
+        func.dfg.clear_results(inst);
+        func.dfg.change_to_alias(result, reduced);
+    }
+}
+
+/// Unroll the loop, keeping the original loop to run the remaining iterations.
+///
+/// Returns `true` if the loop was unrolled.
+fn unroll(func: &mut Function, lp: &CountedLoop) -> bool {
+    let header = lp.header;
+    let body: Vec<Inst> = func
+        .layout
+        .ebb_insts(header)
+        .filter(|&inst| inst != lp.branch && inst != lp.jump)
+        .collect();
+    let factor = if body.len() * UNROLL_FACTOR <= MAX_UNROLLED_SIZE {
+        UNROLL_FACTOR
+    } else if body.len() * 2 <= MAX_UNROLLED_SIZE {
+        2
+    } else {
+        return false;
+    };
+
+    // The unrolled body runs `factor - 1` iterations without checking the exit condition, so the
+    // guard evaluates the condition that the last of those checks would see. As long as the
+    // induction variable doesn't wrap around, the earlier checks would succeed as well.
+    let ty = func.dfg.value_type(lp.iv_param(func));
+    let signed = match lp.cond {
+        IntCC::SignedLessThan
+        | IntCC::SignedLessThanOrEqual
+        | IntCC::SignedGreaterThan
+        | IntCC::SignedGreaterThanOrEqual => true,
+        _ => false,
+    };
+    let checks = (factor - 2) as i64 + if lp.offset != 0 { 1 } else { 0 };
+    let distance = i128::from(checks) * i128::from(lp.step);
+    let (min, max) = type_range(ty, signed);
+    if distance.abs() > type_range(ty, true).1 {
+        return false;
+    }
+
+    let params = func.dfg.ebb_params(header).to_vec();
+    let back_args: Vec<Value> = func
+        .dfg
+        .inst_variable_args(lp.back_edge)
+        .iter()
+        .map(|&arg| func.dfg.resolve_aliases(arg))
+        .collect();
+    let exit_dest = func.dfg[lp.exit].branch_destination().unwrap();
+    let exit_args = func.dfg.inst_variable_args(lp.exit).to_vec();
+    let mut escaping = escaping_values(func, header);
+    if !escaping.is_empty() {
+        for &arg in &exit_args {
+            let arg = func.dfg.resolve_aliases(arg);
+            if is_defined_in(func, arg, header) && !escaping.contains(&arg) {
+                escaping.push(arg);
+            }
+        }
+    }
+
+    let guard = func.dfg.make_ebb();
+    let unrolled = func.dfg.make_ebb();
+    func.layout.insert_ebb(guard, header);
+    func.layout.insert_ebb(unrolled, header);
+    let guard_params: Vec<Value> = params
+        .iter()
+        .map(|&param| {
+            let ty = func.dfg.value_type(param);
+            func.dfg.append_ebb_param(guard, ty)
+        })
+        .collect();
+
+    // Fill in the guard EBB.
+    let mut pos = FuncCursor::new(func).at_bottom(guard);
+    pos.use_srcloc(lp.branch);
+    let iv = guard_params[lp.iv];
+    let ok = if checks == 0 {
+        compare_with_bound(&mut pos, lp, iv)
+    } else {
+        // Make sure that `iv + distance` doesn't overflow.
+        let (cond, limit) = match (lp.step > 0, signed) {
+            (true, true) => (IntCC::SignedLessThanOrEqual, max - distance),
+            (true, false) => (IntCC::UnsignedLessThanOrEqual, max - distance),
+            (false, true) => (IntCC::SignedGreaterThanOrEqual, min - distance),
+            (false, false) => (IntCC::UnsignedGreaterThanOrEqual, min - distance),
+        };
+        let limit = pos.ins().iconst(ty, sign_extend(limit as i64, ty));
+        let no_overflow = pos.ins().icmp(cond, iv, limit);
+        let last = pos.ins().iadd_imm(iv, distance as i64);
+        let in_range = compare_with_bound(&mut pos, lp, last);
+        pos.ins().band(no_overflow, in_range)
+    };
+    pos.ins().brz(ok, header, &guard_params);
+    pos.ins().jump(unrolled, &[]);
+
+    // Fill in the unrolled EBB with copies of the body, chaining the values passed on the back
+    // edge into the next copy.
+    let mut map = SecondaryMap::<Value, PackedOption<Value>>::new();
+    for (&param, &arg) in params.iter().zip(&guard_params) {
+        map[param] = arg.into();
+    }
+    for copy in 0..factor {
+        for &inst in &body {
+            copy_inst(func, inst, &mut map, unrolled);
+        }
+        if copy + 1 < factor {
+            let next: Vec<Value> = back_args
+                .iter()
+                .map(|&arg| map_value(func, &map, arg))
+                .collect();
+            for (&param, &arg) in params.iter().zip(&next) {
+                map[param] = arg.into();
+            }
+        }
+    }
+    let branch = copy_inst(func, lp.branch, &mut map, unrolled);
+    let jump = copy_inst(func, lp.jump, &mut map, unrolled);
+    let (back_edge, exit) = if lp.back_edge == lp.branch {
+        (branch, jump)
+    } else {
+        (jump, branch)
+    };
+    func.change_branch_destination(back_edge, guard);
+
+    // Values computed in the loop and used after it now have two definitions, so they are merged
+    // in a new EBB on the exit path. The exit arguments go through that EBB as well.
+    if !escaping.is_empty() {
+        let exit_block = func.dfg.make_ebb();
+        func.layout.insert_ebb_after(exit_block, header);
+        let mut subst = SecondaryMap::<Value, PackedOption<Value>>::new();
+        for &value in &escaping {
+            let ty = func.dfg.value_type(value);
+            subst[value] = func.dfg.append_ebb_param(exit_block, ty).into();
+        }
+
+        let unrolled_args: Vec<Value> = escaping
+            .iter()
+            .map(|&value| map_value(func, &map, value))
+            .collect();
+        set_branch_args(func, exit, exit_block, &unrolled_args);
+        set_branch_args(func, lp.exit, exit_block, &escaping);
+
+        let merged_args: Vec<Value> = exit_args
+            .iter()
+            .map(|&arg| map_value(func, &subst, arg))
+            .collect();
+        let mut pos = FuncCursor::new(func).at_bottom(exit_block);
+        pos.ins().jump(exit_dest, &merged_args);
+
+        let mut pos = FuncCursor::new(func);
+        while let Some(ebb) = pos.next_ebb() {
+            if ebb == header || ebb == guard || ebb == unrolled || ebb == exit_block {
+                continue;
+            }
+            while let Some(inst) = pos.next_inst() {
+                for i in 0..pos.func.dfg.inst_args(inst).len() {
+                    let arg = pos.func.dfg.inst_args(inst)[i];
+                    pos.func.dfg.inst_args_mut(inst)[i] = map_value(pos.func, &subst, arg);
+                }
+            }
+        }
+    }
+
+    for &entry in &lp.entries {
+        func.change_branch_destination(entry, guard);
+    }
+    true
+}
+
+/// Get the range of values of the integer type `ty`.
+fn type_range(ty: Type, signed: bool) -> (i128, i128) {
+    let bits = ty.bits();
+    if signed {
+        (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+    } else {
+        (0, (1 << bits) - 1)
+    }
+}
+
+/// Insert the loop condition, applied to `value` instead of the induction variable.
+fn compare_with_bound(pos: &mut FuncCursor, lp: &CountedLoop, value: Value) -> Value {
+    match lp.bound {
+        Bound::Value(bound) => pos.ins().icmp(lp.cond, value, bound),
+        Bound::Imm(imm) => pos.ins().icmp_imm(lp.cond, value, imm),
+    }
+}
+
+/// Collect the values defined in `ebb` and used in other EBBs.
+fn escaping_values(func: &Function, ebb: Ebb) -> Vec<Value> {
+    let mut escaping = Vec::new();
+    for other in func.layout.ebbs() {
+        if other == ebb {
+            continue;
+        }
+        for inst in func.layout.ebb_insts(other) {
+            for &arg in func.dfg.inst_args(inst) {
+                let arg = func.dfg.resolve_aliases(arg);
+                if is_defined_in(func, arg, ebb) && !escaping.contains(&arg) {
+                    escaping.push(arg);
+                }
+            }
+        }
+    }
+    escaping
+}
+
+/// Get the value replacing `value` in `map`, or `value` itself if it isn't mapped.
+fn map_value(
+    func: &Function,
+    map: &SecondaryMap<Value, PackedOption<Value>>,
+    value: Value,
+) -> Value {
+    let value = func.dfg.resolve_aliases(value);
+    map[value].expand().unwrap_or(value)
+}
+
+/// Append a copy of `inst` to `ebb`, with its arguments rewritten through `map`. The results of
+/// the copy are recorded in `map`.
+fn copy_inst(
+    func: &mut Function,
+    inst: Inst,
+    map: &mut SecondaryMap<Value, PackedOption<Value>>,
+    ebb: Ebb,
+) -> Inst {
+    let mut data = func.dfg[inst].clone();
+    if let Some(list) = data.take_value_list() {
+        let args = list.as_slice(&func.dfg.value_lists).to_vec();
+        data.put_value_list(ValueList::from_slice(&args, &mut func.dfg.value_lists));
+    }
+    let copy = func.dfg.make_inst(data);
+    for i in 0..func.dfg.inst_args(copy).len() {
+        let arg = func.dfg.inst_args(copy)[i];
+        func.dfg.inst_args_mut(copy)[i] = map_value(func, map, arg);
+    }
+
+    let ctrl_typevar = func.dfg.ctrl_typevar(inst);
+    func.dfg.make_inst_results(copy, ctrl_typevar);
+    for (&old, &new) in func
+        .dfg
+        .inst_results(inst)
+        .iter()
+        .zip(func.dfg.inst_results(copy))
+    {
+        map[old] = new.into();
+    }
+
+    func.layout.append_inst(copy, ebb);
+    let srcloc = func.srclocs[inst];
+    if !srcloc.is_default() {
+        func.srclocs[copy] = srcloc;
+    }
+    copy
+}
+
+/// Redirect `branch` to `dest`, passing `args` as the EBB arguments.
+fn set_branch_args(func: &mut Function, branch: Inst, dest: Ebb, args: &[Value]) {
+    let mut values = func.dfg.inst_fixed_args(branch).to_vec();
+    values.extend_from_slice(args);
+    let mut list = func.dfg[branch]
+        .take_value_list()
+        .expect("Branches have value lists");
+    list.clear(&mut func.dfg.value_lists);
+    list.extend(values, &mut func.dfg.value_lists);
+    func.dfg[branch].put_value_list(list);
+    func.change_branch_destination(branch, dest);
+}
@@ -408,6 +408,11 @@ impl AvailEnv {
         self.map[dst as usize] = self.map[src as usize];
     }
 
+    // Exchanges the bindings (if any) of `reg1` and `reg2`.
+    fn swap_regs(&mut self, reg1: RegUnit, reg2: RegUnit) {
+        self.map.swap(reg1 as usize, reg2 as usize);
+    }
+
     // Does `env` have the exact binding characterised by `(reg, kind, offset, size)` ?
     fn has_exact_binding(&self, reg: RegUnit, kind: StackSlotKind, offset: i32, size: u32) -> bool {
         debug_assert!(is_slot_kind_tracked(kind));
@@ -598,6 +603,9 @@ impl RedundantReloadRemover {
                 // tracking the copy (at the machine level, it's really a copy) in `avail_env`.
                 avail_env.copy_reg(*src, *dst);
             }
+            InstructionData::RegSwap { src, dst, .. } => {
+                avail_env.swap_regs(*src, *dst);
+            }
             InstructionData::RegSpill { .. }
             | InstructionData::RegFill { .. }
             | InstructionData::Call { .. }
@@ -1116,7 +1116,12 @@ impl<'a> Context<'a> {
     fn shuffle_inputs(&mut self, regs: &mut RegisterSet) {
         use crate::regalloc::solver::Move::*;
 
-        let spills = self.solver.schedule_moves(regs);
+        let spills = {
+            let isa = self.cur.isa;
+            let func = &self.cur.func;
+            self.solver
+                .schedule_moves(regs, |value1, value2| can_swap(isa, func, value1, value2))
+        };
 
         // The move operations returned by `schedule_moves` refer to emergency spill slots by
         // consecutive indexes starting from 0. Map these to real stack slots.
@@ -1140,6 +1145,20 @@ impl<'a> Context<'a> {
                     self.divert.regmove(value, from, to);
                     self.cur.ins().regmove(value, from, to);
                 }
+                Swap {
+                    value,
+                    other,
+                    from,
+                    to,
+                    rc,
+                } => {
+                    debug_assert!(
+                        !self.is_pinned_reg(rc, from) && !self.is_pinned_reg(rc, to),
+                        "pinned register used in a regswap"
+                    );
+                    self.divert.regswap(value, from, other, to);
+                    self.cur.ins().regswap(value, other, from, to);
+                }
                 Spill {
                     value,
                     from,
@@ -1280,6 +1299,18 @@ impl<'a> Context<'a> {
     }
 }
 
+/// Can `isa` exchange the registers holding `value1` and `value2` with a `regswap` instruction?
+fn can_swap(isa: &dyn TargetIsa, func: &Function, value1: Value, value2: Value) -> bool {
+    let data = InstructionData::RegSwap {
+        opcode: Opcode::Regswap,
+        args: [value1, value2],
+        src: 0,
+        dst: 0,
+    };
+    let ty = func.dfg.value_type(value1);
+    ty == func.dfg.value_type(value2) && isa.encode(func, &data, ty).is_ok()
+}
+
 /// Keep track of the set of available registers in two interference domains: all registers
 /// considering diversions and global registers not considering diversions.
 struct AvailableRegs {
@@ -8,17 +8,21 @@ use crate::dominator_tree::DominatorTree;
 use crate::flowgraph::ControlFlowGraph;
 use crate::ir::Function;
 use crate::isa::TargetIsa;
+use crate::loop_analysis::LoopAnalysis;
 #[cfg(feature = "basic-blocks")]
 use crate::regalloc::branch_splitting;
 use crate::regalloc::coalescing::Coalescing;
 use crate::regalloc::coloring::Coloring;
+use crate::regalloc::linear_scan::LinearScan;
 use crate::regalloc::live_value_tracker::LiveValueTracker;
 use crate::regalloc::liveness::Liveness;
 use crate::regalloc::reload::Reload;
 use crate::regalloc::safepoint::emit_stackmaps;
 use crate::regalloc::spilling::Spilling;
+use crate::regalloc::splitting::Splitting;
 use crate::regalloc::virtregs::VirtRegs;
 use crate::result::CodegenResult;
+use crate::settings::Regalloc;
 use crate::timing;
 use crate::topo_order::TopoOrder;
 use crate::verifier::{
@@ -32,7 +36,10 @@ pub struct Context {
     coalescing: Coalescing,
     topo: TopoOrder,
     tracker: LiveValueTracker,
+    loop_analysis: LoopAnalysis,
+    splitting: Splitting,
     spilling: Spilling,
+    linear_scan: LinearScan,
     reload: Reload,
     coloring: Coloring,
 }
@@ -49,7 +56,10 @@ impl Context {
             coalescing: Coalescing::new(),
             topo: TopoOrder::new(),
             tracker: LiveValueTracker::new(),
+            loop_analysis: LoopAnalysis::new(),
+            splitting: Splitting::new(),
             spilling: Spilling::new(),
+            linear_scan: LinearScan::new(),
             reload: Reload::new(),
             coloring: Coloring::new(),
         }
@@ -62,7 +72,10 @@ impl Context {
         self.coalescing.clear();
         self.topo.clear();
         self.tracker.clear();
+        self.loop_analysis.clear();
+        self.splitting.clear();
         self.spilling.clear();
+        self.linear_scan.clear();
         self.reload.clear();
         self.coloring.clear();
     }
@@ -112,60 +125,112 @@ impl Context {
             }
         }
 
-        // Pass: Coalesce and create Conventional SSA form.
-        self.coalescing.conventional_ssa(
-            isa,
-            func,
-            cfg,
-            domtree,
-            &mut self.liveness,
-            &mut self.virtregs,
-        );
+        if isa.flags().regalloc() == Regalloc::LinearScan {
+            // Pass: Create CSSA form and spill with a linear scan over live intervals.
+            self.linear_scan.run(
+                isa,
+                func,
+                cfg,
+                domtree,
+                &mut self.liveness,
+                &mut self.virtregs,
+            );
 
-        if isa.flags().enable_verifier() {
-            let ok = verify_context(func, cfg, domtree, isa, &mut errors).is_ok()
-                && verify_liveness(isa, func, cfg, &self.liveness, &mut errors).is_ok()
-                && verify_cssa(
-                    func,
-                    cfg,
-                    domtree,
-                    &self.liveness,
-                    &self.virtregs,
-                    &mut errors,
-                )
-                .is_ok();
+            if isa.flags().enable_verifier() {
+                let ok = verify_context(func, cfg, domtree, isa, &mut errors).is_ok()
+                    && verify_liveness(isa, func, cfg, &self.liveness, &mut errors).is_ok()
+                    && verify_cssa(
+                        func,
+                        cfg,
+                        domtree,
+                        &self.liveness,
+                        &self.virtregs,
+                        &mut errors,
+                    )
+                    .is_ok();
 
-            if !ok {
-                return Err(errors.into());
+                if !ok {
+                    return Err(errors.into());
+                }
             }
-        }
+        } else {
+            // Pass: Split live ranges at calls.
+            self.splitting.run(
+                isa,
+                func,
+                cfg,
+                domtree,
+                &mut self.liveness,
+                &mut self.topo,
+                &mut self.tracker,
+            );
 
-        // Pass: Spilling.
-        self.spilling.run(
-            isa,
-            func,
-            domtree,
-            &mut self.liveness,
-            &self.virtregs,
-            &mut self.topo,
-            &mut self.tracker,
-        );
+            if isa.flags().enable_verifier() {
+                let ok = verify_context(func, cfg, domtree, isa, &mut errors).is_ok()
+                    && verify_liveness(isa, func, cfg, &self.liveness, &mut errors).is_ok();
 
-        if isa.flags().enable_verifier() {
-            let ok = verify_context(func, cfg, domtree, isa, &mut errors).is_ok()
-                && verify_liveness(isa, func, cfg, &self.liveness, &mut errors).is_ok()
-                && verify_cssa(
-                    func,
-                    cfg,
-                    domtree,
-                    &self.liveness,
-                    &self.virtregs,
-                    &mut errors,
-                )
-                .is_ok();
+                if !ok {
+                    return Err(errors.into());
+                }
+            }
 
-            if !ok {
-                return Err(errors.into());
+            // Pass: Coalesce and create Conventional SSA form.
+            self.coalescing.conventional_ssa(
+                isa,
+                func,
+                cfg,
+                domtree,
+                &mut self.liveness,
+                &mut self.virtregs,
+            );
+
+            if isa.flags().enable_verifier() {
+                let ok = verify_context(func, cfg, domtree, isa, &mut errors).is_ok()
+                    && verify_liveness(isa, func, cfg, &self.liveness, &mut errors).is_ok()
+                    && verify_cssa(
+                        func,
+                        cfg,
+                        domtree,
+                        &self.liveness,
+                        &self.virtregs,
+                        &mut errors,
+                    )
+                    .is_ok();
+
+                if !ok {
+                    return Err(errors.into());
+                }
+            }
+
+            // Pass: Spilling.
+            self.loop_analysis.compute(func, cfg, domtree);
+            self.spilling.run(
+                isa,
+                func,
+                domtree,
+                &self.loop_analysis,
+                &mut self.liveness,
+                &self.virtregs,
+                &mut self.topo,
+                &mut self.tracker,
+            );
+
+            if isa.flags().enable_verifier() {
+                let ok = verify_context(func, cfg, domtree, isa, &mut errors).is_ok()
+                    && verify_liveness(isa, func, cfg, &self.liveness, &mut errors).is_ok()
+                    && verify_cssa(
+                        func,
+                        cfg,
+                        domtree,
+                        &self.liveness,
+                        &self.virtregs,
+                        &mut errors,
+                    )
+                    .is_ok();
+
+                if !ok {
+                    return Err(errors.into());
+                }
             }
         }
 
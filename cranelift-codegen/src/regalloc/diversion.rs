@@ -132,6 +132,13 @@ impl RegDiversions {
         self.divert(value, ValueLoc::Reg(from), ValueLoc::Reg(to));
     }
 
+    /// Record a register exchange: `value1` moves from `reg1` to `reg2` and `value2` moves from
+    /// `reg2` to `reg1`.
+    pub fn regswap(&mut self, value1: Value, reg1: RegUnit, value2: Value, reg2: RegUnit) {
+        self.regmove(value1, reg1, reg2);
+        self.regmove(value2, reg2, reg1);
+    }
+
     /// Record a register -> stack move.
     pub fn regspill(&mut self, value: Value, from: RegUnit, to: StackSlot) {
         self.divert(value, ValueLoc::Reg(from), ValueLoc::Stack(to));
@@ -144,8 +151,8 @@ impl RegDiversions {
 
     /// Apply the effect of `inst`.
     ///
-    /// If `inst` is a `regmove`, `regfill`, or `regspill` instruction, update the diversions to
-    /// match.
+    /// If `inst` is a `regmove`, `regswap`, `regfill`, or `regspill` instruction, update the
+    /// diversions to match.
     pub fn apply(&mut self, inst: &InstructionData) {
         match *inst {
             InstructionData::RegMove {
@@ -154,6 +161,12 @@ impl RegDiversions {
                 src,
                 dst,
             } => self.regmove(arg, src, dst),
+            InstructionData::RegSwap {
+                opcode: Opcode::Regswap,
+                args,
+                src,
+                dst,
+            } => self.regswap(args[0], src, args[1], dst),
             InstructionData::RegSpill {
                 opcode: Opcode::Regspill,
                 arg,
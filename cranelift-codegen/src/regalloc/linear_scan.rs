@@ -0,0 +1,649 @@
+//! Linear scan spilling.
+//!
+//! This pass is a faster alternative to the live range splitting, coalescing, and spilling passes.
+//! It is selected with the `regalloc = "linear_scan"` setting, and it trades some code quality for
+//! compile time on very large functions:
+//!
+//! 1. Conventional SSA form is created by copying every EBB argument immediately before its
+//!    branch. The copies never interfere with the EBB parameters, so the virtual registers can be
+//!    built without any of the interference checks done by the coalescing pass.
+//! 2. Each virtual register gets a single live interval in layout order, from the first point
+//!    where one of its values is live to the last. Holes in the live ranges are ignored.
+//! 3. The intervals are scanned in order of their start points. When there are not enough
+//!    registers, the active interval that ends furthest away is spilled. All the intervals that
+//!    are active across a call are spilled.
+//!
+//! Since the intervals cover all the program points where their values are live, the register
+//! pressure is at least as low as after the normal spilling pass. The reload and coloring passes
+//! then run as usual, so the resulting value locations and register diversions are the same kind
+//! as with the default allocator.
+
+use crate::cursor::{Cursor, EncCursor};
+use crate::dominator_tree::{DominatorTree, DominatorTreePreorder};
+use crate::entity::SecondaryMap;
+use crate::flowgraph::{BasicBlock, ControlFlowGraph};
+use crate::ir::{ArgumentLoc, Ebb, ExpandedProgramPoint, Function, Inst, InstBuilder};
+use crate::ir::{ProgramPoint, Value, ValueLoc};
+use crate::isa::registers::{RegClass, RegClassIndex, RegClassMask};
+use crate::isa::{ConstraintKind, EncInfo, RegInfo, TargetIsa};
+use crate::regalloc::affinity::Affinity;
+use crate::regalloc::liveness::Liveness;
+use crate::regalloc::pressure::Pressure;
+use crate::regalloc::virtregs::VirtRegs;
+use crate::timing;
+use alloc::vec::Vec;
+use core::cmp;
+use log::debug;
+
+/// Persistent data structures for the linear scan pass.
+pub struct LinearScan {
+    preorder: DominatorTreePreorder,
+    reg_uses: Vec<RegUse>,
+    values: Vec<Value>,
+    ebb_pos: SecondaryMap<Ebb, u32>,
+    inst_pos: SecondaryMap<Inst, u32>,
+    intervals: Vec<Interval>,
+    interval_of: SecondaryMap<Value, Option<usize>>,
+    order: Vec<usize>,
+    active: Vec<usize>,
+    used: Vec<usize>,
+}
+
+/// Context data structure that gets instantiated once per pass.
+struct Context<'a> {
+    // Current instruction as well as reference to function and ISA.
+    cur: EncCursor<'a>,
+
+    // Cached ISA information.
+    reginfo: RegInfo,
+    encinfo: EncInfo,
+
+    // References to contextual data structures we need.
+    cfg: &'a ControlFlowGraph,
+    preorder: &'a DominatorTreePreorder,
+    liveness: &'a mut Liveness,
+    virtregs: &'a mut VirtRegs,
+
+    // Current register pressure of the active intervals.
+    pressure: Pressure,
+
+    reg_uses: &'a mut Vec<RegUse>,
+    values: &'a mut Vec<Value>,
+
+    // Position of every EBB header and instruction in the layout.
+    ebb_pos: &'a mut SecondaryMap<Ebb, u32>,
+    inst_pos: &'a mut SecondaryMap<Inst, u32>,
+
+    // Live intervals, and the interval containing each value.
+    intervals: &'a mut Vec<Interval>,
+    interval_of: &'a mut SecondaryMap<Value, Option<usize>>,
+
+    // Indexes of `intervals` ordered by start position.
+    order: &'a mut Vec<usize>,
+
+    // Intervals currently assigned to a register.
+    active: &'a mut Vec<usize>,
+
+    // Intervals used by the current instruction.
+    used: &'a mut Vec<usize>,
+}
+
+impl LinearScan {
+    /// Create a new linear scan data structure.
+    pub fn new() -> Self {
+        Self {
+            preorder: DominatorTreePreorder::new(),
+            reg_uses: Vec::new(),
+            values: Vec::new(),
+            ebb_pos: SecondaryMap::new(),
+            inst_pos: SecondaryMap::new(),
+            intervals: Vec::new(),
+            interval_of: SecondaryMap::new(),
+            order: Vec::new(),
+            active: Vec::new(),
+            used: Vec::new(),
+        }
+    }
+
+    /// Clear all data structures in this linear scan pass.
+    pub fn clear(&mut self) {
+        self.reg_uses.clear();
+        self.values.clear();
+        self.ebb_pos.clear();
+        self.inst_pos.clear();
+        self.intervals.clear();
+        self.interval_of.clear();
+        self.order.clear();
+        self.active.clear();
+        self.used.clear();
+    }
+
+    /// Convert `func` to Conventional SSA form and spill values with the linear scan algorithm.
+    ///
+    /// The live ranges in `liveness` are recomputed, and `virtregs` must be empty.
+    pub fn run(
+        &mut self,
+        isa: &dyn TargetIsa,
+        func: &mut Function,
+        cfg: &ControlFlowGraph,
+        domtree: &DominatorTree,
+        liveness: &mut Liveness,
+        virtregs: &mut VirtRegs,
+    ) {
+        let _tt = timing::ra_linear_scan();
+        debug!("Linear scan for:\n{}", func.display(isa));
+        self.clear();
+        self.preorder.compute(domtree, &func.layout);
+        let reginfo = isa.register_info();
+        let usable_regs = isa.allocatable_registers(func);
+        let mut ctx = Context {
+            cur: EncCursor::new(func, isa),
+            reginfo: isa.register_info(),
+            encinfo: isa.encoding_info(),
+            cfg,
+            preorder: &self.preorder,
+            liveness,
+            virtregs,
+            pressure: Pressure::new(&reginfo, &usable_regs),
+            reg_uses: &mut self.reg_uses,
+            values: &mut self.values,
+            ebb_pos: &mut self.ebb_pos,
+            inst_pos: &mut self.inst_pos,
+            intervals: &mut self.intervals,
+            interval_of: &mut self.interval_of,
+            order: &mut self.order,
+            active: &mut self.active,
+            used: &mut self.used,
+        };
+        ctx.run()
+    }
+}
+
+impl<'a> Context<'a> {
+    fn run(&mut self) {
+        self.insert_copies();
+        self.liveness.compute(self.cur.isa, self.cur.func, self.cfg);
+        self.build_virtregs();
+        self.build_intervals();
+        self.scan();
+    }
+
+    /// Insert all the copies needed before computing the final live ranges:
+    ///
+    /// - EBB arguments are copied before their branch to form Conventional SSA.
+    /// - EBB parameters are copied at the top of their EBB when they could interfere with the
+    ///   copied arguments.
+    /// - Values used by tied operands that are not killed by the instruction are copied, and
+    ///   values used by more than one fixed register operand are copied for all but one.
+    fn insert_copies(&mut self) {
+        while let Some(ebb) = self.cur.next_ebb() {
+            if self.needs_isolated_params(ebb) {
+                self.isolate_params(ebb);
+            }
+
+            while let Some(inst) = self.cur.next_inst() {
+                if self.cur.func.dfg[inst].opcode().is_ghost() {
+                    continue;
+                }
+
+                self.collect_reg_uses(inst, ebb);
+                self.copy_reg_uses(inst);
+
+                if self.cur.func.dfg[inst].opcode().is_branch() {
+                    self.copy_branch_args(inst);
+                }
+            }
+        }
+    }
+
+    /// Can the parameters of `ebb` interfere with the copies of the predecessor branch arguments?
+    ///
+    /// This happens when a predecessor branch isn't the last instruction of its EBB, since the
+    /// parameters can be live past the branch. It also happens when a parameter is passed back as
+    /// an argument, since it is live until the last argument copy.
+    fn needs_isolated_params(&self, ebb: Ebb) -> bool {
+        let func = &self.cur.func;
+        let params = func.dfg.ebb_params(ebb);
+        !params.is_empty()
+            && self.cfg.pred_iter(ebb).any(|BasicBlock { inst, .. }| {
+                func.layout.next_inst(inst).is_some()
+                    || func
+                        .dfg
+                        .inst_variable_args(inst)
+                        .iter()
+                        .any(|arg| params.contains(arg))
+            })
+    }
+
+    /// Replace the parameters of `ebb` with new values that are immediately copied to the old
+    /// parameter values.
+    fn isolate_params(&mut self, ebb: Ebb) {
+        self.cur.goto_first_inst(ebb);
+        if let Some(inst) = self.cur.current_inst() {
+            self.cur.use_srcloc(inst);
+        }
+        for idx in 0..self.cur.func.dfg.num_ebb_params(ebb) {
+            let param = self.cur.func.dfg.ebb_params(ebb)[idx];
+            let ty = self.cur.func.dfg.value_type(param);
+            let new_val = self.cur.func.dfg.replace_ebb_param(param, ty);
+            self.cur.ins().with_result(param).copy(new_val);
+        }
+        self.cur.goto_top(ebb);
+    }
+
+    /// Copy all the EBB arguments of the branch `inst`.
+    fn copy_branch_args(&mut self, inst: Inst) {
+        self.cur.use_srcloc(inst);
+        for idx in 0..self.cur.func.dfg.inst_variable_args(inst).len() {
+            let arg = self.cur.func.dfg.inst_variable_args(inst)[idx];
+            let copy = self.cur.ins().copy(arg);
+            self.cur.func.dfg.inst_variable_args_mut(inst)[idx] = copy;
+        }
+    }
+
+    /// Collect all the register uses of `inst` in `self.reg_uses`.
+    fn collect_reg_uses(&mut self, inst: Inst, ebb: Ebb) {
+        self.reg_uses.clear();
+        let func = &self.cur.func;
+        let args = func.dfg.inst_args(inst);
+
+        let num_fixed_ins = match self.encinfo.operand_constraints(func.encodings[inst]) {
+            Some(constraints) => {
+                for (idx, (op, &arg)) in constraints.ins.iter().zip(args).enumerate() {
+                    let mut reguse = RegUse::new(arg, idx, op.regclass.into());
+                    match op.kind {
+                        ConstraintKind::Stack => continue,
+                        ConstraintKind::Reg => {}
+                        ConstraintKind::FixedReg(_) => reguse.fixed = true,
+                        ConstraintKind::Tied(_) => {
+                            reguse.tied = !self.liveness[arg].killed_at(inst, ebb, &func.layout);
+                        }
+                        ConstraintKind::FixedTied(_) => {
+                            reguse.fixed = true;
+                            reguse.tied = !self.liveness[arg].killed_at(inst, ebb, &func.layout);
+                        }
+                    }
+                    self.reg_uses.push(reguse);
+                }
+                constraints.ins.len()
+            }
+            None => 0,
+        };
+
+        // Return values and call arguments in ABI registers.
+        if func.dfg[inst].opcode().is_return() {
+            for (idx, (abi, &arg)) in func.signature.returns.iter().zip(args).enumerate() {
+                if let ArgumentLoc::Reg(_) = abi.location {
+                    let rc = self.cur.isa.regclass_for_abi_type(abi.value_type);
+                    let mut reguse = RegUse::new(arg, num_fixed_ins + idx, rc.into());
+                    reguse.fixed = true;
+                    self.reg_uses.push(reguse);
+                }
+            }
+        } else if let Some(sig) = func.dfg.call_signature(inst) {
+            let num_fixed_args = func.dfg[inst]
+                .opcode()
+                .constraints()
+                .num_fixed_value_arguments();
+            let varargs = func.dfg.inst_variable_args(inst);
+            for (idx, (abi, &arg)) in func.dfg.signatures[sig]
+                .params
+                .iter()
+                .zip(varargs)
+                .enumerate()
+            {
+                if let ArgumentLoc::Reg(_) = abi.location {
+                    let rc = self.cur.isa.regclass_for_abi_type(abi.value_type);
+                    let mut reguse = RegUse::new(arg, num_fixed_args + idx, rc.into());
+                    reguse.fixed = true;
+                    self.reg_uses.push(reguse);
+                }
+            }
+        }
+    }
+
+    /// Insert copies for the register uses of `inst` that need them.
+    fn copy_reg_uses(&mut self, inst: Inst) {
+        self.reg_uses.sort_unstable_by_key(|u| (u.value, u.opidx));
+        self.cur.use_srcloc(inst);
+        for i in 0..self.reg_uses.len() {
+            let ru = self.reg_uses[i];
+            let need_copy = ru.tied
+                || (ru.fixed
+                    && self.reg_uses[..i]
+                        .iter()
+                        .any(|ru2| ru2.value == ru.value && (ru2.fixed || ru2.tied)));
+            if need_copy {
+                let copy = self.cur.ins().copy(ru.value);
+                self.cur.func.dfg.inst_args_mut(inst)[ru.opidx as usize] = copy;
+            }
+        }
+    }
+
+    /// Build a virtual register for every EBB parameter and the corresponding arguments.
+    fn build_virtregs(&mut self) {
+        let func = &self.cur.func;
+        for ebb in func.layout.ebbs() {
+            for (idx, &param) in func.dfg.ebb_params(ebb).iter().enumerate() {
+                self.values.clear();
+                self.values.push(param);
+                for BasicBlock { inst, .. } in self.cfg.pred_iter(ebb) {
+                    self.values.push(func.dfg.inst_variable_args(inst)[idx]);
+                }
+                if self.values.len() > 1 {
+                    let vreg = self.virtregs.unify(&self.values[..]);
+                    self.virtregs.sort_values(vreg, func, self.preorder);
+                }
+            }
+        }
+    }
+
+    /// Number all the program points and compute the live interval of every virtual register.
+    fn build_intervals(&mut self) {
+        let mut pos = 0;
+        for ebb in self.cur.func.layout.ebbs() {
+            self.ebb_pos[ebb] = pos;
+            pos += 1;
+            for inst in self.cur.func.layout.ebb_insts(ebb) {
+                self.inst_pos[inst] = pos;
+                pos += 1;
+            }
+        }
+
+        for value in self.cur.func.dfg.values() {
+            let lr = match self.liveness.get(value) {
+                Some(lr) => lr,
+                None => continue,
+            };
+            let rci = match lr.affinity {
+                Affinity::Reg(rci) => rci,
+                Affinity::Stack | Affinity::Unassigned => continue,
+            };
+
+            let mut start = self.pp_pos(lr.def());
+            let mut end = self.pp_pos(lr.def_local_end());
+            for (ebb, inst) in lr.liveins() {
+                start = cmp::min(start, self.ebb_pos[ebb]);
+                end = cmp::max(end, self.inst_pos[inst]);
+            }
+
+            let leader = self
+                .virtregs
+                .get(value)
+                .map_or(value, |vreg| self.virtregs.values(vreg)[0]);
+            let idx = match self.interval_of[leader] {
+                Some(idx) => {
+                    let interval = &mut self.intervals[idx];
+                    interval.start = cmp::min(interval.start, start);
+                    interval.end = cmp::max(interval.end, end);
+                    idx
+                }
+                None => {
+                    self.intervals.push(Interval {
+                        value: leader,
+                        rc: self.reginfo.rc(rci),
+                        start,
+                        end,
+                        spilled: false,
+                    });
+                    self.intervals.len() - 1
+                }
+            };
+            self.interval_of[leader] = Some(idx);
+            self.interval_of[value] = Some(idx);
+        }
+
+        // A virtual register with a value already on the stack must be spilled entirely.
+        for idx in 0..self.intervals.len() {
+            let value = self.intervals[idx].value;
+            if self.virtregs.congruence_class(&value).iter().any(|&v| {
+                self.liveness
+                    .get(v)
+                    .map_or(false, |lr| lr.affinity.is_stack())
+            }) {
+                self.spill(idx);
+            }
+        }
+
+        let intervals = &*self.intervals;
+        self.order.extend(0..intervals.len());
+        self.order
+            .sort_unstable_by_key(|&idx| (intervals[idx].start, idx));
+    }
+
+    /// Get the position of a program point.
+    fn pp_pos(&self, pp: ProgramPoint) -> u32 {
+        match pp.into() {
+            ExpandedProgramPoint::Ebb(ebb) => self.ebb_pos[ebb],
+            ExpandedProgramPoint::Inst(inst) => self.inst_pos[inst],
+        }
+    }
+
+    /// Scan the program points in layout order and spill intervals when there are not enough
+    /// registers.
+    fn scan(&mut self) {
+        let mut next = 0;
+        let mut next_ebb = self.cur.func.layout.entry_block();
+        while let Some(ebb) = next_ebb {
+            next_ebb = self.cur.func.layout.next_ebb(ebb);
+            let pos = self.ebb_pos[ebb];
+            self.expire(pos);
+            self.activate(pos, &mut next);
+
+            let mut next_inst = self.cur.func.layout.first_inst(ebb);
+            while let Some(inst) = next_inst {
+                next_inst = self.cur.func.layout.next_inst(inst);
+                self.visit_inst(inst, ebb, &mut next);
+            }
+        }
+    }
+
+    fn visit_inst(&mut self, inst: Inst, ebb: Ebb, next: &mut usize) {
+        let pos = self.inst_pos[inst];
+        self.expire(pos);
+
+        if !self.cur.func.dfg[inst].opcode().is_ghost() {
+            // Spilling a value used by the instruction wouldn't help. EBB arguments on branches
+            // can be spilled since their whole virtual register is spilled.
+            self.used.clear();
+            let args = if self.cur.func.dfg[inst].opcode().is_branch() {
+                self.cur.func.dfg.inst_fixed_args(inst)
+            } else {
+                self.cur.func.dfg.inst_args(inst)
+            };
+            for &arg in args {
+                if let Some(idx) = self.interval_of[arg] {
+                    self.used.push(idx);
+                }
+            }
+
+            // The reload pass needs a register for every register use of a spilled value.
+            self.collect_reg_uses(inst, ebb);
+            for i in 0..self.reg_uses.len() {
+                let ru = self.reg_uses[i];
+                if self.liveness[ru.value].affinity.is_stack() {
+                    let rc = self.reginfo.rc(ru.rci);
+                    while let Err(mask) = self.pressure.take_transient(rc) {
+                        match self.spill_candidate(mask) {
+                            Some(cand) => self.spill(cand),
+                            None => panic!(
+                                "Ran out of {} registers for {}",
+                                rc,
+                                self.cur.display_inst(inst)
+                            ),
+                        }
+                    }
+                }
+            }
+            self.pressure.reset_transient();
+            self.used.clear();
+
+            // The intervals ending here are killed. Everything else that is still active is live
+            // across the instruction.
+            self.expire(pos + 1);
+
+            // Spill all the intervals that are live across a call.
+            if self.cur.func.dfg.call_signature(inst).is_some() {
+                while let Some(&idx) = self.active.last() {
+                    self.spill(idx);
+                }
+            }
+
+            // Make sure there are enough registers for the register defs.
+            let constraints = self
+                .encinfo
+                .operand_constraints(self.cur.func.encodings[inst]);
+            if let Some(constraints) = constraints {
+                for op in constraints.outs {
+                    if op.kind != ConstraintKind::Stack {
+                        while let Err(mask) = self.pressure.take_transient(op.regclass) {
+                            match self.spill_candidate(mask) {
+                                Some(cand) => self.spill(cand),
+                                None => panic!(
+                                    "Ran out of {} registers for {}",
+                                    op.regclass,
+                                    self.cur.display_inst(inst)
+                                ),
+                            }
+                        }
+                    }
+                }
+                self.pressure.reset_transient();
+            }
+        }
+
+        self.activate(pos, next);
+    }
+
+    /// Remove the active intervals that end before `pos`.
+    fn expire(&mut self, pos: u32) {
+        let intervals = &*self.intervals;
+        let pressure = &mut self.pressure;
+        self.active.retain(|&idx| {
+            let interval = &intervals[idx];
+            if interval.end < pos {
+                pressure.free(interval.rc);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Activate the intervals starting at `pos`, spilling when there are not enough registers.
+    fn activate(&mut self, pos: u32, next: &mut usize) {
+        while let Some(&idx) = self.order.get(*next) {
+            if self.intervals[idx].start > pos {
+                break;
+            }
+            *next += 1;
+            if self.intervals[idx].spilled {
+                continue;
+            }
+
+            let rc = self.intervals[idx].rc;
+            loop {
+                let mask = self.pressure.check_avail(rc);
+                if mask == 0 {
+                    self.pressure.take(rc);
+                    self.active.push(idx);
+                    break;
+                }
+
+                // Spill the interval that ends furthest away. That may be the new one.
+                match self.spill_candidate(mask) {
+                    Some(cand) if self.intervals[cand].end > self.intervals[idx].end => {
+                        self.spill(cand)
+                    }
+                    _ => {
+                        self.spill(idx);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Find the active interval ending furthest away whose top-level register class is in
+    /// `mask`, and which is not used by the current instruction.
+    fn spill_candidate(&self, mask: RegClassMask) -> Option<usize> {
+        self.active
+            .iter()
+            .cloned()
+            .filter(|&idx| {
+                (mask & (1 << self.intervals[idx].rc.toprc)) != 0 && !self.used.contains(&idx)
+            })
+            .max_by_key(|&idx| (self.intervals[idx].end, idx))
+    }
+
+    /// Spill the interval `idx`, assigning a single spill slot to its whole virtual register.
+    fn spill(&mut self, idx: usize) {
+        let interval = &mut self.intervals[idx];
+        debug_assert!(!interval.spilled, "{} already spilled", interval.value);
+        interval.spilled = true;
+        if let Some(i) = self.active.iter().position(|&a| a == idx) {
+            self.active.swap_remove(i);
+            self.pressure.free(interval.rc);
+        }
+
+        let value = interval.value;
+        debug!(
+            "Spilling {} in [{}, {}]",
+            value, interval.start, interval.end
+        );
+        let ss = self
+            .cur
+            .func
+            .stack_slots
+            .make_spill_slot(self.cur.func.dfg.value_type(value));
+        for &v in self.virtregs.congruence_class(&value) {
+            self.liveness.spill(v);
+            self.cur.func.locations[v] = ValueLoc::Stack(ss);
+        }
+    }
+}
+
+/// A live interval covering all the program points where the values of a virtual register are
+/// live.
+struct Interval {
+    // The first value of the virtual register, or the value itself when it doesn't belong to one.
+    value: Value,
+
+    // Register class of the values.
+    rc: RegClass,
+
+    // First and last position where the interval is live, inclusive.
+    start: u32,
+    end: u32,
+
+    // The interval has been spilled.
+    spilled: bool,
+}
+
+/// A register use of a value.
+#[derive(Clone, Copy)]
+struct RegUse {
+    value: Value,
+    opidx: u16,
+
+    // Register class required by the use.
+    rci: RegClassIndex,
+
+    // A use with a fixed register constraint.
+    fixed: bool,
+
+    // A use with a tied register constraint *and* the used value is not killed.
+    tied: bool,
+}
+
+impl RegUse {
+    fn new(value: Value, idx: usize, rci: RegClassIndex) -> Self {
+        Self {
+            value,
+            opidx: idx as u16,
+            rci,
+            fixed: false,
+            tied: false,
+        }
+    }
+}
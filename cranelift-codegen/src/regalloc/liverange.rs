@@ -188,7 +188,9 @@ pub struct GenericLiveRange<PO: ProgramOrder> {
     /// defined.
     liveins: SmallVec<[Interval; 2]>,
 
-    po: PhantomData<*const PO>,
+    // Like `*const PO`, this doesn't own a `PO`, but it keeps the live range `Send` and `Sync` so
+    // a `Context` can be moved to another thread.
+    po: PhantomData<fn() -> PO>,
 }
 
 /// A simple helper macro to make comparisons more natural to read.
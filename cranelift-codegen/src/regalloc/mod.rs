@@ -14,13 +14,17 @@ mod branch_splitting;
 mod coalescing;
 mod context;
 mod diversion;
+mod linear_scan;
 mod pressure;
 mod reload;
 mod safepoint;
 mod solver;
 mod spilling;
+mod splitting;
 
 pub use self::context::Context;
 pub use self::diversion::{EntryRegDiversions, RegDiversions};
 pub use self::register_set::RegisterSet;
 pub use self::safepoint::emit_stackmaps;
+
+pub(crate) use self::pressure::Pressure;
@@ -136,7 +136,7 @@ impl Pressure {
     /// If not, returns a bit-mask of top-level register classes that are interfering. Register
     /// pressure should be eased in one of the returned top-level register classes before calling
     /// `can_take()` to check again.
-    fn check_avail(&self, rc: RegClass) -> RegClassMask {
+    pub fn check_avail(&self, rc: RegClass) -> RegClassMask {
         let entry = match self.toprc.get(rc.toprc as usize) {
             None => return 0, // Not a pressure tracked bank.
             Some(e) => e,
@@ -8,11 +8,16 @@
 //! The secondary responsibility of the reload pass is to reuse values in registers as much as
 //! possible to minimize the number of `fill` instructions needed. This must not cause the register
 //! pressure limits to be exceeded.
+//!
+//! Spilled values that are computed by a cheap instruction without side effects, like a constant,
+//! are rematerialized instead of being reloaded: the defining instruction is repeated in front of
+//! each register use. When all the uses of such a value are rematerialized, its original
+//! definition and the `spill` that followed it are removed.
 
 use crate::cursor::{Cursor, EncCursor};
 use crate::dominator_tree::DominatorTree;
-use crate::entity::{SparseMap, SparseMapValue};
-use crate::ir::{AbiParam, ArgumentLoc, InstBuilder};
+use crate::entity::{EntitySet, SparseMap, SparseMapValue};
+use crate::ir::{AbiParam, ArgumentLoc, InstBuilder, InstBuilderBase, ValueDef};
 use crate::ir::{Ebb, Function, Inst, InstructionData, Opcode, Value, ValueLoc};
 use crate::isa::RegClass;
 use crate::isa::{ConstraintKind, EncInfo, Encoding, RecipeConstraints, TargetIsa};
@@ -28,6 +33,19 @@ use log::debug;
 pub struct Reload {
     candidates: Vec<ReloadCandidate>,
     reloads: SparseMap<Value, ReloadedValue>,
+    remats: Vec<Value>,
+    used: EntitySet<Value>,
+}
+
+/// Can the instruction `data` be repeated at a reload point instead of filling its result?
+///
+/// This is limited to cheap instructions without side effects or value operands, so the copy
+/// doesn't depend on anything being live at the reload point.
+pub fn can_rematerialize(data: &InstructionData) -> bool {
+    match data.opcode() {
+        Opcode::Iconst | Opcode::Bconst | Opcode::F32const | Opcode::F64const => true,
+        _ => false,
+    }
 }
 
 /// Context data structure that gets instantiated once per pass.
@@ -45,6 +63,10 @@ struct Context<'a> {
 
     candidates: &'a mut Vec<ReloadCandidate>,
     reloads: &'a mut SparseMap<Value, ReloadedValue>,
+
+    // Spilled values whose definition can be rematerialized.
+    remats: &'a mut Vec<Value>,
+    used: &'a mut EntitySet<Value>,
 }
 
 impl Reload {
@@ -53,6 +75,8 @@ impl Reload {
         Self {
             candidates: Vec::new(),
             reloads: SparseMap::new(),
+            remats: Vec::new(),
+            used: EntitySet::new(),
         }
     }
 
@@ -60,6 +84,8 @@ impl Reload {
     pub fn clear(&mut self) {
         self.candidates.clear();
         self.reloads.clear();
+        self.remats.clear();
+        self.used.clear();
     }
 
     /// Run the reload algorithm over `func`.
@@ -82,6 +108,8 @@ impl Reload {
             topo,
             candidates: &mut self.candidates,
             reloads: &mut self.reloads,
+            remats: &mut self.remats,
+            used: &mut self.used,
         };
         ctx.run(tracker)
     }
@@ -117,6 +145,7 @@ impl<'a> Context<'a> {
         while let Some(ebb) = self.topo.next(&self.cur.func.layout, self.domtree) {
             self.visit_ebb(ebb, tracker);
         }
+        self.remove_dead_remats();
     }
 
     fn visit_ebb(&mut self, ebb: Ebb, tracker: &mut LiveValueTracker) {
@@ -301,6 +330,9 @@ impl<'a> Context<'a> {
                         let ok = self.cur.func.update_encoding(inst, self.cur.isa).is_ok();
                         debug_assert!(ok);
                     } else {
+                        if can_rematerialize(&self.cur.func.dfg[inst]) {
+                            self.remats.push(lv.value);
+                        }
                         let value_type = self.cur.func.dfg.value_type(lv.value);
                         let reg = self.cur.func.dfg.replace_result(lv.value, value_type);
                         self.liveness.create_dead(reg, inst, Affinity::new(op));
@@ -348,8 +380,21 @@ impl<'a> Context<'a> {
                 continue;
             }
 
-            let reg = self.cur.ins().fill(cand.value);
-            let fill = self.cur.built_inst();
+            let (reg, fill) =
+                match remat_encoding(self.cur.func, self.cur.isa, &self.encinfo, cand.value) {
+                    Some((src, encoding)) => {
+                        let data = self.cur.func.dfg[src].clone();
+                        let ctrl_type = self.cur.func.dfg.ctrl_typevar(src);
+                        let (remat, dfg) = self.cur.ins().build(data, ctrl_type);
+                        let reg = dfg.first_result(remat);
+                        self.cur.func.encodings[remat] = encoding;
+                        (reg, remat)
+                    }
+                    None => {
+                        let reg = self.cur.ins().fill(cand.value);
+                        (reg, self.cur.built_inst())
+                    }
+                };
 
             self.reloads.insert(ReloadedValue {
                 stack: cand.value,
@@ -387,12 +432,55 @@ impl<'a> Context<'a> {
         debug_assert!(self.candidates.is_empty() || self.candidates.len() == 1);
 
         if let Some(cand) = self.candidates.pop() {
-            self.cur.func.dfg.replace(inst).fill(cand.value);
-            let ok = self.cur.func.update_encoding(inst, self.cur.isa).is_ok();
-            debug_assert!(ok);
+            if let Some((src, encoding)) =
+                remat_encoding(self.cur.func, self.cur.isa, &self.encinfo, cand.value)
+            {
+                self.cur.func.dfg[inst] = self.cur.func.dfg[src].clone();
+                self.cur.func.encodings[inst] = encoding;
+            } else {
+                self.cur.func.dfg.replace(inst).fill(cand.value);
+                let ok = self.cur.func.update_encoding(inst, self.cur.isa).is_ok();
+                debug_assert!(ok);
+            }
         }
     }
 
+    /// Remove the definitions of rematerialized values that no longer have any uses.
+    ///
+    /// These are spilled values where all the register uses were rematerialized and there are no
+    /// uses that need the value on the stack.
+    fn remove_dead_remats(&mut self) {
+        if self.remats.is_empty() {
+            return;
+        }
+
+        self.used.clear();
+        for ebb in self.cur.func.layout.ebbs() {
+            for inst in self.cur.func.layout.ebb_insts(ebb) {
+                for &arg in self.cur.func.dfg.inst_args(inst) {
+                    self.used.insert(arg);
+                }
+            }
+        }
+
+        for &value in self.remats.iter() {
+            if self.used.contains(value) {
+                continue;
+            }
+            let spill = self.cur.func.dfg.value_def(value).unwrap_inst();
+            let reg = self.cur.func.dfg.inst_args(spill)[0];
+            let src = self.cur.func.dfg.value_def(reg).unwrap_inst();
+            debug!(
+                "Removing rematerialized {} = {}",
+                value,
+                self.cur.display_inst(src)
+            );
+            self.cur.func.layout.remove_inst(spill);
+            self.cur.func.layout.remove_inst(src);
+        }
+        self.remats.clear();
+    }
+
     // Find reload candidates for `inst` and add them to `self.candidates`.
     //
     // These are uses of spilled values where the operand constraint requires a register.
@@ -483,3 +571,33 @@ fn handle_abi_args(
         }
     }
 }
+
+/// Get the instruction that can recompute the spilled `value` in a register, along with the
+/// encoding to use for the copy.
+///
+/// The rematerialized instruction is inserted right before a use where the CPU flags may be live,
+/// so only encodings that leave the flags alone are considered.
+fn remat_encoding(
+    func: &Function,
+    isa: &dyn TargetIsa,
+    encinfo: &EncInfo,
+    value: Value,
+) -> Option<(Inst, Encoding)> {
+    // A spilled definition `v2 = iconst` has been rewritten as `v7 = iconst; v2 = spill v7`.
+    let spill = match func.dfg.value_def(value) {
+        ValueDef::Result(inst, _) if func.dfg[inst].opcode() == Opcode::Spill => inst,
+        _ => return None,
+    };
+    let src = match func.dfg.value_def(func.dfg.inst_args(spill)[0]) {
+        ValueDef::Result(inst, _) if can_rematerialize(&func.dfg[inst]) => inst,
+        _ => return None,
+    };
+    let ctrl_type = func.dfg.ctrl_typevar(src);
+    isa.legal_encodings(func, &func.dfg[src], ctrl_type)
+        .find(|&encoding| {
+            encinfo
+                .operand_constraints(encoding)
+                .map_or(false, |constraints| !constraints.clobbers_flags)
+        })
+        .map(|encoding| (src, encoding))
+}
@@ -97,6 +97,15 @@
 //! If the search fails to find a solution, we may need to reassign more registers. Find an
 //! appropriate candidate among the set of live register values, add it as a variable and start
 //! over.
+//!
+//! # Scheduling moves
+//!
+//! Once a solution is found, the reassignments are executed as a parallel copy before the
+//! instruction. The copy is sequentialized into `regmove` instructions, and cycles are broken by:
+//!
+//! 1. Moving a value through an available register.
+//! 2. Exchanging two registers with a `regswap` instruction, if the target ISA can encode it.
+//! 3. Temporarily spilling a value to an emergency stack slot.
 
 use super::RegisterSet;
 use crate::dbg::DisplayList;
@@ -265,6 +274,15 @@ pub enum Move {
         from: RegUnit,
         to: RegUnit,
     },
+    /// Exchange two registers: `value` moves from `from` to `to` while `other` moves from `to` to
+    /// `from`.
+    Swap {
+        value: Value,
+        other: Value,
+        rc: RegClass,
+        from: RegUnit,
+        to: RegUnit,
+    },
     #[allow(dead_code)] // rustc doesn't see it isn't dead.
     Spill {
         value: Value,
@@ -299,7 +317,9 @@ impl Move {
     #[cfg_attr(feature = "cargo-clippy", allow(clippy::wrong_self_convention))]
     fn from_reg(&self) -> Option<(RegClass, RegUnit)> {
         match *self {
-            Self::Reg { rc, from, .. } | Self::Spill { rc, from, .. } => Some((rc, from)),
+            Self::Reg { rc, from, .. }
+            | Self::Swap { rc, from, .. }
+            | Self::Spill { rc, from, .. } => Some((rc, from)),
             Self::Fill { .. } => None,
         }
     }
@@ -307,7 +327,9 @@ impl Move {
     /// Get the "to" register and register class, if possible.
     fn to_reg(&self) -> Option<(RegClass, RegUnit)> {
         match *self {
-            Self::Reg { rc, to, .. } | Self::Fill { rc, to, .. } => Some((rc, to)),
+            Self::Reg { rc, to, .. } | Self::Swap { rc, to, .. } | Self::Fill { rc, to, .. } => {
+                Some((rc, to))
+            }
             Self::Spill { .. } => None,
         }
     }
@@ -317,6 +339,7 @@ impl Move {
         mem::replace(
             match *self {
                 Self::Reg { ref mut to, .. } | Self::Fill { ref mut to, .. } => to,
+                Self::Swap { .. } => panic!("Can't replace the to register of a swap {}", self),
                 Self::Spill { .. } => panic!("No to register in a spill {}", self),
             },
             new,
@@ -347,14 +370,20 @@ impl Move {
     /// Get the value being moved.
     fn value(&self) -> Value {
         match *self {
-            Self::Reg { value, .. } | Self::Fill { value, .. } | Self::Spill { value, .. } => value,
+            Self::Reg { value, .. }
+            | Self::Swap { value, .. }
+            | Self::Fill { value, .. }
+            | Self::Spill { value, .. } => value,
         }
     }
 
     /// Get the associated register class.
     fn rc(&self) -> RegClass {
         match *self {
-            Self::Reg { rc, .. } | Self::Fill { rc, .. } | Self::Spill { rc, .. } => rc,
+            Self::Reg { rc, .. }
+            | Self::Swap { rc, .. }
+            | Self::Fill { rc, .. }
+            | Self::Spill { rc, .. } => rc,
         }
     }
 }
@@ -375,6 +404,21 @@ impl fmt::Display for Move {
                 rc.info.display_regunit(from),
                 rc.info.display_regunit(to)
             ),
+            Self::Swap {
+                value,
+                other,
+                from,
+                to,
+                rc,
+            } => write!(
+                f,
+                "{}:{}({} <-> {}):{}",
+                value,
+                rc,
+                rc.info.display_regunit(from),
+                rc.info.display_regunit(to),
+                other
+            ),
             Self::Spill {
                 value,
                 from,
@@ -1007,14 +1051,15 @@ impl Solver {
     /// Try to schedule a sequence of `regmove` instructions that will shuffle registers into
     /// place.
     ///
-    /// This may require the use of additional available registers, and it can fail if no
-    /// additional registers are available.
-    ///
-    /// TODO: Handle failure by generating a sequence of register swaps, or by temporarily spilling
-    /// a register.
+    /// Cycles of moves are broken with an additional available register when possible. Otherwise,
+    /// two values are exchanged with a `regswap` when `can_swap` says the target supports it for
+    /// the two values. As a last resort, a value is temporarily spilled to an emergency slot.
     ///
     /// Returns the number of spills that had to be emitted.
-    pub fn schedule_moves(&mut self, regs: &RegisterSet) -> usize {
+    pub fn schedule_moves<F>(&mut self, regs: &RegisterSet, can_swap: F) -> usize
+    where
+        F: Fn(Value, Value) -> bool,
+    {
         self.collect_moves();
         debug_assert!(self.fills.is_empty());
 
@@ -1051,10 +1096,8 @@ impl Solver {
             // only cycles remaining. The cycles can be broken in a few ways:
             //
             // 1. Grab an available register and use it to break a cycle.
-            // 2. Move a value temporarily into a stack slot instead of a register.
-            // 3. Use swap instructions.
-            //
-            // TODO: So far we only implement 1 and 2.
+            // 2. Use swap instructions.
+            // 3. Move a value temporarily into a stack slot instead of a register.
 
             // Pick an assignment with the largest possible width. This is more likely to break up
             // a cycle than an assignment with fewer register units. For example, it may be
@@ -1103,6 +1146,41 @@ impl Solver {
                 continue;
             }
 
+            // Without an available register, exchange `m.value` with the value occupying its
+            // destination register. The other value may still need to move on from there.
+            if let Some(k) = self.find_swap(i, &can_swap) {
+                let other = self.moves[k].value();
+                let (from, to) = match m {
+                    Move::Reg { from, to, .. } => (from, to),
+                    _ => unreachable!(),
+                };
+                debug!("breaking cycle at {} by swapping with {}", m, other);
+                self.moves[i] = Move::Swap {
+                    value: m.value(),
+                    other,
+                    rc: toprc,
+                    from,
+                    to,
+                };
+                // The other value is now in `from`, which may be where it was going.
+                let done = match self.moves[k] {
+                    Move::Reg {
+                        from: ref mut other_from,
+                        to: other_to,
+                        ..
+                    } => {
+                        *other_from = from;
+                        other_to == from
+                    }
+                    _ => unreachable!(),
+                };
+                if done {
+                    self.moves.remove(k);
+                }
+                i += 1;
+                continue;
+            }
+
             // It was impossible to free up a register in toprc, so use an emergency spill slot as
             // a last resort.
             let slot = num_spill_slots;
@@ -1120,6 +1198,37 @@ impl Solver {
         num_spill_slots
     }
 
+    /// Find a move in `moves[i+1..]` that can be swapped with the register move `moves[i]`.
+    ///
+    /// The other move must be moving a value out of the destination register of `moves[i]`, and
+    /// the two registers must have the same width.
+    fn find_swap<F>(&self, i: usize, can_swap: &F) -> Option<usize>
+    where
+        F: Fn(Value, Value) -> bool,
+    {
+        let (value, rc, to) = match self.moves[i] {
+            Move::Reg { value, rc, to, .. } => (value, rc, to),
+            _ => return None,
+        };
+        self.moves[i + 1..]
+            .iter()
+            .position(|m| match *m {
+                Move::Reg {
+                    value: other,
+                    rc: other_rc,
+                    from,
+                    ..
+                } => {
+                    from == to
+                        && other_rc.width == rc.width
+                        && other_rc.toprc() == rc.toprc()
+                        && can_swap(value, other)
+                }
+                _ => false,
+            })
+            .map(|k| i + 1 + k)
+    }
+
     /// Borrow the scheduled set of register moves that was computed by `schedule_moves()`.
     pub fn moves(&self) -> &[Move] {
         &self.moves
@@ -1196,6 +1305,16 @@ mod tests {
         }
     }
 
+    fn swap(value: Value, other: Value, rc: RegClass, from: RegUnit, to: RegUnit) -> Move {
+        Move::Swap {
+            value,
+            other,
+            rc,
+            from,
+            to,
+        }
+    }
+
     fn fill(value: Value, rc: RegClass, from_slot: usize, to: RegUnit) -> Move {
         Move::Fill {
             value,
@@ -1225,7 +1344,7 @@ mod tests {
         solver.reassign_in(v10, gpr, r1, r0);
         solver.inputs_done();
         assert!(solver.quick_solve(&gregs, false).is_ok());
-        assert_eq!(solver.schedule_moves(&regs), 0);
+        assert_eq!(solver.schedule_moves(&regs, |_, _| false), 0);
         assert_eq!(solver.moves(), &[mov(v10, gpr, r1, r0)]);
 
         // A bit harder: r0, r1 need to go in r1, r2.
@@ -1235,7 +1354,7 @@ mod tests {
         solver.reassign_in(v11, gpr, r1, r2);
         solver.inputs_done();
         assert!(solver.quick_solve(&gregs, false).is_ok());
-        assert_eq!(solver.schedule_moves(&regs), 0);
+        assert_eq!(solver.schedule_moves(&regs, |_, _| false), 0);
         assert_eq!(
             solver.moves(),
             &[mov(v11, gpr, r1, r2), mov(v10, gpr, r0, r1)]
@@ -1247,7 +1366,7 @@ mod tests {
         solver.reassign_in(v11, gpr, r1, r0);
         solver.inputs_done();
         assert!(solver.quick_solve(&gregs, false).is_ok());
-        assert_eq!(solver.schedule_moves(&regs), 0);
+        assert_eq!(solver.schedule_moves(&regs, |_, _| false), 0);
         assert_eq!(
             solver.moves(),
             &[
@@ -1287,7 +1406,7 @@ mod tests {
         solver.reassign_in(v12, s, s3, s1);
         solver.inputs_done();
         assert!(solver.quick_solve(&gregs, false).is_ok());
-        assert_eq!(solver.schedule_moves(&regs), 0);
+        assert_eq!(solver.schedule_moves(&regs, |_, _| false), 0);
         assert_eq!(
             solver.moves(),
             &[
@@ -1308,7 +1427,7 @@ mod tests {
         solver.reassign_in(v10, d, d1, d0);
         solver.inputs_done();
         assert!(solver.quick_solve(&gregs, false).is_ok());
-        assert_eq!(solver.schedule_moves(&regs), 0);
+        assert_eq!(solver.schedule_moves(&regs, |_, _| false), 0);
         assert_eq!(
             solver.moves(),
             &[
@@ -1353,7 +1472,7 @@ mod tests {
         solver.reassign_in(v12, gpr, r2, r0);
         solver.inputs_done();
         assert!(solver.quick_solve(&gregs, false).is_ok());
-        assert_eq!(solver.schedule_moves(&regs), 1);
+        assert_eq!(solver.schedule_moves(&regs, |_, _| false), 1);
         assert_eq!(
             solver.moves(),
             &[
@@ -1378,7 +1497,7 @@ mod tests {
         solver.inputs_done();
         assert!(solver.quick_solve(&gregs, false).is_ok());
         // We resolve two cycles with one spill.
-        assert_eq!(solver.schedule_moves(&regs), 1);
+        assert_eq!(solver.schedule_moves(&regs, |_, _| false), 1);
         assert_eq!(
             solver.moves(),
             &[
@@ -1393,4 +1512,56 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn swap_cycles() {
+        let isa = arm32().expect("This test requires arm32 support");
+        let reginfo = isa.register_info();
+        let gpr = rc_by_name(&reginfo, "GPR");
+        let r0 = gpr.unit(0);
+        let r1 = gpr.unit(1);
+        let r2 = gpr.unit(2);
+        let gregs = RegisterSet::new();
+        let mut regs = RegisterSet::new();
+        let mut solver = Solver::new();
+        let v10 = Value::new(10);
+        let v11 = Value::new(11);
+        let v12 = Value::new(12);
+
+        // Claim all the registers for other values.
+        for i in 0..16 {
+            regs.take(gpr, gpr.unit(i));
+        }
+
+        // A permutation cycle is resolved with two swaps.
+        solver.reset(&regs);
+        solver.reassign_in(v10, gpr, r0, r1);
+        solver.reassign_in(v11, gpr, r1, r2);
+        solver.reassign_in(v12, gpr, r2, r0);
+        solver.inputs_done();
+        assert!(solver.quick_solve(&gregs, false).is_ok());
+        assert_eq!(solver.schedule_moves(&regs, |_, _| true), 0);
+        assert_eq!(
+            solver.moves(),
+            &[swap(v10, v11, gpr, r0, r1), swap(v11, v12, gpr, r0, r2)]
+        );
+
+        // Fall back to an emergency spill for values that can't be swapped.
+        solver.reset(&regs);
+        solver.reassign_in(v10, gpr, r0, r1);
+        solver.reassign_in(v11, gpr, r1, r2);
+        solver.reassign_in(v12, gpr, r2, r0);
+        solver.inputs_done();
+        assert!(solver.quick_solve(&gregs, false).is_ok());
+        assert_eq!(solver.schedule_moves(&regs, |_, other| other != v12), 1);
+        assert_eq!(
+            solver.moves(),
+            &[
+                swap(v10, v11, gpr, r0, r1),
+                spill(v11, gpr, r0, 0),
+                mov(v12, gpr, r2, r0),
+                fill(v11, gpr, 0, r2),
+            ]
+        );
+    }
 }
@@ -17,13 +17,16 @@
 
 use crate::cursor::{Cursor, EncCursor};
 use crate::dominator_tree::DominatorTree;
-use crate::ir::{ArgumentLoc, Ebb, Function, Inst, InstBuilder, SigRef, Value, ValueLoc};
+use crate::entity::SecondaryMap;
+use crate::ir::{ArgumentLoc, Ebb, Function, Inst, InstBuilder, SigRef, Value, ValueDef, ValueLoc};
 use crate::isa::registers::{RegClass, RegClassIndex, RegClassMask, RegUnit};
 use crate::isa::{ConstraintKind, EncInfo, RecipeConstraints, RegInfo, TargetIsa};
+use crate::loop_analysis::LoopAnalysis;
 use crate::regalloc::affinity::Affinity;
 use crate::regalloc::live_value_tracker::{LiveValue, LiveValueTracker};
 use crate::regalloc::liveness::Liveness;
 use crate::regalloc::pressure::Pressure;
+use crate::regalloc::reload::can_rematerialize;
 use crate::regalloc::virtregs::VirtRegs;
 use crate::timing;
 use crate::topo_order::TopoOrder;
@@ -40,10 +43,14 @@ fn toprc_containing_regunit(unit: RegUnit, reginfo: &RegInfo) -> RegClass {
         .expect("reg unit should be in a toprc")
 }
 
+/// Weight of a use inside a loop relative to a use outside the loop.
+const LOOP_WEIGHT: u32 = 10;
+
 /// Persistent data structures for the spilling pass.
 pub struct Spilling {
     spills: Vec<Value>,
     reg_uses: Vec<RegUse>,
+    costs: SecondaryMap<Value, u32>,
 }
 
 /// Context data structure that gets instantiated once per pass.
@@ -71,6 +78,9 @@ struct Context<'a> {
 
     // Uses of register values in the current instruction.
     reg_uses: &'a mut Vec<RegUse>,
+
+    // Estimated cost of spilling the virtual register containing each value.
+    costs: &'a SecondaryMap<Value, u32>,
 }
 
 impl Spilling {
@@ -79,6 +89,7 @@ impl Spilling {
         Self {
             spills: Vec::new(),
             reg_uses: Vec::new(),
+            costs: SecondaryMap::new(),
         }
     }
 
@@ -86,6 +97,7 @@ impl Spilling {
     pub fn clear(&mut self) {
         self.spills.clear();
         self.reg_uses.clear();
+        self.costs.clear();
     }
 
     /// Run the spilling algorithm over `func`.
@@ -94,6 +106,7 @@ impl Spilling {
         isa: &dyn TargetIsa,
         func: &mut Function,
         domtree: &DominatorTree,
+        loop_analysis: &LoopAnalysis,
         liveness: &mut Liveness,
         virtregs: &VirtRegs,
         topo: &mut TopoOrder,
@@ -101,6 +114,7 @@ impl Spilling {
     ) {
         let _tt = timing::ra_spilling();
         debug!("Spilling for:\n{}", func.display(isa));
+        self.compute_costs(func, loop_analysis, virtregs);
         let reginfo = isa.register_info();
         let usable_regs = isa.allocatable_registers(func);
         let mut ctx = Context {
@@ -114,9 +128,67 @@ impl Spilling {
             pressure: Pressure::new(&reginfo, &usable_regs),
             spills: &mut self.spills,
             reg_uses: &mut self.reg_uses,
+            costs: &self.costs,
         };
         ctx.run(tracker)
     }
+
+    /// Estimate the cost of spilling each virtual register.
+    ///
+    /// Spilling a value costs a store at its definition and a load at each use, and these are
+    /// weighted by the loop depth where they happen. Rematerializable values don't need either.
+    /// Since whole virtual registers are spilled, the costs are summed per virtual register.
+    fn compute_costs(
+        &mut self,
+        func: &Function,
+        loop_analysis: &LoopAnalysis,
+        virtregs: &VirtRegs,
+    ) {
+        self.costs.clear();
+        for ebb in func.layout.ebbs() {
+            let mut depth = 0;
+            let mut lp = loop_analysis.innermost_loop(ebb);
+            while let Some(l) = lp {
+                depth += 1;
+                lp = loop_analysis.loop_parent(l);
+            }
+            let weight = LOOP_WEIGHT.saturating_pow(depth);
+
+            for &param in func.dfg.ebb_params(ebb) {
+                self.costs[param] = self.costs[param].saturating_add(weight);
+            }
+            for inst in func.layout.ebb_insts(ebb) {
+                for &arg in func.dfg.inst_args(inst) {
+                    if !is_rematerializable(func, arg) {
+                        self.costs[arg] = self.costs[arg].saturating_add(weight);
+                    }
+                }
+                if !can_rematerialize(&func.dfg[inst]) {
+                    for &res in func.dfg.inst_results(inst) {
+                        self.costs[res] = self.costs[res].saturating_add(weight);
+                    }
+                }
+            }
+        }
+
+        for vreg in virtregs.all_virtregs() {
+            let values = virtregs.values(vreg);
+            let cost = values
+                .iter()
+                .fold(0u32, |sum, &v| sum.saturating_add(self.costs[v]));
+            for &v in values {
+                self.costs[v] = cost;
+            }
+        }
+    }
+}
+
+/// Is `value` defined by an instruction that the reload pass can rematerialize?
+fn is_rematerializable(func: &Function, value: Value) -> bool {
+    match func.dfg.value_def(value) {
+        ValueDef::Result(inst, _) => can_rematerialize(&func.dfg[inst]),
+        ValueDef::Param(..) => false,
+    }
 }
 
 impl<'a> Context<'a> {
@@ -486,9 +558,9 @@ impl<'a> Context<'a> {
     {
         // Find the best viable spill candidate.
         //
-        // The very simple strategy implemented here is to spill the value with the earliest def in
-        // the reverse post-order. This strategy depends on a good reload pass to generate good
-        // code.
+        // The strategy implemented here is to spill the value with the lowest estimated spill
+        // cost, preferring the value with the earliest def in the reverse post-order among equally
+        // expensive values. This strategy depends on a good reload pass to generate good code.
         //
         // We know that all candidate defs dominate the current instruction, so one of them will
         // dominate the others. That is the earliest def.
@@ -507,12 +579,14 @@ impl<'a> Context<'a> {
                 None
             })
             .min_by(|&a, &b| {
-                // Find the minimum candidate according to the RPO of their defs.
-                self.domtree.rpo_cmp(
-                    self.cur.func.dfg.value_def(a),
-                    self.cur.func.dfg.value_def(b),
-                    &self.cur.func.layout,
-                )
+                // Find the minimum candidate according to their cost, then the RPO of their defs.
+                self.costs[a].cmp(&self.costs[b]).then_with(|| {
+                    self.domtree.rpo_cmp(
+                        self.cur.func.dfg.value_def(a),
+                        self.cur.func.dfg.value_def(b),
+                        &self.cur.func.layout,
+                    )
+                })
             })
     }
 
@@ -0,0 +1,295 @@
+//! Live range splitting pass.
+//!
+//! The spilling pass spills whole virtual registers, and it spills every value that is live across
+//! a call. Such a value then lives on the stack from its definition, and every use before the call
+//! needs a `fill` too.
+//!
+//! This pass runs before coalescing and splits those live ranges at the call instead: a copy of
+//! the value is inserted in front of the call, and the uses after the call are rewritten to use the
+//! copy. Only the copy is live across the call, so the original value can stay in a register and
+//! the reload pass turns the copy into a `spill`.
+//!
+//! A live range is only split when that leaves the original value dead after the call. This is
+//! the case when all the EBBs the value is live-in to, except the one containing the call, are
+//! dominated by the call, and the value doesn't flow back into the call's EBB around a loop.
+//!
+//! Live ranges are only split at calls, not at loop boundaries. A value that is live through a
+//! loop and gets spilled because of the register pressure inside it is still spilled for its whole
+//! live range, so its uses before and after the loop need a `fill` as well. The spilling pass
+//! makes this less likely by weighting the spill cost of uses inside loops.
+
+use crate::cursor::{Cursor, EncCursor};
+use crate::dominator_tree::DominatorTree;
+use crate::entity::EntitySet;
+use crate::flowgraph::{BasicBlock, ControlFlowGraph};
+use crate::ir::{Ebb, Function, Inst, InstBuilder, InstructionData, Opcode, ProgramPoint};
+use crate::ir::{Layout, Value, ValueDef};
+use crate::isa::TargetIsa;
+use crate::regalloc::live_value_tracker::{LiveValue, LiveValueTracker};
+use crate::regalloc::liveness::Liveness;
+use crate::regalloc::liverange::LiveRange;
+use crate::regalloc::reload::can_rematerialize;
+use crate::timing;
+use crate::topo_order::TopoOrder;
+use alloc::vec::Vec;
+use core::iter;
+use log::debug;
+
+/// Persistent data structures for the live range splitting pass.
+pub struct Splitting {
+    // Values that were live across a call we have already visited. These values are going to be
+    // spilled at that call anyway, so there is no point in splitting them at a later one.
+    crossing: EntitySet<Value>,
+
+    // Values that have a use in the instructions visited so far.
+    used: EntitySet<Value>,
+
+    // Split points found so far: a value and the call where its live range is split.
+    splits: Vec<(Value, Inst)>,
+}
+
+/// Context data structure that gets instantiated once per pass.
+struct Context<'a> {
+    func: &'a mut Function,
+    isa: &'a dyn TargetIsa,
+
+    // References to contextual data structures we need.
+    cfg: &'a ControlFlowGraph,
+    domtree: &'a DominatorTree,
+    liveness: &'a mut Liveness,
+    topo: &'a mut TopoOrder,
+
+    crossing: &'a mut EntitySet<Value>,
+    used: &'a mut EntitySet<Value>,
+    splits: &'a mut Vec<(Value, Inst)>,
+}
+
+impl Splitting {
+    /// Create a new splitting data structure.
+    pub fn new() -> Self {
+        Self {
+            crossing: EntitySet::new(),
+            used: EntitySet::new(),
+            splits: Vec::new(),
+        }
+    }
+
+    /// Clear all data structures in this splitting pass.
+    pub fn clear(&mut self) {
+        self.crossing.clear();
+        self.used.clear();
+        self.splits.clear();
+    }
+
+    /// Run the live range splitting algorithm over `func`.
+    ///
+    /// The live ranges in `liveness` are recomputed if any live range was split.
+    pub fn run(
+        &mut self,
+        isa: &dyn TargetIsa,
+        func: &mut Function,
+        cfg: &ControlFlowGraph,
+        domtree: &DominatorTree,
+        liveness: &mut Liveness,
+        topo: &mut TopoOrder,
+        tracker: &mut LiveValueTracker,
+    ) {
+        let _tt = timing::ra_splitting();
+        debug!("Splitting live ranges for:\n{}", func.display(isa));
+        self.clear();
+        let mut ctx = Context {
+            func,
+            isa,
+            cfg,
+            domtree,
+            liveness,
+            topo,
+            crossing: &mut self.crossing,
+            used: &mut self.used,
+            splits: &mut self.splits,
+        };
+        ctx.run(tracker)
+    }
+}
+
+impl<'a> Context<'a> {
+    fn run(&mut self, tracker: &mut LiveValueTracker) {
+        // Find all the split points first. The live ranges can't be updated as we go since the
+        // tracker depends on them.
+        self.topo.reset(self.func.layout.ebbs());
+        while let Some(ebb) = self.topo.next(&self.func.layout, self.domtree) {
+            self.visit_ebb(ebb, tracker);
+        }
+
+        // The tracker saves live sets at branches, and they must not leak into the spilling pass.
+        tracker.clear();
+
+        if self.splits.is_empty() {
+            return;
+        }
+        for i in 0..self.splits.len() {
+            let (value, call) = self.splits[i];
+            self.split(value, call);
+        }
+        self.liveness.compute(self.isa, self.func, self.cfg);
+    }
+
+    fn visit_ebb(&mut self, ebb: Ebb, tracker: &mut LiveValueTracker) {
+        tracker.ebb_top(
+            ebb,
+            &self.func.dfg,
+            self.liveness,
+            &self.func.layout,
+            self.domtree,
+        );
+        tracker.drop_dead_params();
+
+        let mut next = self.func.layout.first_inst(ebb);
+        while let Some(inst) = next {
+            next = self.func.layout.next_inst(inst);
+            if self.func.dfg[inst].opcode().is_ghost() {
+                tracker.process_ghost(inst);
+            } else {
+                let (throughs, _kills, _defs) =
+                    tracker.process_inst(inst, &self.func.dfg, self.liveness);
+                if self.func.dfg[inst].opcode().is_call() {
+                    for lv in throughs {
+                        if self.crossing.insert(lv.value) && self.can_split(lv, inst, ebb) {
+                            debug!("Splitting {} at {}", lv.value, inst);
+                            self.splits.push((lv.value, inst));
+                        }
+                    }
+                }
+            }
+            for &arg in self.func.dfg.inst_args(inst) {
+                self.used.insert(arg);
+            }
+            tracker.drop_dead(inst);
+        }
+    }
+
+    /// Can the live range of `lv` be split at `call` in `ebb`, and is it worth it?
+    fn can_split(&self, lv: &LiveValue, call: Inst, ebb: Ebb) -> bool {
+        let value = lv.value;
+
+        // Only a value with register uses before the call benefits from staying in a register.
+        // All the uses seen so far come before the call since the uses after it are dominated by
+        // the call.
+        if !lv.affinity.is_reg() || !self.used.contains(value) {
+            return false;
+        }
+
+        // Constants are cheaper to rematerialize after the call.
+        if let ValueDef::Result(def, _) = self.func.dfg.value_def(value) {
+            if can_rematerialize(&self.func.dfg[def]) {
+                return false;
+            }
+        }
+
+        // We need to be able to copy the value.
+        let ty = self.func.dfg.value_type(value);
+        let copy = InstructionData::Unary {
+            opcode: Opcode::Copy,
+            arg: value,
+        };
+        if ty.is_flags() || self.isa.encode(self.func, &copy, ty).is_err() {
+            return false;
+        }
+
+        // The uses after the call can only be rewritten if the call dominates them.
+        let layout = &self.func.layout;
+        let lr = &self.liveness[value];
+        if livein_ebbs(lr, layout)
+            .any(|(livein, _)| livein != ebb && !self.domtree.dominates(call, livein, layout))
+        {
+            return false;
+        }
+
+        // If the value is live into the call's EBB, it must not come back around a loop.
+        // Otherwise the original value would still be live across the call.
+        !lr.is_livein(ebb, layout)
+            || !self
+                .cfg
+                .pred_iter(ebb)
+                .any(|BasicBlock { inst, .. }| self.domtree.dominates(call, inst, layout))
+    }
+
+    /// Split the live range of `value` at `call` by inserting a copy before the call and rewriting
+    /// all the uses after the call.
+    fn split(&mut self, value: Value, call: Inst) {
+        let ebb = self.func.layout.inst_ebb(call).expect("call not in layout");
+
+        // Collect the live-in EBBs and the end of the live range in `ebb` before changing the
+        // layout.
+        let lr = &self.liveness[value];
+        let mut liveins: Vec<(Ebb, Inst)> = livein_ebbs(lr, &self.func.layout).collect();
+        let local_end = match liveins.iter().position(|&(livein, _)| livein == ebb) {
+            Some(idx) => liveins.swap_remove(idx).1.into(),
+            None => lr.def_local_end(),
+        };
+
+        let mut pos = EncCursor::new(self.func, self.isa).at_inst(call);
+        pos.use_srcloc(call);
+        let copy = pos.ins().copy(value);
+
+        // Rewrite the uses after the call in its own EBB.
+        let mut inst = call;
+        while ProgramPoint::from(inst) != local_end {
+            inst = pos
+                .func
+                .layout
+                .next_inst(inst)
+                .expect("live range past EBB end");
+            replace_uses(pos.func, inst, value, copy);
+        }
+
+        // Then rewrite the uses in the EBBs dominated by the call.
+        for (livein, end) in liveins {
+            let mut next = pos.func.layout.first_inst(livein);
+            while let Some(inst) = next {
+                replace_uses(pos.func, inst, value, copy);
+                next = if inst == end {
+                    None
+                } else {
+                    pos.func.layout.next_inst(inst)
+                };
+            }
+        }
+    }
+}
+
+/// Iterate over the EBBs where `lr` is live-in, along with the last instruction of the live range
+/// in each of them.
+///
+/// A single live-in interval can span several EBBs that are consecutive in the layout.
+fn livein_ebbs<'a>(
+    lr: &'a LiveRange,
+    layout: &'a Layout,
+) -> impl Iterator<Item = (Ebb, Inst)> + 'a {
+    lr.liveins().flat_map(move |(begin, end)| {
+        let end_ebb = layout.inst_ebb(end).expect("live range end not in layout");
+        iter::successors(Some(begin), move |&ebb| {
+            if ebb == end_ebb {
+                None
+            } else {
+                layout.next_ebb(ebb)
+            }
+        })
+        .map(move |ebb| {
+            if ebb == end_ebb {
+                (ebb, end)
+            } else {
+                (ebb, layout.last_inst(ebb).expect("empty EBB in live range"))
+            }
+        })
+    })
+}
+
+/// Replace all uses of `value` in the arguments to `inst` with `copy`.
+fn replace_uses(func: &mut Function, inst: Inst, value: Value, copy: Value) {
+    for arg in func.dfg.inst_args_mut(inst) {
+        if *arg == value {
+            *arg = copy;
+        }
+    }
+}
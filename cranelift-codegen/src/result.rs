@@ -1,6 +1,7 @@
 //! Result and error types representing the outcome of compiling a function.
 
 use crate::verifier::VerifierErrors;
+use alloc::string::String;
 use failure_derive::Fail;
 
 /// A compilation error.
@@ -30,6 +31,12 @@ pub enum CodegenError {
     /// is exceeded, compilation fails.
     #[fail(display = "Code for function is too large")]
     CodeTooLarge,
+
+    /// The function uses a feature that the target ISA doesn't support.
+    ///
+    /// The string describes the unsupported feature.
+    #[fail(display = "Unsupported feature: {}", _0)]
+    Unsupported(String),
 }
 
 /// A convenient alias for a `Result` that uses `CodegenError` as the error type.
@@ -0,0 +1,448 @@
+//! Instruction scheduling.
+//!
+//! This pass reorders instructions before register allocation to hide the latency of long-running
+//! instructions like loads, multiplications and divisions. It is a list scheduler working on the
+//! dependency DAG of each region of an EBB between branches, using the latency model provided by
+//! the target ISA.
+//!
+//! The DAG has edges for:
+//!
+//! - the data dependencies between instructions,
+//! - the ordering of memory accesses: loads can be reordered with each other, but not across
+//!   stores, calls, or instructions that can trap or have other side effects,
+//! - CPU flags: an instruction clobbering the flags can't be moved into the live range of a flags
+//!   value, following the rules checked by the flags verifier.
+//!
+//! Among the ready instructions, the scheduler prefers the ones whose operands are available at
+//! the current cycle, and then the ones on the longest path to the end of the region. When a
+//! register class is running out of registers, instructions that don't increase its pressure come
+//! first.
+
+use crate::entity::{EntitySet, SecondaryMap};
+use crate::ir::{Ebb, Function, Inst, Opcode, Value, ValueDef};
+use crate::isa::{EncInfo, RegClass, TargetIsa};
+use crate::regalloc::Pressure;
+use crate::timing;
+use alloc::vec::Vec;
+
+/// Regions with more instructions than this are left alone to bound compile time.
+const MAX_REGION_SIZE: usize = 512;
+
+/// Schedule the instructions of `func` for `isa`.
+///
+/// Nothing is done if the ISA doesn't have a latency model.
+pub fn do_scheduling(isa: &dyn TargetIsa, func: &mut Function) {
+    let _tt = timing::scheduling();
+    if isa.instruction_latency(Opcode::Nop).is_none() {
+        return;
+    }
+
+    let mut scheduler = Scheduler::new(isa, func);
+    let mut region = Vec::new();
+    let ebbs: Vec<Ebb> = func.layout.ebbs().collect();
+    for ebb in ebbs {
+        let insts: Vec<Inst> = func.layout.ebb_insts(ebb).collect();
+        for inst in insts {
+            let opcode = func.dfg[inst].opcode();
+            if opcode.is_branch() || opcode.is_terminator() {
+                scheduler.schedule_region(func, ebb, &region, Some(inst));
+                region.clear();
+            } else {
+                region.push(inst);
+            }
+        }
+        scheduler.schedule_region(func, ebb, &region, None);
+        region.clear();
+    }
+}
+
+/// A node in the dependency DAG of a region.
+struct Node {
+    inst: Inst,
+    latency: u32,
+    /// Successors in the DAG, with the latency of the edge.
+    succs: Vec<(usize, u32)>,
+    /// Number of predecessors which haven't been scheduled yet.
+    preds: u32,
+    /// Length of the longest path from this node to the end of the region.
+    height: u32,
+    /// Earliest cycle where the operands are available.
+    earliest: u32,
+}
+
+struct Scheduler<'a> {
+    isa: &'a dyn TargetIsa,
+    encinfo: EncInfo,
+    pressure: Pressure,
+
+    /// Number of uses of each value in the whole function.
+    uses: SecondaryMap<Value, u32>,
+
+    /// Number of uses of each value in the current region which haven't been scheduled yet.
+    region_uses: SecondaryMap<Value, u32>,
+
+    /// Values used in the current region which are also used after it.
+    live_out: EntitySet<Value>,
+
+    /// Index of the instructions in the current region.
+    index: SecondaryMap<Inst, Option<usize>>,
+
+    nodes: Vec<Node>,
+}
+
+impl<'a> Scheduler<'a> {
+    fn new(isa: &'a dyn TargetIsa, func: &Function) -> Self {
+        let mut uses = SecondaryMap::new();
+        for ebb in func.layout.ebbs() {
+            for inst in func.layout.ebb_insts(ebb) {
+                for &arg in func.dfg.inst_args(inst) {
+                    uses[func.dfg.resolve_aliases(arg)] += 1;
+                }
+            }
+        }
+
+        Self {
+            isa,
+            encinfo: isa.encoding_info(),
+            pressure: Pressure::new(&isa.register_info(), &isa.allocatable_registers(func)),
+            uses,
+            region_uses: SecondaryMap::new(),
+            live_out: EntitySet::new(),
+            index: SecondaryMap::new(),
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Schedule the instructions in `region`, which are followed by `end` in `ebb`.
+    fn schedule_region(
+        &mut self,
+        func: &mut Function,
+        ebb: Ebb,
+        region: &[Inst],
+        end: Option<Inst>,
+    ) {
+        if region.len() < 3 || region.len() > MAX_REGION_SIZE {
+            return;
+        }
+
+        self.build_dag(func, region);
+        let order = self.list_schedule(func);
+
+        for &inst in region {
+            self.index[inst] = None;
+            for &arg in func.dfg.inst_args(inst) {
+                self.region_uses[func.dfg.resolve_aliases(arg)] = 0;
+            }
+        }
+
+        if order.iter().enumerate().all(|(i, &node)| i == node) {
+            return;
+        }
+        for &inst in region {
+            func.layout.remove_inst(inst);
+        }
+        for node in order {
+            let inst = region[node];
+            match end {
+                Some(end) => func.layout.insert_inst(inst, end),
+                None => func.layout.append_inst(inst, ebb),
+            }
+        }
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize, latency: u32) {
+        self.nodes[from].succs.push((to, latency));
+        self.nodes[to].preds += 1;
+    }
+
+    /// Build the dependency DAG of `region` in `self.nodes`.
+    fn build_dag(&mut self, func: &Function, region: &[Inst]) {
+        self.nodes.clear();
+        for (i, &inst) in region.iter().enumerate() {
+            self.index[inst] = Some(i);
+            self.nodes.push(Node {
+                inst,
+                latency: self
+                    .isa
+                    .instruction_latency(func.dfg[inst].opcode())
+                    .unwrap_or(1),
+                succs: Vec::new(),
+                preds: 0,
+                height: 0,
+                earliest: 0,
+            });
+        }
+
+        // Data dependencies.
+        for (i, &inst) in region.iter().enumerate() {
+            for &arg in func.dfg.inst_args(inst) {
+                let arg = func.dfg.resolve_aliases(arg);
+                self.region_uses[arg] += 1;
+                if let ValueDef::Result(def, _) = func.dfg.value_def(arg) {
+                    if let Some(j) = self.index[def] {
+                        let latency = self.nodes[j].latency;
+                        self.add_edge(j, i, latency);
+                    }
+                }
+            }
+        }
+        self.live_out.clear();
+        for &inst in region {
+            for &arg in func.dfg.inst_args(inst) {
+                let arg = func.dfg.resolve_aliases(arg);
+                if self.uses[arg] > self.region_uses[arg] {
+                    self.live_out.insert(arg);
+                }
+            }
+        }
+
+        // Memory and side effects.
+        let mut last_effect = None;
+        let mut loads = Vec::new();
+        for (i, &inst) in region.iter().enumerate() {
+            let opcode = func.dfg[inst].opcode();
+            if has_side_effects(opcode) {
+                if let Some(j) = last_effect {
+                    self.add_edge(j, i, 0);
+                }
+                for j in loads.drain(..) {
+                    self.add_edge(j, i, 0);
+                }
+                last_effect = Some(i);
+            } else if opcode.can_load() {
+                if let Some(j) = last_effect {
+                    self.add_edge(j, i, 0);
+                }
+                loads.push(i);
+            }
+        }
+
+        // CPU flags.
+        let clobbers: Vec<usize> = region
+            .iter()
+            .enumerate()
+            .filter(|&(_, &inst)| self.clobbers_flags(func, inst))
+            .map(|(i, _)| i)
+            .collect();
+        if !clobbers.is_empty() {
+            let mut flags = Vec::new();
+            for &inst in region {
+                for &value in func
+                    .dfg
+                    .inst_results(inst)
+                    .iter()
+                    .chain(func.dfg.inst_args(inst))
+                {
+                    let value = func.dfg.resolve_aliases(value);
+                    if func.dfg.value_type(value).is_flags() && !flags.contains(&value) {
+                        flags.push(value);
+                    }
+                }
+            }
+            for value in flags {
+                self.add_flags_edges(func, region, &clobbers, value);
+            }
+        }
+
+        // Compute the heights bottom-up. All the edges go forward in the region.
+        for i in (0..self.nodes.len()).rev() {
+            let height = self.nodes[i]
+                .succs
+                .iter()
+                .map(|&(succ, latency)| latency + self.nodes[succ].height)
+                .max()
+                .unwrap_or(0);
+            self.nodes[i].height = height.max(self.nodes[i].latency);
+        }
+    }
+
+    /// Keep the clobbers of the flags out of the live range of the flags `value`.
+    fn add_flags_edges(
+        &mut self,
+        func: &Function,
+        region: &[Inst],
+        clobbers: &[usize],
+        value: Value,
+    ) {
+        let def = match func.dfg.value_def(value) {
+            ValueDef::Result(inst, _) => self.index[inst],
+            ValueDef::Param(..) => None,
+        };
+        let users: Vec<usize> = region
+            .iter()
+            .enumerate()
+            .filter(|&(_, &inst)| {
+                func.dfg
+                    .inst_args(inst)
+                    .iter()
+                    .any(|&arg| func.dfg.resolve_aliases(arg) == value)
+            })
+            .map(|(i, _)| i)
+            .collect();
+        let last = users.iter().cloned().chain(def).max().unwrap_or(0);
+
+        for &clobber in clobbers {
+            if Some(clobber) == def || users.contains(&clobber) {
+                continue;
+            }
+            match def {
+                Some(def) if clobber < def => self.add_edge(clobber, def, 0),
+                _ if clobber > last => {
+                    for &user in &users {
+                        self.add_edge(user, clobber, 0);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Does `inst` write the CPU flags?
+    fn clobbers_flags(&self, func: &Function, inst: Inst) -> bool {
+        let clobbers = match self.encinfo.operand_constraints(func.encodings[inst]) {
+            Some(constraints) => constraints.clobbers_flags,
+            None => false,
+        };
+        clobbers
+            || func
+                .dfg
+                .inst_results(inst)
+                .iter()
+                .any(|&res| func.dfg.value_type(res).is_flags())
+    }
+
+    /// Get the register class of `value`, if it needs a register.
+    fn regclass(&self, func: &Function, value: Value) -> Option<RegClass> {
+        let ty = func.dfg.value_type(value);
+        if ty.is_flags() || ty.is_invalid() {
+            None
+        } else {
+            Some(self.isa.regclass_for_abi_type(ty))
+        }
+    }
+
+    /// Is `value` used for the last time by `inst` in the current region, and dead after it?
+    fn is_killed_by(&self, func: &Function, value: Value, inst: Inst) -> bool {
+        let uses_here = func
+            .dfg
+            .inst_args(inst)
+            .iter()
+            .filter(|&&arg| func.dfg.resolve_aliases(arg) == value)
+            .count() as u32;
+        self.region_uses[value] == uses_here && !self.live_out.contains(value)
+    }
+
+    /// Order the nodes of the DAG.
+    fn list_schedule(&mut self, func: &Function) -> Vec<usize> {
+        let n = self.nodes.len();
+        let mut order = Vec::with_capacity(n);
+        let mut ready: Vec<usize> = (0..n).filter(|&i| self.nodes[i].preds == 0).collect();
+        let mut cycle = 0;
+
+        self.pressure.reset();
+        let mut live_in = Vec::new();
+        for node in &self.nodes {
+            for &arg in func.dfg.inst_args(node.inst) {
+                let arg = func.dfg.resolve_aliases(arg);
+                let defined_here = match func.dfg.value_def(arg) {
+                    ValueDef::Result(def, _) => self.index[def].is_some(),
+                    ValueDef::Param(..) => false,
+                };
+                if !defined_here && !live_in.contains(&arg) {
+                    live_in.push(arg);
+                }
+            }
+        }
+        for &value in &live_in {
+            if let Some(rc) = self.regclass(func, value) {
+                self.pressure.take(rc);
+            }
+        }
+
+        while !ready.is_empty() {
+            let (pos, _) = ready
+                .iter()
+                .enumerate()
+                .max_by_key(|&(_, &i)| {
+                    let node = &self.nodes[i];
+                    (
+                        self.fits_pressure(func, node.inst),
+                        node.earliest <= cycle,
+                        node.height,
+                        n - i,
+                    )
+                })
+                .unwrap();
+            let i = ready.swap_remove(pos);
+            let start = cycle.max(self.nodes[i].earliest);
+            cycle = start + 1;
+            order.push(i);
+
+            let inst = self.nodes[i].inst;
+            for &res in func.dfg.inst_results(inst) {
+                if self.uses[res] > 0 {
+                    if let Some(rc) = self.regclass(func, res) {
+                        self.pressure.take(rc);
+                    }
+                }
+            }
+            let args = func.dfg.inst_args(inst);
+            for (a, &arg) in args.iter().enumerate() {
+                let arg = func.dfg.resolve_aliases(arg);
+                if args[..a]
+                    .iter()
+                    .any(|&prev| func.dfg.resolve_aliases(prev) == arg)
+                {
+                    continue;
+                }
+                if self.is_killed_by(func, arg, inst) {
+                    if let Some(rc) = self.regclass(func, arg) {
+                        self.pressure.free(rc);
+                    }
+                }
+            }
+            for &arg in args {
+                self.region_uses[func.dfg.resolve_aliases(arg)] -= 1;
+            }
+
+            for s in 0..self.nodes[i].succs.len() {
+                let (succ, latency) = self.nodes[i].succs[s];
+                let node = &mut self.nodes[succ];
+                node.earliest = node.earliest.max(start + latency);
+                node.preds -= 1;
+                if node.preds == 0 {
+                    ready.push(succ);
+                }
+            }
+        }
+
+        debug_assert_eq!(order.len(), n);
+        order
+    }
+
+    /// Can `inst` be scheduled without running out of registers?
+    fn fits_pressure(&self, func: &Function, inst: Inst) -> bool {
+        func.dfg.inst_results(inst).iter().all(|&res| {
+            let rc = match self.regclass(func, res) {
+                Some(rc) => rc,
+                None => return true,
+            };
+            self.pressure.check_avail(rc) == 0
+                || func.dfg.inst_args(inst).iter().any(|&arg| {
+                    let arg = func.dfg.resolve_aliases(arg);
+                    self.regclass(func, arg).map(|rc| rc.toprc) == Some(rc.toprc)
+                        && self.is_killed_by(func, arg, inst)
+                })
+        })
+    }
+}
+
+/// Must instructions with `opcode` keep their order relative to memory accesses and each other?
+fn has_side_effects(opcode: Opcode) -> bool {
+    opcode.is_call()
+        || opcode.is_return()
+        || opcode.can_store()
+        || opcode.can_trap()
+        || opcode.other_side_effects()
+        || opcode == Opcode::GetPinnedReg
+}
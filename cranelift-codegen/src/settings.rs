@@ -379,6 +379,7 @@ mod tests {
             f.to_string(),
             "[shared]\n\
              opt_level = \"none\"\n\
+             regalloc = \"coloring\"\n\
              libcall_call_conv = \"isa_default\"\n\
              baldrdash_prologue_words = 0\n\
              probestack_size_log2 = 12\n\
@@ -394,6 +395,8 @@ mod tests {
              enable_atomics = true\n\
              enable_safepoints = false\n\
              allones_funcaddrs = false\n\
+             enable_shrink_wrapping = false\n\
+             omit_frame_pointer = false\n\
              probestack_enabled = true\n\
              probestack_func_adjusts_sp = false\n\
              jump_tables_enabled = true\n"
@@ -0,0 +1,389 @@
+//! Stack slot coloring.
+//!
+//! The register allocator creates a spill slot for each spilled virtual register, and frontends
+//! create an explicit slot for each stack allocated object. Without this pass, `layout_stack`
+//! gives every one of them its own memory, so functions with many temporaries get huge frames.
+//!
+//! This pass computes the liveness of spill slots and explicit slots, and lets slots with
+//! disjoint lifetimes share memory. A slot is live at a program point if its contents may be read
+//! later before being completely overwritten:
+//!
+//! - Slots are read by `stack_load`, `regfill`, loads from a `stack_addr` address, and by any
+//!   instruction using a value that lives in the slot.
+//! - Slots are completely overwritten by instructions defining a value that lives in the slot, and
+//!   by `stack_store` instructions and stores to a `stack_addr` address covering the whole slot.
+//!   Other stores only overwrite part of the slot, so the rest of it stays live.
+//!
+//! Two slots interfere if one of them is written while the other is live, or if they are both
+//! live on entry to the function. Slots are then greedily assigned to the first group of slots
+//! with the same kind and size that they don't interfere with, and all the slots in a group are
+//! given the same offset by `layout_stack`.
+//!
+//! Explicit slots whose `stack_addr` address is used for anything but the address of a load or a
+//! store may be accessed in ways we can't see, and slots used by `regspill` diversions hold values
+//! that aren't reflected in the value locations. Those slots are never shared.
+
+use crate::entity::{EntitySet, SecondaryMap};
+use crate::ir::instructions::BranchInfo;
+use crate::ir::stackslot::StackSlotKind;
+use crate::ir::{Ebb, Function, Inst, InstructionData, Opcode, StackSlot, Value, ValueLoc};
+use crate::timing;
+use alloc::vec::Vec;
+
+/// A set of slots, represented by their index in the list of candidates.
+#[derive(Clone, Default, PartialEq, Eq)]
+struct SlotSet {
+    bits: Vec<u64>,
+}
+
+impl SlotSet {
+    fn contains(&self, slot: usize) -> bool {
+        self.bits
+            .get(slot / 64)
+            .map_or(false, |word| word & (1 << (slot % 64)) != 0)
+    }
+
+    fn insert(&mut self, slot: usize) {
+        if self.bits.len() <= slot / 64 {
+            self.bits.resize(slot / 64 + 1, 0);
+        }
+        self.bits[slot / 64] |= 1 << (slot % 64);
+    }
+
+    fn remove(&mut self, slot: usize) {
+        if let Some(word) = self.bits.get_mut(slot / 64) {
+            *word &= !(1 << (slot % 64));
+        }
+    }
+
+    fn union_with(&mut self, other: &Self) {
+        if self.bits.len() < other.bits.len() {
+            self.bits.resize(other.bits.len(), 0);
+        }
+        for (word, other_word) in self.bits.iter_mut().zip(&other.bits) {
+            *word |= *other_word;
+        }
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
+        (0..self.bits.len() * 64).filter(move |&slot| self.contains(slot))
+    }
+}
+
+/// Stack slot coloring pass state.
+struct StackColoring<'a> {
+    func: &'a Function,
+
+    /// The slots that may share memory with other slots.
+    candidates: Vec<StackSlot>,
+
+    /// The index of each candidate slot in `candidates`.
+    index: SecondaryMap<StackSlot, Option<usize>>,
+
+    /// The slot and offset addressed by each `stack_addr` result.
+    addr_slots: SecondaryMap<Value, Option<(StackSlot, i32)>>,
+
+    /// The slots that are live on entry to each EBB.
+    live_in: SecondaryMap<Ebb, SlotSet>,
+}
+
+impl<'a> StackColoring<'a> {
+    fn new(func: &'a Function) -> Self {
+        // Find the stack addresses, and exclude the slots whose address escapes.
+        let mut excluded = EntitySet::new();
+        let mut addr_slots = SecondaryMap::new();
+        for ebb in func.layout.ebbs() {
+            for inst in func.layout.ebb_insts(ebb) {
+                match func.dfg[inst] {
+                    InstructionData::StackLoad {
+                        opcode: Opcode::StackAddr,
+                        stack_slot,
+                        offset,
+                    } => {
+                        let offset: i32 = offset.into();
+                        addr_slots[func.dfg.first_result(inst)] = Some((stack_slot, offset));
+                    }
+                    InstructionData::RegSpill { dst, .. } => {
+                        excluded.insert(dst);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for ebb in func.layout.ebbs() {
+            for inst in func.layout.ebb_insts(ebb) {
+                for &arg in func.dfg.inst_args(inst) {
+                    let arg = func.dfg.resolve_aliases(arg);
+                    if let Some((ss, _)) = addr_slots[arg] {
+                        if memory_access_addr(func, inst) != Some(arg) {
+                            excluded.insert(ss);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut candidates = Vec::new();
+        let mut index = SecondaryMap::new();
+        for (ss, data) in func.stack_slots.iter() {
+            match data.kind {
+                StackSlotKind::SpillSlot | StackSlotKind::ExplicitSlot
+                    if !excluded.contains(ss) && func.stack_slots.shared_with(ss).is_none() =>
+                {
+                    index[ss] = Some(candidates.len());
+                    candidates.push(ss);
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            func,
+            candidates,
+            index,
+            addr_slots,
+            live_in: SecondaryMap::new(),
+        }
+    }
+
+    /// Get the candidate slot accessed by the load or store `inst` and the offset of the access
+    /// in the slot, if any.
+    fn accessed_slot(&self, inst: Inst) -> Option<(usize, i32)> {
+        let addr = memory_access_addr(self.func, inst)?;
+        let (ss, addr_offset) = self.addr_slots[addr]?;
+        let offset: i32 = match self.func.dfg[inst] {
+            InstructionData::Load { offset, .. } | InstructionData::Store { offset, .. } => {
+                offset.into()
+            }
+            _ => return None,
+        };
+        Some((self.index[ss]?, addr_offset.wrapping_add(offset)))
+    }
+
+    /// Get the candidate slot holding the value `value`, if any.
+    fn value_slot(&self, value: Value) -> Option<usize> {
+        match self.func.locations[value] {
+            ValueLoc::Stack(ss) => self.index[ss],
+            _ => None,
+        }
+    }
+
+    /// Call `def` for each candidate slot written by `inst`, along with whether the slot is
+    /// completely overwritten.
+    fn defs<F: FnMut(usize, bool)>(&self, inst: Inst, mut def: F) {
+        let func = self.func;
+        for &result in func.dfg.inst_results(inst) {
+            if let Some(slot) = self.value_slot(result) {
+                def(slot, true);
+            }
+        }
+        match func.dfg[inst] {
+            InstructionData::StackStore {
+                arg,
+                stack_slot,
+                offset,
+                ..
+            } => {
+                if let Some(slot) = self.index[stack_slot] {
+                    let offset: i32 = offset.into();
+                    def(
+                        slot,
+                        self.covers(slot, offset, func.dfg.value_type(arg).bytes()),
+                    );
+                }
+            }
+            InstructionData::Store {
+                opcode: Opcode::Store,
+                args,
+                ..
+            } => {
+                if let Some((slot, offset)) = self.accessed_slot(inst) {
+                    def(
+                        slot,
+                        self.covers(slot, offset, func.dfg.value_type(args[0]).bytes()),
+                    );
+                }
+            }
+            InstructionData::Store { .. } => {
+                if let Some((slot, _)) = self.accessed_slot(inst) {
+                    def(slot, false);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Does a store of `size` bytes at `offset` cover the whole `slot`?
+    fn covers(&self, slot: usize, offset: i32, size: u32) -> bool {
+        offset == 0 && size == self.func.stack_slots[self.candidates[slot]].size
+    }
+
+    /// Call `use_` for each candidate slot read by `inst`.
+    fn uses<F: FnMut(usize)>(&self, inst: Inst, mut use_: F) {
+        let func = self.func;
+        for &arg in func.dfg.inst_args(inst) {
+            if let Some(slot) = self.value_slot(arg) {
+                use_(slot);
+            }
+        }
+        match func.dfg[inst] {
+            InstructionData::StackLoad {
+                opcode: Opcode::StackLoad,
+                stack_slot,
+                ..
+            }
+            | InstructionData::RegFill {
+                src: stack_slot, ..
+            } => {
+                if let Some(slot) = self.index[stack_slot] {
+                    use_(slot);
+                }
+            }
+            InstructionData::Load { .. } => {
+                if let Some((slot, _)) = self.accessed_slot(inst) {
+                    use_(slot);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Compute the slots live on entry to `ebb` from the current `live_in` sets of its
+    /// successors. Call `def` for each slot written, along with the slots live after the write.
+    fn scan_ebb<F: FnMut(usize, &SlotSet)>(&self, ebb: Ebb, mut def: F) -> SlotSet {
+        let func = self.func;
+        let mut live = SlotSet::default();
+        for inst in func.layout.ebb_insts(ebb).rev() {
+            match func.dfg.analyze_branch(inst) {
+                BranchInfo::NotABranch => {}
+                BranchInfo::SingleDest(dest, _) => live.union_with(&self.live_in[dest]),
+                BranchInfo::Table(jt, default) => {
+                    for &dest in func.jump_tables[jt].iter() {
+                        live.union_with(&self.live_in[dest]);
+                    }
+                    if let Some(dest) = default {
+                        live.union_with(&self.live_in[dest]);
+                    }
+                }
+            }
+
+            let mut kills = Vec::new();
+            self.defs(inst, |slot, whole| {
+                def(slot, &live);
+                if whole {
+                    kills.push(slot);
+                }
+            });
+            for slot in kills {
+                live.remove(slot);
+            }
+            self.uses(inst, |slot| live.insert(slot));
+        }
+        live
+    }
+
+    /// Solve the liveness data flow equations.
+    fn compute_liveness(&mut self) {
+        let ebbs: Vec<Ebb> = self.func.layout.ebbs().collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &ebb in ebbs.iter().rev() {
+                let live = self.scan_ebb(ebb, |_, _| {});
+                if live != self.live_in[ebb] {
+                    self.live_in[ebb] = live;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /// Compute the interference between all candidate slots.
+    fn compute_interference(&self) -> Vec<SlotSet> {
+        let mut interference = vec![SlotSet::default(); self.candidates.len()];
+        for ebb in self.func.layout.ebbs() {
+            self.scan_ebb(ebb, |slot, live| {
+                for other in live.iter() {
+                    if other != slot {
+                        interference[slot].insert(other);
+                        interference[other].insert(slot);
+                    }
+                }
+            });
+        }
+
+        if let Some(entry) = self.func.layout.entry_block() {
+            let live = &self.live_in[entry];
+            for slot in live.iter() {
+                interference[slot].union_with(live);
+                interference[slot].remove(slot);
+            }
+        }
+        interference
+    }
+}
+
+/// Get the address operand of the simple load or store `inst`, if it is one.
+fn memory_access_addr(func: &Function, inst: Inst) -> Option<Value> {
+    match func.dfg[inst] {
+        InstructionData::Load { arg, .. } => Some(func.dfg.resolve_aliases(arg)),
+        InstructionData::Store { args, .. } => {
+            let addr = func.dfg.resolve_aliases(args[1]);
+            if func.dfg.resolve_aliases(args[0]) == addr {
+                None
+            } else {
+                Some(addr)
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Let stack slots with disjoint lifetimes share memory.
+pub fn color_stack_slots(func: &mut Function) {
+    let _tt = timing::stack_coloring();
+
+    let groups = {
+        let mut coloring = StackColoring::new(func);
+        if coloring.candidates.len() < 2 {
+            return;
+        }
+        coloring.compute_liveness();
+        let interference = coloring.compute_interference();
+
+        // Each group is a list of candidate indexes, starting with the slot whose memory is shared
+        // by the others.
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for (slot, &ss) in coloring.candidates.iter().enumerate() {
+            let data = &func.stack_slots[ss];
+            let group = groups.iter_mut().find(|group| {
+                let rep = &func.stack_slots[coloring.candidates[group[0]]];
+                rep.kind == data.kind
+                    && rep.size == data.size
+                    && group
+                        .iter()
+                        .all(|&member| !interference[slot].contains(member))
+            });
+            match group {
+                Some(group) => group.push(slot),
+                None => groups.push(vec![slot]),
+            }
+        }
+
+        groups
+            .into_iter()
+            .map(|group| {
+                group
+                    .into_iter()
+                    .map(|slot| coloring.candidates[slot])
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for group in groups {
+        for &ss in &group[1..] {
+            func.stack_slots.share(ss, group[0]);
+        }
+    }
+}
@@ -71,8 +71,14 @@ pub fn layout_stack(frame: &mut StackSlots, alignment: StackSize) -> CodegenResu
     let mut offset = incoming_min;
     debug_assert!(min_align.is_power_of_two());
     while min_align <= alignment {
-        for slot in frame.values_mut() {
+        for ss in frame.keys() {
+            // Slots sharing the memory of another slot are assigned below.
+            if frame.shared_with(ss).is_some() {
+                continue;
+            }
+
             // Pick out explicit and spill slots with exact alignment `min_align`.
+            let slot = &mut frame[ss];
             match slot.kind {
                 StackSlotKind::SpillSlot
                 | StackSlotKind::ExplicitSlot
@@ -97,6 +103,12 @@ pub fn layout_stack(frame: &mut StackSlots, alignment: StackSize) -> CodegenResu
         min_align *= 2;
     }
 
+    for ss in frame.keys() {
+        if let Some(with) = frame.shared_with(ss) {
+            frame[ss].offset = frame[with].offset;
+        }
+    }
+
     // Finally, make room for the outgoing arguments.
     offset = offset
         .checked_sub(outgoing_max)
@@ -125,8 +137,8 @@ mod tests {
         assert_eq!(layout_stack(sss, 16), Ok(0));
 
         // Same for incoming arguments with non-negative offsets.
-        let in0 = sss.make_incoming_arg(types::I64, 0);
-        let in1 = sss.make_incoming_arg(types::I64, 8);
+        let in0 = sss.make_incoming_arg(8, 0);
+        let in1 = sss.make_incoming_arg(8, 8);
 
         assert_eq!(layout_stack(sss, 1), Ok(0));
         assert_eq!(layout_stack(sss, 16), Ok(0));
@@ -151,7 +163,7 @@ mod tests {
 
         // An incoming argument with negative offset counts towards the total frame size, but it
         // should still pack nicely with the spill slots.
-        let in2 = sss.make_incoming_arg(types::I32, -4);
+        let in2 = sss.make_incoming_arg(4, -4);
 
         assert_eq!(layout_stack(sss, 1), Ok(16));
         assert_eq!(sss[in0].offset, Some(0));
@@ -168,7 +180,7 @@ mod tests {
         assert_eq!(sss[ss1].offset, Some(-8));
 
         // Finally, make sure there is room for the outgoing args.
-        let out0 = sss.get_outgoing_arg(types::I32, 0);
+        let out0 = sss.get_outgoing_arg(4, 0);
 
         assert_eq!(layout_stack(sss, 1), Ok(20));
         assert_eq!(sss[in0].offset, Some(0));
@@ -187,7 +199,7 @@ mod tests {
         assert_eq!(sss[out0].offset, Some(0));
 
         // Also test that an unsupported offset is rejected.
-        sss.get_outgoing_arg(types::I8, StackOffset::max_value() - 1);
+        sss.get_outgoing_arg(1, StackOffset::max_value() - 1);
         assert_eq!(layout_stack(sss, 1), Err(CodegenError::ImplLimitExceeded));
     }
 
@@ -208,4 +220,22 @@ mod tests {
         assert_eq!(sss[ss1].offset, Some(-8));
         assert_eq!(sss[ss2].offset, Some(-12));
     }
+
+    #[test]
+    fn shared_slots() {
+        let sss = &mut StackSlots::new();
+
+        let ss0 = sss.make_spill_slot(types::I64);
+        let ss1 = sss.make_spill_slot(types::I32);
+        let ss2 = sss.make_spill_slot(types::I64);
+        let ss3 = sss.make_spill_slot(types::I32);
+        sss.share(ss2, ss0);
+        sss.share(ss3, ss1);
+
+        assert_eq!(layout_stack(sss, 1), Ok(12));
+        assert_eq!(sss[ss0].offset, Some(-8));
+        assert_eq!(sss[ss1].offset, Some(-12));
+        assert_eq!(sss[ss2].offset, Some(-8));
+        assert_eq!(sss[ss3].offset, Some(-12));
+    }
 }
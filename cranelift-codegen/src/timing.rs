@@ -61,15 +61,23 @@ define_passes! {
     legalize: "Legalization",
     gvn: "Global value numbering",
     licm: "Loop invariant code motion",
+    loop_unrolling: "Loop unrolling",
+    bounds_checks: "Heap bounds check elimination",
+    scheduling: "Instruction scheduling",
+    block_layout: "Hot/cold block layout",
     unreachable_code: "Remove unreachable blocks",
+    inline: "Function inlining",
 
     regalloc: "Register allocation",
     ra_liveness: "RA liveness analysis",
+    ra_splitting: "RA live range splitting",
     ra_cssa: "RA coalescing CSSA",
     ra_spilling: "RA spilling",
+    ra_linear_scan: "RA linear scan",
     ra_reload: "RA reloading",
     ra_coloring: "RA coloring",
 
+    stack_coloring: "Stack slot coloring",
     prologue_epilogue: "Prologue/epilogue insertion",
     shrink_instructions: "Instruction encoding shrinking",
     relax_branches: "Branch relaxation",
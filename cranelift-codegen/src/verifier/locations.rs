@@ -272,13 +272,36 @@ impl<'a> LocationVerifier<'a> {
         divert: &mut RegDiversions,
         errors: &mut VerifierErrors,
     ) -> VerifierStepResult<()> {
-        let (arg, src) = match self.func.dfg[inst] {
+        match self.func.dfg[inst] {
             ir::InstructionData::RegMove { arg, src, .. }
-            | ir::InstructionData::RegSpill { arg, src, .. } => (arg, ir::ValueLoc::Reg(src)),
-            ir::InstructionData::RegFill { arg, src, .. } => (arg, ir::ValueLoc::Stack(src)),
+            | ir::InstructionData::RegSpill { arg, src, .. } => {
+                self.check_current_location(inst, divert, arg, ir::ValueLoc::Reg(src), errors)?
+            }
+            ir::InstructionData::RegFill { arg, src, .. } => {
+                self.check_current_location(inst, divert, arg, ir::ValueLoc::Stack(src), errors)?
+            }
+            ir::InstructionData::RegSwap { args, src, dst, .. } => {
+                let (loc0, loc1) = (ir::ValueLoc::Reg(src), ir::ValueLoc::Reg(dst));
+                self.check_current_location(inst, divert, args[0], loc0, errors)?;
+                self.check_current_location(inst, divert, args[1], loc1, errors)?;
+            }
             _ => return Ok(()),
         };
 
+        divert.apply(&self.func.dfg[inst]);
+
+        Ok(())
+    }
+
+    /// Check that `arg` is currently in `src` before it is moved by `inst`.
+    fn check_current_location(
+        &self,
+        inst: ir::Inst,
+        divert: &RegDiversions,
+        arg: ir::Value,
+        src: ir::ValueLoc,
+        errors: &mut VerifierErrors,
+    ) -> VerifierStepResult<()> {
         if let Some(d) = divert.diversion(arg) {
             if d.to != src {
                 return fatal!(
@@ -298,8 +321,6 @@ impl<'a> LocationVerifier<'a> {
             );
         }
 
-        divert.apply(&self.func.dfg[inst]);
-
         Ok(())
     }
 
@@ -57,6 +57,7 @@
 //!   of arguments must match the destination type, and the lane indexes must be in range.
 
 use self::flags::verify_flags;
+use crate::abi::struct_in_memory;
 use crate::dbg::DisplayList;
 use crate::dominator_tree::DominatorTree;
 use crate::entity::SparseSet;
@@ -725,6 +726,7 @@ impl<'a> Verifier<'a> {
             | Load { .. }
             | Store { .. }
             | RegMove { .. }
+            | RegSwap { .. }
             | CopySpecial { .. }
             | CopyToSsa { .. }
             | Trap { .. }
@@ -1378,7 +1380,10 @@ impl<'a> Verifier<'a> {
                             slot
                         );
                     }
-                    if slot.size != abi.value_type.bytes() {
+                    // A struct passed in memory gets a slot covering the whole struct.
+                    let size = struct_in_memory(sig.call_conv, &abi)
+                        .unwrap_or_else(|| abi.value_type.bytes());
+                    if slot.size != size {
                         return fatal!(
                             errors,
                             inst,
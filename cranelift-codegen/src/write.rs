@@ -248,6 +248,9 @@ pub fn write_ebb_header(
 ) -> fmt::Result {
     // The `indent` is the instruction indentation. EBB headers are 4 spaces out from that.
     write!(w, "{1:0$}{2}", indent - 4, "", ebb)?;
+    if func.layout.is_cold(ebb) {
+        write!(w, " cold")?;
+    }
 
     let regs = isa.map(TargetIsa::register_info);
     let regs = regs.as_ref();
@@ -608,7 +611,13 @@ pub fn write_operands(
             ..
         } => write!(w, " {}, {}{}", arg, stack_slot, offset),
         HeapAddr { heap, arg, imm, .. } => write!(w, " {}, {}, {}", heap, arg, imm),
-        TableAddr { table, arg, .. } => write!(w, " {}, {}", table, arg),
+        TableAddr {
+            table, arg, offset, ..
+        } => {
+            // The offset operand can't be omitted, so zero is written out explicitly.
+            let offset: i32 = offset.into();
+            write!(w, " {}, {}, {:+}", table, arg, offset)
+        }
         Load {
             flags, arg, offset, ..
         } => write!(w, "{} {}{}", flags, arg, offset),
@@ -663,6 +672,21 @@ pub fn write_operands(
                 write!(w, " {}, %{} -> %{}", arg, src, dst)
             }
         }
+        RegSwap { args, src, dst, .. } => {
+            if let Some(isa) = isa {
+                let regs = isa.register_info();
+                write!(
+                    w,
+                    " {}, {}, {} -> {}",
+                    args[0],
+                    args[1],
+                    regs.display_regunit(src),
+                    regs.display_regunit(dst)
+                )
+            } else {
+                write!(w, " {}, {}, %{} -> %{}", args[0], args[1], src, dst)
+            }
+        }
         CopySpecial { src, dst, .. } => {
             if let Some(isa) = isa {
                 let regs = isa.register_info();
@@ -711,8 +735,8 @@ pub fn write_operands(
     }
 }
 
-/// Write EBB args using optional parantheses.
-fn write_ebb_args(w: &mut dyn Write, args: &[Value]) -> fmt::Result {
+/// Write EBB args using optional parentheses.
+pub fn write_ebb_args<T: fmt::Display>(w: &mut dyn Write, args: &[T]) -> fmt::Result {
     if args.is_empty() {
         Ok(())
     } else {
@@ -721,9 +745,9 @@ fn write_ebb_args(w: &mut dyn Write, args: &[Value]) -> fmt::Result {
 }
 
 /// Displayable slice of values.
-struct DisplayValues<'a>(&'a [Value]);
+struct DisplayValues<'a, T: fmt::Display>(&'a [T]);
 
-impl<'a> fmt::Display for DisplayValues<'a> {
+impl<'a, T: fmt::Display> fmt::Display for DisplayValues<'a, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for (i, val) in self.0.iter().enumerate() {
             if i == 0 {
@@ -9,7 +9,7 @@ use cranelift_codegen::isa::TargetIsa;
 use cranelift_codegen::{self, binemit, ir};
 use cranelift_module::{
     Backend, DataContext, DataDescription, DataId, FuncId, Init, Linkage, ModuleError,
-    ModuleNamespace, ModuleResult,
+    ModuleNamespace, ModuleResult, SymbolAttributes, Visibility,
 };
 use faerie;
 use failure::Error;
@@ -123,9 +123,18 @@ impl Backend for FaerieBackend {
         &*self.isa
     }
 
-    fn declare_function(&mut self, _id: FuncId, name: &str, linkage: Linkage) {
+    fn declare_function(
+        &mut self,
+        _id: FuncId,
+        name: &str,
+        linkage: Linkage,
+        attributes: &SymbolAttributes,
+    ) {
         self.artifact
-            .declare(name, translate_function_linkage(linkage))
+            .declare(
+                name,
+                translate_function_linkage(linkage, attributes.visibility),
+            )
             .expect("inconsistent declarations");
     }
 
@@ -136,20 +145,26 @@ impl Backend for FaerieBackend {
         linkage: Linkage,
         writable: bool,
         align: Option<u8>,
+        attributes: &SymbolAttributes,
     ) {
         self.artifact
-            .declare(name, translate_data_linkage(linkage, writable, align))
+            .declare(
+                name,
+                translate_data_linkage(linkage, attributes.visibility, writable, align),
+            )
             .expect("inconsistent declarations");
     }
 
     fn define_function(
         &mut self,
-        _id: FuncId,
+        id: FuncId,
         name: &str,
         ctx: &cranelift_codegen::Context,
         namespace: &ModuleNamespace<Self>,
         total_size: u32,
     ) -> ModuleResult<FaerieCompiledFunction> {
+        check_attributes(name, &namespace.get_function_decl(&id.into()).attributes)?;
+
         let mut code: Vec<u8> = vec![0; total_size as usize];
         // TODO: Replace this with FaerieStackmapSink once it is implemented.
         let mut stackmap_sink = NullStackmapSink {};
@@ -202,13 +217,15 @@ impl Backend for FaerieBackend {
 
     fn define_data(
         &mut self,
-        _id: DataId,
+        id: DataId,
         name: &str,
         _writable: bool,
         _align: Option<u8>,
         data_ctx: &DataContext,
         namespace: &ModuleNamespace<Self>,
     ) -> ModuleResult<FaerieCompiledData> {
+        check_attributes(name, &namespace.get_data_decl(&id.into()).attributes)?;
+
         let &DataDescription {
             ref init,
             ref function_decls,
@@ -347,17 +364,55 @@ impl FaerieProduct {
     }
 }
 
-fn translate_function_linkage(linkage: Linkage) -> faerie::Decl {
+/// Check that faerie can express the attributes of the definition of `name`.
+fn check_attributes(name: &str, attributes: &SymbolAttributes) -> ModuleResult<()> {
+    if let Some(ref section) = attributes.section {
+        return Err(ModuleError::Backend(format!(
+            "faerie can't place {} in section {}",
+            name, section
+        )));
+    }
+    if let Some(ref comdat) = attributes.comdat {
+        return Err(ModuleError::Backend(format!(
+            "faerie can't emit COMDAT group {} for {}",
+            comdat, name
+        )));
+    }
+    Ok(())
+}
+
+fn translate_visibility(visibility: Visibility) -> faerie::Visibility {
+    match visibility {
+        Visibility::Default => faerie::Visibility::Default,
+        Visibility::Hidden => faerie::Visibility::Hidden,
+        Visibility::Protected => faerie::Visibility::Protected,
+    }
+}
+
+fn translate_function_linkage(linkage: Linkage, visibility: Visibility) -> faerie::Decl {
+    let visibility = translate_visibility(visibility);
     match linkage {
         Linkage::Import => faerie::Decl::function_import().into(),
         Linkage::Local => faerie::Decl::function().into(),
-        Linkage::Export => faerie::Decl::function().global().into(),
-        Linkage::Preemptible => faerie::Decl::function().weak().into(),
+        Linkage::Export => faerie::Decl::function()
+            .global()
+            .with_visibility(visibility)
+            .into(),
+        Linkage::Preemptible => faerie::Decl::function()
+            .weak()
+            .with_visibility(visibility)
+            .into(),
     }
 }
 
-fn translate_data_linkage(linkage: Linkage, writable: bool, align: Option<u8>) -> faerie::Decl {
+fn translate_data_linkage(
+    linkage: Linkage,
+    visibility: Visibility,
+    writable: bool,
+    align: Option<u8>,
+) -> faerie::Decl {
     let align = align.map(|align| u64::from(align));
+    let visibility = translate_visibility(visibility);
     match linkage {
         Linkage::Import => faerie::Decl::data_import().into(),
         Linkage::Local => faerie::Decl::data()
@@ -366,11 +421,13 @@ fn translate_data_linkage(linkage: Linkage, writable: bool, align: Option<u8>) -
             .into(),
         Linkage::Export => faerie::Decl::data()
             .global()
+            .with_visibility(visibility)
             .with_writable(writable)
             .with_align(align)
             .into(),
         Linkage::Preemptible => faerie::Decl::data()
             .weak()
+            .with_visibility(visibility)
             .with_writable(writable)
             .with_align(align)
             .into(),
@@ -38,10 +38,12 @@ mod runone;
 mod subtest;
 
 mod test_binemit;
+mod test_bounds_checks;
 mod test_cat;
 mod test_compile;
 mod test_dce;
 mod test_domtree;
+mod test_inline;
 mod test_legalizer;
 mod test_licm;
 mod test_postopt;
@@ -51,9 +53,12 @@ mod test_regalloc;
 mod test_rodata;
 mod test_run;
 mod test_safepoint;
+mod test_schedule;
+mod test_serialize;
 mod test_shrink;
 mod test_simple_gvn;
 mod test_simple_preopt;
+mod test_unroll;
 mod test_verifier;
 
 /// The result of running the test in a file.
@@ -118,11 +123,13 @@ pub fn run_passes(
 fn new_subtest(parsed: &TestCommand) -> subtest::SubtestResult<Box<dyn subtest::SubTest>> {
     match parsed.command {
         "binemit" => test_binemit::subtest(parsed),
+        "bounds-checks" => test_bounds_checks::subtest(parsed),
         "cat" => test_cat::subtest(parsed),
         "compile" => test_compile::subtest(parsed),
         "rodata" => test_rodata::subtest(parsed),
         "dce" => test_dce::subtest(parsed),
         "domtree" => test_domtree::subtest(parsed),
+        "inline" => test_inline::subtest(parsed),
         "legalizer" => test_legalizer::subtest(parsed),
         "licm" => test_licm::subtest(parsed),
         "postopt" => test_postopt::subtest(parsed),
@@ -132,9 +139,12 @@ fn new_subtest(parsed: &TestCommand) -> subtest::SubtestResult<Box<dyn subtest::
         "run" => test_run::subtest(parsed),
         "shrink" => test_shrink::subtest(parsed),
         "simple-gvn" => test_simple_gvn::subtest(parsed),
+        "unroll" => test_unroll::subtest(parsed),
         "verifier" => test_verifier::subtest(parsed),
         "preopt" => test_preopt::subtest(parsed),
         "safepoint" => test_safepoint::subtest(parsed),
+        "schedule" => test_schedule::subtest(parsed),
+        "serialize" => test_serialize::subtest(parsed),
         _ => Err(format!("unknown test command '{}'", parsed.command)),
     }
 }
@@ -109,6 +109,13 @@ pub fn run(path: &Path, passes: Option<&[String]>, target: Option<&str>) -> Test
         Some(t) => t,
     };
 
+    // Keep a pristine copy of all the functions for the tests that need to see them.
+    let all_functions: Vec<Function> = if tests.iter().any(|t| t.needs_all_functions()) {
+        testfile.functions.iter().map(|(f, _)| f.clone()).collect()
+    } else {
+        Vec::new()
+    };
+
     for (func, details) in testfile.functions {
         let mut context = Context {
             preamble_comments: &testfile.preamble_comments,
@@ -116,6 +123,7 @@ pub fn run(path: &Path, passes: Option<&[String]>, target: Option<&str>) -> Test
             verified: false,
             flags,
             isa: None,
+            functions: &all_functions,
         };
 
         for tuple in &tuples {
@@ -26,6 +26,10 @@ pub struct Context<'a> {
     /// Target ISA to test against. Only guaranteed to be present for sub-tests whose `needs_isa`
     /// method returned `true`. For other sub-tests, this is set if the test file has a unique ISA.
     pub isa: Option<&'a dyn TargetIsa>,
+
+    /// All the functions in the test file, as parsed. Only populated when one of the sub-tests
+    /// in the file returned `true` from its `needs_all_functions` method.
+    pub functions: &'a [Function],
 }
 
 impl<'a> Context<'a> {
@@ -62,6 +66,11 @@ pub trait SubTest {
         false
     }
 
+    /// Does this test need to see the other functions in the test file?
+    fn needs_all_functions(&self) -> bool {
+        false
+    }
+
     /// Run this test on `func`.
     fn run(&self, func: Cow<Function>, context: &Context) -> SubtestResult<()>;
 }
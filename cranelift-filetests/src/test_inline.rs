@@ -0,0 +1,54 @@
+//! Test command for testing the inliner.
+//!
+//! The `inline` test command inlines the direct calls in each function to other functions defined
+//! in the same test file, as selected by the default size heuristic.
+//!
+//! The resulting function is sent to `filecheck`.
+
+use crate::subtest::{run_filecheck, Context, SubTest, SubtestResult};
+use cranelift_codegen::inline::{inline_calls, SizeHeuristic};
+use cranelift_codegen::ir::Function;
+use cranelift_codegen::print_errors::pretty_verifier_error;
+use cranelift_codegen::verify_function;
+use cranelift_reader::TestCommand;
+use std::borrow::Cow;
+
+struct TestInline;
+
+pub fn subtest(parsed: &TestCommand) -> SubtestResult<Box<dyn SubTest>> {
+    assert_eq!(parsed.command, "inline");
+    if !parsed.options.is_empty() {
+        Err(format!("No options allowed on {}", parsed))
+    } else {
+        Ok(Box::new(TestInline))
+    }
+}
+
+impl SubTest for TestInline {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    fn is_mutating(&self) -> bool {
+        true
+    }
+
+    fn needs_all_functions(&self) -> bool {
+        true
+    }
+
+    fn run(&self, func: Cow<Function>, context: &Context) -> SubtestResult<()> {
+        let mut func = func.into_owned();
+
+        inline_calls(
+            &mut func,
+            |name| context.functions.iter().find(|f| f.name == *name),
+            &SizeHeuristic::default(),
+        );
+        verify_function(&func, context.flags_or_isa())
+            .map_err(|errors| pretty_verifier_error(&func, context.isa, None, errors))?;
+
+        let text = func.display(context.isa).to_string();
+        run_filecheck(&text, context)
+    }
+}
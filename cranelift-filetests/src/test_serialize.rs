@@ -0,0 +1,85 @@
+//! The `serialize` subtest.
+
+use crate::subtest::{run_filecheck, Context, SubTest, SubtestResult};
+use cranelift_codegen::binary::{deserialize_function, serialize_function};
+use cranelift_codegen::binemit::{
+    CodeInfo, MemoryCodeSink, NullRelocSink, NullStackmapSink, NullTrapSink,
+};
+use cranelift_codegen::ir::Function;
+use cranelift_codegen::isa::TargetIsa;
+use cranelift_codegen::print_errors::pretty_error;
+use cranelift_reader::TestCommand;
+use std::borrow::Cow;
+
+/// Object implementing the `test serialize` sub-test.
+///
+/// This command is used for testing the binary format of functions. It serializes and deserializes
+/// each function, and checks that the result prints like the original. When an ISA is given, the
+/// function is then compiled and the compiled function goes through the same round trip, after
+/// which it must still emit the same code.
+///
+/// The deserialized function, compiled if there is an ISA, is verified by filecheck.
+struct TestSerialize;
+
+pub fn subtest(parsed: &TestCommand) -> SubtestResult<Box<dyn SubTest>> {
+    assert_eq!(parsed.command, "serialize");
+    if !parsed.options.is_empty() {
+        Err(format!("No options allowed on {}", parsed))
+    } else {
+        Ok(Box::new(TestSerialize))
+    }
+}
+
+impl SubTest for TestSerialize {
+    fn name(&self) -> &'static str {
+        "serialize"
+    }
+
+    fn run(&self, func: Cow<Function>, context: &Context) -> SubtestResult<()> {
+        let copy = roundtrip(&func, None)?;
+        let text = match context.isa {
+            None => copy.display(None).to_string(),
+            Some(isa) => {
+                let mut comp_ctx = cranelift_codegen::Context::for_function(copy);
+                let info = comp_ctx
+                    .compile(isa)
+                    .map_err(|e| pretty_error(&comp_ctx.func, context.isa, e))?;
+                let compiled = roundtrip(&comp_ctx.func, Some(isa))?;
+                if emit(&compiled, isa, info) != emit(&comp_ctx.func, isa, info) {
+                    return Err("deserialized function emits different code".to_string());
+                }
+                compiled.display(isa).to_string()
+            }
+        };
+        run_filecheck(&text, context)
+    }
+}
+
+/// Serialize and deserialize `func`, and check that the result prints the same.
+fn roundtrip(func: &Function, isa: Option<&dyn TargetIsa>) -> SubtestResult<Function> {
+    let data = serialize_function(func);
+    let copy = deserialize_function(&data).map_err(|e| e.to_string())?;
+    let (before, after) = (func.display(isa).to_string(), copy.display(isa).to_string());
+    if before != after {
+        return Err(format!(
+            "deserialized function differs:\n{}\nexpected:\n{}",
+            after, before
+        ));
+    }
+    if serialize_function(&copy) != data {
+        return Err("deserialized function serializes differently".to_string());
+    }
+    Ok(copy)
+}
+
+/// Emit the code of the compiled function `func`.
+fn emit(func: &Function, isa: &dyn TargetIsa, info: CodeInfo) -> Vec<u8> {
+    let mut code = vec![0; info.total_size as usize];
+    let mut relocs = NullRelocSink {};
+    let mut traps = NullTrapSink {};
+    let mut stackmaps = NullStackmapSink {};
+    let mut sink =
+        unsafe { MemoryCodeSink::new(code.as_mut_ptr(), &mut relocs, &mut traps, &mut stackmaps) };
+    isa.emit_function_to_memory(func, &mut sink);
+    code
+}
@@ -246,6 +246,13 @@ impl<'a> FunctionBuilder<'a> {
         ebb
     }
 
+    /// Mark `ebb` as cold, i.e. rarely executed, like the slow path of an operation.
+    ///
+    /// Cold EBBs are moved out of the way of the hot code when the function is compiled.
+    pub fn set_cold_block(&mut self, ebb: Ebb) {
+        self.func.layout.set_cold(ebb);
+    }
+
     /// After the call to this function, new instructions will be inserted into the designated
     /// block, in the order they are declared. You must declare the types of the Ebb arguments
     /// you will use here.
@@ -896,7 +903,9 @@ mod tests {
     use alloc::string::ToString;
     use cranelift_codegen::entity::EntityRef;
     use cranelift_codegen::ir::types::*;
-    use cranelift_codegen::ir::{AbiParam, ExternalName, Function, InstBuilder, Signature};
+    use cranelift_codegen::ir::{
+        AbiParam, ExternalName, Function, InstBuilder, Signature, TrapCode,
+    };
     use cranelift_codegen::isa::CallConv;
     use cranelift_codegen::settings;
     use cranelift_codegen::verifier::verify_function;
@@ -1298,6 +1307,54 @@ ebb0:
         );
     }
 
+    #[test]
+    fn cold_block() {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(I32));
+
+        let mut fn_ctx = FunctionBuilderContext::new();
+        let mut func = Function::with_name_signature(ExternalName::testcase("sample"), sig);
+        {
+            let mut builder = FunctionBuilder::new(&mut func, &mut fn_ctx);
+
+            let block0 = builder.create_ebb();
+            let block1 = builder.create_ebb();
+            let block2 = builder.create_ebb();
+            builder.set_cold_block(block1);
+            builder.append_ebb_params_for_function_params(block0);
+
+            builder.switch_to_block(block0);
+            let x = builder.ebb_params(block0)[0];
+            builder.ins().brz(x, block1, &[]);
+            builder.ins().jump(block2, &[]);
+
+            builder.switch_to_block(block1);
+            builder.ins().trap(TrapCode::User(0));
+
+            builder.switch_to_block(block2);
+            builder.ins().return_(&[]);
+
+            builder.seal_all_blocks();
+            builder.finalize();
+        }
+
+        assert_eq!(
+            func.display(None).to_string(),
+            "function %sample(i32) system_v {
+ebb0(v0: i32):
+    brz v0, ebb1
+    jump ebb2
+
+ebb1 cold:
+    trap user0
+
+ebb2:
+    return
+}
+"
+        );
+    }
+
     #[test]
     fn test_greatest_divisible_power_of_two() {
         assert_eq!(64, greatest_divisible_power_of_two(64));
@@ -4,8 +4,10 @@ use crate::DataContext;
 use crate::DataId;
 use crate::FuncId;
 use crate::Linkage;
+use crate::ModuleError;
 use crate::ModuleNamespace;
 use crate::ModuleResult;
+use crate::SymbolAttributes;
 use core::marker;
 use cranelift_codegen::isa::TargetIsa;
 use cranelift_codegen::Context;
@@ -13,6 +15,7 @@ use cranelift_codegen::{binemit, ir};
 
 use std::borrow::ToOwned;
 use std::boxed::Box;
+use std::format;
 use std::string::String;
 
 /// A `Backend` implements the functionality needed to support a `Module`.
@@ -58,9 +61,19 @@ where
     fn isa(&self) -> &dyn TargetIsa;
 
     /// Declare a function.
-    fn declare_function(&mut self, id: FuncId, name: &str, linkage: Linkage);
+    ///
+    /// Attributes the backend can't express are reported when the function is defined.
+    fn declare_function(
+        &mut self,
+        id: FuncId,
+        name: &str,
+        linkage: Linkage,
+        attributes: &SymbolAttributes,
+    );
 
     /// Declare a data object.
+    ///
+    /// Attributes the backend can't express are reported when the data object is defined.
     fn declare_data(
         &mut self,
         id: DataId,
@@ -68,6 +81,7 @@ where
         linkage: Linkage,
         writable: bool,
         align: Option<u8>,
+        attributes: &SymbolAttributes,
     );
 
     /// Define a function, producing the function body from the given `Context`.
@@ -141,6 +155,28 @@ where
     /// "Publish" all finalized functions and data objects to their ultimate destinations.
     fn publish(&mut self);
 
+    /// Release the resources used by the compiled function `func`, which won't be used anymore.
+    ///
+    /// The default implementation does nothing.
+    fn free_function(&mut self, _id: FuncId, _func: Self::CompiledFunction) {}
+
+    /// Release the resources used by the compiled data object `data`, which won't be used anymore.
+    ///
+    /// The default implementation does nothing.
+    fn free_data(&mut self, _id: DataId, _data: Self::CompiledData) {}
+
+    /// Prepare for the already finalized function `id` to be defined again.
+    ///
+    /// Backends supporting this must make sure that once the new definition is finalized, all the
+    /// calls to the function, including the ones from code finalized earlier, reach it. The default
+    /// implementation reports an error.
+    fn prepare_for_function_redefine(&mut self, id: FuncId) -> ModuleResult<()> {
+        Err(ModuleError::Backend(format!(
+            "redefinition of function {} is not supported by this backend",
+            id
+        )))
+    }
+
     /// Consume this `Backend` and return a result. Some implementations may
     /// provide additional functionality through this result.
     fn finish(self) -> Self::Product;
@@ -162,5 +198,9 @@ pub fn default_libcall_names() -> Box<dyn Fn(ir::LibCall) -> String> {
         ir::LibCall::Memcpy => "memcpy".to_owned(),
         ir::LibCall::Memset => "memset".to_owned(),
         ir::LibCall::Memmove => "memmove".to_owned(),
+        ir::LibCall::UdivI128 => "__udivti3".to_owned(),
+        ir::LibCall::SdivI128 => "__divti3".to_owned(),
+        ir::LibCall::UremI128 => "__umodti3".to_owned(),
+        ir::LibCall::SremI128 => "__modti3".to_owned(),
     })
 }
@@ -40,7 +40,7 @@ pub use crate::backend::{default_libcall_names, Backend};
 pub use crate::data_context::{DataContext, DataDescription, Init};
 pub use crate::module::{
     DataId, FuncId, FuncOrDataId, Linkage, Module, ModuleError, ModuleFunction, ModuleNamespace,
-    ModuleResult,
+    ModuleResult, SymbolAttributes, Visibility,
 };
 
 /// Version number of this crate.
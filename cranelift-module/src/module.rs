@@ -9,11 +9,15 @@ use super::HashMap;
 use crate::data_context::DataContext;
 use crate::Backend;
 use cranelift_codegen::binemit::{self, CodeInfo};
+use cranelift_codegen::cache::CompilationCache;
 use cranelift_codegen::entity::{entity_impl, PrimaryMap};
+use cranelift_codegen::inline::{inline_calls, InlineHeuristic};
 use cranelift_codegen::{ir, isa, CodegenError, Context};
 use failure::Fail;
 use log::info;
 use std::borrow::ToOwned;
+use std::boxed::Box;
+use std::format;
 use std::string::String;
 use std::vec::Vec;
 
@@ -55,6 +59,8 @@ pub enum Linkage {
     /// Defined inside the module, but not visible outside it.
     Local,
     /// Defined inside the module, visible outside it, and may be preempted.
+    ///
+    /// Object file backends emit this as a weak definition.
     Preemptible,
     /// Defined inside the module, and visible outside it.
     Export,
@@ -94,6 +100,84 @@ impl Linkage {
     }
 }
 
+/// Visibility of a symbol to other modules once it is linked into a shared object or executable.
+///
+/// This only matters for symbols which are visible outside their module, that is which aren't
+/// `Linkage::Local`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Visibility {
+    /// Visible to other modules, which may also preempt it.
+    Default,
+    /// Not visible outside the shared object or executable the symbol ends up in.
+    Hidden,
+    /// Visible to other modules, but references from inside its own shared object or
+    /// executable can't be preempted.
+    Protected,
+}
+
+impl Visibility {
+    /// Merge two visibilities, keeping the most restrictive one.
+    fn merge(a: Self, b: Self) -> Self {
+        match (a, b) {
+            (Visibility::Hidden, _) | (_, Visibility::Hidden) => Visibility::Hidden,
+            (Visibility::Protected, _) | (_, Visibility::Protected) => Visibility::Protected,
+            (Visibility::Default, Visibility::Default) => Visibility::Default,
+        }
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Visibility::Default
+    }
+}
+
+/// Object file attributes of a declared function or data object, besides its linkage.
+///
+/// Not every backend supports every attribute: backends which can't express an attribute
+/// report an error when the function or data object carrying it is defined.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SymbolAttributes {
+    /// The visibility of the symbol.
+    pub visibility: Visibility,
+    /// The name of the section to place the definition in, instead of the default section
+    /// for its kind.
+    pub section: Option<String>,
+    /// The name of the COMDAT group the definition belongs to. The linker keeps a single
+    /// copy of each group with a given name.
+    pub comdat: Option<String>,
+}
+
+impl SymbolAttributes {
+    /// Merge the attributes of a new declaration of the symbol `name` into `self`.
+    ///
+    /// The visibility becomes the most restrictive of both, and sections and COMDAT groups
+    /// need to agree when they are given in both. `self` is left unchanged when they don't.
+    fn merge(&mut self, name: &str, other: &Self) -> ModuleResult<()> {
+        if conflicting_names(&self.section, &other.section)
+            || conflicting_names(&self.comdat, &other.comdat)
+        {
+            return Err(ModuleError::IncompatibleDeclaration(name.to_owned()));
+        }
+        self.visibility = Visibility::merge(self.visibility, other.visibility);
+        if self.section.is_none() {
+            self.section = other.section.clone();
+        }
+        if self.comdat.is_none() {
+            self.comdat = other.comdat.clone();
+        }
+        Ok(())
+    }
+}
+
+/// Are two optional section or COMDAT group names both given, and different?
+fn conflicting_names(a: &Option<String>, b: &Option<String>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a != b,
+        _ => false,
+    }
+}
+
 /// A declared name may refer to either a function or data declaration
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
 pub enum FuncOrDataId {
@@ -118,6 +202,7 @@ pub struct FunctionDeclaration {
     pub name: String,
     pub linkage: Linkage,
     pub signature: ir::Signature,
+    pub attributes: SymbolAttributes,
 }
 
 /// Error messages for all `Module` and `Backend` methods
@@ -126,6 +211,9 @@ pub enum ModuleError {
     /// Indicates an identifier was used before it was declared
     #[fail(display = "Undeclared identifier: {}", _0)]
     Undeclared(String),
+    /// Indicates an identifier needed a definition, but wasn't defined
+    #[fail(display = "Undefined identifier: {}", _0)]
+    Undefined(String),
     /// Indicates an identifier was used as data/function first, but then used as the other
     #[fail(display = "Incompatible declaration of identifier: {}", _0)]
     IncompatibleDeclaration(String),
@@ -168,8 +256,12 @@ impl<B> ModuleFunction<B>
 where
     B: Backend,
 {
-    fn merge(&mut self, linkage: Linkage, sig: &ir::Signature) -> Result<(), ModuleError> {
-        self.decl.linkage = Linkage::merge(self.decl.linkage, linkage);
+    fn merge(
+        &mut self,
+        linkage: Linkage,
+        sig: &ir::Signature,
+        attributes: &SymbolAttributes,
+    ) -> Result<(), ModuleError> {
         if &self.decl.signature != sig {
             return Err(ModuleError::IncompatibleSignature(
                 self.decl.name.clone(),
@@ -177,6 +269,8 @@ where
                 sig.clone(),
             ));
         }
+        self.decl.attributes.merge(&self.decl.name, attributes)?;
+        self.decl.linkage = Linkage::merge(self.decl.linkage, linkage);
         Ok(())
     }
 }
@@ -187,6 +281,7 @@ pub struct DataDeclaration {
     pub linkage: Linkage,
     pub writable: bool,
     pub align: Option<u8>,
+    pub attributes: SymbolAttributes,
 }
 
 /// A data object belonging to a `Module`.
@@ -204,10 +299,18 @@ impl<B> ModuleData<B>
 where
     B: Backend,
 {
-    fn merge(&mut self, linkage: Linkage, writable: bool, align: Option<u8>) {
+    fn merge(
+        &mut self,
+        linkage: Linkage,
+        writable: bool,
+        align: Option<u8>,
+        attributes: &SymbolAttributes,
+    ) -> Result<(), ModuleError> {
+        self.decl.attributes.merge(&self.decl.name, attributes)?;
         self.decl.linkage = Linkage::merge(self.decl.linkage, linkage);
         self.decl.writable = self.decl.writable || writable;
         self.decl.align = self.decl.align.max(align);
+        Ok(())
     }
 }
 
@@ -342,6 +445,9 @@ where
     contents: ModuleContents<B>,
     functions_to_finalize: Vec<FuncId>,
     data_objects_to_finalize: Vec<DataId>,
+    inline_heuristic: Option<Box<dyn InlineHeuristic>>,
+    inline_bodies: HashMap<FuncId, ir::Function>,
+    cache: Option<Box<dyn CompilationCache>>,
     backend: B,
 }
 
@@ -359,10 +465,32 @@ where
             },
             functions_to_finalize: Vec::new(),
             data_objects_to_finalize: Vec::new(),
+            inline_heuristic: None,
+            inline_bodies: HashMap::new(),
+            cache: None,
             backend: B::new(backend_builder),
         }
     }
 
+    /// Enable inlining of calls to functions defined in this module.
+    ///
+    /// From now on, `define_function` keeps a copy of the IR of every function that can't be
+    /// preempted, and calls to such functions in subsequently defined functions are inlined when
+    /// `heuristic` agrees. Functions should be defined callees first to get the most out of this.
+    pub fn enable_inlining(&mut self, heuristic: Box<dyn InlineHeuristic>) {
+        self.inline_heuristic = Some(heuristic);
+    }
+
+    /// Look up the compiled code of functions in `cache` before compiling them.
+    ///
+    /// From now on, `define_function` and `define_functions` only compile the functions whose
+    /// code isn't found in `cache`, and store the code of the functions they compile in it. A
+    /// function found in the cache is left uncompiled in its `Context`, which holds its cached
+    /// code instead.
+    pub fn enable_cache(&mut self, cache: Box<dyn CompilationCache>) {
+        self.cache = Some(cache);
+    }
+
     /// Get the module identifier for a given name, if that name
     /// has been declared.
     pub fn get_name(&self, name: &str) -> Option<FuncOrDataId> {
@@ -415,6 +543,25 @@ where
         name: &str,
         linkage: Linkage,
         signature: &ir::Signature,
+    ) -> ModuleResult<FuncId> {
+        self.declare_function_with_attributes(
+            name,
+            linkage,
+            signature,
+            &SymbolAttributes::default(),
+        )
+    }
+
+    /// Declare a function in this module, with the given object file attributes.
+    ///
+    /// When the function was already declared, the attributes are merged with the ones of the
+    /// previous declarations, and differing sections or COMDAT groups are an error.
+    pub fn declare_function_with_attributes(
+        &mut self,
+        name: &str,
+        linkage: Linkage,
+        signature: &ir::Signature,
+        attributes: &SymbolAttributes,
     ) -> ModuleResult<FuncId> {
         // TODO: Can we avoid allocating names so often?
         use super::hash_map::Entry::*;
@@ -422,9 +569,13 @@ where
             Occupied(entry) => match *entry.get() {
                 FuncOrDataId::Func(id) => {
                     let existing = &mut self.contents.functions[id];
-                    existing.merge(linkage, signature)?;
-                    self.backend
-                        .declare_function(id, name, existing.decl.linkage);
+                    existing.merge(linkage, signature, attributes)?;
+                    self.backend.declare_function(
+                        id,
+                        name,
+                        existing.decl.linkage,
+                        &existing.decl.attributes,
+                    );
                     Ok(id)
                 }
                 FuncOrDataId::Data(..) => {
@@ -437,11 +588,12 @@ where
                         name: name.to_owned(),
                         linkage,
                         signature: signature.clone(),
+                        attributes: attributes.clone(),
                     },
                     compiled: None,
                 });
                 entry.insert(FuncOrDataId::Func(id));
-                self.backend.declare_function(id, name, linkage);
+                self.backend.declare_function(id, name, linkage, attributes);
                 Ok(id)
             }
         }
@@ -459,6 +611,27 @@ where
         linkage: Linkage,
         writable: bool,
         align: Option<u8>, // An alignment bigger than 128 is unlikely
+    ) -> ModuleResult<DataId> {
+        self.declare_data_with_attributes(
+            name,
+            linkage,
+            writable,
+            align,
+            &SymbolAttributes::default(),
+        )
+    }
+
+    /// Declare a data object in this module, with the given object file attributes.
+    ///
+    /// When the data object was already declared, the attributes are merged with the ones of
+    /// the previous declarations, and differing sections or COMDAT groups are an error.
+    pub fn declare_data_with_attributes(
+        &mut self,
+        name: &str,
+        linkage: Linkage,
+        writable: bool,
+        align: Option<u8>,
+        attributes: &SymbolAttributes,
     ) -> ModuleResult<DataId> {
         // TODO: Can we avoid allocating names so often?
         use super::hash_map::Entry::*;
@@ -466,13 +639,14 @@ where
             Occupied(entry) => match *entry.get() {
                 FuncOrDataId::Data(id) => {
                     let existing = &mut self.contents.data_objects[id];
-                    existing.merge(linkage, writable, align);
+                    existing.merge(linkage, writable, align, attributes)?;
                     self.backend.declare_data(
                         id,
                         name,
                         existing.decl.linkage,
                         existing.decl.writable,
                         existing.decl.align,
+                        &existing.decl.attributes,
                     );
                     Ok(id)
                 }
@@ -488,12 +662,13 @@ where
                         linkage,
                         writable,
                         align,
+                        attributes: attributes.clone(),
                     },
                     compiled: None,
                 });
                 entry.insert(FuncOrDataId::Data(id));
                 self.backend
-                    .declare_data(id, name, linkage, writable, align);
+                    .declare_data(id, name, linkage, writable, align, attributes);
                 Ok(id)
             }
         }
@@ -547,7 +722,13 @@ where
         func: FuncId,
         ctx: &mut Context,
     ) -> ModuleResult<binemit::CodeOffset> {
-        let CodeInfo { total_size, .. } = ctx.compile(self.backend.isa()).map_err(|e| {
+        let inline_body = self.inline_into(func, ctx);
+        let isa = self.backend.isa();
+        let compiled = match self.cache {
+            Some(ref mut cache) => ctx.compile_cached(isa, &mut **cache),
+            None => ctx.compile(isa),
+        };
+        let CodeInfo { total_size, .. } = compiled.map_err(|e| {
             info!(
                 "defining function {}: {}",
                 func,
@@ -555,6 +736,174 @@ where
             );
             ModuleError::Compilation(e)
         })?;
+        self.define_compiled_function(func, ctx, total_size)?;
+        self.record_inline_body(func, inline_body);
+        Ok(total_size)
+    }
+
+    /// Define the functions in `funcs`, compiling them in parallel on `threads` threads.
+    ///
+    /// Each function is compiled with its own `Context`, and the compiled functions are then
+    /// handed to the backend in the order of `funcs`, so the result doesn't depend on the number
+    /// of threads. If any function fails to compile, the error for the first one in `funcs` is
+    /// returned and none of them is defined.
+    ///
+    /// Returns the sizes of the functions' code and constant data, in the order of `funcs`.
+    ///
+    /// Note: After calling this function the given `Context`s will contain the compiled functions.
+    #[cfg(feature = "std")]
+    pub fn define_functions(
+        &mut self,
+        funcs: &mut [(FuncId, Context)],
+        threads: usize,
+    ) -> ModuleResult<Vec<binemit::CodeOffset>> {
+        use cranelift_codegen::cache::CacheKey;
+        use std::sync::Mutex;
+
+        assert!(threads > 0, "at least one thread is needed");
+        for (i, &(func, _)) in funcs.iter().enumerate() {
+            let info = &self.contents.functions[func];
+            if info.compiled.is_some() || funcs[..i].iter().any(|&(f, _)| f == func) {
+                return Err(ModuleError::DuplicateDefinition(info.decl.name.clone()));
+            }
+            if !info.decl.linkage.is_definable() {
+                return Err(ModuleError::InvalidImportDefinition(info.decl.name.clone()));
+            }
+        }
+        let inline_bodies: Vec<_> = funcs
+            .iter_mut()
+            .map(|&mut (func, ref mut ctx)| self.inline_into(func, ctx))
+            .collect();
+
+        // Look up the functions in the cache first, and only compile the others.
+        let isa = self.backend.isa();
+        let mut results: Vec<Option<Result<CodeInfo, CodegenError>>> =
+            (0..funcs.len()).map(|_| None).collect();
+        let mut keys = Vec::new();
+        if let Some(ref mut cache) = self.cache {
+            for (i, &mut (_, ref mut ctx)) in funcs.iter_mut().enumerate() {
+                let key = CacheKey::new(&ctx.func, isa);
+                match cache.get(&key) {
+                    Some(code) => {
+                        results[i] = Some(Ok(code.info));
+                        ctx.set_cached_code(code);
+                    }
+                    None => keys.push((i, key)),
+                }
+            }
+        }
+        let caching = self.cache.is_some();
+        let cached: Vec<bool> = results.iter().map(Option::is_some).collect();
+
+        // The threads take the next function to compile from a shared queue.
+        let queue = Mutex::new(funcs.iter_mut().enumerate().filter(|&(i, _)| !cached[i]));
+        crossbeam_utils::thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|_| {
+                        let mut done = Vec::new();
+                        loop {
+                            let next = queue.lock().unwrap().next();
+                            match next {
+                                Some((i, &mut (_, ref mut ctx))) => {
+                                    let result = ctx.compile(isa);
+                                    if let Ok(info) = result {
+                                        if caching {
+                                            ctx.record_cached_code(isa, info);
+                                        }
+                                    }
+                                    done.push((i, result));
+                                }
+                                None => return done,
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                for (i, result) in handle.join().unwrap() {
+                    results[i] = Some(result);
+                }
+            }
+        })
+        .unwrap();
+
+        let mut sizes = Vec::with_capacity(funcs.len());
+        for (&(func, ref ctx), result) in funcs.iter().zip(results) {
+            match result.expect("function not compiled") {
+                Ok(CodeInfo { total_size, .. }) => sizes.push(total_size),
+                Err(e) => {
+                    info!(
+                        "defining function {}: {}",
+                        func,
+                        ctx.func.display(self.backend.isa())
+                    );
+                    return Err(ModuleError::Compilation(e));
+                }
+            }
+        }
+        if let Some(ref mut cache) = self.cache {
+            for (i, key) in keys {
+                let code = funcs[i]
+                    .1
+                    .cached_code()
+                    .expect("compiled code not recorded");
+                cache.insert(&key, code);
+            }
+        }
+        for ((&(func, ref ctx), &total_size), inline_body) in
+            funcs.iter().zip(&sizes).zip(inline_bodies)
+        {
+            self.define_compiled_function(func, ctx, total_size)?;
+            self.record_inline_body(func, inline_body);
+        }
+        Ok(sizes)
+    }
+
+    /// Inline the calls to the functions with a known body into the function `func` in `ctx`,
+    /// when inlining is enabled.
+    ///
+    /// Returns the body to remember for inlining `func` into other functions, which is recorded
+    /// with `record_inline_body()` once `func` is successfully defined.
+    fn inline_into(&self, func: FuncId, ctx: &mut Context) -> Option<ir::Function> {
+        if let Some(ref heuristic) = self.inline_heuristic {
+            let contents = &self.contents;
+            let bodies = &self.inline_bodies;
+            let inlined = inline_calls(
+                &mut ctx.func,
+                |name| match *name {
+                    ir::ExternalName::User { namespace: 0, .. } => {
+                        bodies.get(&contents.get_function_id(name))
+                    }
+                    _ => None,
+                },
+                &**heuristic,
+            );
+            if inlined > 0 {
+                info!("inlined {} call sites into {}", inlined, func);
+            }
+            let info = &self.contents.functions[func];
+            if info.decl.linkage.is_final() && info.compiled.is_none() {
+                return Some(ctx.func.clone());
+            }
+        }
+        None
+    }
+
+    /// Remember the body of the function `func` for inlining, see `inline_into()`.
+    fn record_inline_body(&mut self, func: FuncId, body: Option<ir::Function>) {
+        if let Some(body) = body {
+            self.inline_bodies.insert(func, body);
+        }
+    }
+
+    /// Hand the function `func`, compiled in `ctx`, to the backend.
+    fn define_compiled_function(
+        &mut self,
+        func: FuncId,
+        ctx: &Context,
+        total_size: binemit::CodeOffset,
+    ) -> ModuleResult<()> {
         let info = &self.contents.functions[func];
         if info.compiled.is_some() {
             return Err(ModuleError::DuplicateDefinition(info.decl.name.clone()));
@@ -575,7 +924,65 @@ where
 
         self.contents.functions[func].compiled = compiled;
         self.functions_to_finalize.push(func);
-        Ok(total_size)
+        Ok(())
+    }
+
+    /// Allow the already finalized function `func` to be defined again.
+    ///
+    /// After this, `define_function` accepts a new definition for `func`, and once it is finalized
+    /// the calls to `func` are redirected to it, including the ones from code finalized earlier.
+    /// The previous definition stays in memory, as it may still be running. Calls to `func` which
+    /// have already been inlined into other functions are not affected.
+    ///
+    /// This requires support from the backend, and returns an error otherwise.
+    pub fn prepare_for_function_redefine(&mut self, func: FuncId) -> ModuleResult<()> {
+        let info = &self.contents.functions[func];
+        if !info.decl.linkage.is_definable() {
+            return Err(ModuleError::InvalidImportDefinition(info.decl.name.clone()));
+        }
+        if info.compiled.is_none() || self.functions_to_finalize.contains(&func) {
+            return Err(ModuleError::Backend(format!(
+                "function {} must be finalized before it can be redefined",
+                info.decl.name
+            )));
+        }
+        self.backend.prepare_for_function_redefine(func)?;
+        self.contents.functions[func].compiled = None;
+        self.inline_bodies.remove(&func);
+        Ok(())
+    }
+
+    /// Free the definition of the function `func`.
+    ///
+    /// The backend can then reclaim the memory used by the function, and `func` goes back to being
+    /// declared but not defined, so it can be defined again. Calling the function, or any code
+    /// referring to it, after this is undefined behavior.
+    pub fn free_function(&mut self, func: FuncId) -> ModuleResult<()> {
+        let info = &mut self.contents.functions[func];
+        let compiled = match info.compiled.take() {
+            Some(compiled) => compiled,
+            None => return Err(ModuleError::Undefined(info.decl.name.clone())),
+        };
+        self.functions_to_finalize.retain(|&f| f != func);
+        self.inline_bodies.remove(&func);
+        self.backend.free_function(func, compiled);
+        Ok(())
+    }
+
+    /// Free the definition of the data object `data`.
+    ///
+    /// The backend can then reclaim the memory used by the data object, and `data` goes back to
+    /// being declared but not defined, so it can be defined again. Accessing the data object, or
+    /// running any code referring to it, after this is undefined behavior.
+    pub fn free_data(&mut self, data: DataId) -> ModuleResult<()> {
+        let info = &mut self.contents.data_objects[data];
+        let compiled = match info.compiled.take() {
+            Some(compiled) => compiled,
+            None => return Err(ModuleError::Undefined(info.decl.name.clone())),
+        };
+        self.data_objects_to_finalize.retain(|&d| d != data);
+        self.backend.free_data(data, compiled);
+        Ok(())
     }
 
     /// Define a data object, producing the data contents from the given `DataContext`.
@@ -716,6 +1123,11 @@ where
         self.backend.isa()
     }
 
+    /// Return the backend, to access the functionality specific to it.
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
     /// Consume the module and return the resulting `Product`. Some `Backend`
     /// implementations may provide additional functionality available after
     /// a `Module` is complete.
@@ -31,6 +31,11 @@ use target_lexicon::Triple;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use raw_cpuid::CpuId;
 
+#[cfg(target_arch = "x86")]
+use core::arch::x86::_xgetbv;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::_xgetbv;
+
 /// Return an `isa` builder configured for the current host
 /// machine, or `Err(())` if the host machine is not supported
 /// in the current configuration.
@@ -47,11 +52,37 @@ pub fn builder() -> Result<isa::Builder, &'static str> {
     Ok(isa_builder)
 }
 
+/// XCR0 bits for the SSE (XMM) and AVX (upper YMM) register state.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const XCR0_AVX_STATE: u64 = 0b110;
+
+/// XCR0 bits for the AVX-512 opmask, upper ZMM0-15 and ZMM16-31 register state.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const XCR0_AVX512_STATE: u64 = 0b1110_0000;
+
+/// Read XCR0, which tells which register state the OS saves and restores on context switches.
+///
+/// This must only be called when CPUID reports OSXSAVE, otherwise `xgetbv` faults.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "xsave")]
+unsafe fn read_xcr0() -> u64 {
+    _xgetbv(0)
+}
+
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 fn parse_x86_cpuid(isa_builder: &mut isa::Builder) -> Result<(), &'static str> {
     use cranelift_codegen::settings::Configurable;
     let cpuid = CpuId::new();
 
+    // The AVX and AVX-512 instructions are only usable if the OS has enabled the wider register
+    // state too.
+    let xcr0 = match cpuid.get_feature_info() {
+        Some(ref info) if info.has_oxsave() => unsafe { read_xcr0() },
+        _ => 0,
+    };
+    let os_saves_avx = xcr0 & XCR0_AVX_STATE == XCR0_AVX_STATE;
+    let os_saves_avx512 = os_saves_avx && xcr0 & XCR0_AVX512_STATE == XCR0_AVX512_STATE;
+
     if let Some(info) = cpuid.get_feature_info() {
         if !info.has_sse2() {
             return Err("x86 support requires SSE2");
@@ -71,17 +102,36 @@ fn parse_x86_cpuid(isa_builder: &mut isa::Builder) -> Result<(), &'static str> {
         if info.has_popcnt() {
             isa_builder.enable("has_popcnt").unwrap();
         }
-        if info.has_avx() {
+        if info.has_avx() && os_saves_avx {
             isa_builder.enable("has_avx").unwrap();
         }
     }
     if let Some(info) = cpuid.get_extended_feature_info() {
+        if info.has_avx2() && os_saves_avx {
+            isa_builder.enable("has_avx2").unwrap();
+        }
         if info.has_bmi1() {
             isa_builder.enable("has_bmi1").unwrap();
         }
         if info.has_bmi2() {
             isa_builder.enable("has_bmi2").unwrap();
         }
+        if info.has_avx512f() && os_saves_avx512 {
+            isa_builder.enable("has_avx512f").unwrap();
+        }
+        if info.has_avx512dq() && os_saves_avx512 {
+            isa_builder.enable("has_avx512dq").unwrap();
+        }
+        if info.has_avx512bw() && os_saves_avx512 {
+            isa_builder.enable("has_avx512bw").unwrap();
+        }
+        if info.has_avx512vl() && os_saves_avx512 {
+            isa_builder.enable("has_avx512vl").unwrap();
+        }
+        // raw-cpuid doesn't know about AVX512_VBMI, which is bit 1 of ECX in the same leaf.
+        if os_saves_avx512 && raw_cpuid::native_cpuid::cpuid_count(7, 0).ecx & (1 << 1) != 0 {
+            isa_builder.enable("has_avx512vbmi").unwrap();
+        }
     }
     if let Some(info) = cpuid.get_extended_function_info() {
         if info.has_lzcnt() {
@@ -8,12 +8,18 @@ use cranelift_codegen::entity::SecondaryMap;
 use cranelift_codegen::isa::TargetIsa;
 use cranelift_codegen::{self, binemit, ir};
 use cranelift_module::{
-    Backend, DataContext, DataDescription, DataId, FuncId, Init, Linkage, ModuleNamespace,
-    ModuleResult,
+    Backend, DataContext, DataDescription, DataId, FuncId, Init, Linkage, ModuleError,
+    ModuleNamespace, ModuleResult, SymbolAttributes, Visibility,
 };
-use object::write::{Object, Relocation, SectionId, StandardSection, Symbol, SymbolId};
-use object::{RelocationEncoding, RelocationKind, SymbolKind, SymbolScope};
-use std::collections::HashMap;
+use object::write::{
+    Comdat, Object, Relocation, SectionId, StandardSection, StandardSegment, Symbol, SymbolId,
+    SymbolSection,
+};
+use object::{
+    elf, BinaryFormat, ComdatKind, RelocationEncoding, RelocationKind, SectionFlags, SectionKind,
+    SymbolFlags, SymbolKind, SymbolScope,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use target_lexicon::PointerWidth;
 
 #[derive(Debug)]
@@ -29,6 +35,9 @@ pub enum ObjectTrapCollection {
 /// A builder for `ObjectBackend`.
 pub struct ObjectBuilder {
     isa: Box<dyn TargetIsa>,
+    binary_format: BinaryFormat,
+    architecture: object::Architecture,
+    endian: object::Endianness,
     name: String,
     collect_traps: ObjectTrapCollection,
     libcall_names: Box<dyn Fn(ir::LibCall) -> String>,
@@ -52,8 +61,47 @@ impl ObjectBuilder {
         collect_traps: ObjectTrapCollection,
         libcall_names: Box<dyn Fn(ir::LibCall) -> String>,
     ) -> ModuleResult<Self> {
+        let triple = isa.triple();
+        let binary_format = match triple.binary_format {
+            target_lexicon::BinaryFormat::Elf => BinaryFormat::Elf,
+            target_lexicon::BinaryFormat::Coff => BinaryFormat::Coff,
+            target_lexicon::BinaryFormat::Macho => BinaryFormat::MachO,
+            _ => {
+                return Err(ModuleError::Backend(format!(
+                    "object can't emit {} files",
+                    triple.binary_format
+                )))
+            }
+        };
+        let architecture = match triple.architecture {
+            target_lexicon::Architecture::I386
+            | target_lexicon::Architecture::I586
+            | target_lexicon::Architecture::I686 => object::Architecture::I386,
+            target_lexicon::Architecture::X86_64 => object::Architecture::X86_64,
+            target_lexicon::Architecture::Arm(_) => object::Architecture::Arm,
+            target_lexicon::Architecture::Aarch64(_) => object::Architecture::Aarch64,
+            _ => {
+                return Err(ModuleError::Backend(format!(
+                    "object can't emit code for {}",
+                    triple.architecture
+                )))
+            }
+        };
+        let endian = match triple.endianness() {
+            Ok(target_lexicon::Endianness::Little) => object::Endianness::Little,
+            Ok(target_lexicon::Endianness::Big) => object::Endianness::Big,
+            Err(()) => {
+                return Err(ModuleError::Backend(format!(
+                    "unknown endianness for {}",
+                    triple
+                )))
+            }
+        };
         Ok(Self {
             isa,
+            binary_format,
+            architecture,
+            endian,
             name,
             collect_traps,
             libcall_names,
@@ -81,6 +129,9 @@ pub struct ObjectBackend {
     libcall_names: Box<dyn Fn(ir::LibCall) -> String>,
     collect_traps: ObjectTrapCollection,
     function_alignment: u64,
+    sections: HashMap<(String, Option<String>), (SectionId, SectionKind)>,
+    comdats: BTreeMap<String, Vec<SectionId>>,
+    protected: HashSet<SymbolId>,
 }
 
 impl Backend for ObjectBackend {
@@ -98,8 +149,7 @@ impl Backend for ObjectBackend {
 
     /// Create a new `ObjectBackend` using the given Cranelift target.
     fn new(builder: ObjectBuilder) -> Self {
-        let triple = builder.isa.triple();
-        let mut object = Object::new(triple.binary_format, triple.architecture);
+        let mut object = Object::new(builder.binary_format, builder.architecture, builder.endian);
         object.add_file_symbol(builder.name.as_bytes().to_vec());
         Self {
             isa: builder.isa,
@@ -111,6 +161,9 @@ impl Backend for ObjectBackend {
             libcall_names: builder.libcall_names,
             collect_traps: builder.collect_traps,
             function_alignment: builder.function_alignment,
+            sections: HashMap::new(),
+            comdats: BTreeMap::new(),
+            protected: HashSet::new(),
         }
     }
 
@@ -118,13 +171,20 @@ impl Backend for ObjectBackend {
         &*self.isa
     }
 
-    fn declare_function(&mut self, id: FuncId, name: &str, linkage: Linkage) {
-        let (scope, weak) = translate_linkage(linkage);
+    fn declare_function(
+        &mut self,
+        id: FuncId,
+        name: &str,
+        linkage: Linkage,
+        attributes: &SymbolAttributes,
+    ) {
+        let (scope, weak) = translate_linkage(linkage, attributes.visibility);
 
-        if let Some(function) = self.functions[id] {
+        let symbol_id = if let Some(function) = self.functions[id] {
             let symbol = self.object.symbol_mut(function);
             symbol.scope = scope;
             symbol.weak = weak;
+            function
         } else {
             let symbol_id = self.object.add_symbol(Symbol {
                 name: name.as_bytes().to_vec(),
@@ -133,10 +193,13 @@ impl Backend for ObjectBackend {
                 kind: SymbolKind::Text,
                 scope,
                 weak,
-                section: None,
+                section: SymbolSection::Undefined,
+                flags: SymbolFlags::None,
             });
             self.functions[id] = Some(symbol_id);
-        }
+            symbol_id
+        };
+        self.set_protected(symbol_id, linkage, attributes.visibility);
     }
 
     fn declare_data(
@@ -146,13 +209,15 @@ impl Backend for ObjectBackend {
         linkage: Linkage,
         _writable: bool,
         _align: Option<u8>,
+        attributes: &SymbolAttributes,
     ) {
-        let (scope, weak) = translate_linkage(linkage);
+        let (scope, weak) = translate_linkage(linkage, attributes.visibility);
 
-        if let Some(data) = self.data_objects[id] {
+        let symbol_id = if let Some(data) = self.data_objects[id] {
             let symbol = self.object.symbol_mut(data);
             symbol.scope = scope;
             symbol.weak = weak;
+            data
         } else {
             let symbol_id = self.object.add_symbol(Symbol {
                 name: name.as_bytes().to_vec(),
@@ -161,20 +226,26 @@ impl Backend for ObjectBackend {
                 kind: SymbolKind::Data,
                 scope,
                 weak,
-                section: None,
+                section: SymbolSection::Undefined,
+                flags: SymbolFlags::None,
             });
             self.data_objects[id] = Some(symbol_id);
-        }
+            symbol_id
+        };
+        self.set_protected(symbol_id, linkage, attributes.visibility);
     }
 
     fn define_function(
         &mut self,
         func_id: FuncId,
-        _name: &str,
+        name: &str,
         ctx: &cranelift_codegen::Context,
-        _namespace: &ModuleNamespace<Self>,
+        namespace: &ModuleNamespace<Self>,
         code_size: u32,
     ) -> ModuleResult<ObjectCompiledFunction> {
+        let attributes = &namespace.get_function_decl(&func_id.into()).attributes;
+        self.check_attributes(name, attributes)?;
+
         let mut code: Vec<u8> = vec![0; code_size as usize];
         let mut reloc_sink = ObjectRelocSink::default();
         let mut trap_sink = ObjectTrapSink::default();
@@ -204,7 +275,7 @@ impl Backend for ObjectBackend {
         }
 
         let symbol = self.functions[func_id].unwrap();
-        let section = self.object.section_id(StandardSection::Text);
+        let section = self.section_id(name, attributes, StandardSection::Text)?;
         let offset = self
             .object
             .add_symbol_data(symbol, section, &code, self.function_alignment);
@@ -220,12 +291,15 @@ impl Backend for ObjectBackend {
     fn define_data(
         &mut self,
         data_id: DataId,
-        _name: &str,
+        name: &str,
         writable: bool,
         align: Option<u8>,
         data_ctx: &DataContext,
-        _namespace: &ModuleNamespace<Self>,
+        namespace: &ModuleNamespace<Self>,
     ) -> ModuleResult<ObjectCompiledData> {
+        let attributes = &namespace.get_data_decl(&data_id.into()).attributes;
+        self.check_attributes(name, attributes)?;
+
         let &DataDescription {
             ref init,
             ref function_decls,
@@ -276,13 +350,17 @@ impl Backend for ObjectBackend {
         }
 
         let symbol = self.data_objects[data_id].unwrap();
-        let section = self.object.section_id(if writable {
-            StandardSection::Data
-        } else if relocs.is_empty() {
-            StandardSection::ReadOnlyData
-        } else {
-            StandardSection::ReadOnlyDataWithRel
-        });
+        let section = self.section_id(
+            name,
+            attributes,
+            if writable {
+                StandardSection::Data
+            } else if relocs.is_empty() {
+                StandardSection::ReadOnlyData
+            } else {
+                StandardSection::ReadOnlyDataWithRel
+            },
+        )?;
         let offset =
             self.object
                 .add_symbol_data(symbol, section, &data, u64::from(align.unwrap_or(1)));
@@ -390,7 +468,9 @@ impl Backend for ObjectBackend {
         // Nothing to do.
     }
 
-    fn finish(self) -> ObjectProduct {
+    fn finish(mut self) -> ObjectProduct {
+        self.add_comdats();
+        self.set_protected_flags();
         ObjectProduct {
             object: self.object,
             functions: self.functions,
@@ -401,6 +481,156 @@ impl Backend for ObjectBackend {
 }
 
 impl ObjectBackend {
+    /// Check that the object file can express the attributes of the definition of `name`.
+    fn check_attributes(&self, name: &str, attributes: &SymbolAttributes) -> ModuleResult<()> {
+        // COFF needs the key symbol of a COMDAT to follow the section symbol of its section,
+        // which can't be arranged when symbols are added as they're declared.
+        if let Some(ref comdat) = attributes.comdat {
+            if self.object.format() != BinaryFormat::Elf {
+                return Err(ModuleError::Backend(format!(
+                    "object can't emit COMDAT group {} for {} in {:?} files",
+                    comdat,
+                    name,
+                    self.object.format()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the section to place the definition of `name` with the given attributes in,
+    /// which is `standard` unless the attributes name another section or a COMDAT group.
+    /// Custom sections get the segment and kind of `standard` from their first definition,
+    /// and it's an error to place a definition of another kind in them later. A COMDAT group
+    /// gets its own copy of each section holding its members.
+    fn section_id(
+        &mut self,
+        name: &str,
+        attributes: &SymbolAttributes,
+        standard: StandardSection,
+    ) -> ModuleResult<SectionId> {
+        let section_name = match (&attributes.section, &attributes.comdat) {
+            (None, None) => return Ok(self.object.section_id(standard)),
+            (Some(name), _) => name.clone(),
+            // Name the group's copy of a standard section the way C compilers do. Only ELF
+            // has COMDAT groups, see `check_attributes`.
+            (None, Some(comdat)) => match standard {
+                StandardSection::Text => format!(".text.{}", comdat),
+                StandardSection::ReadOnlyData => format!(".rodata.{}", comdat),
+                StandardSection::ReadOnlyDataWithRel => format!(".data.rel.ro.{}", comdat),
+                _ => format!(".data.{}", comdat),
+            },
+        };
+        let (segment, kind) = match standard {
+            StandardSection::Text => (StandardSegment::Text, SectionKind::Text),
+            StandardSection::ReadOnlyData => (StandardSegment::Text, SectionKind::ReadOnlyData),
+            _ => (StandardSegment::Data, SectionKind::Data),
+        };
+        let key = (section_name, attributes.comdat.clone());
+        if let Some(&(section, section_kind)) = self.sections.get(&key) {
+            if section_kind != kind {
+                return Err(ModuleError::Backend(format!(
+                    "{} can't be placed in section {}, which holds {:?} rather than {:?}",
+                    name, key.0, section_kind, kind
+                )));
+            }
+            return Ok(section);
+        }
+        let segment = self.object.segment_name(segment).to_vec();
+        let section = self
+            .object
+            .add_section(segment, key.0.as_bytes().to_vec(), kind);
+        if let Some(ref comdat) = key.1 {
+            // `object` doesn't mark the members of a section group itself.
+            let sh_flags = match kind {
+                SectionKind::Text => elf::SHF_ALLOC | elf::SHF_EXECINSTR,
+                SectionKind::ReadOnlyData => elf::SHF_ALLOC,
+                _ => elf::SHF_ALLOC | elf::SHF_WRITE,
+            };
+            self.object.section_mut(section).flags = SectionFlags::Elf {
+                sh_flags: u64::from(sh_flags | elf::SHF_GROUP),
+            };
+            self.comdats
+                .entry(comdat.clone())
+                .or_default()
+                .push(section);
+        }
+        self.sections.insert(key, (section, kind));
+        Ok(section)
+    }
+
+    /// Add the COMDAT groups collected while defining functions and data objects.
+    ///
+    /// A group is keyed by the symbol named after it if the group defines one, like the
+    /// groups C++ compilers emit for inline functions. Otherwise a local symbol with the name
+    /// of the group is added at the start of its first section.
+    fn add_comdats(&mut self) {
+        for (name, sections) in &self.comdats {
+            let symbol = match self.object.symbol_id(name.as_bytes()) {
+                Some(symbol)
+                    if match self.object.symbol(symbol).section {
+                        SymbolSection::Section(section) => sections.contains(&section),
+                        _ => false,
+                    } =>
+                {
+                    symbol
+                }
+                _ => self.object.add_symbol(Symbol {
+                    name: name.as_bytes().to_vec(),
+                    value: 0,
+                    size: 0,
+                    kind: SymbolKind::Label,
+                    scope: SymbolScope::Compilation,
+                    weak: false,
+                    section: SymbolSection::Section(sections[0]),
+                    flags: SymbolFlags::None,
+                }),
+            };
+            self.object.add_comdat(Comdat {
+                kind: ComdatKind::Any,
+                symbol,
+                sections: sections.clone(),
+            });
+        }
+    }
+
+    /// Record whether the symbol of a declaration gets protected visibility. Only ELF has
+    /// protected visibility, elsewhere it's the same as the default one.
+    fn set_protected(&mut self, symbol: SymbolId, linkage: Linkage, visibility: Visibility) {
+        let protected = self.object.format() == BinaryFormat::Elf
+            && visibility == Visibility::Protected
+            && (linkage == Linkage::Export || linkage == Linkage::Preemptible);
+        if protected {
+            self.protected.insert(symbol);
+        } else {
+            self.protected.remove(&symbol);
+        }
+    }
+
+    /// Give the defined protected symbols their visibility. `object` only derives default and
+    /// hidden visibility from the symbol scope, so the whole ELF symbol info is spelled out.
+    fn set_protected_flags(&mut self) {
+        for &symbol in &self.protected {
+            let symbol = self.object.symbol_mut(symbol);
+            if symbol.is_undefined() {
+                continue;
+            }
+            let st_bind = if symbol.weak {
+                elf::STB_WEAK
+            } else {
+                elf::STB_GLOBAL
+            };
+            let st_type = match symbol.kind {
+                SymbolKind::Text => elf::STT_FUNC,
+                _ => elf::STT_OBJECT,
+            };
+            symbol.flags = SymbolFlags::Elf {
+                st_info: (st_bind << 4) + st_type,
+                st_other: elf::STV_PROTECTED,
+            };
+        }
+    }
+
     // This should only be called during finalization because it creates
     // symbols for missing libcalls.
     fn get_symbol(
@@ -432,7 +662,8 @@ impl ObjectBackend {
                         kind: SymbolKind::Text,
                         scope: SymbolScope::Unknown,
                         weak: false,
-                        section: None,
+                        section: SymbolSection::Undefined,
+                        flags: SymbolFlags::None,
                     });
                     self.libcalls.insert(*libcall, symbol);
                     symbol
@@ -443,11 +674,13 @@ impl ObjectBackend {
     }
 }
 
-fn translate_linkage(linkage: Linkage) -> (SymbolScope, bool) {
-    let scope = match linkage {
-        Linkage::Import => SymbolScope::Unknown,
-        Linkage::Local => SymbolScope::Compilation,
-        Linkage::Export | Linkage::Preemptible => SymbolScope::Dynamic,
+fn translate_linkage(linkage: Linkage, visibility: Visibility) -> (SymbolScope, bool) {
+    let scope = match (linkage, visibility) {
+        (Linkage::Local, _) => SymbolScope::Compilation,
+        // Hidden symbols are still visible to the other objects linked with this one.
+        (_, Visibility::Hidden) => SymbolScope::Linkage,
+        (Linkage::Import, _) => SymbolScope::Unknown,
+        (Linkage::Export, _) | (Linkage::Preemptible, _) => SymbolScope::Dynamic,
     };
     // TODO: this matches rustc_codegen_cranelift, but may be wrong.
     let weak = linkage == Linkage::Preemptible;
@@ -475,6 +708,8 @@ pub struct ObjectCompiledData {
 /// compilation.
 pub struct ObjectProduct {
     /// Object artifact with all functions and data from the module defined.
+    ///
+    /// This is an `object::write::Object` from version 0.22 of the `object` crate.
     pub object: Object,
     /// Symbol IDs for functions (both declared and defined).
     pub functions: SecondaryMap<FuncId, Option<SymbolId>>,
@@ -500,7 +735,7 @@ impl ObjectProduct {
     /// Write the object bytes in memory.
     #[inline]
     pub fn emit(self) -> Result<Vec<u8>, String> {
-        self.object.write()
+        self.object.write().map_err(|err| err.to_string())
     }
 }
 
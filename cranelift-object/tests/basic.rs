@@ -0,0 +1,413 @@
+use cranelift_codegen::ir::*;
+use cranelift_codegen::{isa, settings, Context};
+use cranelift_frontend::*;
+use cranelift_module::*;
+use cranelift_object::*;
+use object::{elf, Object, ObjectComdat, ObjectSection, ObjectSymbol, SectionFlags, SymbolFlags};
+use std::str::FromStr;
+use target_lexicon::Triple;
+
+fn new_module(triple: &str) -> Module<ObjectBackend> {
+    let isa = isa::lookup(Triple::from_str(triple).unwrap())
+        .unwrap()
+        .finish(settings::Flags::new(settings::builder()));
+    let builder = ObjectBuilder::new(
+        isa,
+        "test".to_owned(),
+        ObjectTrapCollection::Disabled,
+        default_libcall_names(),
+    )
+    .unwrap();
+    Module::new(builder)
+}
+
+fn define_function(
+    module: &mut Module<ObjectBackend>,
+    name: &str,
+    linkage: Linkage,
+    attributes: &SymbolAttributes,
+) -> ModuleResult<FuncId> {
+    let sig = module.make_signature();
+    let func_id = module.declare_function_with_attributes(name, linkage, &sig, attributes)?;
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let ebb = bcx.create_ebb();
+        bcx.switch_to_block(ebb);
+        bcx.ins().return_(&[]);
+    }
+
+    module.define_function(func_id, &mut ctx)?;
+    Ok(func_id)
+}
+
+fn define_data(
+    module: &mut Module<ObjectBackend>,
+    name: &str,
+    attributes: &SymbolAttributes,
+) -> ModuleResult<DataId> {
+    let data_id =
+        module.declare_data_with_attributes(name, Linkage::Preemptible, true, None, attributes)?;
+    let mut data_ctx = DataContext::new();
+    data_ctx.define(vec![1, 2, 3, 4].into_boxed_slice());
+    module.define_data(data_id, &data_ctx)?;
+    Ok(data_id)
+}
+
+fn attributes(visibility: Visibility, comdat: Option<&str>) -> SymbolAttributes {
+    SymbolAttributes {
+        visibility,
+        section: None,
+        comdat: comdat.map(str::to_owned),
+    }
+}
+
+/// Return the names of the sections of all COMDAT groups in `file`, by group name.
+fn comdats(file: &object::File) -> Vec<(String, Vec<String>)> {
+    file.comdats()
+        .map(|comdat| {
+            assert_eq!(comdat.kind(), object::ComdatKind::Any);
+            let sections = comdat
+                .sections()
+                .map(|index| {
+                    let section = file.section_by_index(index).unwrap();
+                    section.name().unwrap().to_owned()
+                })
+                .collect();
+            (comdat.name().unwrap().to_owned(), sections)
+        })
+        .collect()
+}
+
+fn symbol<'data, 'file>(
+    file: &'file object::File<'data>,
+    name: &str,
+) -> object::read::Symbol<'data, 'file> {
+    file.symbols()
+        .find(|symbol| symbol.name() == Ok(name))
+        .unwrap_or_else(|| panic!("no symbol {}", name))
+}
+
+fn st_other(file: &object::File, name: &str) -> u8 {
+    match symbol(file, name).flags() {
+        SymbolFlags::Elf { st_other, .. } => st_other,
+        flags => panic!("unexpected flags {:?} for {}", flags, name),
+    }
+}
+
+#[test]
+fn elf_comdat_group() {
+    let mut module = new_module("x86_64-unknown-linux-gnu");
+    let in_group = attributes(Visibility::Default, Some("inline_fn"));
+    define_function(&mut module, "inline_fn", Linkage::Preemptible, &in_group).unwrap();
+    define_data(&mut module, "inline_fn_guard", &in_group).unwrap();
+    define_function(
+        &mut module,
+        "plain",
+        Linkage::Export,
+        &SymbolAttributes::default(),
+    )
+    .unwrap();
+    define_function(
+        &mut module,
+        "keyless",
+        Linkage::Export,
+        &attributes(Visibility::Default, Some("group")),
+    )
+    .unwrap();
+
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&bytes).unwrap();
+    assert_eq!(
+        comdats(&file),
+        vec![
+            ("group".to_owned(), vec![".text.group".to_owned()]),
+            (
+                "inline_fn".to_owned(),
+                vec![".text.inline_fn".to_owned(), ".data.inline_fn".to_owned()]
+            ),
+        ]
+    );
+    for name in &[".text.group", ".text.inline_fn", ".data.inline_fn"] {
+        let section = file.section_by_name(name).unwrap();
+        match section.flags() {
+            SectionFlags::Elf { sh_flags } => {
+                assert_ne!(sh_flags & u64::from(elf::SHF_GROUP), 0, "{}", name)
+            }
+            flags => panic!("unexpected flags {:?} for {}", flags, name),
+        }
+    }
+    assert_eq!(
+        file.section_by_name(".text").unwrap().flags(),
+        SectionFlags::Elf {
+            sh_flags: u64::from(elf::SHF_ALLOC | elf::SHF_EXECINSTR)
+        }
+    );
+
+    // A group is keyed by the symbol named after it, or by a new local symbol.
+    let inline_fn = symbol(&file, "inline_fn");
+    assert!(inline_fn.is_weak());
+    assert_eq!(
+        inline_fn.section_index(),
+        Some(file.section_by_name(".text.inline_fn").unwrap().index())
+    );
+    assert!(symbol(&file, "group").is_local());
+}
+
+#[test]
+fn custom_section_kinds() {
+    let mut module = new_module("x86_64-unknown-linux-gnu");
+    let in_section = SymbolAttributes {
+        section: Some(".custom".to_owned()),
+        ..SymbolAttributes::default()
+    };
+    define_function(&mut module, "first", Linkage::Export, &in_section).unwrap();
+    define_function(&mut module, "second", Linkage::Export, &in_section).unwrap();
+    match define_data(&mut module, "data", &in_section) {
+        Err(ModuleError::Backend(message)) => assert!(message.contains(".custom")),
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&bytes).unwrap();
+    let section = file.section_by_name(".custom").unwrap();
+    assert_eq!(section.kind(), object::SectionKind::Text);
+    assert_eq!(
+        symbol(&file, "second").section_index(),
+        Some(section.index())
+    );
+}
+
+#[test]
+fn elf_symbol_visibility() {
+    let mut module = new_module("x86_64-unknown-linux-gnu");
+    define_function(
+        &mut module,
+        "default",
+        Linkage::Export,
+        &SymbolAttributes::default(),
+    )
+    .unwrap();
+    define_function(
+        &mut module,
+        "hidden",
+        Linkage::Export,
+        &attributes(Visibility::Hidden, None),
+    )
+    .unwrap();
+    define_function(
+        &mut module,
+        "protected",
+        Linkage::Export,
+        &attributes(Visibility::Protected, None),
+    )
+    .unwrap();
+    define_function(
+        &mut module,
+        "protected_weak",
+        Linkage::Preemptible,
+        &attributes(Visibility::Protected, None),
+    )
+    .unwrap();
+    define_data(
+        &mut module,
+        "protected_data",
+        &attributes(Visibility::Protected, None),
+    )
+    .unwrap();
+
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&bytes).unwrap();
+    assert_eq!(st_other(&file, "default"), elf::STV_DEFAULT);
+    assert_eq!(st_other(&file, "hidden"), elf::STV_HIDDEN);
+    assert_eq!(st_other(&file, "protected"), elf::STV_PROTECTED);
+    assert_eq!(st_other(&file, "protected_weak"), elf::STV_PROTECTED);
+    assert_eq!(st_other(&file, "protected_data"), elf::STV_PROTECTED);
+    assert!(!symbol(&file, "protected").is_weak());
+    assert!(symbol(&file, "protected_weak").is_weak());
+    assert_eq!(symbol(&file, "protected").kind(), object::SymbolKind::Text);
+    assert_eq!(
+        symbol(&file, "protected_data").kind(),
+        object::SymbolKind::Data
+    );
+}
+
+#[test]
+fn coff_symbol_visibility() {
+    let mut module = new_module("x86_64-pc-windows-msvc");
+    define_function(
+        &mut module,
+        "hidden",
+        Linkage::Export,
+        &attributes(Visibility::Hidden, None),
+    )
+    .unwrap();
+    define_function(
+        &mut module,
+        "protected",
+        Linkage::Export,
+        &attributes(Visibility::Protected, None),
+    )
+    .unwrap();
+
+    // COMDAT groups are only supported for ELF.
+    define_function(
+        &mut module,
+        "inline_fn",
+        Linkage::Preemptible,
+        &attributes(Visibility::Default, Some("inline_fn")),
+    )
+    .err()
+    .unwrap();
+
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&bytes).unwrap();
+    assert_eq!(file.comdats().count(), 0);
+    // COFF has no symbol visibility, both are plain external symbols.
+    for name in &["hidden", "protected"] {
+        let symbol = symbol(&file, name);
+        assert!(symbol.is_global() && !symbol.is_undefined(), "{}", name);
+    }
+}
+
+#[test]
+fn macho_symbol_visibility() {
+    let mut module = new_module("x86_64-apple-darwin");
+    define_function(
+        &mut module,
+        "default",
+        Linkage::Export,
+        &SymbolAttributes::default(),
+    )
+    .unwrap();
+    define_function(
+        &mut module,
+        "hidden",
+        Linkage::Export,
+        &attributes(Visibility::Hidden, None),
+    )
+    .unwrap();
+    define_function(
+        &mut module,
+        "protected",
+        Linkage::Export,
+        &attributes(Visibility::Protected, None),
+    )
+    .unwrap();
+
+    // Mach-O has no COMDAT groups.
+    define_function(
+        &mut module,
+        "inline_fn",
+        Linkage::Preemptible,
+        &attributes(Visibility::Default, Some("inline_fn")),
+    )
+    .err()
+    .unwrap();
+
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&bytes).unwrap();
+    assert_eq!(file.comdats().count(), 0);
+    assert_eq!(
+        symbol(&file, "_default").scope(),
+        object::SymbolScope::Dynamic
+    );
+    assert_eq!(
+        symbol(&file, "_hidden").scope(),
+        object::SymbolScope::Linkage
+    );
+    assert_eq!(
+        symbol(&file, "_protected").scope(),
+        object::SymbolScope::Dynamic
+    );
+}
+
+/// Build a module of functions calling each other, an imported function and referencing a data
+/// object, defined one at a time or in parallel, and emit it.
+fn emit_calls(parallel: bool) -> Vec<u8> {
+    let mut module = new_module("x86_64-unknown-linux-gnu");
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    let ext_id = module
+        .declare_function("ext", Linkage::Import, &sig)
+        .unwrap();
+    let data_id = module
+        .declare_data("counter", Linkage::Export, true, None)
+        .unwrap();
+    let func_ids: Vec<_> = (0..8)
+        .map(|i| {
+            module
+                .declare_function(&format!("f{}", i), Linkage::Export, &sig)
+                .unwrap()
+        })
+        .collect();
+
+    // The data object holds the address of the last function.
+    let mut data_ctx = DataContext::new();
+    data_ctx.define_zeroinit(8);
+    let last = module.declare_func_in_data(func_ids[7], &mut data_ctx);
+    data_ctx.write_function_addr(0, last);
+    module.define_data(data_id, &data_ctx).unwrap();
+
+    // Each function adds the address of the data object to its argument, and passes it on to the
+    // previous function, or to the imported one.
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut funcs: Vec<_> = func_ids
+        .iter()
+        .enumerate()
+        .map(|(i, &func_id)| {
+            let mut ctx = Context::new();
+            ctx.func =
+                Function::with_name_signature(ExternalName::user(0, func_id.as_u32()), sig.clone());
+            {
+                let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+                let callee_id = if i == 0 { ext_id } else { func_ids[i - 1] };
+                let callee = module.declare_func_in_func(callee_id, bcx.func);
+                let data = module.declare_data_in_func(data_id, bcx.func);
+                let ebb = bcx.create_ebb();
+                bcx.append_ebb_params_for_function_params(ebb);
+                bcx.switch_to_block(ebb);
+                let x = bcx.ebb_params(ebb)[0];
+                let addr = bcx.ins().symbol_value(types::I64, data);
+                let v = bcx.ins().iadd(x, addr);
+                let call = bcx.ins().call(callee, &[v]);
+                let v = bcx.inst_results(call)[0];
+                bcx.ins().return_(&[v]);
+                bcx.seal_all_blocks();
+                bcx.finalize();
+            }
+            (func_id, ctx)
+        })
+        .collect();
+
+    if parallel {
+        module.define_functions(&mut funcs, 4).unwrap();
+    } else {
+        for &mut (func_id, ref mut ctx) in &mut funcs {
+            module.define_function(func_id, ctx).unwrap();
+        }
+    }
+    module.finalize_definitions();
+    module.finish().emit().unwrap()
+}
+
+#[test]
+fn parallel_compilation_is_deterministic() {
+    let serial = emit_calls(false);
+    let parallel = emit_calls(true);
+    assert!(
+        serial == parallel,
+        "parallel compilation changed the object"
+    );
+
+    // Both the code and the data have relocations, which must come out the same too.
+    let file = object::File::parse(&serial).unwrap();
+    let text = file.section_by_name(".text").unwrap();
+    assert_eq!(text.relocations().count(), 16);
+    let data = file.section_by_name(".data").unwrap();
+    assert_eq!(data.relocations().count(), 1);
+}
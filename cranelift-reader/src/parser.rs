@@ -14,10 +14,10 @@ use cranelift_codegen::ir::instructions::{InstructionData, InstructionFormat, Va
 use cranelift_codegen::ir::types::INVALID;
 use cranelift_codegen::ir::types::*;
 use cranelift_codegen::ir::{
-    AbiParam, ArgumentExtension, ArgumentLoc, ConstantData, Ebb, ExtFuncData, ExternalName,
-    FuncRef, Function, GlobalValue, GlobalValueData, Heap, HeapData, HeapStyle, JumpTable,
-    JumpTableData, MemFlags, Opcode, SigRef, Signature, StackSlot, StackSlotData, StackSlotKind,
-    Table, TableData, Type, Value, ValueLoc,
+    AbiParam, ArgumentExtension, ArgumentLoc, ArgumentPurpose, ConstantData, Ebb, ExtFuncData,
+    ExternalName, FuncRef, Function, GlobalValue, GlobalValueData, Heap, HeapData, HeapStyle,
+    JumpTable, JumpTableData, MemFlags, Opcode, SigRef, Signature, StackSlot, StackSlotData,
+    StackSlotKind, Table, TableData, Type, Value, ValueLoc,
 };
 use cranelift_codegen::isa::{self, CallConv, Encoding, RegUnit, TargetIsa};
 use cranelift_codegen::packed_option::ReservedValue;
@@ -1225,6 +1225,25 @@ impl<'a> Parser<'a> {
             match s {
                 "uext" => arg.extension = ArgumentExtension::Uext,
                 "sext" => arg.extension = ArgumentExtension::Sext,
+                "sarg" => {
+                    // sarg ::= "sarg" * "(" uimm32 "," uimm32 [ "," classes ] ")"
+                    self.consume();
+                    self.match_token(Token::LPar, "expected '(' after sarg")?;
+                    let size = self.match_uimm32("expected struct size in bytes")?;
+                    self.match_token(Token::Comma, "expected ',' after struct size")?;
+                    let align = self.match_uimm32("expected struct alignment in bytes")?;
+                    let mut floats = 0;
+                    if self.optional(Token::Comma) {
+                        floats = self.match_struct_classes()?;
+                    }
+                    self.match_token(Token::RPar, "expected ')' after struct alignment")?;
+                    arg.purpose = ArgumentPurpose::StructArgument {
+                        size: size.into(),
+                        align: align.into(),
+                        floats,
+                    };
+                    continue;
+                }
                 _ => {
                     if let Ok(purpose) = s.parse() {
                         arg.purpose = purpose;
@@ -1242,6 +1261,22 @@ impl<'a> Parser<'a> {
         Ok(arg)
     }
 
+    // Parse the classes of the eightbytes of a struct argument, one `i` or `f` each, into a mask
+    // of the float ones.
+    fn match_struct_classes(&mut self) -> ParseResult<u8> {
+        // classes ::= * { "i" | "f" }
+        if let Some(Token::Identifier(classes)) = self.token() {
+            if classes.len() <= 8 && classes.bytes().all(|c| c == b'i' || c == b'f') {
+                self.consume();
+                return Ok(classes
+                    .bytes()
+                    .rev()
+                    .fold(0, |floats, c| floats << 1 | u8::from(c == b'f')));
+            }
+        }
+        err!(self.loc, "expected struct eightbyte classes like 'if'")
+    }
+
     // Parse an argument location specifier; either a register or a byte offset into the stack.
     fn parse_argument_location(
         &mut self,
@@ -1753,7 +1788,7 @@ impl<'a> Parser<'a> {
     // Parse an extended basic block, add contents to `ctx`.
     //
     // extended-basic-block ::= * ebb-header { instruction }
-    // ebb-header           ::= Ebb(ebb) [ebb-params] ":"
+    // ebb-header           ::= Ebb(ebb) ["cold"] [ebb-params] ":"
     //
     fn parse_extended_basic_block(&mut self, ctx: &mut Context) -> ParseResult<()> {
         // Collect comments for the next ebb.
@@ -1762,8 +1797,13 @@ impl<'a> Parser<'a> {
         let ebb_num = self.match_ebb("expected EBB header")?;
         let ebb = ctx.add_ebb(ebb_num, self.loc)?;
 
+        // ebb-header ::= Ebb(ebb) [ * "cold" ] [ebb-params] ":"
+        if self.optional(Token::Identifier("cold")) {
+            ctx.function.layout.set_cold(ebb);
+        }
+
         if !self.optional(Token::Colon) {
-            // ebb-header ::= Ebb(ebb) [ * ebb-params ] ":"
+            // ebb-header ::= Ebb(ebb) ["cold"] [ * ebb-params ] ":"
             self.parse_ebb_params(ctx, ebb)?;
             self.match_token(Token::Colon, "expected ':' after EBB parameters")?;
         }
@@ -2691,6 +2731,21 @@ impl<'a> Parser<'a> {
                     dst,
                 }
             }
+            InstructionFormat::RegSwap => {
+                let x = self.match_value("expected SSA value operand")?;
+                self.match_token(Token::Comma, "expected ',' between operands")?;
+                let y = self.match_value("expected SSA value operand")?;
+                self.match_token(Token::Comma, "expected ',' between operands")?;
+                let src = self.match_regunit(ctx.unique_isa)?;
+                self.match_token(Token::Arrow, "expected '->' between register units")?;
+                let dst = self.match_regunit(ctx.unique_isa)?;
+                InstructionData::RegSwap {
+                    opcode,
+                    args: [x, y],
+                    src,
+                    dst,
+                }
+            }
             InstructionFormat::CopySpecial => {
                 let src = self.match_regunit(ctx.unique_isa)?;
                 self.match_token(Token::Arrow, "expected '->' between register units")?;
@@ -2779,8 +2834,8 @@ mod tests {
     use crate::testfile::{Comment, Details};
     use cranelift_codegen::ir::entities::AnyEntity;
     use cranelift_codegen::ir::types;
+    use cranelift_codegen::ir::ArgumentExtension;
     use cranelift_codegen::ir::StackSlotKind;
-    use cranelift_codegen::ir::{ArgumentExtension, ArgumentPurpose};
     use cranelift_codegen::isa::CallConv;
 
     #[test]
@@ -2800,6 +2855,38 @@ mod tests {
         assert!(!is_warning);
     }
 
+    #[test]
+    fn struct_argument() {
+        let mut p = Parser::new("i64 sarg(24, 8) f64 sarg(12, 4, ff) i64 sarg(3)");
+        let arg = p.parse_abi_param(None).unwrap();
+        assert_eq!(arg.value_type, types::I64);
+        assert_eq!(
+            arg.purpose,
+            ArgumentPurpose::StructArgument {
+                size: 24,
+                align: 8,
+                floats: 0,
+            }
+        );
+        assert_eq!(arg.to_string(), "i64 sarg(24, 8)");
+        let arg = p.parse_abi_param(None).unwrap();
+        assert_eq!(
+            arg.purpose,
+            ArgumentPurpose::StructArgument {
+                size: 12,
+                align: 4,
+                floats: 0b11,
+            }
+        );
+        assert_eq!(arg.to_string(), "f64 sarg(12, 4, ff)");
+        let ParseError { message, .. } = p.parse_abi_param(None).unwrap_err();
+        assert_eq!(message, "expected ',' after struct size");
+        let ParseError { message, .. } = Parser::new("i64 sarg(8, 8, x)")
+            .parse_abi_param(None)
+            .unwrap_err();
+        assert_eq!(message, "expected struct eightbyte classes like 'if'");
+    }
+
     #[test]
     fn aliases() {
         let (func, details) = Parser::new(
@@ -2941,6 +3028,31 @@ mod tests {
         assert_eq!(func.dfg.value_type(ebb4_args[0]), types::I32);
     }
 
+    #[test]
+    fn cold_ebb_header() {
+        let (func, _) = Parser::new(
+            "function %ebbs() system_v {
+                                     ebb0:
+                                     ebb1 cold:
+                                     ebb2 cold(v3: i32):
+                                     }",
+        )
+        .parse_function(None)
+        .unwrap();
+
+        let mut ebbs = func.layout.ebbs();
+
+        let ebb0 = ebbs.next().unwrap();
+        assert!(!func.layout.is_cold(ebb0));
+
+        let ebb1 = ebbs.next().unwrap();
+        assert!(func.layout.is_cold(ebb1));
+
+        let ebb2 = ebbs.next().unwrap();
+        assert!(func.layout.is_cold(ebb2));
+        assert_eq!(func.dfg.ebb_params(ebb2).len(), 1);
+    }
+
     #[test]
     fn duplicate_ebb() {
         let ParseError {
@@ -24,36 +24,31 @@
 
 use clap::{App, Arg, SubCommand};
 use cranelift_reader::parse_functions;
+use cranelift_serde::{from_json, to_json};
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{self, Write};
 use std::process;
 
-mod serde_clif_json;
-
 fn call_ser(file: &str, pretty: bool) -> Result<(), String> {
     let ret_of_parse = parse_functions(file);
     match ret_of_parse {
         Ok(funcs) => {
-            let ser_funcs = serde_clif_json::SerObj::new(&funcs);
-            let ser_str = if pretty {
-                serde_json::to_string_pretty(&ser_funcs).unwrap()
-            } else {
-                serde_json::to_string(&ser_funcs).unwrap()
-            };
-            println!("{}", ser_str);
+            println!("{}", to_json(&funcs, None, pretty));
             Ok(())
         }
         Err(_pe) => Err("There was a parsing error".to_string()),
     }
 }
 
-fn call_de(file: &File) -> Result<(), String> {
-    let de: serde_clif_json::SerObj = match serde_json::from_reader(file) {
-        Result::Ok(val) => val,
-        Result::Err(err) => panic!("{}", err),
-    };
-    println!("{:?}", de);
+fn call_de(file: &str) -> Result<(), String> {
+    let funcs = from_json(file)?;
+    for (idx, func) in funcs.into_iter().enumerate() {
+        if idx != 0 {
+            println!();
+        }
+        print!("{}", func);
+    }
     Ok(())
 }
 
@@ -73,7 +68,7 @@ fn main() {
         )
         .subcommand(
             SubCommand::with_name("deserialize")
-                .about("Deserializes Cranelift IR into JSON.")
+                .about("Deserializes JSON into Cranelift IR.")
                 .arg(
                     Arg::with_name("FILE")
                         .required(true)
@@ -97,8 +92,12 @@ fn main() {
             }
         }
         ("deserialize", Some(m)) => {
-            let file = File::open(m.value_of("FILE").unwrap()).expect("Unable to open the file");
-            call_de(&file)
+            let mut file =
+                File::open(m.value_of("FILE").unwrap()).expect("Unable to open the file");
+            let mut contents = String::new();
+            file.read_to_string(&mut contents)
+                .expect("Unable to read the file");
+            call_de(&contents)
         }
         _ => Err("Invalid subcommand.".to_string()),
     };
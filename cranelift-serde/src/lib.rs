@@ -0,0 +1,184 @@
+//! Serialization of Cranelift IR into JSON, and deserialization back into Cranelift IR.
+
+#![deny(trivial_numeric_casts, unused_extern_crates, unstable_features)]
+#![warn(unused_import_braces)]
+#![cfg_attr(feature = "clippy", plugin(clippy(conf_file = "../../clippy.toml")))]
+#![cfg_attr(feature = "cargo-clippy", allow(clippy::new_without_default))]
+#![cfg_attr(
+    feature = "cargo-clippy",
+    warn(
+        clippy::float_arithmetic,
+        clippy::mut_mut,
+        clippy::nonminimal_bool,
+        clippy::option_map_unwrap_or,
+        clippy::option_map_unwrap_or_else,
+        clippy::unicode_not_nfc,
+        clippy::use_self
+    )
+)]
+
+use cranelift_codegen::ir::Function;
+use cranelift_codegen::isa::TargetIsa;
+
+pub mod serde_clif_json;
+
+/// Serialize `funcs` into JSON, optionally pretty-printed.
+///
+/// If `isa` is given, the instruction encodings and value locations for that ISA are serialized
+/// too.
+pub fn to_json(funcs: &[Function], isa: Option<&dyn TargetIsa>, pretty: bool) -> String {
+    let ser_funcs = serde_clif_json::SerObj::new(funcs, isa);
+    if pretty {
+        serde_json::to_string_pretty(&ser_funcs).unwrap()
+    } else {
+        serde_json::to_string(&ser_funcs).unwrap()
+    }
+}
+
+/// Deserialize the functions in the JSON `text` into Cranelift IR.
+pub fn from_json(text: &str) -> Result<Vec<Function>, String> {
+    let de: serde_clif_json::SerObj = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    de.to_functions()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cranelift_reader::parse_functions;
+
+    #[test]
+    fn roundtrip() {
+        let text = "function %sample(i64 vmctx, i32) -> i32 system_v {
+    ss0 = explicit_slot 8
+    gv0 = vmctx
+    gv1 = load.i64 notrap aligned readonly gv0+8
+    heap0 = static gv1, min 4096, bound 0x0001_0000_0000, offset_guard 0x8000_0000
+    table0 = dynamic gv1, min 4, bound gv1, element_size 8, index_type i32
+    sig0 = (i32) -> i32 fast
+    fn0 = colocated %callee sig0
+    jt0 = jump_table [ebb1, ebb2]
+
+ebb0(v0: i64, v1: i32):
+    v2 = heap_addr.i64 heap0, v1, 4
+    v3 = table_addr.i64 table0, v1, +8
+    v4 = vconst.i8x16 0x00010203
+    stack_store v1, ss0+4
+    v5 = call fn0(v1)
+    br_table v5, ebb2, jt0
+
+ebb1 cold:
+    v6 = iconst.i32 7
+    v8 -> v6
+@0010 return v8
+
+ebb2:
+    v7 = load.i32 notrap v3
+    return v7
+}
+";
+        let funcs = parse_functions(text).unwrap();
+        let json = to_json(&funcs, None, false);
+        let copies = from_json(&json).unwrap();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(copies[0].to_string(), funcs[0].to_string());
+        assert_eq!(to_json(&copies, None, false), json);
+    }
+
+    #[test]
+    fn roundtrip_compiled() {
+        use cranelift_codegen::{isa, settings, Context};
+
+        let text = "function %sample(i64, i64) -> i64 system_v {
+    ss0 = explicit_slot 8
+    sig0 = (i64) -> i64 system_v
+    fn0 = %callee sig0
+
+ebb0(v0: i64, v1: i64):
+    v2 = iadd v0, v1
+    stack_store v2, ss0
+    v3 = icmp ult v0, v1
+    brz v3, ebb1(v0)
+    jump ebb2
+
+ebb2:
+    v4 = call fn0(v1)
+    v5 = stack_load.i64 ss0
+    v6 = iadd v4, v5
+    jump ebb1(v6)
+
+ebb1(v7: i64):
+    return v7
+}
+";
+        let isa = isa::lookup_by_name("x86_64")
+            .expect("This test requires x86_64 support.")
+            .finish(settings::Flags::new(settings::builder()));
+        let mut ctx = Context::for_function(parse_functions(text).unwrap().remove(0));
+        ctx.compile(&*isa).unwrap();
+        // Code offsets are computed during binary emission and aren't part of the IR.
+        ctx.func.offsets.clear();
+
+        let json = to_json(&[ctx.func.clone()], Some(&*isa), false);
+        let copies = from_json(&json).unwrap();
+        assert_eq!(copies.len(), 1);
+        let copy = &copies[0];
+        assert_eq!(
+            copy.display(&*isa).to_string(),
+            ctx.func.display(&*isa).to_string()
+        );
+        // Instructions are renumbered when parsing, but the layout is the same.
+        for ebb in ctx.func.layout.ebbs() {
+            let insts = ctx.func.layout.ebb_insts(ebb);
+            for (inst, copy_inst) in insts.zip(copy.layout.ebb_insts(ebb)) {
+                assert_eq!(copy.encodings[copy_inst], ctx.func.encodings[inst]);
+                let results = ctx.func.dfg.inst_results(inst);
+                for (&r, &copy_r) in results.iter().zip(copy.dfg.inst_results(copy_inst)) {
+                    assert_eq!(copy.locations[copy_r], ctx.func.locations[r]);
+                }
+            }
+            for &param in ctx.func.dfg.ebb_params(ebb) {
+                assert_eq!(copy.locations[param], ctx.func.locations[param]);
+            }
+        }
+        assert_eq!(copy.signature, ctx.func.signature);
+
+        // Without an ISA, the function is serialized target independently.
+        let copies = from_json(&to_json(&[ctx.func.clone()], None, false)).unwrap();
+        assert!(copies[0].encodings.is_empty());
+        assert!(copies[0].locations.is_empty());
+    }
+
+    #[test]
+    fn shared_stack_slots() {
+        use cranelift_codegen::entity::EntityRef;
+        use cranelift_codegen::ir::StackSlot;
+
+        let text = "function %sample() system_v {
+    ss0 = spill_slot 8
+    ss1 = spill_slot 4
+    ss2 = spill_slot 8
+
+ebb0:
+    return
+}
+";
+        let mut func = parse_functions(text).unwrap().remove(0);
+        let (ss0, ss2) = (StackSlot::new(0), StackSlot::new(2));
+        func.stack_slots.share(ss2, ss0);
+        let json = to_json(&[func], None, false);
+        let copy = from_json(&json).unwrap().remove(0);
+        assert_eq!(copy.stack_slots.shared_with(ss0), None);
+        assert_eq!(copy.stack_slots.shared_with(ss2), Some(ss0));
+
+        // Slots of different sizes can't share memory.
+        let bad = json.replace("[\"ss2\",\"ss0\"]", "[\"ss1\",\"ss0\"]");
+        assert_ne!(bad, json);
+        assert!(from_json(&bad).is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(from_json("{").is_err());
+        assert!(from_json("{\"functions\": []}").unwrap().is_empty());
+    }
+}
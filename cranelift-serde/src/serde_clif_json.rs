@@ -1,5 +1,25 @@
-use cranelift_codegen::ir::{Ebb, Function, Inst, InstructionData, Signature};
+//! Serializable versions of the Cranelift IR data structures.
+//!
+//! A function is converted back into Cranelift IR by writing it out in the text format and parsing
+//! that with `cranelift-reader`, so anything that round-trips through the text format round-trips
+//! through JSON. The stack slots sharing the memory of other stack slots, which the text format
+//! can't express, are recorded separately.
+//!
+//! Encodings and register or stack locations are only meaningful for a specific target ISA. They
+//! are serialized when the functions are serialized for an ISA, and the target is recorded so
+//! they can be parsed again.
+
+use cranelift_codegen::ir::entities::AnyEntity;
+use cranelift_codegen::ir::{
+    ArgumentLoc, Ebb, Function, Inst, InstructionData, SigRef, Signature, Value,
+};
+use cranelift_codegen::isa::{RegInfo, RegUnit, TargetIsa};
+use cranelift_codegen::packed_option::ReservedValue;
+use cranelift_codegen::write::write_ebb_args;
+use cranelift_reader::{parse_test, ParseOptions};
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Write};
 
 /// Serializable version of the original Cranelift IR
 #[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
@@ -58,6 +78,10 @@ pub enum SerInstData {
         arg: String,
         lane: String,
     },
+    UnaryConst {
+        opcode: String,
+        constant: String,
+    },
     Shuffle {
         opcode: String,
         args: [String; 2],
@@ -210,6 +234,12 @@ pub enum SerInstData {
         src: String,
         dst: String,
     },
+    RegSwap {
+        opcode: String,
+        args: [String; 2],
+        src: String,
+        dst: String,
+    },
     CopySpecial {
         opcode: String,
         src: String,
@@ -254,8 +284,25 @@ pub enum SerInstData {
     },
 }
 
+/// Name a register unit, using the register names of `regs` if they are known.
+fn regunit_name(unit: RegUnit, regs: Option<&RegInfo>) -> String {
+    match regs {
+        // Register names are written with a `%` prefix, like register unit numbers.
+        Some(regs) => regs.display_regunit(unit).to_string()[1..].to_string(),
+        None => unit.to_string(),
+    }
+}
+
 /// Convert Cranelift IR instructions to JSON format.
-pub fn get_inst_data(inst_index: Inst, func: &Function) -> SerInstData {
+///
+/// Registers are named according to `isa` if it is given, and by register unit number otherwise.
+pub fn get_inst_data(
+    inst_index: Inst,
+    func: &Function,
+    isa: Option<&dyn TargetIsa>,
+) -> SerInstData {
+    let regs = isa.map(TargetIsa::register_info);
+    let regs = regs.as_ref();
     let inst = &func.dfg[inst_index];
     match *inst {
         InstructionData::Unary { opcode, arg } => SerInstData::Unary {
@@ -341,9 +388,9 @@ pub fn get_inst_data(inst_index: Inst, func: &Function) -> SerInstData {
             constant_handle,
         } => {
             let constant = func.dfg.constants.get(constant_handle);
-            SerInstData::UnaryImm {
+            SerInstData::UnaryConst {
                 opcode: opcode.to_string(),
-                imm: format!("{:?}", constant),
+                constant: constant.to_string(),
             }
         }
         InstructionData::Shuffle { opcode, args, mask } => {
@@ -355,7 +402,7 @@ pub fn get_inst_data(inst_index: Inst, func: &Function) -> SerInstData {
             SerInstData::Shuffle {
                 opcode: opcode.to_string(),
                 args: [args[0].to_string(), args[1].to_string()],
-                mask: format!("{:?}", mask),
+                mask: mask.to_string(),
             }
         }
         InstructionData::IntCompare { opcode, args, cond } => {
@@ -674,17 +721,31 @@ pub fn get_inst_data(inst_index: Inst, func: &Function) -> SerInstData {
         } => SerInstData::RegMove {
             opcode: opcode.to_string(),
             arg: arg.to_string(),
-            src: src.to_string(),
-            dst: dst.to_string(),
+            src: regunit_name(src, regs),
+            dst: regunit_name(dst, regs),
         },
+        InstructionData::RegSwap {
+            opcode,
+            args,
+            src,
+            dst,
+        } => {
+            let hold_args = [args[0].to_string(), args[1].to_string()];
+            SerInstData::RegSwap {
+                opcode: opcode.to_string(),
+                args: hold_args,
+                src: regunit_name(src, regs),
+                dst: regunit_name(dst, regs),
+            }
+        }
         InstructionData::CopySpecial { opcode, src, dst } => SerInstData::CopySpecial {
             opcode: opcode.to_string(),
-            src: src.to_string(),
-            dst: dst.to_string(),
+            src: regunit_name(src, regs),
+            dst: regunit_name(dst, regs),
         },
         InstructionData::CopyToSsa { opcode, src } => SerInstData::CopyToSsa {
             opcode: opcode.to_string(),
-            src: src.to_string(),
+            src: regunit_name(src, regs),
         },
         InstructionData::RegSpill {
             opcode,
@@ -694,7 +755,7 @@ pub fn get_inst_data(inst_index: Inst, func: &Function) -> SerInstData {
         } => SerInstData::RegSpill {
             opcode: opcode.to_string(),
             arg: arg.to_string(),
-            src: src.to_string(),
+            src: regunit_name(src, regs),
             dst: dst.to_string(),
         },
         InstructionData::RegFill {
@@ -706,7 +767,7 @@ pub fn get_inst_data(inst_index: Inst, func: &Function) -> SerInstData {
             opcode: opcode.to_string(),
             arg: arg.to_string(),
             src: src.to_string(),
-            dst: dst.to_string(),
+            dst: regunit_name(dst, regs),
         },
         InstructionData::Trap { opcode, code } => SerInstData::Trap {
             opcode: opcode.to_string(),
@@ -742,20 +803,383 @@ pub fn get_inst_data(inst_index: Inst, func: &Function) -> SerInstData {
     }
 }
 
+impl SerInstData {
+    /// The name of the instruction's opcode.
+    pub fn opcode(&self) -> &str {
+        match *self {
+            SerInstData::Unary { ref opcode, .. }
+            | SerInstData::UnaryImm { ref opcode, .. }
+            | SerInstData::UnaryIeee32 { ref opcode, .. }
+            | SerInstData::UnaryIeee64 { ref opcode, .. }
+            | SerInstData::UnaryBool { ref opcode, .. }
+            | SerInstData::UnaryGlobalValue { ref opcode, .. }
+            | SerInstData::Binary { ref opcode, .. }
+            | SerInstData::BinaryImm { ref opcode, .. }
+            | SerInstData::Ternary { ref opcode, .. }
+            | SerInstData::MultiAry { ref opcode, .. }
+            | SerInstData::NullAry { ref opcode }
+            | SerInstData::InsertLane { ref opcode, .. }
+            | SerInstData::ExtractLane { ref opcode, .. }
+            | SerInstData::UnaryConst { ref opcode, .. }
+            | SerInstData::Shuffle { ref opcode, .. }
+            | SerInstData::IntCompare { ref opcode, .. }
+            | SerInstData::IntCompareImm { ref opcode, .. }
+            | SerInstData::IntCond { ref opcode, .. }
+            | SerInstData::FloatCompare { ref opcode, .. }
+            | SerInstData::FloatCond { ref opcode, .. }
+            | SerInstData::IntSelect { ref opcode, .. }
+            | SerInstData::Jump { ref opcode, .. }
+            | SerInstData::Branch { ref opcode, .. }
+            | SerInstData::BranchInt { ref opcode, .. }
+            | SerInstData::BranchFloat { ref opcode, .. }
+            | SerInstData::BranchIcmp { ref opcode, .. }
+            | SerInstData::BranchTable { ref opcode, .. }
+            | SerInstData::BranchTableEntry { ref opcode, .. }
+            | SerInstData::BranchTableBase { ref opcode, .. }
+            | SerInstData::IndirectJump { ref opcode, .. }
+            | SerInstData::Call { ref opcode, .. }
+            | SerInstData::CallIndirect { ref opcode, .. }
+            | SerInstData::FuncAddr { ref opcode, .. }
+            | SerInstData::Load { ref opcode, .. }
+            | SerInstData::LoadComplex { ref opcode, .. }
+            | SerInstData::Store { ref opcode, .. }
+            | SerInstData::StoreComplex { ref opcode, .. }
+            | SerInstData::StackLoad { ref opcode, .. }
+            | SerInstData::StackStore { ref opcode, .. }
+            | SerInstData::HeapAddr { ref opcode, .. }
+            | SerInstData::TableAddr { ref opcode, .. }
+            | SerInstData::RegMove { ref opcode, .. }
+            | SerInstData::RegSwap { ref opcode, .. }
+            | SerInstData::CopySpecial { ref opcode, .. }
+            | SerInstData::CopyToSsa { ref opcode, .. }
+            | SerInstData::RegSpill { ref opcode, .. }
+            | SerInstData::RegFill { ref opcode, .. }
+            | SerInstData::Trap { ref opcode, .. }
+            | SerInstData::CondTrap { ref opcode, .. }
+            | SerInstData::IntCondTrap { ref opcode, .. }
+            | SerInstData::FloatCondTrap { ref opcode, .. } => opcode,
+        }
+    }
+
+    /// Write the operands of the instruction to `w` in the Cranelift IR text format, with a
+    /// prepended space. This mirrors `cranelift_codegen::write::write_operands`.
+    fn write_operands(&self, w: &mut dyn Write) -> fmt::Result {
+        match *self {
+            SerInstData::Unary { ref arg, .. } => write!(w, " {}", arg),
+            SerInstData::UnaryImm { ref imm, .. }
+            | SerInstData::UnaryIeee32 { ref imm, .. }
+            | SerInstData::UnaryIeee64 { ref imm, .. } => write!(w, " {}", imm),
+            SerInstData::UnaryBool { imm, .. } => write!(w, " {}", imm),
+            SerInstData::UnaryGlobalValue {
+                ref global_value, ..
+            } => write!(w, " {}", global_value),
+            SerInstData::Binary { ref args, .. } => write!(w, " {}, {}", args[0], args[1]),
+            SerInstData::BinaryImm {
+                ref arg, ref imm, ..
+            } => write!(w, " {}, {}", arg, imm),
+            SerInstData::Ternary { ref args, .. } => {
+                write!(w, " {}, {}, {}", args[0], args[1], args[2])
+            }
+            SerInstData::MultiAry { ref args, .. } => {
+                if args.is_empty() {
+                    Ok(())
+                } else {
+                    write!(w, " {}", args.join(", "))
+                }
+            }
+            SerInstData::NullAry { .. } => write!(w, " "),
+            SerInstData::InsertLane {
+                ref args, ref lane, ..
+            } => write!(w, " {}, {}, {}", args[0], lane, args[1]),
+            SerInstData::ExtractLane {
+                ref arg, ref lane, ..
+            } => write!(w, " {}, {}", arg, lane),
+            SerInstData::UnaryConst { ref constant, .. } => write!(w, " {}", constant),
+            SerInstData::Shuffle {
+                ref args, ref mask, ..
+            } => write!(w, " {}, {}, {}", args[0], args[1], mask),
+            SerInstData::IntCompare {
+                ref args, ref cond, ..
+            }
+            | SerInstData::FloatCompare {
+                ref args, ref cond, ..
+            } => write!(w, " {} {}, {}", cond, args[0], args[1]),
+            SerInstData::IntCompareImm {
+                ref arg,
+                ref cond,
+                ref imm,
+                ..
+            } => write!(w, " {} {}, {}", cond, arg, imm),
+            SerInstData::IntCond {
+                ref arg, ref cond, ..
+            }
+            | SerInstData::FloatCond {
+                ref arg, ref cond, ..
+            } => write!(w, " {} {}", cond, arg),
+            SerInstData::IntSelect {
+                ref args, ref cond, ..
+            } => write!(w, " {} {}, {}, {}", cond, args[0], args[1], args[2]),
+            SerInstData::Jump {
+                ref args,
+                ref destination,
+                ..
+            } => {
+                write!(w, " {}", destination)?;
+                write_ebb_args(w, args)
+            }
+            SerInstData::Branch {
+                ref args,
+                ref destination,
+                ..
+            } => {
+                let (fixed, ebb_args) = split_args(args, 1)?;
+                write!(w, " {}, {}", fixed[0], destination)?;
+                write_ebb_args(w, ebb_args)
+            }
+            SerInstData::BranchInt {
+                ref args,
+                ref cond,
+                ref destination,
+                ..
+            }
+            | SerInstData::BranchFloat {
+                ref args,
+                ref cond,
+                ref destination,
+                ..
+            } => {
+                let (fixed, ebb_args) = split_args(args, 1)?;
+                write!(w, " {} {}, {}", cond, fixed[0], destination)?;
+                write_ebb_args(w, ebb_args)
+            }
+            SerInstData::BranchIcmp {
+                ref args,
+                ref cond,
+                ref destination,
+                ..
+            } => {
+                let (fixed, ebb_args) = split_args(args, 2)?;
+                write!(w, " {} {}, {}, {}", cond, fixed[0], fixed[1], destination)?;
+                write_ebb_args(w, ebb_args)
+            }
+            SerInstData::BranchTable {
+                ref arg,
+                ref destination,
+                ref table,
+                ..
+            } => write!(w, " {}, {}, {}", arg, destination, table),
+            SerInstData::BranchTableEntry {
+                ref args,
+                ref imm,
+                ref table,
+                ..
+            } => write!(w, " {}, {}, {}, {}", args[0], args[1], imm, table),
+            SerInstData::BranchTableBase { ref table, .. } => write!(w, " {}", table),
+            SerInstData::IndirectJump {
+                ref arg, ref table, ..
+            } => write!(w, " {}, {}", arg, table),
+            SerInstData::Call {
+                ref args,
+                ref func_ref,
+                ..
+            } => write!(w, " {}({})", func_ref, args.join(", ")),
+            SerInstData::CallIndirect {
+                ref args,
+                ref sig_ref,
+                ..
+            } => {
+                let (callee, call_args) = split_args(args, 1)?;
+                write!(w, " {}, {}({})", sig_ref, callee[0], call_args.join(", "))
+            }
+            SerInstData::FuncAddr { ref func_ref, .. } => write!(w, " {}", func_ref),
+            SerInstData::Load {
+                ref arg,
+                ref flags,
+                ref offset,
+                ..
+            } => write!(w, "{} {}{}", flags, arg, offset),
+            SerInstData::LoadComplex {
+                ref args,
+                ref flags,
+                ref offset,
+                ..
+            } => write!(w, "{} {}{}", flags, args.join("+"), offset),
+            SerInstData::Store {
+                ref args,
+                ref flags,
+                ref offset,
+                ..
+            } => write!(w, "{} {}, {}{}", flags, args[0], args[1], offset),
+            SerInstData::StoreComplex {
+                ref args,
+                ref flags,
+                ref offset,
+                ..
+            } => {
+                let (value, addr) = split_args(args, 1)?;
+                write!(w, "{} {}, {}{}", flags, value[0], addr.join("+"), offset)
+            }
+            SerInstData::StackLoad {
+                ref stack_slot,
+                ref offset,
+                ..
+            } => write!(w, " {}{}", stack_slot, offset),
+            SerInstData::StackStore {
+                ref arg,
+                ref stack_slot,
+                ref offset,
+                ..
+            } => write!(w, " {}, {}{}", arg, stack_slot, offset),
+            SerInstData::HeapAddr {
+                ref arg,
+                ref heap,
+                ref imm,
+                ..
+            } => write!(w, " {}, {}, {}", heap, arg, imm),
+            SerInstData::TableAddr {
+                ref arg,
+                ref table,
+                ref offset,
+                ..
+            } => write!(w, " {}, {}, {}", table, arg, offset),
+            SerInstData::RegMove {
+                ref arg,
+                ref src,
+                ref dst,
+                ..
+            } => write!(w, " {}, %{} -> %{}", arg, src, dst),
+            SerInstData::RegSwap {
+                ref args,
+                ref src,
+                ref dst,
+                ..
+            } => write!(w, " {}, {}, %{} -> %{}", args[0], args[1], src, dst),
+            SerInstData::CopySpecial {
+                ref src, ref dst, ..
+            } => write!(w, " %{} -> %{}", src, dst),
+            SerInstData::CopyToSsa { ref src, .. } => write!(w, " %{}", src),
+            SerInstData::RegSpill {
+                ref arg,
+                ref src,
+                ref dst,
+                ..
+            } => write!(w, " {}, %{} -> {}", arg, src, dst),
+            SerInstData::RegFill {
+                ref arg,
+                ref src,
+                ref dst,
+                ..
+            } => write!(w, " {}, {} -> %{}", arg, src, dst),
+            SerInstData::Trap { ref code, .. } => write!(w, " {}", code),
+            SerInstData::CondTrap {
+                ref arg, ref code, ..
+            } => write!(w, " {}, {}", arg, code),
+            SerInstData::IntCondTrap {
+                ref arg,
+                ref cond,
+                ref code,
+                ..
+            }
+            | SerInstData::FloatCondTrap {
+                ref arg,
+                ref cond,
+                ref code,
+                ..
+            } => write!(w, " {} {}, {}", cond, arg, code),
+        }
+    }
+}
+
+/// Split the first `n` fixed arguments off a value list, failing if there are too few.
+fn split_args(args: &[String], n: usize) -> Result<(&[String], &[String]), fmt::Error> {
+    if args.len() < n {
+        return Err(fmt::Error);
+    }
+    Ok(args.split_at(n))
+}
+
 /// Serializable version of Cranelift IR instructions.
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct SerInst {
     pub inst_name: String,
     pub inst_data: SerInstData,
+    /// The result values of the instruction.
+    pub results: Vec<String>,
+    /// The controlling type variable of polymorphic instructions.
+    pub ctrl_type: Option<String>,
+    /// The source location of the instruction, if it has one.
+    pub srcloc: Option<String>,
+    /// The encoding of the instruction, if it was serialized for a target ISA and has one.
+    #[serde(default)]
+    pub encoding: Option<String>,
+    /// The locations of the result values, if the instruction is encoded and they are known.
+    #[serde(default)]
+    pub locations: Vec<String>,
 }
 
 impl SerInst {
-    pub fn new(inst: Inst, func: &Function) -> Self {
+    pub fn new(inst: Inst, func: &Function, isa: Option<&dyn TargetIsa>) -> Self {
+        let results = func.dfg.inst_results(inst);
+        let ctrl_type = if func.dfg[inst].opcode().constraints().is_polymorphic() {
+            Some(func.dfg.ctrl_typevar(inst).to_string())
+        } else {
+            None
+        };
+        let srcloc = func.srclocs[inst];
+        let mut encoding = None;
+        let mut locations = Vec::new();
+        if let (Some(isa), Some(&enc)) = (isa, func.encodings.get(inst)) {
+            encoding = Some(isa.encoding_info().display(enc).to_string());
+            if !func.locations.is_empty() {
+                let regs = isa.register_info();
+                locations = results
+                    .iter()
+                    .map(|&r| func.locations[r].display(&regs).to_string())
+                    .collect();
+            }
+        }
         Self {
             inst_name: inst.to_string(),
-            inst_data: get_inst_data(inst, func),
+            inst_data: get_inst_data(inst, func, isa),
+            results: results.iter().map(ToString::to_string).collect(),
+            ctrl_type,
+            srcloc: if srcloc.is_default() {
+                None
+            } else {
+                Some(srcloc.to_string())
+            },
+            encoding,
+            locations,
         }
     }
+
+    /// Write the instruction to `w` in the Cranelift IR text format.
+    fn write(&self, w: &mut dyn Write, aliases: &Aliases) -> fmt::Result {
+        write!(w, "    ")?;
+        if let Some(ref srcloc) = self.srcloc {
+            write!(w, "{} ", srcloc)?;
+        }
+        if let Some(ref encoding) = self.encoding {
+            write!(w, "[{}", encoding)?;
+            for loc in &self.locations {
+                write!(w, ",{}", loc)?;
+            }
+            write!(w, "] ")?;
+        }
+        if !self.results.is_empty() {
+            write!(w, "{} = ", self.results.join(", "))?;
+        }
+        write!(w, "{}", self.inst_data.opcode())?;
+        if let Some(ref ctrl_type) = self.ctrl_type {
+            write!(w, ".{}", ctrl_type)?;
+        }
+        self.inst_data.write_operands(w)?;
+        writeln!(w)?;
+        for result in &self.results {
+            write_value_aliases(w, aliases, result)?;
+        }
+        Ok(())
+    }
 }
 
 /// Serializable version of Cranelift IR Ebbs.
@@ -763,6 +1187,13 @@ impl SerInst {
 pub struct SerEbb {
     pub ebb: String,
     pub params: Vec<String>,
+    /// The types of the values in `params`.
+    pub param_types: Vec<String>,
+    /// Whether the EBB is marked as cold.
+    pub cold: bool,
+    /// The locations assigned to the values in `params`, if any.
+    #[serde(default)]
+    pub param_locations: Vec<Option<String>>,
     pub insts: Vec<SerInst>,
 }
 
@@ -771,16 +1202,53 @@ impl SerEbb {
         Self {
             ebb: name,
             params: Vec::new(),
+            param_types: Vec::new(),
+            cold: false,
+            param_locations: Vec::new(),
             insts: Vec::new(),
         }
     }
+
+    /// Write the EBB to `w` in the Cranelift IR text format.
+    fn write(&self, w: &mut dyn Write, aliases: &Aliases) -> fmt::Result {
+        if self.params.len() != self.param_types.len()
+            || (!self.param_locations.is_empty() && self.params.len() != self.param_locations.len())
+        {
+            return Err(fmt::Error);
+        }
+        write!(w, "{}", self.ebb)?;
+        if self.cold {
+            write!(w, " cold")?;
+        }
+        if !self.params.is_empty() {
+            let params: Vec<String> = self
+                .params
+                .iter()
+                .zip(&self.param_types)
+                .enumerate()
+                .map(|(i, (param, ty))| match self.param_locations.get(i) {
+                    Some(Some(loc)) => format!("{}: {} [{}]", param, ty, loc),
+                    _ => format!("{}: {}", param, ty),
+                })
+                .collect();
+            write!(w, "({})", params.join(", "))?;
+        }
+        writeln!(w, ":")?;
+        for param in &self.params {
+            write_value_aliases(w, aliases, param)?;
+        }
+        for inst in &self.insts {
+            inst.write(w, aliases)?;
+        }
+        Ok(())
+    }
 }
 
-pub fn populate_inst(func: &Function, ebb: Ebb) -> Vec<SerInst> {
+pub fn populate_inst(func: &Function, ebb: Ebb, isa: Option<&dyn TargetIsa>) -> Vec<SerInst> {
     let mut ser_vec: Vec<SerInst> = Vec::new();
     let ret_iter = func.layout.ebb_insts(ebb);
     for inst in ret_iter {
-        let ser_inst: SerInst = SerInst::new(inst, &func);
+        let ser_inst: SerInst = SerInst::new(inst, &func, isa);
         ser_vec.push(ser_inst);
     }
     ser_vec
@@ -796,35 +1264,131 @@ pub fn populate_params(func: &Function, ebb: Ebb) -> Vec<String> {
     ser_vec
 }
 
+/// Translating the locations of Ebb parameters into serializable locations. Nothing is recorded
+/// unless the function is serialized for a target ISA.
+pub fn populate_param_locations(
+    func: &Function,
+    ebb: Ebb,
+    isa: Option<&dyn TargetIsa>,
+) -> Vec<Option<String>> {
+    let isa = match isa {
+        Some(isa) if !func.locations.is_empty() => isa,
+        _ => return Vec::new(),
+    };
+    let regs = isa.register_info();
+    func.dfg
+        .ebb_params(ebb)
+        .iter()
+        .map(|&param| {
+            let loc = func.locations[param];
+            if loc.is_assigned() {
+                Some(loc.display(&regs).to_string())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Translating the types of Ebb parameters into serializable types.
+pub fn populate_param_types(func: &Function, ebb: Ebb) -> Vec<String> {
+    let mut ser_vec: Vec<String> = Vec::new();
+    let parameters = func.dfg.ebb_params(ebb);
+    for param in parameters {
+        ser_vec.push(func.dfg.value_type(*param).to_string());
+    }
+    ser_vec
+}
+
+/// Serializable value alias.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct SerValueAlias {
+    pub alias: String,
+    pub original: String,
+}
+
+/// The aliases in a data flow graph, keyed by the value they refer to directly.
+type Aliases<'a> = HashMap<&'a str, Vec<&'a str>>;
+
+/// Write out any aliases to the given target, including indirect aliases.
+fn write_value_aliases(w: &mut dyn Write, aliases: &Aliases, target: &str) -> fmt::Result {
+    let mut todo_stack = vec![target];
+    while let Some(target) = todo_stack.pop() {
+        for &a in aliases.get(target).into_iter().flatten() {
+            writeln!(w, "    {} -> {}", a, target)?;
+            todo_stack.push(a);
+        }
+    }
+    Ok(())
+}
+
 /// Serializable Data Flow Graph.
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SerDataFlowGraph {
     ebbs: Vec<SerEbb>,
+    aliases: Vec<SerValueAlias>,
 }
 
 /// Serialize all parts of the Cranelift Ebb data structure, this includes name, parameters, and
 /// instructions.
-pub fn populate_ebbs(func: &Function) -> Vec<SerEbb> {
+pub fn populate_ebbs(func: &Function, isa: Option<&dyn TargetIsa>) -> Vec<SerEbb> {
     let mut ebb_vec: Vec<SerEbb> = Vec::new();
     for ebb in func.layout.ebbs() {
         let mut ser_ebb: SerEbb = SerEbb::new(ebb.to_string());
         ser_ebb.params = populate_params(&func, ebb);
-        ser_ebb.insts = populate_inst(&func, ebb);
+        ser_ebb.param_types = populate_param_types(func, ebb);
+        ser_ebb.cold = func.layout.is_cold(ebb);
+        ser_ebb.param_locations = populate_param_locations(func, ebb, isa);
+        ser_ebb.insts = populate_inst(&func, ebb, isa);
         ebb_vec.push(ser_ebb);
     }
     ebb_vec
 }
 
+/// Serialize the value aliases of a function.
+pub fn populate_aliases(func: &Function) -> Vec<SerValueAlias> {
+    let mut alias_vec: Vec<SerValueAlias> = Vec::new();
+    for value in func.dfg.values() {
+        if let Some(original) = func.dfg.value_alias_dest_for_serialization(value) {
+            // Values that were never defined are aliases of the reserved value.
+            if original != Value::reserved_value() {
+                alias_vec.push(SerValueAlias {
+                    alias: value.to_string(),
+                    original: original.to_string(),
+                });
+            }
+        }
+    }
+    alias_vec
+}
+
 /// Serializable Cranelift IR data flow graph, including all ebbs.
 impl SerDataFlowGraph {
-    pub fn create_new(func: &Function) -> Self {
+    pub fn create_new(func: &Function, isa: Option<&dyn TargetIsa>) -> Self {
         Self {
-            ebbs: populate_ebbs(func),
+            ebbs: populate_ebbs(func, isa),
+            aliases: populate_aliases(func),
         }
     }
 
-    pub fn new(func: &Function) -> Self {
-        Self::create_new(func)
+    pub fn new(func: &Function, isa: Option<&dyn TargetIsa>) -> Self {
+        Self::create_new(func, isa)
+    }
+
+    /// Write all EBBs to `w` in the Cranelift IR text format.
+    fn write(&self, w: &mut dyn Write) -> fmt::Result {
+        let mut aliases = Aliases::new();
+        for alias in &self.aliases {
+            aliases
+                .entry(&alias.original)
+                .or_default()
+                .push(&alias.alias);
+        }
+        for ebb in &self.ebbs {
+            writeln!(w)?;
+            ebb.write(w, &aliases)?;
+        }
+        Ok(())
     }
 }
 
@@ -833,57 +1397,214 @@ impl SerDataFlowGraph {
 pub struct SerSignature {
     pub func_params: Vec<String>,
     pub func_returns: Vec<String>,
+    /// The calling convention of the function.
+    pub call_conv: String,
+}
+
+/// Display `sig` with its argument locations if they can be written for `isa`. Without a target
+/// ISA the locations can't be parsed again, so they are stripped.
+fn display_signature(sig: &Signature, isa: Option<&dyn TargetIsa>) -> String {
+    match isa {
+        Some(isa) => sig.display(Some(&isa.register_info())).to_string(),
+        None => strip_locations(sig).to_string(),
+    }
+}
+
+/// Strip the argument locations from `sig`.
+fn strip_locations(sig: &Signature) -> Signature {
+    let mut sig = sig.clone();
+    for param in sig.params.iter_mut().chain(sig.returns.iter_mut()) {
+        param.location = ArgumentLoc::Unassigned;
+    }
+    sig
 }
 
 impl SerSignature {
     /// Creating serializable signature data structure from all Cranelift IR functions.
-    fn create_new(sig: &Signature) -> Self {
+    fn create_new(sig: &Signature, isa: Option<&dyn TargetIsa>) -> Self {
         let mut params_vec: Vec<String> = Vec::new();
         let mut returns_vec: Vec<String> = Vec::new();
+        let regs = isa.map(TargetIsa::register_info);
+        let sig = match regs {
+            Some(_) => sig.clone(),
+            None => strip_locations(sig),
+        };
         for param in &sig.params {
-            params_vec.push(param.to_string());
+            params_vec.push(param.display(regs.as_ref()).to_string());
         }
         for ret in &sig.returns {
-            returns_vec.push(ret.to_string());
+            returns_vec.push(ret.display(regs.as_ref()).to_string());
         }
         Self {
             func_params: params_vec,
             func_returns: returns_vec,
+            call_conv: sig.call_conv.to_string(),
         }
     }
 
-    pub fn new(func: &Function) -> Self {
-        Self::create_new(&func.signature)
+    pub fn new(func: &Function, isa: Option<&dyn TargetIsa>) -> Self {
+        Self::create_new(&func.signature, isa)
+    }
+
+    /// Write the signature to `w` in the Cranelift IR text format.
+    fn write(&self, w: &mut dyn Write) -> fmt::Result {
+        write!(w, "({})", self.func_params.join(", "))?;
+        if !self.func_returns.is_empty() {
+            write!(w, " -> {}", self.func_returns.join(", "))?;
+        }
+        write!(w, " {}", self.call_conv)
     }
 }
 
-/// Serializable Function type, including name, signature, global values, and data flow graph.
+/// Serializable entity declaration from the function preamble, such as a stack slot or a global
+/// value. The `data` is the declaration as it appears in the Cranelift IR text format.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct SerEntity {
+    pub name: String,
+    pub data: String,
+}
+
+impl SerEntity {
+    fn new<K: ToString, V: ToString>(entity: K, data: V) -> Self {
+        Self {
+            name: entity.to_string(),
+            data: data.to_string(),
+        }
+    }
+}
+
+/// Serializable Function type, including name, signature, the entities declared in the preamble,
+/// and data flow graph.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SerFunction {
     pub name: String,
     pub signature: SerSignature,
-    pub globals: Vec<String>,
+    pub stack_slots: Vec<SerEntity>,
+    /// The stack slots sharing the memory of another stack slot, which the text format can't
+    /// express, as pairs of the sharing slot and the slot it shares.
+    #[serde(default)]
+    pub shared_stack_slots: Vec<(String, String)>,
+    pub globals: Vec<SerEntity>,
+    pub heaps: Vec<SerEntity>,
+    pub tables: Vec<SerEntity>,
+    pub signatures: Vec<SerEntity>,
+    pub func_refs: Vec<SerEntity>,
+    pub jump_tables: Vec<SerEntity>,
     pub dfg: SerDataFlowGraph,
 }
 
 impl SerFunction {
-    /// Creates serializable global values, as well as the functions signature, name, and data flow
-    /// graph.
-    fn create_new(func: &Function) -> Self {
-        let mut global_vec: Vec<String> = Vec::new();
-        for (glob_name, _) in func.global_values.iter() {
-            global_vec.push(glob_name.to_string());
-        }
+    /// Creates serializable preamble entities, as well as the functions signature, name, and data
+    /// flow graph.
+    fn create_new(func: &Function, isa: Option<&dyn TargetIsa>) -> Self {
         Self {
             name: func.name.to_string(),
-            signature: SerSignature::new(&func),
-            globals: global_vec,
-            dfg: SerDataFlowGraph::new(&func),
+            signature: SerSignature::new(&func, isa),
+            stack_slots: func
+                .stack_slots
+                .iter()
+                .map(|(ss, data)| SerEntity::new(ss, data))
+                .collect(),
+            shared_stack_slots: func
+                .stack_slots
+                .keys()
+                .filter_map(|ss| {
+                    let with = func.stack_slots.shared_with(ss)?;
+                    Some((ss.to_string(), with.to_string()))
+                })
+                .collect(),
+            globals: func
+                .global_values
+                .iter()
+                .map(|(gv, data)| SerEntity::new(gv, data))
+                .collect(),
+            heaps: func
+                .heaps
+                .iter()
+                .filter(|(_, data)| !data.index_type.is_invalid())
+                .map(|(heap, data)| SerEntity::new(heap, data))
+                .collect(),
+            tables: func
+                .tables
+                .iter()
+                .filter(|(_, data)| !data.index_type.is_invalid())
+                .map(|(table, data)| SerEntity::new(table, data))
+                .collect(),
+            signatures: func
+                .dfg
+                .signatures
+                .iter()
+                .map(|(sig, data)| SerEntity::new(sig, display_signature(data, isa)))
+                .collect(),
+            func_refs: func
+                .dfg
+                .ext_funcs
+                .iter()
+                .filter(|(_, data)| data.signature != SigRef::reserved_value())
+                .map(|(fn_ref, data)| SerEntity::new(fn_ref, data))
+                .collect(),
+            jump_tables: func
+                .jump_tables
+                .iter()
+                .map(|(jt, data)| SerEntity::new(jt, data))
+                .collect(),
+            dfg: SerDataFlowGraph::new(&func, isa),
         }
     }
 
-    pub fn new(func: &Function) -> Self {
-        Self::create_new(func)
+    pub fn new(func: &Function, isa: Option<&dyn TargetIsa>) -> Self {
+        Self::create_new(func, isa)
+    }
+
+    /// Write the function to `w` in the Cranelift IR text format.
+    fn write(&self, w: &mut dyn Write) -> fmt::Result {
+        write!(w, "function {}", self.name)?;
+        self.signature.write(w)?;
+        writeln!(w, " {{")?;
+        let preamble = self
+            .stack_slots
+            .iter()
+            .chain(&self.globals)
+            .chain(&self.heaps)
+            .chain(&self.tables)
+            .chain(&self.signatures)
+            .chain(&self.func_refs)
+            .chain(&self.jump_tables);
+        for entity in preamble {
+            writeln!(w, "    {} = {}", entity.name, entity.data)?;
+        }
+        self.dfg.write(w)?;
+        writeln!(w, "}}")
+    }
+
+    /// Convert the serialized function back into a Cranelift IR function. Encodings and locations
+    /// are parsed for the `target` ISA, if one is given.
+    pub fn to_function(&self, target: Option<&str>) -> Result<Function, String> {
+        let mut text = String::new();
+        if let Some(target) = target {
+            writeln!(text, "target {}", target)
+                .map_err(|_| format!("{}: malformed target", self.name))?;
+        }
+        self.write(&mut text)
+            .map_err(|_| format!("{}: malformed function", self.name))?;
+        let mut funcs = parse_test(&text, ParseOptions::default())
+            .map_err(|e| format!("{}: {}", self.name, e))?
+            .functions;
+        let (mut func, details) = match funcs.pop() {
+            Some(func) if funcs.is_empty() => func,
+            _ => return Err(format!("{}: malformed function", self.name)),
+        };
+        for (ss, with) in &self.shared_stack_slots {
+            match (details.map.lookup_str(ss), details.map.lookup_str(with)) {
+                (Some(AnyEntity::StackSlot(ss)), Some(AnyEntity::StackSlot(with)))
+                    if func.stack_slots.can_share(ss, with) =>
+                {
+                    func.stack_slots.share(ss, with)
+                }
+                _ => return Err(format!("{}: {} can't share {}", self.name, ss, with)),
+            }
+        }
+        Ok(func)
     }
 }
 
@@ -891,20 +1612,36 @@ impl SerFunction {
 /// serialized. Files have one SerObj each, with all SerFunctions contained inside that SerObj.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SerObj {
+    /// The target triple the functions were serialized for, if they were serialized with their
+    /// encodings and locations.
+    #[serde(default)]
+    pub target: Option<String>,
     pub functions: Vec<SerFunction>,
 }
 
 impl SerObj {
-    fn create_new(funcs: Vec<SerFunction>) -> Self {
-        Self { functions: funcs }
+    fn create_new(funcs: Vec<SerFunction>, target: Option<String>) -> Self {
+        Self {
+            target,
+            functions: funcs,
+        }
     }
 
-    pub fn new(funcs: &[Function]) -> Self {
+    pub fn new(funcs: &[Function], isa: Option<&dyn TargetIsa>) -> Self {
         let mut func_vec: Vec<SerFunction> = Vec::new();
         for func in funcs {
-            let ser_func: SerFunction = SerFunction::new(&func);
+            let ser_func: SerFunction = SerFunction::new(&func, isa);
             func_vec.push(ser_func);
         }
-        Self::create_new(func_vec)
+        Self::create_new(func_vec, isa.map(|isa| isa.triple().to_string()))
+    }
+
+    /// Convert all serialized functions back into Cranelift IR functions.
+    pub fn to_functions(&self) -> Result<Vec<Function>, String> {
+        let target = self.target.as_ref().map(String::as_str);
+        self.functions
+            .iter()
+            .map(|func| func.to_function(target))
+            .collect()
     }
 }
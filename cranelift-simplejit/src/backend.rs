@@ -1,23 +1,26 @@
 //! Defines `SimpleJITBackend`.
 
+use crate::lazy::{self, LazyFunction};
 use crate::memory::Memory;
+use crate::profiler::{self, PerfMapAgent, ProfilingAgent};
 use cranelift_codegen::binemit::{
     Addend, CodeOffset, NullTrapSink, Reloc, RelocSink, Stackmap, StackmapSink,
 };
 use cranelift_codegen::isa::TargetIsa;
 use cranelift_codegen::{self, ir, settings};
 use cranelift_module::{
-    Backend, DataContext, DataDescription, DataId, FuncId, Init, Linkage, ModuleNamespace,
-    ModuleResult,
+    Backend, DataContext, DataDescription, DataId, FuncId, Init, Linkage, ModuleError,
+    ModuleNamespace, ModuleResult, SymbolAttributes,
 };
 use cranelift_native;
 #[cfg(not(windows))]
 use libc;
 use std::collections::HashMap;
 use std::ffi::CString;
-use std::io::Write;
 use std::ptr;
-use target_lexicon::PointerWidth;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use target_lexicon::{Architecture, PointerWidth};
 #[cfg(windows)]
 use winapi;
 
@@ -25,11 +28,22 @@ const EXECUTABLE_DATA_ALIGNMENT: u8 = 0x10;
 const WRITABLE_DATA_ALIGNMENT: u8 = 0x8;
 const READONLY_DATA_ALIGNMENT: u8 = 0x1;
 
+/// Size of a hot code replacement stub: `movabs $slot, %r11; jmp *(%r11)`.
+const STUB_SIZE: usize = 13;
+
+/// Size of a GOT entry, holding the address of a symbol.
+const GOT_ENTRY_SIZE: usize = 8;
+
+/// Size reserved for a veneer, or PLT stub: `movabs $target, %r11; jmp *%r11`.
+const VENEER_SIZE: usize = 16;
+
 /// A builder for `SimpleJITBackend`.
 pub struct SimpleJITBuilder {
     isa: Box<dyn TargetIsa>,
     symbols: HashMap<String, *const u8>,
     libcall_names: Box<dyn Fn(ir::LibCall) -> String>,
+    hotswap: bool,
+    profiler: Option<Box<dyn ProfilingAgent>>,
 }
 
 impl SimpleJITBuilder {
@@ -51,7 +65,8 @@ impl SimpleJITBuilder {
     /// Create a new `SimpleJITBuilder` with an arbitrary target. This is mainly
     /// useful for testing.
     ///
-    /// SimpleJIT requires a `TargetIsa` configured for non-PIC.
+    /// The `TargetIsa` may be configured for PIC, in which case SimpleJIT provides the GOT and
+    /// the PLT stubs the code refers to.
     ///
     /// To create a `SimpleJITBuilder` for native use, use the `new` constructor
     /// instead.
@@ -64,15 +79,52 @@ impl SimpleJITBuilder {
         isa: Box<dyn TargetIsa>,
         libcall_names: Box<dyn Fn(ir::LibCall) -> String>,
     ) -> Self {
-        debug_assert!(!isa.flags().is_pic(), "SimpleJIT requires non-PIC code");
         let symbols = HashMap::new();
+        let profiler: Option<Box<dyn ProfilingAgent>> =
+            if cfg!(target_os = "linux") && ::std::env::var_os("PERF_BUILDID_DIR").is_some() {
+                PerfMapAgent::new()
+                    .ok()
+                    .map(|agent| Box::new(agent) as Box<dyn ProfilingAgent>)
+            } else {
+                None
+            };
         Self {
             isa,
             symbols,
             libcall_names,
+            hotswap: false,
+            profiler,
         }
     }
 
+    /// Enable or disable hot code replacement.
+    ///
+    /// When enabled, the calls to the functions defined in the module, and the pointers returned
+    /// by `get_finalized_function`, go through small stubs jumping to the current definition of
+    /// each function. This allows finalized functions to be redefined after calling
+    /// `Module::prepare_for_function_redefine`.
+    ///
+    /// Hot code replacement is only supported on x86-64, and enabling it on other targets returns
+    /// an error.
+    pub fn hotswap(&mut self, enabled: bool) -> ModuleResult<&mut Self> {
+        if enabled && self.isa.triple().architecture != Architecture::X86_64 {
+            return Err(ModuleError::Backend(
+                "hot code replacement is only supported on x86-64".to_string(),
+            ));
+        }
+        self.hotswap = enabled;
+        Ok(self)
+    }
+
+    /// Set the agent notified of the functions loaded and unloaded, on behalf of a profiler.
+    ///
+    /// By default, a `PerfMapAgent` is used on Linux when the `PERF_BUILDID_DIR` environment
+    /// variable is set, and no agent otherwise.
+    pub fn profiler(&mut self, agent: Box<dyn ProfilingAgent>) -> &mut Self {
+        self.profiler = Some(agent);
+        self
+    }
+
     /// Define a symbol in the internal symbol table.
     ///
     /// The JIT will use the symbol table to resolve names that are declared,
@@ -121,6 +173,20 @@ pub struct SimpleJITBackend {
     code_memory: Memory,
     readonly_memory: Memory,
     writable_memory: Memory,
+    hotswap: bool,
+    stubs: HashMap<FuncId, Stub>,
+    pending_stub_updates: Vec<(FuncId, *const u8)>,
+    lazy_functions: HashMap<FuncId, Box<LazyFunction>>,
+    profiler: Option<Box<dyn ProfilingAgent>>,
+}
+
+/// An indirection through which the calls to a function are made in hot code replacement mode,
+/// or when the function is compiled lazily.
+struct Stub {
+    /// The code of the stub, which jumps to the address in `slot`.
+    code: *const u8,
+    /// The address of the current definition of the function, or null.
+    slot: *mut u8,
 }
 
 /// A record of a relocation to perform.
@@ -139,14 +205,26 @@ struct StackmapRecord {
 }
 
 pub struct SimpleJITCompiledFunction {
+    name: String,
     code: *mut u8,
     size: usize,
     relocs: Vec<RelocRecord>,
+    /// The symbols with an entry in the GOT of the function, which follows its code at
+    /// `got_offset`.
+    got: Vec<ir::ExternalName>,
+    got_offset: usize,
+    /// The symbols called by the function, which may need a veneer if they are out of range of
+    /// a PC-relative call. The veneers follow the GOT at `veneers_offset`.
+    veneers: Vec<ir::ExternalName>,
+    veneers_offset: usize,
+    stub: Option<*const u8>,
+    srclocs: Vec<(CodeOffset, ir::SourceLoc)>,
 }
 
 pub struct SimpleJITCompiledData {
     storage: *mut u8,
     size: usize,
+    writable: bool,
     relocs: Vec<RelocRecord>,
 }
 
@@ -166,6 +244,9 @@ impl SimpleJITBackend {
         match *name {
             ir::ExternalName::User { .. } => {
                 if namespace.is_function(name) {
+                    if let Some(stub) = self.stubs.get(&namespace.get_function_id(name)) {
+                        return stub.code;
+                    }
                     let (def, name_str, _signature) = namespace.get_function_definition(&name);
                     match def {
                         Some(compiled) => compiled.code,
@@ -186,6 +267,67 @@ impl SimpleJITBackend {
             _ => panic!("invalid ExternalName {}", name),
         }
     }
+
+    /// Create the stub for the function `id`, which initially jumps to address 0.
+    fn make_stub(&mut self, id: FuncId) {
+        let slot = self
+            .writable_memory
+            .allocate(8, WRITABLE_DATA_ALIGNMENT)
+            .expect("TODO: handle OOM etc.");
+        let code = self
+            .code_memory
+            .allocate(STUB_SIZE, EXECUTABLE_DATA_ALIGNMENT)
+            .expect("TODO: handle OOM etc.");
+        let mut bytes = [0u8; STUB_SIZE];
+        // movabs $slot, %r11
+        bytes[0] = 0x49;
+        bytes[1] = 0xbb;
+        bytes[2..10].copy_from_slice(&(slot as u64).to_le_bytes());
+        // jmp *(%r11)
+        bytes[10] = 0x41;
+        bytes[11] = 0xff;
+        bytes[12] = 0x23;
+        unsafe {
+            ptr::write_bytes(slot, 0, 8);
+            ptr::copy_nonoverlapping(bytes.as_ptr(), code, STUB_SIZE);
+        }
+        self.stubs.insert(id, Stub { code, slot });
+    }
+
+    /// Make the stub of the function `id` jump to the trampoline compiling `lazy`.
+    pub(crate) fn make_lazy(&mut self, id: FuncId, lazy: Box<LazyFunction>) -> ModuleResult<()> {
+        if self.lazy_functions.contains_key(&id) {
+            return Err(ModuleError::Backend(format!(
+                "function {} is already compiled lazily",
+                id
+            )));
+        }
+        if !self.stubs.contains_key(&id) {
+            self.make_stub(id);
+        }
+        let bytes = lazy::trampoline_code(&*lazy as *const LazyFunction as *mut LazyFunction);
+        let code = self
+            .code_memory
+            .allocate(bytes.len(), EXECUTABLE_DATA_ALIGNMENT)
+            .expect("TODO: handle OOM etc.");
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), code, bytes.len()) };
+        self.pending_stub_updates.push((id, code));
+        self.lazy_functions.insert(id, lazy);
+        Ok(())
+    }
+
+    /// Is `lazy` the record of the lazily compiled function `id` in this backend?
+    pub(crate) fn is_lazy(&self, id: FuncId, lazy: *const LazyFunction) -> bool {
+        self.lazy_functions
+            .get(&id)
+            .map_or(false, |f| ptr::eq(&**f, lazy))
+    }
+
+    /// Get the address of the code the stub of the function `id` currently jumps to.
+    pub(crate) fn stub_target(&self, id: FuncId) -> *const u8 {
+        let slot = self.stubs[&id].slot as *const AtomicUsize;
+        unsafe { (*slot).load(Ordering::Acquire) as *const u8 }
+    }
 }
 
 impl<'simple_jit_backend> Backend for SimpleJITBackend {
@@ -216,6 +358,11 @@ impl<'simple_jit_backend> Backend for SimpleJITBackend {
             code_memory: Memory::new(),
             readonly_memory: Memory::new(),
             writable_memory: Memory::new(),
+            hotswap: builder.hotswap,
+            stubs: HashMap::new(),
+            pending_stub_updates: Vec::new(),
+            lazy_functions: HashMap::new(),
+            profiler: builder.profiler,
         }
     }
 
@@ -223,8 +370,16 @@ impl<'simple_jit_backend> Backend for SimpleJITBackend {
         &*self.isa
     }
 
-    fn declare_function(&mut self, _id: FuncId, _name: &str, _linkage: Linkage) {
-        // Nothing to do.
+    fn declare_function(
+        &mut self,
+        id: FuncId,
+        _name: &str,
+        linkage: Linkage,
+        _attributes: &SymbolAttributes,
+    ) {
+        if self.hotswap && linkage.is_definable() && !self.stubs.contains_key(&id) {
+            self.make_stub(id);
+        }
     }
 
     fn declare_data(
@@ -234,33 +389,23 @@ impl<'simple_jit_backend> Backend for SimpleJITBackend {
         _linkage: Linkage,
         _writable: bool,
         _align: Option<u8>,
+        _attributes: &SymbolAttributes,
     ) {
         // Nothing to do.
     }
 
     fn define_function(
         &mut self,
-        _id: FuncId,
+        id: FuncId,
         name: &str,
         ctx: &cranelift_codegen::Context,
-        _namespace: &ModuleNamespace<Self>,
+        namespace: &ModuleNamespace<Self>,
         code_size: u32,
     ) -> ModuleResult<Self::CompiledFunction> {
-        let size = code_size as usize;
-        let ptr = self
-            .code_memory
-            .allocate(size, EXECUTABLE_DATA_ALIGNMENT)
-            .expect("TODO: handle OOM etc.");
-
-        if cfg!(target_os = "linux") && ::std::env::var_os("PERF_BUILDID_DIR").is_some() {
-            let mut map_file = ::std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(format!("/tmp/perf-{}.map", ::std::process::id()))
-                .unwrap();
+        check_attributes(name, &namespace.get_function_decl(&id.into()).attributes)?;
 
-            let _ = writeln!(map_file, "{:x} {:x} {}", ptr as usize, code_size, name);
-        }
+        let size = code_size as usize;
+        let mut buffer = vec![0u8; size];
 
         let mut reloc_sink = SimpleJITRelocSink::new();
         // Ignore traps for now. For now, frontends should just avoid generating code
@@ -270,29 +415,68 @@ impl<'simple_jit_backend> Backend for SimpleJITBackend {
         unsafe {
             ctx.emit_to_memory(
                 &*self.isa,
-                ptr,
+                buffer.as_mut_ptr(),
                 &mut reloc_sink,
                 &mut trap_sink,
                 &mut stackmap_sink,
             )
         };
 
+        // The GOT entries and the veneers are placed right after the code, so they are always in
+        // range of its PC-relative references.
+        let mut got = Vec::new();
+        let mut veneers = Vec::new();
+        for record in &reloc_sink.relocs {
+            let names = match record.reloc {
+                Reloc::X86GOTPCRel4 => &mut got,
+                Reloc::X86CallPCRel4 | Reloc::X86CallPLTRel4 => &mut veneers,
+                _ => continue,
+            };
+            if !names.contains(&record.name) {
+                names.push(record.name.clone());
+            }
+        }
+        let got_offset = (size + GOT_ENTRY_SIZE - 1) & !(GOT_ENTRY_SIZE - 1);
+        let veneers_offset = got_offset + got.len() * GOT_ENTRY_SIZE;
+        let total_size = veneers_offset + veneers.len() * VENEER_SIZE;
+
+        let ptr = self
+            .code_memory
+            .allocate(total_size, EXECUTABLE_DATA_ALIGNMENT)
+            .expect("TODO: handle OOM etc.");
+        unsafe { ptr::copy_nonoverlapping(buffer.as_ptr(), ptr, size) };
+
+        let srclocs = if self.profiler.is_some() {
+            profiler::function_srclocs(&*self.isa, ctx)
+        } else {
+            Vec::new()
+        };
+
         Ok(Self::CompiledFunction {
+            name: name.to_owned(),
             code: ptr,
             size,
             relocs: reloc_sink.relocs,
+            got,
+            got_offset,
+            veneers,
+            veneers_offset,
+            stub: self.stubs.get(&id).map(|stub| stub.code),
+            srclocs,
         })
     }
 
     fn define_data(
         &mut self,
-        _id: DataId,
-        _name: &str,
+        id: DataId,
+        name: &str,
         writable: bool,
         align: Option<u8>,
         data: &DataContext,
-        _namespace: &ModuleNamespace<Self>,
+        namespace: &ModuleNamespace<Self>,
     ) -> ModuleResult<Self::CompiledData> {
+        check_attributes(name, &namespace.get_data_decl(&id.into()).attributes)?;
+
         let &DataDescription {
             ref init,
             ref function_decls,
@@ -351,6 +535,7 @@ impl<'simple_jit_backend> Backend for SimpleJITBackend {
         Ok(Self::CompiledData {
             storage,
             size,
+            writable,
             relocs,
         })
     }
@@ -376,7 +561,7 @@ impl<'simple_jit_backend> Backend for SimpleJITBackend {
 
     fn finalize_function(
         &mut self,
-        _id: FuncId,
+        id: FuncId,
         func: &Self::CompiledFunction,
         namespace: &ModuleNamespace<Self>,
     ) -> Self::FinalizedFunction {
@@ -393,14 +578,14 @@ impl<'simple_jit_backend> Backend for SimpleJITBackend {
             debug_assert!((offset as usize) < func.size);
             let at = unsafe { ptr.offset(offset as isize) };
             let base = self.get_definition(namespace, name);
-            // TODO: Handle overflow.
-            let what = unsafe { base.offset(addend as isize) };
+            let what = base.wrapping_offset(addend as isize);
             match reloc {
                 Reloc::Abs4 => {
-                    // TODO: Handle overflow.
+                    let what = abs4(what)
+                        .unwrap_or_else(|| panic!("address of {} out of range of Abs4", name));
                     #[cfg_attr(feature = "cargo-clippy", allow(clippy::cast_ptr_alignment))]
                     unsafe {
-                        write_unaligned(at as *mut u32, what as u32)
+                        write_unaligned(at as *mut u32, what)
                     };
                 }
                 Reloc::Abs8 => {
@@ -409,23 +594,55 @@ impl<'simple_jit_backend> Backend for SimpleJITBackend {
                         write_unaligned(at as *mut u64, what as u64)
                     };
                 }
-                Reloc::X86PCRel4 | Reloc::X86CallPCRel4 => {
-                    // TODO: Handle overflow.
-                    let pcrel = ((what as isize) - (at as isize)) as i32;
+                Reloc::X86PCRel4 => {
+                    let pcrel = pcrel4(at, what)
+                        .unwrap_or_else(|| panic!("{} out of range of X86PCRel4", name));
                     #[cfg_attr(feature = "cargo-clippy", allow(clippy::cast_ptr_alignment))]
                     unsafe {
                         write_unaligned(at as *mut i32, pcrel)
                     };
                 }
-                Reloc::X86GOTPCRel4 | Reloc::X86CallPLTRel4 => panic!("unexpected PIC relocation"),
+                Reloc::X86CallPCRel4 | Reloc::X86CallPLTRel4 => {
+                    // Calls out of range go through a veneer jumping to the absolute address.
+                    let pcrel = pcrel4(at, what).unwrap_or_else(|| {
+                        let index = func.veneers.iter().position(|n| n == name).unwrap();
+                        let veneer = unsafe { ptr.add(func.veneers_offset + index * VENEER_SIZE) };
+                        write_veneer(veneer, base);
+                        pcrel4(at, veneer.wrapping_offset(addend as isize)).unwrap()
+                    });
+                    #[cfg_attr(feature = "cargo-clippy", allow(clippy::cast_ptr_alignment))]
+                    unsafe {
+                        write_unaligned(at as *mut i32, pcrel)
+                    };
+                }
+                Reloc::X86GOTPCRel4 => {
+                    let index = func.got.iter().position(|n| n == name).unwrap();
+                    let entry = unsafe { ptr.add(func.got_offset + index * GOT_ENTRY_SIZE) };
+                    let pcrel = pcrel4(at, entry.wrapping_offset(addend as isize)).unwrap();
+                    #[cfg_attr(feature = "cargo-clippy", allow(clippy::cast_ptr_alignment))]
+                    unsafe {
+                        write_unaligned(entry as *mut u64, base as u64);
+                        write_unaligned(at as *mut i32, pcrel)
+                    };
+                }
                 _ => unimplemented!(),
             }
         }
-        func.code
+        if let Some(ref mut profiler) = self.profiler {
+            let code = unsafe { slice::from_raw_parts(func.code, func.size) };
+            profiler.function_loaded(&func.name, code, &func.srclocs);
+        }
+        if let Some(stub) = func.stub {
+            // The stub is only redirected once the code is executable, in `publish`.
+            self.pending_stub_updates.push((id, func.code));
+            stub
+        } else {
+            func.code
+        }
     }
 
     fn get_finalized_function(&self, func: &Self::CompiledFunction) -> Self::FinalizedFunction {
-        func.code
+        func.stub.unwrap_or(func.code)
     }
 
     fn finalize_data(
@@ -447,14 +664,14 @@ impl<'simple_jit_backend> Backend for SimpleJITBackend {
             debug_assert!((offset as usize) < data.size);
             let at = unsafe { ptr.offset(offset as isize) };
             let base = self.get_definition(namespace, name);
-            // TODO: Handle overflow.
-            let what = unsafe { base.offset(addend as isize) };
+            let what = base.wrapping_offset(addend as isize);
             match reloc {
                 Reloc::Abs4 => {
-                    // TODO: Handle overflow.
+                    let what = abs4(what)
+                        .unwrap_or_else(|| panic!("address of {} out of range of Abs4", name));
                     #[cfg_attr(feature = "cargo-clippy", allow(clippy::cast_ptr_alignment))]
                     unsafe {
-                        write_unaligned(at as *mut u32, what as u32)
+                        write_unaligned(at as *mut u32, what)
                     };
                 }
                 Reloc::Abs8 => {
@@ -481,6 +698,41 @@ impl<'simple_jit_backend> Backend for SimpleJITBackend {
         // Now that we're done patching, prepare the memory for execution!
         self.readonly_memory.set_readonly();
         self.code_memory.set_readable_and_executable();
+
+        for (id, code) in self.pending_stub_updates.drain(..) {
+            let slot = self.stubs[&id].slot as *const AtomicUsize;
+            unsafe { (*slot).store(code as usize, Ordering::Release) };
+        }
+    }
+
+    fn free_function(&mut self, id: FuncId, func: Self::CompiledFunction) {
+        if let Some(stub) = self.stubs.get(&id) {
+            self.pending_stub_updates.retain(|&(f, _)| f != id);
+            let slot = stub.slot as *const AtomicUsize;
+            unsafe { (*slot).store(0, Ordering::Release) };
+        }
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.function_unloaded(&func.name, func.code);
+        }
+        self.code_memory.free(func.code);
+    }
+
+    fn free_data(&mut self, _id: DataId, data: Self::CompiledData) {
+        if data.writable {
+            self.writable_memory.free(data.storage);
+        } else {
+            self.readonly_memory.free(data.storage);
+        }
+    }
+
+    fn prepare_for_function_redefine(&mut self, id: FuncId) -> ModuleResult<()> {
+        if !self.hotswap {
+            return Err(ModuleError::Backend(
+                "hot code replacement is not enabled".to_owned(),
+            ));
+        }
+        debug_assert!(self.stubs.contains_key(&id));
+        Ok(())
     }
 
     /// SimpleJIT emits code and data into memory as it processes them, so it
@@ -488,6 +740,57 @@ impl<'simple_jit_backend> Backend for SimpleJITBackend {
     fn finish(self) {}
 }
 
+/// Check that SimpleJIT can honor the attributes of the definition of `name`. There is no
+/// linker to resolve symbols against other modules, so the visibility doesn't matter, but
+/// sections and COMDAT groups can't be expressed.
+fn check_attributes(name: &str, attributes: &SymbolAttributes) -> ModuleResult<()> {
+    if let Some(ref section) = attributes.section {
+        return Err(ModuleError::Backend(format!(
+            "SimpleJIT can't place {} in section {}",
+            name, section
+        )));
+    }
+    if let Some(ref comdat) = attributes.comdat {
+        return Err(ModuleError::Backend(format!(
+            "SimpleJIT can't emit COMDAT group {} for {}",
+            comdat, name
+        )));
+    }
+    Ok(())
+}
+
+/// Get the 32-bit absolute address of `what`, if it fits.
+fn abs4(what: *const u8) -> Option<u32> {
+    let what = what as usize;
+    if what as u32 as usize == what {
+        Some(what as u32)
+    } else {
+        None
+    }
+}
+
+/// Get the 32-bit displacement from `at` to `what`, if it fits.
+fn pcrel4(at: *const u8, what: *const u8) -> Option<i32> {
+    let pcrel = (what as isize).wrapping_sub(at as isize);
+    if pcrel as i32 as isize == pcrel {
+        Some(pcrel as i32)
+    } else {
+        None
+    }
+}
+
+/// Write a veneer jumping to `target` at `veneer`: `movabs $target, %r11; jmp *%r11`.
+fn write_veneer(veneer: *mut u8, target: *const u8) {
+    let mut bytes = [0u8; VENEER_SIZE];
+    bytes[0] = 0x49;
+    bytes[1] = 0xbb;
+    bytes[2..10].copy_from_slice(&(target as u64).to_le_bytes());
+    bytes[10] = 0x41;
+    bytes[11] = 0xff;
+    bytes[12] = 0xe3;
+    unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), veneer, VENEER_SIZE) };
+}
+
 #[cfg(not(windows))]
 fn lookup_with_dlsym(name: &str) -> *const u8 {
     let c_str = CString::new(name).unwrap();
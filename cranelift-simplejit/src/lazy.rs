@@ -0,0 +1,137 @@
+//! Lazy compilation of functions on their first call.
+//!
+//! A lazily compiled function is called through a stub, like in hot code replacement mode. Until
+//! the function is defined, the stub jumps to a trampoline which saves the argument registers and
+//! calls `compile_lazy_function`. This runs the compile callback of the function, which defines
+//! it with `Module::define_function`, and finalizes it, which redirects the stub to the new
+//! code. The trampoline then restores the arguments and jumps to it.
+
+use crate::backend::SimpleJITBackend;
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir;
+use cranelift_module::{FuncId, Linkage, Module, ModuleError, ModuleResult};
+use std::process;
+use target_lexicon::Architecture;
+
+/// A callback defining the lazily compiled function `FuncId` in the module.
+pub type LazyCompile = Box<dyn FnMut(&mut Module<SimpleJITBackend>, FuncId) -> ModuleResult<()>>;
+
+/// A function waiting to be compiled, referred to by its trampoline.
+pub(crate) struct LazyFunction {
+    module: *mut Module<SimpleJITBackend>,
+    id: FuncId,
+    compile: Option<LazyCompile>,
+}
+
+/// Declare a function which is compiled by `compile` on its first call.
+///
+/// The function can be called by the code of the module, and by the host through a function
+/// defined in the module, once `finalize_definitions` has been called. It may also be defined
+/// eagerly with `Module::define_function`, in which case `compile` is never called.
+///
+/// The compiled code calls back into `module`, which is why it is boxed: the box may be moved,
+/// but the module itself must stay in it, and must not be borrowed while code from the module is
+/// running. A trampoline which finds another module in its place aborts the process. Errors
+/// returned by `compile` abort the process too, since there is no way to report them to the
+/// caller.
+///
+/// Lazy compilation is only supported on x86-64 with the System V calling convention, and an error
+/// is returned for other targets.
+pub fn declare_lazy_function(
+    module: &mut Box<Module<SimpleJITBackend>>,
+    name: &str,
+    linkage: Linkage,
+    signature: &ir::Signature,
+    compile: LazyCompile,
+) -> ModuleResult<FuncId> {
+    if module.isa().triple().architecture != Architecture::X86_64 || cfg!(windows) {
+        return Err(ModuleError::Backend(
+            "lazy compilation is only supported on x86-64 System V targets".to_string(),
+        ));
+    }
+    if !linkage.is_definable() {
+        return Err(ModuleError::InvalidImportDefinition(name.to_owned()));
+    }
+    let id = module.declare_function(name, linkage, signature)?;
+    if module.declared_functions().as_slice()[id.index()]
+        .compiled
+        .is_some()
+    {
+        return Err(ModuleError::DuplicateDefinition(name.to_owned()));
+    }
+    let lazy = Box::new(LazyFunction {
+        module: &mut **module,
+        id,
+        compile: Some(compile),
+    });
+    module.backend_mut().make_lazy(id, lazy)?;
+    Ok(id)
+}
+
+/// Compile the function of `lazy` and return the address of its code.
+///
+/// This is called from the trampolines with the System V calling convention.
+unsafe extern "sysv64" fn compile_lazy_function(lazy: *mut LazyFunction) -> *const u8 {
+    let id = (*lazy).id;
+    let module = &mut *(*lazy).module;
+    if !module.backend_mut().is_lazy(id, lazy) {
+        eprintln!("lazy function {} called after its module was replaced", id);
+        process::abort();
+    }
+    let mut compile = match (*lazy).compile.take() {
+        Some(compile) => compile,
+        None => {
+            eprintln!("lazy function {} called during its own compilation", id);
+            process::abort();
+        }
+    };
+    if let Err(err) = compile(module, id) {
+        eprintln!("lazy compilation of function {} failed: {}", id, err);
+        process::abort();
+    }
+    if module.declared_functions().as_slice()[id.index()]
+        .compiled
+        .is_none()
+    {
+        eprintln!("lazy function {} was not defined by its callback", id);
+        process::abort();
+    }
+    module.finalize_definitions();
+    module.backend_mut().stub_target(id)
+}
+
+/// Generate the trampoline calling `compile_lazy_function` for `lazy`.
+pub(crate) fn trampoline_code(lazy: *mut LazyFunction) -> Vec<u8> {
+    let mut code = Vec::with_capacity(168);
+    // push %rbp; mov %rsp, %rbp
+    code.extend_from_slice(&[0x55, 0x48, 0x89, 0xe5]);
+    // Save the integer argument registers, and %rax which holds the number of vector registers
+    // used by variadic calls: push %rdi, %rsi, %rdx, %rcx, %r8, %r9, %rax.
+    code.extend_from_slice(&[0x57, 0x56, 0x52, 0x51, 0x41, 0x50, 0x41, 0x51, 0x50]);
+    // sub $0x88, %rsp, to make room for the vector argument registers and realign the stack.
+    code.extend_from_slice(&[0x48, 0x81, 0xec, 0x88, 0x00, 0x00, 0x00]);
+    // movdqu %xmmN, 16*N(%rsp)
+    for n in 0..8 {
+        code.extend_from_slice(&[0xf3, 0x0f, 0x7f, 0x44 | (n << 3), 0x24, 16 * n]);
+    }
+    // movabs $lazy, %rdi
+    code.extend_from_slice(&[0x48, 0xbf]);
+    code.extend_from_slice(&(lazy as u64).to_le_bytes());
+    // movabs $compile_lazy_function, %rax
+    let entry: unsafe extern "sysv64" fn(*mut LazyFunction) -> *const u8 = compile_lazy_function;
+    code.extend_from_slice(&[0x48, 0xb8]);
+    code.extend_from_slice(&(entry as usize as u64).to_le_bytes());
+    // call *%rax; mov %rax, %r11
+    code.extend_from_slice(&[0xff, 0xd0, 0x49, 0x89, 0xc3]);
+    // movdqu 16*N(%rsp), %xmmN
+    for n in 0..8 {
+        code.extend_from_slice(&[0xf3, 0x0f, 0x6f, 0x44 | (n << 3), 0x24, 16 * n]);
+    }
+    // add $0x88, %rsp
+    code.extend_from_slice(&[0x48, 0x81, 0xc4, 0x88, 0x00, 0x00, 0x00]);
+    // pop %rax, %r9, %r8, %rcx, %rdx, %rsi, %rdi, %rbp
+    code.extend_from_slice(&[0x58, 0x41, 0x59, 0x41, 0x58, 0x59, 0x5a, 0x5e, 0x5f, 0x5d]);
+    // jmp *%r11
+    code.extend_from_slice(&[0x41, 0xff, 0xe3]);
+    code
+}
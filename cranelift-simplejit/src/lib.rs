@@ -24,9 +24,15 @@
 )]
 
 mod backend;
+mod lazy;
 mod memory;
+mod profiler;
 
 pub use crate::backend::{SimpleJITBackend, SimpleJITBuilder};
+pub use crate::lazy::{declare_lazy_function, LazyCompile};
+#[cfg(target_os = "linux")]
+pub use crate::profiler::JitDumpAgent;
+pub use crate::profiler::{PerfMapAgent, ProfilingAgent};
 
 /// Version number of this crate.
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
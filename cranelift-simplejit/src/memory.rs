@@ -105,113 +105,207 @@ impl PtrLen {
     }
 }
 
+impl PtrLen {
+    /// Does this memory contain the address `ptr`?
+    fn contains(&self, ptr: *const u8) -> bool {
+        let start = self.ptr as usize;
+        let addr = ptr as usize;
+        addr >= start && addr < start + self.len
+    }
+
+    /// Set the protection of this memory.
+    fn protect(&self, protection: region::Protection) {
+        #[cfg(feature = "selinux-fix")]
+        {
+            if self.map.is_none() {
+                return;
+            }
+        }
+
+        if self.len != 0 {
+            unsafe {
+                region::protect(self.ptr, self.len, protection)
+                    .expect("unable to change memory protection");
+            }
+        }
+    }
+
+    /// Return this memory to the system.
+    #[cfg(all(not(target_os = "windows"), feature = "selinux-fix"))]
+    fn release(self) {
+        // Dropping the map unmaps it.
+    }
+
+    #[cfg(all(not(target_os = "windows"), not(feature = "selinux-fix")))]
+    fn release(self) {
+        if self.len != 0 {
+            // The allocator may write to the memory when it's freed.
+            self.protect(region::Protection::ReadWrite);
+            unsafe { libc::free(self.ptr as *mut libc::c_void) };
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    fn release(self) {
+        use winapi::um::memoryapi::VirtualFree;
+        use winapi::um::winnt::MEM_RELEASE;
+
+        if self.len != 0 {
+            unsafe { VirtualFree(self.ptr as winapi::um::winnt::PVOID, 0, MEM_RELEASE) };
+        }
+    }
+}
+
+/// A region of pages handed out by a `Memory`, with the number of allocations still in use in it.
+struct Chunk {
+    mem: PtrLen,
+    live: usize,
+    protected: bool,
+}
+
+impl Chunk {
+    fn new(mem: PtrLen) -> Self {
+        Self {
+            mem,
+            live: 0,
+            protected: false,
+        }
+    }
+}
+
 /// JIT memory manager. This manages pages of suitably aligned and
 /// accessible memory.
+///
+/// Allocations are carved out of chunks of pages. Once all the allocations in a finished chunk
+/// have been freed, the chunk is made writable again and kept to be reused by later allocations,
+/// up to `MAX_CACHED_BYTES`, beyond which it is returned to the system.
 pub struct Memory {
-    allocations: Vec<PtrLen>,
-    executable: usize,
-    current: PtrLen,
+    chunks: Vec<Chunk>,
+    current: Chunk,
     position: usize,
+    free_chunks: Vec<PtrLen>,
 }
 
+/// Maximum size of the fully freed chunks kept around for reuse by a `Memory`.
+const MAX_CACHED_BYTES: usize = 1 << 20;
+
 impl Memory {
     pub fn new() -> Self {
         Self {
-            allocations: Vec::new(),
-            executable: 0,
-            current: PtrLen::new(),
+            chunks: Vec::new(),
+            current: Chunk::new(PtrLen::new()),
             position: 0,
+            free_chunks: Vec::new(),
         }
     }
 
     fn finish_current(&mut self) {
-        self.allocations
-            .push(mem::replace(&mut self.current, PtrLen::new()));
+        let current = mem::replace(&mut self.current, Chunk::new(PtrLen::new()));
+        if current.live != 0 {
+            self.chunks.push(current);
+        } else if current.mem.len != 0 {
+            self.cache_chunk(current);
+        }
         self.position = 0;
     }
 
+    /// Keep a chunk without live allocations to be reused, or return it to the system if that
+    /// would cache more than `MAX_CACHED_BYTES`.
+    fn cache_chunk(&mut self, chunk: Chunk) {
+        debug_assert_eq!(chunk.live, 0);
+        let cached: usize = self.free_chunks.iter().map(|mem| mem.len).sum();
+        if cached + chunk.mem.len > MAX_CACHED_BYTES {
+            chunk.mem.release();
+        } else {
+            if chunk.protected {
+                chunk.mem.protect(region::Protection::ReadWrite);
+            }
+            self.free_chunks.push(chunk.mem);
+        }
+    }
+
+    /// Get a chunk of at least `size` bytes, reusing a freed one if possible.
+    fn new_chunk(&mut self, size: usize) -> Result<PtrLen, String> {
+        match self.free_chunks.iter().position(|mem| mem.len >= size) {
+            Some(index) => Ok(self.free_chunks.swap_remove(index)),
+            // TODO: Allocate more at a time.
+            None => PtrLen::with_size(size),
+        }
+    }
+
     /// TODO: Use a proper error type.
     pub fn allocate(&mut self, size: usize, align: u8) -> Result<*mut u8, String> {
+        // Zero-sized allocations still get their own address, so they can be freed.
+        let size = size.max(1);
+
         if self.position % align as usize != 0 {
             self.position += align as usize - self.position % align as usize;
             debug_assert!(self.position % align as usize == 0);
         }
 
-        if size <= self.current.len - self.position {
+        if self.position <= self.current.mem.len && size <= self.current.mem.len - self.position {
             // TODO: Ensure overflow is not possible.
-            let ptr = unsafe { self.current.ptr.add(self.position) };
+            let ptr = unsafe { self.current.mem.ptr.add(self.position) };
             self.position += size;
+            self.current.live += 1;
             return Ok(ptr);
         }
 
         self.finish_current();
 
-        // TODO: Allocate more at a time.
-        self.current = PtrLen::with_size(size)?;
+        self.current = Chunk::new(self.new_chunk(size)?);
         self.position = size;
-        Ok(self.current.ptr)
+        self.current.live = 1;
+        Ok(self.current.mem.ptr)
     }
 
-    /// Set all memory allocated in this `Memory` up to now as readable and executable.
-    pub fn set_readable_and_executable(&mut self) {
-        self.finish_current();
-
-        #[cfg(feature = "selinux-fix")]
-        {
-            for &PtrLen { ref map, ptr, len } in &self.allocations[self.executable..] {
-                if len != 0 && map.is_some() {
-                    unsafe {
-                        region::protect(ptr, len, region::Protection::ReadExecute)
-                            .expect("unable to make memory readable+executable");
-                    }
-                }
+    /// Free the allocation at `ptr`.
+    ///
+    /// The memory is only reused once all the allocations sharing its pages have been freed.
+    pub fn free(&mut self, ptr: *const u8) {
+        if self.current.mem.contains(ptr) {
+            self.current.live -= 1;
+            if self.current.live == 0 {
+                self.position = 0;
             }
+            return;
         }
 
-        #[cfg(not(feature = "selinux-fix"))]
-        {
-            for &PtrLen { ptr, len } in &self.allocations[self.executable..] {
-                if len != 0 {
-                    unsafe {
-                        region::protect(ptr, len, region::Protection::ReadExecute)
-                            .expect("unable to make memory readable+executable");
-                    }
-                }
-            }
+        let index = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.mem.contains(ptr))
+            .expect("freeing memory which wasn't allocated here");
+        self.chunks[index].live -= 1;
+        if self.chunks[index].live == 0 {
+            let chunk = self.chunks.swap_remove(index);
+            self.cache_chunk(chunk);
         }
     }
 
+    /// Set all memory allocated in this `Memory` up to now as readable and executable.
+    pub fn set_readable_and_executable(&mut self) {
+        self.protect_finished(region::Protection::ReadExecute);
+    }
+
     /// Set all memory allocated in this `Memory` up to now as readonly.
     pub fn set_readonly(&mut self) {
-        self.finish_current();
+        self.protect_finished(region::Protection::Read);
+    }
 
-        #[cfg(feature = "selinux-fix")]
-        {
-            for &PtrLen { ref map, ptr, len } in &self.allocations[self.executable..] {
-                if len != 0 && map.is_some() {
-                    unsafe {
-                        region::protect(ptr, len, region::Protection::Read)
-                            .expect("unable to make memory readonly");
-                    }
-                }
-            }
-        }
+    fn protect_finished(&mut self, protection: region::Protection) {
+        self.finish_current();
 
-        #[cfg(not(feature = "selinux-fix"))]
-        {
-            for &PtrLen { ptr, len } in &self.allocations[self.executable..] {
-                if len != 0 {
-                    unsafe {
-                        region::protect(ptr, len, region::Protection::Read)
-                            .expect("unable to make memory readonly");
-                    }
-                }
+        for chunk in &mut self.chunks {
+            if !chunk.protected {
+                chunk.mem.protect(protection);
+                chunk.protected = true;
             }
         }
     }
 }
 
-// TODO: Implement Drop to unprotect and deallocate the memory?
+// Memory isn't released on drop, since pointers to the compiled code may outlive the `Memory`.
 
 #[cfg(test)]
 mod tests {
@@ -224,4 +318,40 @@ mod tests {
         assert_eq!(round_up_to_page_size(4096, 4096), 4096);
         assert_eq!(round_up_to_page_size(4097, 4096), 8192);
     }
+
+    #[test]
+    fn reuse_freed_memory() {
+        let mut memory = Memory::new();
+        let page_size = region::page::size();
+
+        for _ in 0..1000 {
+            let a = memory.allocate(100, 16).unwrap();
+            let b = memory.allocate(2 * page_size, 16).unwrap();
+            memory.set_readable_and_executable();
+            memory.free(a);
+            memory.free(b);
+
+            let mut total: usize = memory.chunks.iter().map(|chunk| chunk.mem.len).sum();
+            total += memory.free_chunks.iter().map(|mem| mem.len).sum::<usize>();
+            total += memory.current.mem.len;
+            assert!(total <= 4 * page_size);
+        }
+    }
+
+    #[test]
+    fn limit_cached_memory() {
+        let mut memory = Memory::new();
+
+        // The current chunk is cached like the others when it is finished without any live
+        // allocations, and released when it is too large.
+        let a = memory.allocate(2 * MAX_CACHED_BYTES, 16).unwrap();
+        memory.free(a);
+        memory.set_readonly();
+        assert!(memory.free_chunks.is_empty());
+
+        let b = memory.allocate(100, 16).unwrap();
+        memory.free(b);
+        memory.set_readonly();
+        assert_eq!(memory.free_chunks.len(), 1);
+    }
 }
@@ -0,0 +1,329 @@
+//! Profiler support.
+//!
+//! A `ProfilingAgent` is notified of the functions loaded and unloaded by SimpleJIT, so it can
+//! tell a profiler where the generated code is. Two agents are provided for `perf` on Linux:
+//!
+//! - `PerfMapAgent` writes `/tmp/perf-<pid>.map`, which only gives the names of the functions,
+//! - `JitDumpAgent` writes a jitdump file, which also records the code and the source locations,
+//!   so `perf inject --jit` can annotate the generated code.
+
+use cranelift_codegen::binemit::CodeOffset;
+use cranelift_codegen::ir;
+use cranelift_codegen::isa::TargetIsa;
+use cranelift_codegen::Context;
+use std::fs::File;
+use std::io::{self, Write};
+#[cfg(target_os = "linux")]
+use std::path::Path;
+use std::process;
+#[cfg(target_os = "linux")]
+use std::ptr;
+
+/// An agent notified of the code generated by SimpleJIT, on behalf of a profiler.
+pub trait ProfilingAgent {
+    /// Notify the agent that the function `name` has been finalized, with the final `code`.
+    ///
+    /// `srclocs` maps code offsets to the source location of the instructions starting there, in
+    /// increasing offset order. Only the changes of source location are listed.
+    fn function_loaded(&mut self, name: &str, code: &[u8], srclocs: &[(CodeOffset, ir::SourceLoc)]);
+
+    /// Notify the agent that the code of the function `name` at `code` has been freed.
+    fn function_unloaded(&mut self, _name: &str, _code: *const u8) {}
+}
+
+/// Collect the source locations of the code compiled in `ctx`, for
+/// `ProfilingAgent::function_loaded`.
+pub(crate) fn function_srclocs(
+    isa: &dyn TargetIsa,
+    ctx: &Context,
+) -> Vec<(CodeOffset, ir::SourceLoc)> {
+    // The function isn't compiled when its code comes from a compilation cache, but the cache
+    // entry records the source locations.
+    match ctx.cached_code() {
+        Some(code) => code.srclocs.clone(),
+        None => ctx.func.srcloc_changes(&isa.encoding_info()),
+    }
+}
+
+/// A `ProfilingAgent` writing the names of the functions to `/tmp/perf-<pid>.map`.
+pub struct PerfMapAgent {
+    file: File,
+}
+
+impl PerfMapAgent {
+    /// Create a new `PerfMapAgent`, appending to the map file of the current process.
+    pub fn new() -> io::Result<Self> {
+        let file = ::std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("/tmp/perf-{}.map", process::id()))?;
+        Ok(Self { file })
+    }
+}
+
+impl ProfilingAgent for PerfMapAgent {
+    fn function_loaded(
+        &mut self,
+        name: &str,
+        code: &[u8],
+        _srclocs: &[(CodeOffset, ir::SourceLoc)],
+    ) {
+        let _ = writeln!(
+            self.file,
+            "{:x} {:x} {}",
+            code.as_ptr() as usize,
+            code.len(),
+            name
+        );
+    }
+}
+
+/// The magic number at the start of a jitdump file, "JiTD".
+#[cfg(target_os = "linux")]
+const JITDUMP_MAGIC: u32 = 0x4a69_5444;
+/// The version of the jitdump format.
+#[cfg(target_os = "linux")]
+const JITDUMP_VERSION: u32 = 1;
+/// The size of the jitdump file header.
+#[cfg(target_os = "linux")]
+const JITDUMP_HEADER_SIZE: u32 = 40;
+/// The size of the header of each record.
+#[cfg(target_os = "linux")]
+const JITDUMP_RECORD_HEADER_SIZE: u32 = 16;
+
+/// The jitdump record types.
+#[cfg(target_os = "linux")]
+const JIT_CODE_LOAD: u32 = 0;
+#[cfg(target_os = "linux")]
+const JIT_CODE_CLOSE: u32 = 3;
+#[cfg(target_os = "linux")]
+const JIT_CODE_DEBUG_INFO: u32 = 2;
+
+/// The ELF machine of the host, as recorded in the jitdump header.
+#[cfg(target_os = "linux")]
+fn elf_machine() -> u32 {
+    if cfg!(target_arch = "x86_64") {
+        62
+    } else if cfg!(target_arch = "x86") {
+        3
+    } else if cfg!(target_arch = "aarch64") {
+        183
+    } else if cfg!(target_arch = "arm") {
+        40
+    } else {
+        0
+    }
+}
+
+/// A `ProfilingAgent` writing a jitdump file for `perf inject --jit`.
+///
+/// Each loaded function produces a code-load record with its code, preceded by a debug-info record
+/// when it has source locations. Since a `SourceLoc` is only an opaque number, it is reported as
+/// the line number in a file named after the function.
+///
+/// The timestamps use the monotonic clock, so the profile must be recorded with `perf record -k
+/// mono`. The jitdump format has no record for unloaded code, so freed functions are ignored.
+#[cfg(target_os = "linux")]
+pub struct JitDumpAgent {
+    file: File,
+    marker: *mut libc::c_void,
+    marker_size: usize,
+    code_index: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl JitDumpAgent {
+    /// Create a new `JitDumpAgent`, writing to `jit-<pid>.dump` in `dir`.
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(format!("jit-{}.dump", process::id()));
+        let mut file = ::std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        // `perf record` finds the jitdump file through an executable mapping of it.
+        let marker_size = region::page::size();
+        let marker = unsafe {
+            use std::os::unix::io::AsRawFd;
+            libc::mmap(
+                ptr::null_mut(),
+                marker_size,
+                libc::PROT_READ | libc::PROT_EXEC,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if marker == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut header = Vec::with_capacity(JITDUMP_HEADER_SIZE as usize);
+        header.extend_from_slice(&JITDUMP_MAGIC.to_ne_bytes());
+        header.extend_from_slice(&JITDUMP_VERSION.to_ne_bytes());
+        header.extend_from_slice(&JITDUMP_HEADER_SIZE.to_ne_bytes());
+        header.extend_from_slice(&elf_machine().to_ne_bytes());
+        header.extend_from_slice(&0u32.to_ne_bytes());
+        header.extend_from_slice(&process::id().to_ne_bytes());
+        header.extend_from_slice(&timestamp().to_ne_bytes());
+        header.extend_from_slice(&0u64.to_ne_bytes());
+        file.write_all(&header)?;
+
+        Ok(Self {
+            file,
+            marker,
+            marker_size,
+            code_index: 0,
+        })
+    }
+
+    /// Write a record of type `id` with the given `body`.
+    fn write_record(&mut self, id: u32, body: &[u8]) -> io::Result<()> {
+        let mut record = Vec::with_capacity(JITDUMP_RECORD_HEADER_SIZE as usize + body.len());
+        record.extend_from_slice(&id.to_ne_bytes());
+        record.extend_from_slice(&(JITDUMP_RECORD_HEADER_SIZE + body.len() as u32).to_ne_bytes());
+        record.extend_from_slice(&timestamp().to_ne_bytes());
+        record.extend_from_slice(body);
+        self.file.write_all(&record)
+    }
+
+    fn write_debug_info(
+        &mut self,
+        name: &str,
+        code: &[u8],
+        srclocs: &[(CodeOffset, ir::SourceLoc)],
+    ) -> io::Result<()> {
+        let addr = code.as_ptr() as u64;
+        let mut body = Vec::new();
+        body.extend_from_slice(&addr.to_ne_bytes());
+        body.extend_from_slice(&(srclocs.len() as u64).to_ne_bytes());
+        for &(offset, srcloc) in srclocs {
+            body.extend_from_slice(&(addr + u64::from(offset)).to_ne_bytes());
+            body.extend_from_slice(&(srcloc.bits() as i32).to_ne_bytes());
+            body.extend_from_slice(&0i32.to_ne_bytes());
+            body.extend_from_slice(name.as_bytes());
+            body.push(0);
+        }
+        self.write_record(JIT_CODE_DEBUG_INFO, &body)
+    }
+
+    fn write_code_load(&mut self, name: &str, code: &[u8]) -> io::Result<()> {
+        let addr = code.as_ptr() as u64;
+        let mut body = Vec::with_capacity(40 + name.len() + 1 + code.len());
+        body.extend_from_slice(&process::id().to_ne_bytes());
+        body.extend_from_slice(&thread_id().to_ne_bytes());
+        body.extend_from_slice(&addr.to_ne_bytes());
+        body.extend_from_slice(&addr.to_ne_bytes());
+        body.extend_from_slice(&(code.len() as u64).to_ne_bytes());
+        body.extend_from_slice(&self.code_index.to_ne_bytes());
+        body.extend_from_slice(name.as_bytes());
+        body.push(0);
+        body.extend_from_slice(code);
+        self.code_index += 1;
+        self.write_record(JIT_CODE_LOAD, &body)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ProfilingAgent for JitDumpAgent {
+    fn function_loaded(
+        &mut self,
+        name: &str,
+        code: &[u8],
+        srclocs: &[(CodeOffset, ir::SourceLoc)],
+    ) {
+        // The debug info must precede the code it applies to.
+        if !srclocs.is_empty() {
+            let _ = self.write_debug_info(name, code, srclocs);
+        }
+        let _ = self.write_code_load(name, code);
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for JitDumpAgent {
+    fn drop(&mut self) {
+        let _ = self.write_record(JIT_CODE_CLOSE, &[]);
+        unsafe { libc::munmap(self.marker, self.marker_size) };
+    }
+}
+
+/// The current time of the monotonic clock in nanoseconds.
+#[cfg(target_os = "linux")]
+fn timestamp() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+#[cfg(target_os = "linux")]
+fn thread_id() -> u32 {
+    unsafe { libc::syscall(libc::SYS_gettid) as u32 }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn read_u32(bytes: &[u8], at: usize) -> u32 {
+        let mut b = [0; 4];
+        b.copy_from_slice(&bytes[at..at + 4]);
+        u32::from_ne_bytes(b)
+    }
+
+    #[test]
+    fn jitdump_records() {
+        let dir = ::std::env::temp_dir().join(format!("simplejit-jitdump-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let code = [0x90u8, 0x90, 0xc3];
+        {
+            let mut agent = JitDumpAgent::new(&dir).unwrap();
+            agent.function_loaded("nop", &code, &[(0, ir::SourceLoc::new(7))]);
+            agent.function_loaded("ret", &code[2..], &[]);
+        }
+        let path = dir.join(format!("jit-{}.dump", process::id()));
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(read_u32(&bytes, 0), JITDUMP_MAGIC);
+        assert_eq!(read_u32(&bytes, 8), JITDUMP_HEADER_SIZE);
+        assert_eq!(read_u32(&bytes, 20), process::id());
+
+        let mut records = Vec::new();
+        let mut at = JITDUMP_HEADER_SIZE as usize;
+        while at < bytes.len() {
+            let size = read_u32(&bytes, at + 4) as usize;
+            records.push((read_u32(&bytes, at), &bytes[at + 16..at + size]));
+            at += size;
+        }
+        assert_eq!(at, bytes.len());
+        let ids: Vec<u32> = records.iter().map(|&(id, _)| id).collect();
+        assert_eq!(
+            ids,
+            [
+                JIT_CODE_DEBUG_INFO,
+                JIT_CODE_LOAD,
+                JIT_CODE_LOAD,
+                JIT_CODE_CLOSE
+            ]
+        );
+
+        // The debug info has one entry at the start of the code, on line 7 of "nop".
+        let debug = records[0].1;
+        assert_eq!(read_u32(debug, 8), 1);
+        assert_eq!(read_u32(debug, 24), 7);
+        assert_eq!(&debug[32..], b"nop\0");
+
+        // The code load records end with the name and the code.
+        let load = records[1].1;
+        assert_eq!(&load[40..], b"nop\0\x90\x90\xc3");
+        assert_eq!(read_u32(records[2].1, 32), 1);
+        assert_eq!(&records[2].1[40..], b"ret\0\xc3");
+    }
+}
@@ -1,3 +1,4 @@
+use cranelift_codegen::binemit;
 use cranelift_codegen::ir::*;
 use cranelift_codegen::isa::CallConv;
 use cranelift_codegen::{ir::types::I16, Context};
@@ -25,6 +26,125 @@ fn error_on_incompatible_sig_in_declare_function() {
         .unwrap(); // Make sure this is an error
 }
 
+#[test]
+fn merge_symbol_attributes_in_declarations() {
+    let mut module: Module<SimpleJITBackend> =
+        Module::new(SimpleJITBuilder::new(default_libcall_names()));
+    let sig = module.make_signature();
+    let hidden = SymbolAttributes {
+        visibility: Visibility::Hidden,
+        ..SymbolAttributes::default()
+    };
+    let in_section = SymbolAttributes {
+        section: Some(".text.hot".to_owned()),
+        ..SymbolAttributes::default()
+    };
+    module
+        .declare_function_with_attributes("abc", Linkage::Export, &sig, &hidden)
+        .unwrap();
+    module
+        .declare_function_with_attributes("abc", Linkage::Import, &sig, &in_section)
+        .unwrap();
+    let decl = &module.declared_functions().next().unwrap().decl;
+    assert_eq!(
+        decl.attributes,
+        SymbolAttributes {
+            visibility: Visibility::Hidden,
+            section: Some(".text.hot".to_owned()),
+            comdat: None,
+        }
+    );
+
+    // The section and COMDAT group of a symbol can't change once given.
+    let in_other_section = SymbolAttributes {
+        section: Some(".text.cold".to_owned()),
+        ..SymbolAttributes::default()
+    };
+    module
+        .declare_function_with_attributes("abc", Linkage::Export, &sig, &in_other_section)
+        .err()
+        .unwrap();
+    let in_comdat = |name: &str| SymbolAttributes {
+        comdat: Some(name.to_owned()),
+        ..SymbolAttributes::default()
+    };
+    module
+        .declare_data_with_attributes("data", Linkage::Export, false, None, &in_comdat("a"))
+        .unwrap();
+    module
+        .declare_data_with_attributes("data", Linkage::Export, false, None, &in_comdat("b"))
+        .err()
+        .unwrap();
+
+    // A declaration that fails to merge leaves the existing one unchanged.
+    module
+        .declare_function_with_attributes("def", Linkage::Import, &sig, &in_comdat("a"))
+        .unwrap();
+    let hidden_in_comdat_b = SymbolAttributes {
+        visibility: Visibility::Hidden,
+        ..in_comdat("b")
+    };
+    module
+        .declare_function_with_attributes("def", Linkage::Export, &sig, &hidden_in_comdat_b)
+        .err()
+        .unwrap();
+    let decl = &module.declared_functions().nth(1).unwrap().decl;
+    assert!(decl.linkage == Linkage::Import);
+    assert_eq!(decl.attributes, in_comdat("a"));
+}
+
+#[test]
+fn reject_sections_and_comdats() {
+    let mut module: Module<SimpleJITBackend> =
+        Module::new(SimpleJITBuilder::new(default_libcall_names()));
+    let sig = module.make_signature();
+    let define = |module: &mut Module<SimpleJITBackend>, name, attributes| {
+        let func_id = module
+            .declare_function_with_attributes(name, Linkage::Local, &sig, attributes)
+            .unwrap();
+        let mut ctx = Context::new();
+        ctx.func = Function::with_name_signature(ExternalName::user(0, 0), sig.clone());
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let ebb = bcx.create_ebb();
+            bcx.switch_to_block(ebb);
+            bcx.ins().return_(&[]);
+        }
+        module.define_function(func_id, &mut ctx)
+    };
+
+    // The visibility doesn't matter without a linker, but sections and COMDAT groups can't be
+    // honored.
+    let hidden = SymbolAttributes {
+        visibility: Visibility::Hidden,
+        ..SymbolAttributes::default()
+    };
+    define(&mut module, "hidden", &hidden).unwrap();
+    let in_section = SymbolAttributes {
+        section: Some(".text.hot".to_owned()),
+        ..SymbolAttributes::default()
+    };
+    match define(&mut module, "in_section", &in_section) {
+        Err(ModuleError::Backend(message)) => assert!(message.contains(".text.hot")),
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    let in_comdat = SymbolAttributes {
+        comdat: Some("group".to_owned()),
+        ..SymbolAttributes::default()
+    };
+    let data_id = module
+        .declare_data_with_attributes("data", Linkage::Local, false, None, &in_comdat)
+        .unwrap();
+    let mut data_ctx = DataContext::new();
+    data_ctx.define_zeroinit(4);
+    match module.define_data(data_id, &data_ctx) {
+        Err(ModuleError::Backend(message)) => assert!(message.contains("group")),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
 fn define_simple_function(module: &mut Module<SimpleJITBackend>) -> FuncId {
     let sig = Signature {
         params: vec![],
@@ -195,3 +315,1204 @@ fn libcall_function() {
 
     module.finalize_definitions();
 }
+
+#[test]
+fn inline_module_function() {
+    use cranelift_codegen::inline::SizeHeuristic;
+
+    let mut module: Module<SimpleJITBackend> =
+        Module::new(SimpleJITBuilder::new(default_libcall_names()));
+    module.enable_inlining(Box::new(SizeHeuristic::default()));
+
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I32));
+    sig.returns.push(AbiParam::new(types::I32));
+
+    let double_id = module
+        .declare_function("double", Linkage::Local, &sig)
+        .unwrap();
+    let quad_id = module
+        .declare_function("quad", Linkage::Export, &sig)
+        .unwrap();
+
+    let mut ctx = module.make_context();
+    let mut func_ctx = FunctionBuilderContext::new();
+
+    ctx.func.signature = sig.clone();
+    ctx.func.name = ExternalName::user(0, double_id.as_u32());
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let ebb = bcx.create_ebb();
+        bcx.append_ebb_params_for_function_params(ebb);
+        bcx.switch_to_block(ebb);
+        let x = bcx.ebb_params(ebb)[0];
+        let sum = bcx.ins().iadd(x, x);
+        bcx.ins().return_(&[sum]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(double_id, &mut ctx).unwrap();
+    module.clear_context(&mut ctx);
+
+    ctx.func.signature = sig;
+    ctx.func.name = ExternalName::user(0, quad_id.as_u32());
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let double = module.declare_func_in_func(double_id, bcx.func);
+        let ebb = bcx.create_ebb();
+        bcx.append_ebb_params_for_function_params(ebb);
+        bcx.switch_to_block(ebb);
+        let x = bcx.ebb_params(ebb)[0];
+        let call = bcx.ins().call(double, &[x]);
+        let y = bcx.inst_results(call)[0];
+        let call = bcx.ins().call(double, &[y]);
+        let z = bcx.inst_results(call)[0];
+        bcx.ins().return_(&[z]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(quad_id, &mut ctx).unwrap();
+
+    // Both calls were inlined, so the compiled function doesn't call anything.
+    let has_call = ctx.func.layout.ebbs().any(|ebb| {
+        ctx.func
+            .layout
+            .ebb_insts(ebb)
+            .any(|inst| ctx.func.dfg[inst].opcode().is_call())
+    });
+    assert!(!has_call);
+
+    module.finalize_definitions();
+    let code = module.get_finalized_function(quad_id);
+    let quad = unsafe { core::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(code) };
+    assert_eq!(quad(5), 20);
+}
+
+#[test]
+fn inline_after_failed_definition() {
+    use cranelift_codegen::inline::SizeHeuristic;
+
+    let mut module: Module<SimpleJITBackend> =
+        Module::new(SimpleJITBuilder::new(default_libcall_names()));
+    module.enable_inlining(Box::new(SizeHeuristic::default()));
+
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I32));
+    sig.returns.push(AbiParam::new(types::I32));
+
+    let double_id = module
+        .declare_function("double", Linkage::Local, &sig)
+        .unwrap();
+    let quad_id = module
+        .declare_function("quad", Linkage::Export, &sig)
+        .unwrap();
+
+    let mut ctx = module.make_context();
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut define_double = |module: &mut Module<SimpleJITBackend>, valid: bool| {
+        module.clear_context(&mut ctx);
+        ctx.func.signature = sig.clone();
+        ctx.func.name = ExternalName::user(0, double_id.as_u32());
+        {
+            let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let ebb = bcx.create_ebb();
+            bcx.append_ebb_params_for_function_params(ebb);
+            bcx.switch_to_block(ebb);
+            let x = bcx.ebb_params(ebb)[0];
+            let sum = bcx.ins().iadd(x, x);
+            // The invalid version doesn't return anything, and fails verification.
+            if valid {
+                bcx.ins().return_(&[sum]);
+            } else {
+                bcx.ins().return_(&[]);
+            }
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module.define_function(double_id, &mut ctx)
+    };
+    assert!(define_double(&mut module, false).is_err());
+
+    // The body of the failed definition must not be inlined.
+    let mut ctx = module.make_context();
+    let mut func_ctx = FunctionBuilderContext::new();
+    ctx.func.signature = sig.clone();
+    ctx.func.name = ExternalName::user(0, quad_id.as_u32());
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let double = module.declare_func_in_func(double_id, bcx.func);
+        let ebb = bcx.create_ebb();
+        bcx.append_ebb_params_for_function_params(ebb);
+        bcx.switch_to_block(ebb);
+        let x = bcx.ebb_params(ebb)[0];
+        let call = bcx.ins().call(double, &[x]);
+        let y = bcx.inst_results(call)[0];
+        let call = bcx.ins().call(double, &[y]);
+        let z = bcx.inst_results(call)[0];
+        bcx.ins().return_(&[z]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(quad_id, &mut ctx).unwrap();
+    define_double(&mut module, true).unwrap();
+
+    module.finalize_definitions();
+    let code = module.get_finalized_function(quad_id);
+    let quad = unsafe { core::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(code) };
+    assert_eq!(quad(5), 20);
+}
+
+fn define_const_function(
+    module: &mut Module<SimpleJITBackend>,
+    ctx: &mut Context,
+    func_id: FuncId,
+    value: i64,
+) {
+    module.clear_context(ctx);
+    ctx.func.signature.returns.push(AbiParam::new(types::I32));
+    ctx.func.name = ExternalName::user(0, func_id.as_u32());
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let ebb = bcx.create_ebb();
+        bcx.switch_to_block(ebb);
+        let v = bcx.ins().iconst(types::I32, value);
+        bcx.ins().return_(&[v]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(func_id, ctx).unwrap();
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn hotswap_function() {
+    let mut builder = SimpleJITBuilder::new(default_libcall_names());
+    builder.hotswap(true).unwrap();
+    let mut module: Module<SimpleJITBackend> = Module::new(builder);
+
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I32));
+    let callee_id = module
+        .declare_function("callee", Linkage::Local, &sig)
+        .unwrap();
+    let caller_id = module
+        .declare_function("caller", Linkage::Export, &sig)
+        .unwrap();
+
+    let mut ctx = module.make_context();
+    define_const_function(&mut module, &mut ctx, callee_id, 1);
+
+    module.clear_context(&mut ctx);
+    ctx.func.signature = sig;
+    ctx.func.name = ExternalName::user(0, caller_id.as_u32());
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let callee = module.declare_func_in_func(callee_id, bcx.func);
+        let ebb = bcx.create_ebb();
+        bcx.switch_to_block(ebb);
+        let call = bcx.ins().call(callee, &[]);
+        let v = bcx.inst_results(call)[0];
+        bcx.ins().return_(&[v]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(caller_id, &mut ctx).unwrap();
+
+    module.finalize_definitions();
+    let caller = module.get_finalized_function(caller_id);
+    let caller = unsafe { core::mem::transmute::<*const u8, extern "C" fn() -> i32>(caller) };
+    let callee = module.get_finalized_function(callee_id);
+    let callee = unsafe { core::mem::transmute::<*const u8, extern "C" fn() -> i32>(callee) };
+    assert_eq!(caller(), 1);
+    assert_eq!(callee(), 1);
+
+    // Redefining the callee redirects the calls from the code finalized earlier.
+    module.prepare_for_function_redefine(callee_id).unwrap();
+    define_const_function(&mut module, &mut ctx, callee_id, 2);
+    module.finalize_definitions();
+    assert_eq!(caller(), 2);
+    assert_eq!(callee(), 2);
+}
+
+#[test]
+fn hotswap_unsupported_target() {
+    use cranelift_codegen::{isa, settings};
+
+    let isa = isa::lookup_by_name("i686")
+        .unwrap()
+        .finish(settings::Flags::new(settings::builder()));
+    let mut builder = SimpleJITBuilder::with_isa(isa, default_libcall_names());
+    assert!(builder.hotswap(false).is_ok());
+    match builder.hotswap(true) {
+        Err(ModuleError::Backend(_)) => {}
+        _ => panic!("hot code replacement should only be supported on x86-64"),
+    }
+}
+
+#[test]
+fn redefine_without_hotswap() {
+    let mut module: Module<SimpleJITBackend> =
+        Module::new(SimpleJITBuilder::new(default_libcall_names()));
+
+    let func_id = define_simple_function(&mut module);
+    module.finalize_definitions();
+    match module.prepare_for_function_redefine(func_id) {
+        Err(ModuleError::Backend(_)) => {}
+        _ => panic!("redefinition should fail without hot code replacement"),
+    }
+}
+
+#[test]
+fn free_functions_in_a_loop() {
+    use std::collections::HashSet;
+
+    let mut module: Module<SimpleJITBackend> =
+        Module::new(SimpleJITBuilder::new(default_libcall_names()));
+
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I32));
+    let func_id = module
+        .declare_function("query", Linkage::Export, &sig)
+        .unwrap();
+    let data_id = module
+        .declare_data("table", Linkage::Export, false, None)
+        .unwrap();
+
+    // The freed memory is reused, so only a handful of distinct addresses are ever handed out.
+    let mut ctx = module.make_context();
+    let mut addresses = HashSet::new();
+    for i in 0..1000 {
+        define_const_function(&mut module, &mut ctx, func_id, i);
+        let mut data_ctx = DataContext::new();
+        data_ctx.define(vec![i as u8; 5000].into_boxed_slice());
+        module.define_data(data_id, &data_ctx).unwrap();
+        module.finalize_definitions();
+
+        let code = module.get_finalized_function(func_id);
+        let query = unsafe { core::mem::transmute::<*const u8, extern "C" fn() -> i32>(code) };
+        assert_eq!(query(), i as i32);
+        let (data, size) = module.get_finalized_data(data_id);
+        assert_eq!(size, 5000);
+        assert_eq!(unsafe { *data.add(4999) }, i as u8);
+
+        addresses.insert(code as usize);
+        addresses.insert(data as usize);
+        module.free_function(func_id).unwrap();
+        module.free_data(data_id).unwrap();
+    }
+    assert!(addresses.len() <= 4);
+
+    match module.free_function(func_id) {
+        Err(ModuleError::Undefined(_)) => {}
+        _ => panic!("freeing an undefined function should fail"),
+    }
+}
+
+#[test]
+#[cfg(all(target_arch = "x86_64", not(windows)))]
+fn lazy_compilation() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let mut module: Box<Module<SimpleJITBackend>> =
+        Box::new(Module::new(SimpleJITBuilder::new(default_libcall_names())));
+    let compiled = Rc::new(Cell::new(0));
+
+    // twice(v) = v * 2
+    let mut twice_sig = module.make_signature();
+    twice_sig.params.push(AbiParam::new(types::I64));
+    twice_sig.returns.push(AbiParam::new(types::I64));
+    let counter = compiled.clone();
+    let twice_id = declare_lazy_function(
+        &mut module,
+        "twice",
+        Linkage::Local,
+        &twice_sig.clone(),
+        Box::new(move |module, id| {
+            counter.set(counter.get() + 1);
+            let mut ctx = module.make_context();
+            ctx.func.signature = twice_sig.clone();
+            ctx.func.name = ExternalName::user(0, id.as_u32());
+            let mut func_ctx = FunctionBuilderContext::new();
+            {
+                let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+                let ebb = bcx.create_ebb();
+                bcx.append_ebb_params_for_function_params(ebb);
+                bcx.switch_to_block(ebb);
+                let v = bcx.ebb_params(ebb)[0];
+                let v = bcx.ins().imul_imm(v, 2);
+                bcx.ins().return_(&[v]);
+                bcx.seal_all_blocks();
+                bcx.finalize();
+            }
+            module.define_function(id, &mut ctx).map(|_| ())
+        }),
+    )
+    .unwrap();
+
+    // sum(a, b, x) = twice(a + b) + x, which compiles `twice` lazily in turn.
+    let mut sum_sig = module.make_signature();
+    sum_sig.params.push(AbiParam::new(types::I64));
+    sum_sig.params.push(AbiParam::new(types::I64));
+    sum_sig.params.push(AbiParam::new(types::F64));
+    sum_sig.returns.push(AbiParam::new(types::I64));
+    let counter = compiled.clone();
+    let sig = sum_sig.clone();
+    let sum_id = declare_lazy_function(
+        &mut module,
+        "sum",
+        Linkage::Export,
+        &sum_sig,
+        Box::new(move |module, id| {
+            counter.set(counter.get() + 1);
+            let mut ctx = module.make_context();
+            ctx.func.signature = sig.clone();
+            ctx.func.name = ExternalName::user(0, id.as_u32());
+            let mut func_ctx = FunctionBuilderContext::new();
+            {
+                let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+                let twice = module.declare_func_in_func(twice_id, bcx.func);
+                let ebb = bcx.create_ebb();
+                bcx.append_ebb_params_for_function_params(ebb);
+                bcx.switch_to_block(ebb);
+                let params = bcx.ebb_params(ebb).to_vec();
+                let v = bcx.ins().iadd(params[0], params[1]);
+                let call = bcx.ins().call(twice, &[v]);
+                let v = bcx.inst_results(call)[0];
+                let x = bcx.ins().fcvt_to_sint(types::I64, params[2]);
+                let v = bcx.ins().iadd(v, x);
+                bcx.ins().return_(&[v]);
+                bcx.seal_all_blocks();
+                bcx.finalize();
+            }
+            module.define_function(id, &mut ctx).map(|_| ())
+        }),
+    )
+    .unwrap();
+
+    // main(a, b, x) = sum(a, b, x), defined eagerly.
+    let main_id = module
+        .declare_function("main", Linkage::Export, &sum_sig)
+        .unwrap();
+    let mut ctx = module.make_context();
+    ctx.func.signature = sum_sig.clone();
+    ctx.func.name = ExternalName::user(0, main_id.as_u32());
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let sum = module.declare_func_in_func(sum_id, bcx.func);
+        let ebb = bcx.create_ebb();
+        bcx.append_ebb_params_for_function_params(ebb);
+        bcx.switch_to_block(ebb);
+        let params = bcx.ebb_params(ebb).to_vec();
+        let call = bcx.ins().call(sum, &params);
+        let v = bcx.inst_results(call)[0];
+        bcx.ins().return_(&[v]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(main_id, &mut ctx).unwrap();
+    module.finalize_definitions();
+    assert_eq!(compiled.get(), 0);
+
+    // Moving the box doesn't move the module the trampolines call back into.
+    let moved = module;
+    let mut module = moved;
+
+    let main = module.get_finalized_function(main_id);
+    let main =
+        unsafe { core::mem::transmute::<*const u8, extern "C" fn(i64, i64, f64) -> i64>(main) };
+    assert_eq!(main(1, 2, 0.5), 6);
+    assert_eq!(compiled.get(), 2);
+    assert_eq!(main(10, 20, 7.0), 67);
+    assert_eq!(compiled.get(), 2);
+
+    match declare_lazy_function(
+        &mut module,
+        "main",
+        Linkage::Export,
+        &sum_sig,
+        Box::new(|_, _| Ok(())),
+    ) {
+        Err(ModuleError::DuplicateDefinition(_)) => {}
+        _ => panic!("main is already defined"),
+    }
+}
+
+#[test]
+fn lazy_compilation_unsupported_target() {
+    use cranelift_codegen::{isa, settings};
+
+    let isa = isa::lookup_by_name("i686")
+        .unwrap()
+        .finish(settings::Flags::new(settings::builder()));
+    let mut module: Box<Module<SimpleJITBackend>> = Box::new(Module::new(
+        SimpleJITBuilder::with_isa(isa, default_libcall_names()),
+    ));
+    let sig = module.make_signature();
+    match declare_lazy_function(
+        &mut module,
+        "f",
+        Linkage::Local,
+        &sig,
+        Box::new(|_, _| Ok(())),
+    ) {
+        Err(ModuleError::Backend(_)) => {}
+        _ => panic!("lazy compilation should only be supported on x86-64"),
+    }
+}
+
+#[test]
+fn profiling_agent() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Events = Rc<RefCell<Vec<(String, usize, Vec<SourceLoc>)>>>;
+
+    struct Recorder(Events);
+    impl ProfilingAgent for Recorder {
+        fn function_loaded(
+            &mut self,
+            name: &str,
+            code: &[u8],
+            srclocs: &[(binemit::CodeOffset, SourceLoc)],
+        ) {
+            assert!(srclocs
+                .iter()
+                .all(|&(offset, _)| (offset as usize) < code.len()));
+            let srclocs = srclocs.iter().map(|&(_, srcloc)| srcloc).collect();
+            self.0
+                .borrow_mut()
+                .push((name.to_owned(), code.len(), srclocs));
+        }
+
+        fn function_unloaded(&mut self, name: &str, _code: *const u8) {
+            self.0.borrow_mut().push((name.to_owned(), 0, Vec::new()));
+        }
+    }
+
+    let events = Events::default();
+    let mut builder = SimpleJITBuilder::new(default_libcall_names());
+    builder.profiler(Box::new(Recorder(events.clone())));
+    let mut module: Module<SimpleJITBackend> = Module::new(builder);
+
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I32));
+    sig.returns.push(AbiParam::new(types::I32));
+    let func_id = module
+        .declare_function("square", Linkage::Export, &sig)
+        .unwrap();
+    let mut ctx = module.make_context();
+    ctx.func.signature = sig;
+    ctx.func.name = ExternalName::user(0, func_id.as_u32());
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let ebb = bcx.create_ebb();
+        bcx.append_ebb_params_for_function_params(ebb);
+        bcx.switch_to_block(ebb);
+        let x = bcx.ebb_params(ebb)[0];
+        bcx.set_srcloc(SourceLoc::new(1));
+        let v = bcx.ins().imul(x, x);
+        bcx.set_srcloc(SourceLoc::new(2));
+        bcx.ins().return_(&[v]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(func_id, &mut ctx).unwrap();
+    assert!(events.borrow().is_empty());
+    module.finalize_definitions();
+
+    {
+        let events = events.borrow();
+        assert_eq!(events.len(), 1);
+        let (ref name, size, ref srclocs) = events[0];
+        assert_eq!(name, "square");
+        assert!(size > 0);
+        assert_eq!(*srclocs, [SourceLoc::new(1), SourceLoc::new(2)]);
+    }
+
+    module.free_function(func_id).unwrap();
+    assert_eq!(events.borrow()[1].0, "square");
+    assert_eq!(events.borrow()[1].1, 0);
+}
+
+#[test]
+fn profiling_cached_function() {
+    use cranelift_codegen::cache::{CacheKey, CachedCode, CompilationCache};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    type Entries = Rc<RefCell<HashMap<CacheKey, CachedCode>>>;
+    type Srclocs = Rc<RefCell<Vec<Vec<SourceLoc>>>>;
+
+    struct SharedCache(Entries);
+    impl CompilationCache for SharedCache {
+        fn get(&mut self, key: &CacheKey) -> Option<CachedCode> {
+            self.0.borrow().get(key).cloned()
+        }
+
+        fn insert(&mut self, key: &CacheKey, code: &CachedCode) {
+            self.0.borrow_mut().insert(*key, code.clone());
+        }
+    }
+
+    struct Recorder(Srclocs);
+    impl ProfilingAgent for Recorder {
+        fn function_loaded(
+            &mut self,
+            _name: &str,
+            _code: &[u8],
+            srclocs: &[(binemit::CodeOffset, SourceLoc)],
+        ) {
+            let srclocs = srclocs.iter().map(|&(_, srcloc)| srcloc).collect();
+            self.0.borrow_mut().push(srclocs);
+        }
+    }
+
+    let entries = Entries::default();
+    let srclocs = Srclocs::default();
+
+    // The second module gets the function from the cache, and still reports its source locations.
+    for _ in 0..2 {
+        let mut builder = SimpleJITBuilder::new(default_libcall_names());
+        builder.profiler(Box::new(Recorder(srclocs.clone())));
+        let mut module: Module<SimpleJITBackend> = Module::new(builder);
+        module.enable_cache(Box::new(SharedCache(entries.clone())));
+
+        let mut sig = module.make_signature();
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        let func_id = module
+            .declare_function("square", Linkage::Export, &sig)
+            .unwrap();
+        let mut ctx = module.make_context();
+        ctx.func.signature = sig;
+        ctx.func.name = ExternalName::user(0, func_id.as_u32());
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let ebb = bcx.create_ebb();
+            bcx.append_ebb_params_for_function_params(ebb);
+            bcx.switch_to_block(ebb);
+            let x = bcx.ebb_params(ebb)[0];
+            bcx.set_srcloc(SourceLoc::new(1));
+            let v = bcx.ins().imul(x, x);
+            bcx.set_srcloc(SourceLoc::new(2));
+            bcx.ins().return_(&[v]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module.define_function(func_id, &mut ctx).unwrap();
+        module.finalize_definitions();
+    }
+
+    assert_eq!(entries.borrow().len(), 1);
+    let expected = vec![SourceLoc::new(1), SourceLoc::new(2)];
+    assert_eq!(*srclocs.borrow(), [expected.clone(), expected]);
+}
+
+extern "C" fn host_add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+#[test]
+fn pic_code() {
+    use cranelift_codegen::settings::{self, Configurable};
+
+    let mut flag_builder = settings::builder();
+    flag_builder.enable("is_pic").unwrap();
+    let isa = cranelift_native::builder()
+        .unwrap()
+        .finish(settings::Flags::new(flag_builder));
+    let mut builder = SimpleJITBuilder::with_isa(isa, default_libcall_names());
+    builder.symbol("host_add", host_add as *const u8);
+    let mut module: Module<SimpleJITBackend> = Module::new(builder);
+
+    // The host function is usually more than 2GB away from the JIT memory, so the call to it goes
+    // through a veneer. The preemptible callee and data object are accessed through the PLT and
+    // the GOT.
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I32));
+    sig.params.push(AbiParam::new(types::I32));
+    sig.returns.push(AbiParam::new(types::I32));
+    let host_id = module
+        .declare_function("host_add", Linkage::Import, &sig)
+        .unwrap();
+    let callee_id = module
+        .declare_function("callee", Linkage::Preemptible, &sig)
+        .unwrap();
+    let caller_id = module
+        .declare_function("caller", Linkage::Export, &sig)
+        .unwrap();
+    let data_id = module
+        .declare_data("offset", Linkage::Preemptible, false, None)
+        .unwrap();
+
+    let mut data_ctx = DataContext::new();
+    data_ctx.define(100i32.to_ne_bytes().to_vec().into_boxed_slice());
+    module.define_data(data_id, &data_ctx).unwrap();
+
+    // callee(a, b) = host_add(a, b) + offset
+    let mut ctx = module.make_context();
+    ctx.func.signature = sig.clone();
+    ctx.func.name = ExternalName::user(0, callee_id.as_u32());
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let host = module.declare_func_in_func(host_id, bcx.func);
+        let offset = module.declare_data_in_func(data_id, bcx.func);
+        let ebb = bcx.create_ebb();
+        bcx.append_ebb_params_for_function_params(ebb);
+        bcx.switch_to_block(ebb);
+        let params = bcx.ebb_params(ebb).to_vec();
+        let call = bcx.ins().call(host, &params);
+        let v = bcx.inst_results(call)[0];
+        let addr = bcx.ins().symbol_value(types::I64, offset);
+        let offset = bcx.ins().load(types::I32, MemFlags::new(), addr, 0);
+        let v = bcx.ins().iadd(v, offset);
+        bcx.ins().return_(&[v]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(callee_id, &mut ctx).unwrap();
+
+    // caller(a, b) = callee(a, b) * 2
+    module.clear_context(&mut ctx);
+    ctx.func.signature = sig;
+    ctx.func.name = ExternalName::user(0, caller_id.as_u32());
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let callee = module.declare_func_in_func(callee_id, bcx.func);
+        let ebb = bcx.create_ebb();
+        bcx.append_ebb_params_for_function_params(ebb);
+        bcx.switch_to_block(ebb);
+        let params = bcx.ebb_params(ebb).to_vec();
+        let call = bcx.ins().call(callee, &params);
+        let v = bcx.inst_results(call)[0];
+        let v = bcx.ins().imul_imm(v, 2);
+        bcx.ins().return_(&[v]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(caller_id, &mut ctx).unwrap();
+    module.finalize_definitions();
+
+    let caller = module.get_finalized_function(caller_id);
+    let caller =
+        unsafe { core::mem::transmute::<*const u8, extern "C" fn(i32, i32) -> i32>(caller) };
+    assert_eq!(caller(1, 2), 206);
+}
+
+#[test]
+fn define_functions_in_parallel() {
+    fn compile(threads: usize) -> Vec<Vec<u8>> {
+        let mut module: Module<SimpleJITBackend> =
+            Module::new(SimpleJITBuilder::new(default_libcall_names()));
+        let mut sig = module.make_signature();
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+
+        let mut funcs = Vec::new();
+        for i in 0..20 {
+            let func_id = module
+                .declare_function(&format!("f{}", i), Linkage::Export, &sig)
+                .unwrap();
+            let mut ctx = module.make_context();
+            ctx.func.signature = sig.clone();
+            ctx.func.name = ExternalName::user(0, func_id.as_u32());
+            let mut func_ctx = FunctionBuilderContext::new();
+            {
+                let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+                let ebb = bcx.create_ebb();
+                bcx.append_ebb_params_for_function_params(ebb);
+                bcx.switch_to_block(ebb);
+                let mut v = bcx.ebb_params(ebb)[0];
+                for _ in 0..i {
+                    v = bcx.ins().imul_imm(v, 3);
+                    v = bcx.ins().iadd_imm(v, 1);
+                }
+                bcx.ins().return_(&[v]);
+                bcx.seal_all_blocks();
+                bcx.finalize();
+            }
+            funcs.push((func_id, ctx));
+        }
+        let sizes = module.define_functions(&mut funcs, threads).unwrap();
+        module.finalize_definitions();
+
+        // Each function gets its own code, whatever the number of threads.
+        let mut code = Vec::new();
+        let mut ranges = Vec::new();
+        for (i, (&(func_id, _), &size)) in funcs.iter().zip(&sizes).enumerate() {
+            let ptr = module.get_finalized_function(func_id);
+            ranges.push((ptr as usize, ptr as usize + size as usize));
+            let f = unsafe { core::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(ptr) };
+            assert_eq!(f(0), (0..i).fold(0, |v, _| v * 3 + 1));
+            code.push(unsafe { std::slice::from_raw_parts(ptr, size as usize) }.to_vec());
+        }
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            assert!(pair[0].1 <= pair[1].0, "overlapping code: {:?}", pair);
+        }
+
+        // Defining a function twice is an error.
+        let (func_id, ref ctx) = funcs[0];
+        match module.define_functions(
+            &mut [(func_id, Context::for_function(ctx.func.clone()))],
+            threads,
+        ) {
+            Err(ModuleError::DuplicateDefinition(_)) => {}
+            _ => panic!("duplicate definition should fail"),
+        }
+        code
+    }
+
+    assert_eq!(compile(1), compile(4));
+}
+
+#[test]
+fn compilation_cache() {
+    use cranelift_codegen::cache::{CacheKey, CachedCode, CompilationCache, DiskCache};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct CountingCache {
+        disk: DiskCache,
+        hits: Rc<Cell<usize>>,
+        misses: Rc<Cell<usize>>,
+    }
+
+    impl CompilationCache for CountingCache {
+        fn get(&mut self, key: &CacheKey) -> Option<CachedCode> {
+            let code = self.disk.get(key);
+            let count = if code.is_some() {
+                &self.hits
+            } else {
+                &self.misses
+            };
+            count.set(count.get() + 1);
+            code
+        }
+
+        fn insert(&mut self, key: &CacheKey, code: &CachedCode) {
+            self.disk.insert(key, code)
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("cranelift-cache-test-{}", std::process::id()));
+    let hits = Rc::new(Cell::new(0));
+    let misses = Rc::new(Cell::new(0));
+
+    // `double` calls `add`, so its cached code has a relocation to replay.
+    let run = |parallel: bool| {
+        let mut module: Module<SimpleJITBackend> =
+            Module::new(SimpleJITBuilder::new(default_libcall_names()));
+        module.enable_cache(Box::new(CountingCache {
+            disk: DiskCache::new(&dir).unwrap(),
+            hits: hits.clone(),
+            misses: misses.clone(),
+        }));
+        let mut sig = module.make_signature();
+        sig.params.push(AbiParam::new(types::I32));
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        let add_id = module
+            .declare_function("add", Linkage::Local, &sig)
+            .unwrap();
+        let add_sig = sig.clone();
+        sig.params.pop();
+        let double_id = module
+            .declare_function("double", Linkage::Export, &sig)
+            .unwrap();
+
+        let mut func_ctx = FunctionBuilderContext::new();
+        let mut add_ctx = module.make_context();
+        add_ctx.func.signature = add_sig;
+        add_ctx.func.name = ExternalName::user(0, add_id.as_u32());
+        {
+            let mut bcx = FunctionBuilder::new(&mut add_ctx.func, &mut func_ctx);
+            let ebb = bcx.create_ebb();
+            bcx.append_ebb_params_for_function_params(ebb);
+            bcx.switch_to_block(ebb);
+            let (x, y) = (bcx.ebb_params(ebb)[0], bcx.ebb_params(ebb)[1]);
+            let sum = bcx.ins().iadd(x, y);
+            bcx.ins().return_(&[sum]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        let mut double_ctx = module.make_context();
+        double_ctx.func.signature = sig.clone();
+        double_ctx.func.name = ExternalName::user(0, double_id.as_u32());
+        {
+            let mut bcx = FunctionBuilder::new(&mut double_ctx.func, &mut func_ctx);
+            let ebb = bcx.create_ebb();
+            bcx.append_ebb_params_for_function_params(ebb);
+            bcx.switch_to_block(ebb);
+            let x = bcx.ebb_params(ebb)[0];
+            let add = module.declare_func_in_func(add_id, &mut bcx.func);
+            let call = bcx.ins().call(add, &[x, x]);
+            let result = bcx.inst_results(call)[0];
+            bcx.ins().return_(&[result]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+
+        if parallel {
+            let mut funcs = [(add_id, add_ctx), (double_id, double_ctx)];
+            module.define_functions(&mut funcs, 2).unwrap();
+        } else {
+            module.define_function(add_id, &mut add_ctx).unwrap();
+            module.define_function(double_id, &mut double_ctx).unwrap();
+        }
+        module.finalize_definitions();
+
+        let ptr = module.get_finalized_function(double_id);
+        let double = unsafe { core::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(ptr) };
+        assert_eq!(double(21), 42);
+    };
+
+    run(false);
+    assert_eq!((hits.get(), misses.get()), (0, 2));
+    run(false);
+    assert_eq!((hits.get(), misses.get()), (2, 2));
+    run(true);
+    assert_eq!((hits.get(), misses.get()), (4, 2));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn i128_arithmetic() {
+    let mut module: Module<SimpleJITBackend> =
+        Module::new(SimpleJITBuilder::new(default_libcall_names()));
+
+    // Each function takes `leading` pointer-sized arguments, which it ignores, followed by two
+    // 128-bit integers.
+    let mut define =
+        |name: &str, leading: usize, op: fn(&mut FunctionBuilder, Value, Value) -> Value| {
+            let mut sig = module.make_signature();
+            for _ in 0..leading {
+                sig.params.push(AbiParam::new(types::I64));
+            }
+            sig.params.push(AbiParam::new(types::I128));
+            sig.params.push(AbiParam::new(types::I128));
+            sig.returns.push(AbiParam::new(types::I128));
+            let func_id = module
+                .declare_function(name, Linkage::Export, &sig)
+                .unwrap();
+            let mut ctx = module.make_context();
+            ctx.func.signature = sig;
+            ctx.func.name = ExternalName::user(0, func_id.as_u32());
+            let mut func_ctx = FunctionBuilderContext::new();
+            {
+                let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+                let ebb = bcx.create_ebb();
+                bcx.append_ebb_params_for_function_params(ebb);
+                bcx.switch_to_block(ebb);
+                let (x, y) = (
+                    bcx.ebb_params(ebb)[leading],
+                    bcx.ebb_params(ebb)[leading + 1],
+                );
+                let result = op(&mut bcx, x, y);
+                bcx.ins().return_(&[result]);
+                bcx.seal_all_blocks();
+                bcx.finalize();
+            }
+            module.define_function(func_id, &mut ctx).unwrap();
+            func_id
+        };
+
+    // Division and remainder are library calls.
+    let udiv = define("udiv", 0, |bcx, x, y| bcx.ins().udiv(x, y));
+    let sdiv = define("sdiv", 0, |bcx, x, y| bcx.ins().sdiv(x, y));
+    let urem = define("urem", 0, |bcx, x, y| bcx.ins().urem(x, y));
+    let srem = define("srem", 0, |bcx, x, y| bcx.ins().srem(x, y));
+    let imul = define("imul", 0, |bcx, x, y| bcx.ins().imul(x, y));
+    let ishl = define("ishl", 0, |bcx, x, y| bcx.ins().ishl(x, y));
+    let sshr = define("sshr", 0, |bcx, x, y| bcx.ins().sshr(x, y));
+    let rotr = define("rotr", 0, |bcx, x, y| bcx.ins().rotr(x, y));
+    // With five leading arguments only one register is left, so the first 128-bit integer is
+    // passed on the stack, and the second one too.
+    let isub = define("isub", 5, |bcx, x, y| bcx.ins().isub(x, y));
+    module.finalize_definitions();
+
+    type BinaryOp = extern "C" fn(u128, u128) -> u128;
+    let mut get = |id| unsafe {
+        core::mem::transmute::<*const u8, BinaryOp>(module.get_finalized_function(id))
+    };
+    let big = 0x1234_5678_9abc_def0_1122_3344_5566_7788u128;
+    assert_eq!(get(udiv)(big, 7), big / 7);
+    assert_eq!(get(sdiv)(-1000i128 as u128, 7), (-1000i128 / 7) as u128);
+    assert_eq!(get(urem)(big, 1 << 70), big % (1 << 70));
+    assert_eq!(get(srem)(-1000i128 as u128, 7), (-1000i128 % 7) as u128);
+    assert_eq!(
+        get(imul)(big, 0x1_0000_0001),
+        big.wrapping_mul(0x1_0000_0001)
+    );
+    for &amt in &[0, 1, 63, 64, 65, 127, 128] {
+        assert_eq!(get(ishl)(big, amt), big.wrapping_shl(amt as u32));
+        assert_eq!(
+            get(sshr)(!big, amt),
+            ((!big as i128).wrapping_shr(amt as u32)) as u128
+        );
+        assert_eq!(get(rotr)(big, amt), big.rotate_right(amt as u32));
+    }
+
+    let isub = unsafe {
+        core::mem::transmute::<*const u8, extern "C" fn(u64, u64, u64, u64, u64, u128, u128) -> u128>(
+            module.get_finalized_function(isub),
+        )
+    };
+    assert_eq!(isub(0, 0, 0, 0, 0, big, 1 << 64), big - (1 << 64));
+}
+
+#[cfg(target_arch = "x86_64")]
+mod structs {
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Small {
+        pub a: i32,
+        pub b: i32,
+        pub c: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Mixed {
+        pub x: f64,
+        pub n: i64,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Floats {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Large {
+        pub a: i64,
+        pub b: i64,
+        pub c: i64,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Pair {
+        pub a: i32,
+        pub b: i32,
+    }
+
+    pub extern "sysv64" fn sysv_small(s: Small) -> Small {
+        Small {
+            a: s.c,
+            b: s.a + s.b,
+            c: s.a,
+        }
+    }
+
+    pub extern "sysv64" fn sysv_mixed(s: Mixed) -> Mixed {
+        Mixed {
+            x: s.x * 2.0,
+            n: s.n + 1,
+        }
+    }
+
+    pub extern "sysv64" fn sysv_floats(s: Floats) -> Floats {
+        Floats {
+            x: s.z,
+            y: s.x + s.y,
+            z: s.x,
+        }
+    }
+
+    pub extern "sysv64" fn sysv_large(s: Large) -> Large {
+        Large {
+            a: s.c,
+            b: s.a + s.b,
+            c: s.a,
+        }
+    }
+
+    pub extern "win64" fn win_pair(s: Pair) -> Pair {
+        Pair { a: s.b, b: s.a }
+    }
+
+    pub extern "win64" fn win_large(s: Large) -> Large {
+        Large {
+            a: s.c,
+            b: s.a + s.b,
+            c: s.a,
+        }
+    }
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn struct_arguments_to_host() {
+    use structs::*;
+
+    let mut builder = SimpleJITBuilder::new(default_libcall_names());
+    builder.symbol("sysv_small", sysv_small as *const u8);
+    builder.symbol("sysv_mixed", sysv_mixed as *const u8);
+    builder.symbol("sysv_floats", sysv_floats as *const u8);
+    builder.symbol("sysv_large", sysv_large as *const u8);
+    builder.symbol("win_pair", win_pair as *const u8);
+    builder.symbol("win_large", win_large as *const u8);
+    let mut module: Module<SimpleJITBackend> = Module::new(builder);
+
+    // Each trampoline passes the struct at `src` to the host function and copies the struct it
+    // returns to `dst`.
+    let mut define = |name: &str, call_conv: CallConv, size: u32, align: u32, floats: u8| {
+        let purpose = ArgumentPurpose::StructArgument {
+            size,
+            align,
+            floats,
+        };
+        let mut host_sig = module.make_signature();
+        host_sig.call_conv = call_conv;
+        host_sig.params.push(AbiParam::special(types::I64, purpose));
+        host_sig
+            .returns
+            .push(AbiParam::special(types::I64, purpose));
+        let host_id = module
+            .declare_function(name, Linkage::Import, &host_sig)
+            .unwrap();
+
+        let mut sig = module.make_signature();
+        sig.params.push(AbiParam::new(types::I64));
+        sig.params.push(AbiParam::new(types::I64));
+        let func_id = module
+            .declare_function(&format!("call_{}", name), Linkage::Local, &sig)
+            .unwrap();
+        let mut ctx = module.make_context();
+        ctx.func.signature = sig;
+        ctx.func.name = ExternalName::user(0, func_id.as_u32());
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let host = module.declare_func_in_func(host_id, bcx.func);
+            let ebb = bcx.create_ebb();
+            bcx.append_ebb_params_for_function_params(ebb);
+            bcx.switch_to_block(ebb);
+            let (src, dst) = (bcx.ebb_params(ebb)[0], bcx.ebb_params(ebb)[1]);
+            let call = bcx.ins().call(host, &[src]);
+            let result = bcx.inst_results(call)[0];
+            for offset in (0..size as i32).step_by(4) {
+                let v = bcx
+                    .ins()
+                    .load(types::I32, MemFlags::trusted(), result, offset);
+                bcx.ins().store(MemFlags::trusted(), v, dst, offset);
+            }
+            bcx.ins().return_(&[]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module.define_function(func_id, &mut ctx).unwrap();
+        func_id
+    };
+
+    let small = define("sysv_small", CallConv::SystemV, 12, 4, 0);
+    let mixed = define("sysv_mixed", CallConv::SystemV, 16, 8, 0b01);
+    let floats = define("sysv_floats", CallConv::SystemV, 12, 4, 0b11);
+    let large = define("sysv_large", CallConv::SystemV, 24, 8, 0);
+    // Windows passes 8-byte structs in a register and larger ones by reference.
+    let win_pair = define("win_pair", CallConv::WindowsFastcall, 8, 4, 0);
+    let win_large = define("win_large", CallConv::WindowsFastcall, 24, 8, 0);
+    module.finalize_definitions();
+
+    fn call<T: Copy>(module: &mut Module<SimpleJITBackend>, id: FuncId, arg: T) -> T {
+        let f = unsafe {
+            core::mem::transmute::<*const u8, extern "C" fn(*const T, *mut T)>(
+                module.get_finalized_function(id),
+            )
+        };
+        let mut result = core::mem::MaybeUninit::<T>::uninit();
+        f(&arg, result.as_mut_ptr());
+        unsafe { result.assume_init() }
+    }
+
+    assert_eq!(
+        call(&mut module, small, Small { a: 1, b: 2, c: 3 }),
+        Small { a: 3, b: 3, c: 1 }
+    );
+    assert_eq!(
+        call(&mut module, mixed, Mixed { x: 1.5, n: -7 }),
+        Mixed { x: 3.0, n: -6 }
+    );
+    assert_eq!(
+        call(
+            &mut module,
+            floats,
+            Floats {
+                x: 1.0,
+                y: 2.5,
+                z: -4.0
+            }
+        ),
+        Floats {
+            x: -4.0,
+            y: 3.5,
+            z: 1.0
+        }
+    );
+    let big = Large {
+        a: 1 << 40,
+        b: 2,
+        c: -3,
+    };
+    let expected = Large {
+        a: -3,
+        b: (1 << 40) + 2,
+        c: 1 << 40,
+    };
+    assert_eq!(call(&mut module, large, big), expected);
+    assert_eq!(
+        call(&mut module, win_pair, Pair { a: 5, b: -6 }),
+        Pair { a: -6, b: 5 }
+    );
+    assert_eq!(call(&mut module, win_large, big), expected);
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn struct_argument_alignment() {
+    let mut module: Module<SimpleJITBackend> =
+        Module::new(SimpleJITBuilder::new(default_libcall_names()));
+    let mut sig = module.make_signature();
+    let purpose = ArgumentPurpose::StructArgument {
+        size: 12,
+        align: 3,
+        floats: 0,
+    };
+    sig.params.push(AbiParam::special(types::I64, purpose));
+    let func_id = module
+        .declare_function("odd_alignment", Linkage::Local, &sig)
+        .unwrap();
+    let mut ctx = module.make_context();
+    ctx.func.signature = sig;
+    ctx.func.name = ExternalName::user(0, func_id.as_u32());
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let ebb = bcx.create_ebb();
+        bcx.append_ebb_params_for_function_params(ebb);
+        bcx.switch_to_block(ebb);
+        bcx.ins().return_(&[]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    match module.define_function(func_id, &mut ctx) {
+        Err(ModuleError::Compilation(cranelift_codegen::CodegenError::Unsupported(message))) => {
+            assert!(message.contains("alignment 3"))
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+}
@@ -1,25 +1,36 @@
 //! The `cat` sub-command.
 //!
 //! Read a sequence of Cranelift IR files and print them again to stdout. This has the effect of
-//! normalizing formatting and removing comments.
+//! normalizing formatting and removing comments. Functions can also be read from and written to
+//! the JSON format of `cranelift-serde`.
 
 use crate::utils::read_to_string;
 use crate::CommandResult;
+use cranelift_codegen::ir::Function;
 use cranelift_reader::parse_functions;
 
-pub fn run(files: &[String]) -> CommandResult {
+pub fn run(files: &[String], from_json: bool, to_json: bool) -> CommandResult {
     for (i, f) in files.into_iter().enumerate() {
         if i != 0 {
             println!();
         }
-        cat_one(f)?
+        cat_one(f, from_json, to_json)?
     }
     Ok(())
 }
 
-fn cat_one(filename: &str) -> CommandResult {
+fn cat_one(filename: &str, from_json: bool, to_json: bool) -> CommandResult {
     let buffer = read_to_string(&filename).map_err(|e| format!("{}: {}", filename, e))?;
-    let items = parse_functions(&buffer).map_err(|e| format!("{}: {}", filename, e))?;
+    let items = if from_json {
+        parse_json(&buffer)
+    } else {
+        parse_functions(&buffer).map_err(|e| e.to_string())
+    }
+    .map_err(|e| format!("{}: {}", filename, e))?;
+
+    if to_json {
+        return print_json(&items);
+    }
 
     for (idx, func) in items.into_iter().enumerate() {
         if idx != 0 {
@@ -30,3 +41,24 @@ fn cat_one(filename: &str) -> CommandResult {
 
     Ok(())
 }
+
+#[cfg(feature = "json")]
+fn parse_json(buffer: &str) -> Result<Vec<Function>, String> {
+    cranelift_serde::from_json(buffer)
+}
+
+#[cfg(feature = "json")]
+fn print_json(funcs: &[Function]) -> CommandResult {
+    println!("{}", cranelift_serde::to_json(funcs, None, true));
+    Ok(())
+}
+
+#[cfg(not(feature = "json"))]
+fn parse_json(_buffer: &str) -> Result<Vec<Function>, String> {
+    Err("Error: clif-util was compiled without JSON support.".to_owned())
+}
+
+#[cfg(not(feature = "json"))]
+fn print_json(_funcs: &[Function]) -> CommandResult {
+    Err("Error: clif-util was compiled without JSON support.".to_owned())
+}
@@ -197,7 +197,17 @@ fn main() {
             SubCommand::with_name("cat")
                 .about("Outputs .clif file")
                 .arg(add_input_file_arg())
-                .arg(add_debug_flag()),
+                .arg(add_debug_flag())
+                .arg(
+                    Arg::with_name("from-json")
+                        .long("from-json")
+                        .help("Read the input as JSON instead of Cranelift IR"),
+                )
+                .arg(
+                    Arg::with_name("to-json")
+                        .long("to-json")
+                        .help("Output the functions as JSON instead of Cranelift IR"),
+                ),
         )
         .subcommand(
             SubCommand::with_name("print-cfg")
@@ -234,7 +244,11 @@ fn main() {
     let res_util = match app_cmds.get_matches().subcommand() {
         ("cat", Some(rest_cmd)) => {
             handle_debug_flag(rest_cmd.is_present("debug"));
-            cat::run(&get_vec(rest_cmd.values_of("file")))
+            cat::run(
+                &get_vec(rest_cmd.values_of("file")),
+                rest_cmd.is_present("from-json"),
+                rest_cmd.is_present("to-json"),
+            )
         }
         ("test", Some(rest_cmd)) => {
             handle_debug_flag(rest_cmd.is_present("debug"));